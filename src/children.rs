@@ -0,0 +1,108 @@
+use failure;
+use futures::sync::oneshot;
+use tokio::prelude::*;
+use {KeeperState, WatchedEvent, ZooKeeper};
+
+/// A stream of the children of a node, which yields the current list whenever it changes.
+///
+/// See [`ZooKeeper::watch_children`].
+pub(crate) struct WatchChildren {
+    zk: ZooKeeper,
+    path: String,
+    /// Whether to watch again once the watch has fired.
+    rearm: bool,
+    state: State,
+}
+
+/// Reads the children of a node, and leaves a watch on them.
+type Fetch = Box<
+    dyn Future<
+            Item = (
+                ZooKeeper,
+                Option<(oneshot::Receiver<WatchedEvent>, Vec<String>)>,
+            ),
+            Error = failure::Error,
+        > + Send,
+>;
+
+enum State {
+    /// Reading the children, and leaving a watch on them.
+    Fetching(Fetch),
+    /// Waiting for the children to change.
+    Waiting(oneshot::Receiver<WatchedEvent>),
+    /// Reading the children after they changed, without a watch, as this is the last read.
+    Last(Box<dyn Future<Item = (ZooKeeper, Option<Vec<String>>), Error = failure::Error> + Send>),
+    /// The node is gone, or we can no longer find out about it.
+    Done,
+}
+
+impl WatchChildren {
+    pub(crate) fn new(zk: ZooKeeper, path: &str, rearm: bool) -> Self {
+        let state = fetch(&zk, path);
+        WatchChildren {
+            zk,
+            path: path.to_string(),
+            rearm,
+            state,
+        }
+    }
+}
+
+fn fetch(zk: &ZooKeeper, path: &str) -> State {
+    State::Fetching(Box::new(zk.clone().with_watcher().get_children(path)))
+}
+
+impl Stream for WatchChildren {
+    type Item = Vec<String>;
+    type Error = failure::Error;
+
+    fn poll(&mut self) -> Result<Async<Option<Self::Item>>, Self::Error> {
+        loop {
+            let next = match self.state {
+                State::Fetching(ref mut f) => match f.poll() {
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Ok(Async::Ready((_, Some((watch, children))))) => {
+                        self.state = State::Waiting(watch);
+                        return Ok(Async::Ready(Some(children)));
+                    }
+                    Ok(Async::Ready((_, None))) => State::Done,
+                    // the request was lost along with the connection, so ask again once the
+                    // client has reconnected (requests wait for that)
+                    Err(_) if self.zk.connection.state() == KeeperState::Disconnected => {
+                        fetch(&self.zk, &self.path)
+                    }
+                    Err(e) => {
+                        self.state = State::Done;
+                        return Err(e);
+                    }
+                },
+                State::Waiting(ref mut watch) => match watch.poll() {
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    // the children changed, or the node was deleted, which the next read tells
+                    Ok(Async::Ready(_)) if self.rearm => fetch(&self.zk, &self.path),
+                    Ok(Async::Ready(_)) => {
+                        State::Last(Box::new(self.zk.clone().get_children(&self.path)))
+                    }
+                    Err(oneshot::Canceled) => {
+                        self.state = State::Done;
+                        bail!("children watch dropped, as the client shut down");
+                    }
+                },
+                State::Last(ref mut f) => match f.poll() {
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Ok(Async::Ready((_, Some(children)))) => {
+                        self.state = State::Done;
+                        return Ok(Async::Ready(Some(children)));
+                    }
+                    Ok(Async::Ready((_, None))) => State::Done,
+                    Err(e) => {
+                        self.state = State::Done;
+                        return Err(e);
+                    }
+                },
+                State::Done => return Ok(Async::Ready(None)),
+            };
+            self.state = next;
+        }
+    }
+}
@@ -0,0 +1,133 @@
+use failure;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::str::FromStr;
+
+/// A parsed connection string of the form `host:port[,host:port...][/chroot]`.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct ConnectString {
+    /// The `host:port` pairs of the servers in the ensemble, in the order they were given.
+    pub(crate) hosts: Vec<String>,
+    /// The path that all client paths are relative to, or `None` if it is `/`.
+    pub(crate) chroot: Option<String>,
+}
+
+impl ConnectString {
+    /// Resolve the hosts of this connection string to the addresses of the servers.
+    ///
+    /// A host name may resolve to several addresses, all of which are returned. Note that this
+    /// blocks the current thread until all names have been resolved.
+    pub(crate) fn resolve(&self) -> Result<Vec<SocketAddr>, failure::Error> {
+        let mut addrs = Vec::new();
+        for host in &self.hosts {
+            let resolved = host
+                .to_socket_addrs()
+                .map_err(|e| format_err!("failed to resolve '{}': {}", host, e))?;
+            for addr in resolved {
+                if !addrs.contains(&addr) {
+                    addrs.push(addr);
+                }
+            }
+        }
+        if addrs.is_empty() {
+            bail!("no server addresses found");
+        }
+        Ok(addrs)
+    }
+}
+
+impl FromStr for ConnectString {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (hosts, chroot) = match s.find('/') {
+            Some(i) => (&s[..i], &s[i..]),
+            None => (s, "/"),
+        };
+
+        let hosts = hosts
+            .split(',')
+            .map(str::trim)
+            .map(|host| {
+                // the port is required, and IPv6 addresses must be bracketed
+                match host.rfind(':') {
+                    Some(i) if host[i + 1..].parse::<u16>().is_ok() && i > 0 => {
+                        Ok(host.to_string())
+                    }
+                    _ => Err(format_err!("invalid server address '{}'", host)),
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let chroot = if chroot == "/" {
+            None
+        } else {
+            if chroot.ends_with('/') {
+                bail!("chroot '{}' must not end with '/'", chroot);
+            }
+            if chroot[1..]
+                .split('/')
+                .any(|c| c.is_empty() || c == "." || c == "..")
+            {
+                bail!("chroot '{}' is not a valid path", chroot);
+            }
+            Some(chroot.to_string())
+        };
+
+        Ok(ConnectString { hosts, chroot })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn without_chroot() {
+        for &s in &["127.0.0.1:2181", "127.0.0.1:2181/"] {
+            let cs: ConnectString = s.parse().unwrap();
+            assert_eq!(cs.hosts, vec!["127.0.0.1:2181"]);
+            assert_eq!(cs.chroot, None);
+        }
+    }
+
+    #[test]
+    fn with_chroot() {
+        let cs: ConnectString = "[::1]:2181/my/app".parse().unwrap();
+        assert_eq!(cs.hosts, vec!["[::1]:2181"]);
+        assert_eq!(cs.chroot, Some("/my/app".to_string()));
+    }
+
+    #[test]
+    fn multiple_hosts() {
+        let cs: ConnectString = "zk1:2181, zk2:2182,127.0.0.1:2183/app".parse().unwrap();
+        assert_eq!(cs.hosts, vec!["zk1:2181", "zk2:2182", "127.0.0.1:2183"]);
+        assert_eq!(cs.chroot, Some("/app".to_string()));
+    }
+
+    #[test]
+    fn resolve() {
+        let cs: ConnectString = "127.0.0.1:2181,localhost:2182,127.0.0.1:2181"
+            .parse()
+            .unwrap();
+        let addrs = cs.resolve().unwrap();
+        // duplicates are removed, and the order is preserved
+        assert_eq!(addrs[0], "127.0.0.1:2181".parse().unwrap());
+        assert!(addrs[1..].iter().all(|a| a.port() == 2182));
+        assert!(addrs.len() >= 2);
+    }
+
+    #[test]
+    fn invalid() {
+        for &s in &[
+            "127.0.0.1",
+            "127.0.0.1:2181,",
+            ":2181",
+            "127.0.0.1:port",
+            "127.0.0.1:2181/app/",
+            "127.0.0.1:2181/app//x",
+            "127.0.0.1:2181/app/../x",
+        ] {
+            assert!(s.parse::<ConnectString>().is_err(), "{}", s);
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use std::collections::VecDeque;
+use std::time;
+use tokio::prelude::*;
+use tokio::timer::Delay;
+use {WatchedEvent, WatchedEventType};
+
+/// A stream of watch events in which bursts of the same event for the same node are coalesced.
+///
+/// See [`ZooKeeper::debounced_watch_events`](::ZooKeeper::debounced_watch_events).
+pub(crate) struct Debounce<S> {
+    events: S,
+    ended: bool,
+    window: time::Duration,
+    /// Events that are held back until their window closes, in the order the windows close.
+    pending: VecDeque<(time::Instant, WatchedEvent)>,
+    /// Fires when the window of the first pending event closes.
+    timer: Option<Delay>,
+}
+
+impl<S> Debounce<S> {
+    pub(crate) fn new(events: S, window: time::Duration) -> Self {
+        Debounce {
+            events,
+            ended: false,
+            window,
+            pending: VecDeque::new(),
+            timer: None,
+        }
+    }
+}
+
+impl<S> Stream for Debounce<S>
+where
+    S: Stream<Item = WatchedEvent>,
+{
+    type Item = WatchedEvent;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Result<Async<Option<Self::Item>>, Self::Error> {
+        if self.window == time::Duration::from_secs(0) {
+            return self.events.poll();
+        }
+
+        while !self.ended {
+            match self.events.poll()? {
+                Async::Ready(Some(event)) => {
+                    if event.event_type == WatchedEventType::None {
+                        // changes to the state of the session must not get lost in a burst
+                        return Ok(Async::Ready(Some(event)));
+                    }
+                    let same = self.pending.iter_mut().find(|&&mut (_, ref e)| {
+                        e.path == event.path && e.event_type == event.event_type
+                    });
+                    match same {
+                        Some(pending) => pending.1 = event,
+                        None => {
+                            let closes = time::Instant::now() + self.window;
+                            self.pending.push_back((closes, event));
+                        }
+                    }
+                }
+                Async::Ready(None) => self.ended = true,
+                Async::NotReady => break,
+            }
+        }
+
+        let closes = match self.pending.front() {
+            Some(&(closes, _)) => closes,
+            None if self.ended => return Ok(Async::Ready(None)),
+            None => return Ok(Async::NotReady),
+        };
+        // once no more events can arrive, there is no point in holding back the ones we have
+        if !self.ended {
+            let timer = self.timer.get_or_insert_with(|| Delay::new(closes));
+            if Delay::deadline(timer) != closes {
+                timer.reset(closes);
+            }
+            match timer.poll() {
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                // without a timer, events are no longer held back
+                Ok(Async::Ready(())) | Err(_) => {}
+            }
+        }
+        Ok(Async::Ready(
+            self.pending.pop_front().map(|(_, event)| event),
+        ))
+    }
+}
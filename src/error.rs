@@ -1,151 +1,729 @@
+use failure::Fail;
+use std::fmt;
+use {Acl, Permission, ZkError};
+
+/// Errors that can tell which version a node had when it did not have the expected one.
+pub(crate) trait ActualVersion {
+    /// Where the actual version goes, if this is such an error.
+    fn actual_version(&mut self) -> Option<&mut Option<i32>>;
+}
+
+// The per-operation errors below implement `Fail` by hand (rather than through
+// `#[derive(Fail)]`) so that `Fail::cause` can point back at the `ZkError` code the server
+// responded with. This keeps the full chain visible to code that walks `iter_causes`.
+
+/// Describe a `NoAuth` error for the node at `path`, which needed the `required` permission.
+fn no_auth(f: &mut fmt::Formatter, path: &str, required: Permission) -> fmt::Result {
+    write!(
+        f,
+        "insufficient authentication: {} permission on {} is required",
+        required, path
+    )
+}
+
 /// Errors that may cause a delete request to fail.
-#[derive(Clone, Copy, PartialEq, Eq, Debug, Fail)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum Delete {
     /// No node exists with the given `path`.
-    #[fail(display = "target node does not exist")]
     NoNode,
 
     /// The target node has a different version than was specified by the call to delete.
-    #[fail(
-        display = "target node has different version than expected ({})",
-        expected
-    )]
     BadVersion {
         /// The expected node version.
         expected: i32,
+        /// The version the node had instead, or `None` if it is not known (see
+        /// [`ZooKeeperBuilder::set_fetch_actual_version`](::ZooKeeperBuilder::set_fetch_actual_version)).
+        actual: Option<i32>,
     },
 
     /// The target node has child nodes, and therefore cannot be deleted.
-    #[fail(display = "target node has children, and cannot be deleted")]
     NotEmpty,
+
+    /// The client is connected to a server in read-only mode, which does not accept writes.
+    NotReadOnly,
+}
+
+impl fmt::Display for Delete {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Delete::NoNode => write!(f, "target node does not exist"),
+            Delete::BadVersion {
+                expected,
+                actual: Some(actual),
+            } => write!(
+                f,
+                "target node has version {} rather than the expected {}",
+                actual, expected
+            ),
+            Delete::BadVersion { expected, .. } => write!(
+                f,
+                "target node has different version than expected ({})",
+                expected
+            ),
+            Delete::NotEmpty => write!(f, "target node has children, and cannot be deleted"),
+            Delete::NotReadOnly => write!(f, "server is in read-only mode"),
+        }
+    }
+}
+
+impl Fail for Delete {
+    fn cause(&self) -> Option<&dyn Fail> {
+        match *self {
+            Delete::NoNode => Some(&ZkError::NoNode),
+            Delete::BadVersion { .. } => Some(&ZkError::BadVersion),
+            Delete::NotEmpty => Some(&ZkError::NotEmpty),
+            Delete::NotReadOnly => Some(&ZkError::NotReadOnly),
+        }
+    }
+}
+
+impl Delete {
+    /// Translate the error code the server responded with, or return it if this operation does
+    /// not expect it. `version` is the version the request expected the node to have.
+    pub(crate) fn from_zk(e: ZkError, version: i32) -> Result<Self, ZkError> {
+        Ok(match e {
+            ZkError::NoNode => Delete::NoNode,
+            ZkError::NotEmpty => Delete::NotEmpty,
+            ZkError::BadVersion => Delete::BadVersion {
+                expected: version,
+                actual: None,
+            },
+            ZkError::NotReadOnly => Delete::NotReadOnly,
+            e => return Err(e),
+        })
+    }
+}
+
+impl ActualVersion for Delete {
+    fn actual_version(&mut self) -> Option<&mut Option<i32>> {
+        match *self {
+            Delete::BadVersion { ref mut actual, .. } => Some(actual),
+            _ => None,
+        }
+    }
 }
 
 /// Errors that may cause a `set_data` request to fail.
-#[derive(Clone, Copy, PartialEq, Eq, Debug, Fail)]
+///
+/// This is not `Copy`, as [`SetData::NoAuth`] names the node it was about.
+#[derive(Clone, PartialEq, Eq, Debug)]
 pub enum SetData {
     /// No node exists with the given `path`.
-    #[fail(display = "target node does not exist")]
     NoNode,
 
     /// The target node has a different version than was specified by the call to `set_data`.
-    #[fail(
-        display = "target node has different version than expected ({})",
-        expected
-    )]
     BadVersion {
         /// The expected node version.
         expected: i32,
+        /// The version the node had instead, or `None` if it is not known (see
+        /// [`ZooKeeperBuilder::set_fetch_actual_version`](::ZooKeeperBuilder::set_fetch_actual_version)).
+        actual: Option<i32>,
     },
 
     /// The target node's permission does not accept data modification or requires different
     /// authentication to be altered.
-    #[fail(display = "insuficient authentication")]
-    NoAuth,
+    NoAuth {
+        /// The path of the target node, as given to the call.
+        path: String,
+        /// The permission needed to set the node's data ([`Permission::WRITE`]).
+        required: Permission,
+    },
+
+    /// The client is connected to a server in read-only mode, which does not accept writes.
+    NotReadOnly,
+}
+
+impl fmt::Display for SetData {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SetData::NoNode => write!(f, "target node does not exist"),
+            SetData::BadVersion {
+                expected,
+                actual: Some(actual),
+            } => write!(
+                f,
+                "target node has version {} rather than the expected {}",
+                actual, expected
+            ),
+            SetData::BadVersion { expected, .. } => write!(
+                f,
+                "target node has different version than expected ({})",
+                expected
+            ),
+            SetData::NoAuth { ref path, required } => no_auth(f, path, required),
+            SetData::NotReadOnly => write!(f, "server is in read-only mode"),
+        }
+    }
+}
+
+impl Fail for SetData {
+    fn cause(&self) -> Option<&dyn Fail> {
+        match *self {
+            SetData::NoNode => Some(&ZkError::NoNode),
+            SetData::BadVersion { .. } => Some(&ZkError::BadVersion),
+            SetData::NoAuth { .. } => Some(&ZkError::NoAuth),
+            SetData::NotReadOnly => Some(&ZkError::NotReadOnly),
+        }
+    }
+}
+
+impl SetData {
+    /// Translate the error code the server responded with, or return it if this operation does
+    /// not expect it. `version` is the version the request expected the node at `path` to have.
+    pub(crate) fn from_zk(e: ZkError, version: i32, path: &str) -> Result<Self, ZkError> {
+        Ok(match e {
+            ZkError::NoNode => SetData::NoNode,
+            ZkError::BadVersion => SetData::BadVersion {
+                expected: version,
+                actual: None,
+            },
+            ZkError::NoAuth => SetData::NoAuth {
+                path: path.to_string(),
+                required: Permission::WRITE,
+            },
+            ZkError::NotReadOnly => SetData::NotReadOnly,
+            e => return Err(e),
+        })
+    }
+}
+
+impl ActualVersion for SetData {
+    fn actual_version(&mut self) -> Option<&mut Option<i32>> {
+        match *self {
+            SetData::BadVersion { ref mut actual, .. } => Some(actual),
+            _ => None,
+        }
+    }
 }
 
 /// Errors that may cause a create request to fail.
-#[derive(Clone, Copy, PartialEq, Eq, Debug, Fail)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum Create {
     /// A node with the given `path` already exists.
-    #[fail(display = "target node already exists")]
     NodeExists,
 
     /// The parent node of the given `path` does not exist.
-    #[fail(display = "parent node of target does not exist")]
     NoNode,
 
     /// The parent node of the given `path` is ephemeral, and cannot have children.
-    #[fail(display = "parent node is ephemeral, and cannot have children")]
     NoChildrenForEphemerals,
 
     /// The given ACL is invalid.
-    #[fail(display = "the given ACL is invalid")]
     InvalidAcl,
+
+    /// The TTL of the given [`CreateMode`](::CreateMode) is outside the range accepted by the
+    /// server.
+    InvalidTtl,
+
+    /// The client is connected to a server in read-only mode, which does not accept writes.
+    NotReadOnly,
+}
+
+impl fmt::Display for Create {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Create::NodeExists => write!(f, "target node already exists"),
+            Create::NoNode => write!(f, "parent node of target does not exist"),
+            Create::NoChildrenForEphemerals => {
+                write!(f, "parent node is ephemeral, and cannot have children")
+            }
+            Create::InvalidAcl => write!(f, "the given ACL is invalid"),
+            Create::InvalidTtl => write!(f, "the given TTL is out of range"),
+            Create::NotReadOnly => write!(f, "server is in read-only mode"),
+        }
+    }
+}
+
+impl Fail for Create {
+    fn cause(&self) -> Option<&dyn Fail> {
+        match *self {
+            Create::NodeExists => Some(&ZkError::NodeExists),
+            Create::NoNode => Some(&ZkError::NoNode),
+            Create::NoChildrenForEphemerals => Some(&ZkError::NoChildrenForEphemerals),
+            Create::InvalidAcl => Some(&ZkError::InvalidACL),
+            Create::NotReadOnly => Some(&ZkError::NotReadOnly),
+            // caught before the request is sent to the server
+            Create::InvalidTtl => None,
+        }
+    }
+}
+
+impl Create {
+    /// Translate the error code the server responded with, or return it if this operation does
+    /// not expect it.
+    pub(crate) fn from_zk(e: ZkError) -> Result<Self, ZkError> {
+        Ok(match e {
+            ZkError::NoNode => Create::NoNode,
+            ZkError::NodeExists => Create::NodeExists,
+            ZkError::InvalidACL => Create::InvalidAcl,
+            ZkError::NoChildrenForEphemerals => Create::NoChildrenForEphemerals,
+            ZkError::NotReadOnly => Create::NotReadOnly,
+            e => return Err(e),
+        })
+    }
+}
+
+/// Errors that may cause an `exists` request to fail.
+///
+/// A missing node is not an error, as [`ZooKeeper::exists`](::ZooKeeper::exists) reports it as
+/// `None`. Other failures come as a [`failure::Error`] whose cause is an `Exists` error.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Exists {
+    /// The client is not allowed to look up the target node.
+    NoAuth,
+}
+
+impl fmt::Display for Exists {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Exists::NoAuth => write!(f, "insufficient authentication"),
+        }
+    }
+}
+
+impl Fail for Exists {
+    fn cause(&self) -> Option<&dyn Fail> {
+        match *self {
+            Exists::NoAuth => Some(&ZkError::NoAuth),
+        }
+    }
+}
+
+impl Exists {
+    /// Translate the error code the server responded with, or return it if this operation does
+    /// not expect it.
+    pub(crate) fn from_zk(e: ZkError) -> Result<Self, ZkError> {
+        Ok(match e {
+            ZkError::NoAuth => Exists::NoAuth,
+            e => return Err(e),
+        })
+    }
+}
+
+/// Errors that may cause a `get_data` request to fail.
+///
+/// [`ZooKeeper::get_data`](::ZooKeeper::get_data) reports a missing node as `None`, and fails with
+/// a [`failure::Error`] whose cause is a `GetData` error otherwise, so the reason can be recovered
+/// with `downcast_ref`. This is not `Copy`, as [`GetData::NoAuth`] names the node it was about.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum GetData {
+    /// No node exists with the given `path`.
+    NoNode,
+
+    /// The target node's ACL does not allow the client to read it.
+    NoAuth {
+        /// The path of the target node, as given to the call.
+        path: String,
+        /// The permission needed to read the node ([`Permission::READ`]).
+        required: Permission,
+    },
+}
+
+impl fmt::Display for GetData {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            GetData::NoNode => write!(f, "target node does not exist"),
+            GetData::NoAuth { ref path, required } => no_auth(f, path, required),
+        }
+    }
+}
+
+impl Fail for GetData {
+    fn cause(&self) -> Option<&dyn Fail> {
+        match *self {
+            GetData::NoNode => Some(&ZkError::NoNode),
+            GetData::NoAuth { .. } => Some(&ZkError::NoAuth),
+        }
+    }
+}
+
+impl GetData {
+    /// Translate the error code the server responded with for the node at `path`, or return it
+    /// if this operation does not expect it.
+    pub(crate) fn from_zk(e: ZkError, path: &str) -> Result<Self, ZkError> {
+        Ok(match e {
+            ZkError::NoNode => GetData::NoNode,
+            ZkError::NoAuth => GetData::NoAuth {
+                path: path.to_string(),
+                required: Permission::READ,
+            },
+            e => return Err(e),
+        })
+    }
 }
 
 /// Errors that may cause a `get_acl` request to fail.
-#[derive(Clone, Copy, PartialEq, Eq, Debug, Fail)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum GetAcl {
     /// No node exists with the given `path`.
-    #[fail(display = "target node does not exist")]
     NoNode,
 }
 
+impl fmt::Display for GetAcl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            GetAcl::NoNode => write!(f, "target node does not exist"),
+        }
+    }
+}
+
+impl Fail for GetAcl {
+    fn cause(&self) -> Option<&dyn Fail> {
+        match *self {
+            GetAcl::NoNode => Some(&ZkError::NoNode),
+        }
+    }
+}
+
+impl GetAcl {
+    /// Translate the error code the server responded with, or return it if this operation does
+    /// not expect it.
+    pub(crate) fn from_zk(e: ZkError) -> Result<Self, ZkError> {
+        Ok(match e {
+            ZkError::NoNode => GetAcl::NoNode,
+            e => return Err(e),
+        })
+    }
+}
+
 /// Errors that may cause a `set_acl` request to fail.
-#[derive(Clone, Copy, PartialEq, Eq, Debug, Fail)]
+///
+/// This is not `Copy`, as [`SetAcl::NoAuth`] names the node it was about.
+#[derive(Clone, PartialEq, Eq, Debug)]
 pub enum SetAcl {
     /// No node exists with the given `path`.
-    #[fail(display = "target node does not exist")]
     NoNode,
 
     /// The target node has a different version than was specified by the call to `set_acl`.
-    #[fail(
-        display = "target node has different version than expected ({})",
-        expected
-    )]
     BadVersion {
         /// The expected node version.
         expected: i32,
+        /// The ACL version the node had instead, or `None` if it is not known (see
+        /// [`ZooKeeperBuilder::set_fetch_actual_version`](::ZooKeeperBuilder::set_fetch_actual_version)).
+        actual: Option<i32>,
     },
 
     /// The given ACL is invalid.
-    #[fail(display = "the given ACL is invalid")]
     InvalidAcl,
 
     /// The target node's permission does not accept acl modification or requires different
     /// authentication to be altered.
-    #[fail(display = "insufficient authentication")]
-    NoAuth,
+    NoAuth {
+        /// The path of the target node, as given to the call.
+        path: String,
+        /// The permission needed to change the node's ACL ([`Permission::ADMIN`]).
+        required: Permission,
+    },
+
+    /// The client is connected to a server in read-only mode, which does not accept writes.
+    NotReadOnly,
+}
+
+impl fmt::Display for SetAcl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SetAcl::NoNode => write!(f, "target node does not exist"),
+            SetAcl::BadVersion {
+                expected,
+                actual: Some(actual),
+            } => write!(
+                f,
+                "target node has version {} rather than the expected {}",
+                actual, expected
+            ),
+            SetAcl::BadVersion { expected, .. } => write!(
+                f,
+                "target node has different version than expected ({})",
+                expected
+            ),
+            SetAcl::InvalidAcl => write!(f, "the given ACL is invalid"),
+            SetAcl::NoAuth { ref path, required } => no_auth(f, path, required),
+            SetAcl::NotReadOnly => write!(f, "server is in read-only mode"),
+        }
+    }
+}
+
+impl Fail for SetAcl {
+    fn cause(&self) -> Option<&dyn Fail> {
+        match *self {
+            SetAcl::NoNode => Some(&ZkError::NoNode),
+            SetAcl::BadVersion { .. } => Some(&ZkError::BadVersion),
+            SetAcl::InvalidAcl => Some(&ZkError::InvalidACL),
+            SetAcl::NoAuth { .. } => Some(&ZkError::NoAuth),
+            SetAcl::NotReadOnly => Some(&ZkError::NotReadOnly),
+        }
+    }
+}
+
+impl SetAcl {
+    /// Translate the error code the server responded with, or return it if this operation does
+    /// not expect it. `version` is the version the request expected the node at `path` to have.
+    pub(crate) fn from_zk(e: ZkError, version: i32, path: &str) -> Result<Self, ZkError> {
+        Ok(match e {
+            ZkError::NoNode => SetAcl::NoNode,
+            ZkError::BadVersion => SetAcl::BadVersion {
+                expected: version,
+                actual: None,
+            },
+            ZkError::InvalidACL => SetAcl::InvalidAcl,
+            ZkError::NoAuth => SetAcl::NoAuth {
+                path: path.to_string(),
+                required: Permission::ADMIN,
+            },
+            ZkError::NotReadOnly => SetAcl::NotReadOnly,
+            e => return Err(e),
+        })
+    }
+}
+
+impl ActualVersion for SetAcl {
+    fn actual_version(&mut self) -> Option<&mut Option<i32>> {
+        match *self {
+            SetAcl::BadVersion { ref mut actual, .. } => Some(actual),
+            _ => None,
+        }
+    }
 }
 
 /// Errors that may cause a `check` request to fail.
-#[derive(Clone, Copy, PartialEq, Eq, Debug, Fail)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum Check {
     /// No node exists with the given `path`.
-    #[fail(display = "target node does not exist")]
     NoNode,
 
     /// The target node has a different version than was specified by the call to `check`.
-    #[fail(
-        display = "target node has different version than expected ({})",
-        expected
-    )]
     BadVersion {
         /// The expected node version.
         expected: i32,
     },
 }
 
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Check::NoNode => write!(f, "target node does not exist"),
+            Check::BadVersion { expected } => write!(
+                f,
+                "target node has different version than expected ({})",
+                expected
+            ),
+        }
+    }
+}
+
+impl Fail for Check {
+    fn cause(&self) -> Option<&dyn Fail> {
+        match *self {
+            Check::NoNode => Some(&ZkError::NoNode),
+            Check::BadVersion { .. } => Some(&ZkError::BadVersion),
+        }
+    }
+}
+
+impl Check {
+    /// Translate the error code the server responded with, or return it if this operation does
+    /// not expect it. `version` is the version the request expected the node to have.
+    pub(crate) fn from_zk(e: ZkError, version: i32) -> Result<Self, ZkError> {
+        Ok(match e {
+            ZkError::NoNode => Check::NoNode,
+            ZkError::BadVersion => Check::BadVersion { expected: version },
+            e => return Err(e),
+        })
+    }
+}
+
+/// Errors that may cause a `reconfig` request to fail.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Reconfig {
+    /// The server rejected the given server specifications or IDs.
+    BadArguments,
+
+    /// The ensemble has a different configuration version than was specified by the call to
+    /// `reconfig`.
+    BadVersion {
+        /// The expected configuration version.
+        expected: i64,
+    },
+
+    /// Too few servers of the new configuration are connected and up to date with the leader for
+    /// it to take effect.
+    NewConfigNoQuorum,
+
+    /// Another reconfiguration of the ensemble is in progress.
+    InProgress,
+
+    /// Dynamic reconfiguration is disabled on the server.
+    Disabled,
+
+    /// The client is not allowed to reconfigure the ensemble.
+    NoAuth,
+}
+
+impl fmt::Display for Reconfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Reconfig::BadArguments => write!(f, "invalid server specification"),
+            Reconfig::BadVersion { expected } => write!(
+                f,
+                "ensemble has different config version than expected ({:x})",
+                expected
+            ),
+            Reconfig::NewConfigNoQuorum => write!(f, "new config has no quorum"),
+            Reconfig::InProgress => write!(f, "another reconfiguration is in progress"),
+            Reconfig::Disabled => write!(f, "reconfiguration is disabled"),
+            Reconfig::NoAuth => write!(f, "insufficient authentication"),
+        }
+    }
+}
+
+impl Fail for Reconfig {
+    fn cause(&self) -> Option<&dyn Fail> {
+        match *self {
+            Reconfig::BadArguments => Some(&ZkError::BadArguments),
+            Reconfig::BadVersion { .. } => Some(&ZkError::BadVersion),
+            Reconfig::NewConfigNoQuorum => Some(&ZkError::NewConfigNoQuorum),
+            Reconfig::InProgress => Some(&ZkError::ReconfigInProgress),
+            Reconfig::Disabled => Some(&ZkError::ReconfigDisabled),
+            Reconfig::NoAuth => Some(&ZkError::NoAuth),
+        }
+    }
+}
+
+impl Reconfig {
+    /// Translate the error code the server responded with, or return it if this operation does
+    /// not expect it. `version` is the configuration version the request expected.
+    pub(crate) fn from_zk(e: ZkError, version: i64) -> Result<Self, ZkError> {
+        Ok(match e {
+            ZkError::BadArguments => Reconfig::BadArguments,
+            ZkError::BadVersion => Reconfig::BadVersion { expected: version },
+            ZkError::NewConfigNoQuorum => Reconfig::NewConfigNoQuorum,
+            ZkError::ReconfigInProgress => Reconfig::InProgress,
+            ZkError::ReconfigDisabled => Reconfig::Disabled,
+            ZkError::NoAuth => Reconfig::NoAuth,
+            e => return Err(e),
+        })
+    }
+}
+
+/// Errors that may cause an `add_auth` request to fail.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Auth {
+    /// The server rejected the given credentials.
+    ///
+    /// The server closes the connection after rejecting credentials.
+    AuthFailed,
+}
+
+impl fmt::Display for Auth {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Auth::AuthFailed => write!(f, "authentication failed"),
+        }
+    }
+}
+
+impl Fail for Auth {
+    fn cause(&self) -> Option<&dyn Fail> {
+        match *self {
+            Auth::AuthFailed => Some(&ZkError::AuthFailed),
+        }
+    }
+}
+
+impl Auth {
+    /// Translate the error code the server responded with, or return it if this operation does
+    /// not expect it.
+    pub(crate) fn from_zk(e: ZkError) -> Result<Self, ZkError> {
+        Ok(match e {
+            ZkError::AuthFailed => Auth::AuthFailed,
+            e => return Err(e),
+        })
+    }
+}
+
+/// Errors that may cause a `remove_watches` request to fail.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RemoveWatches {
+    /// No watch of the given type exists on the given `path`.
+    NoWatcher,
+}
+
+impl fmt::Display for RemoveWatches {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RemoveWatches::NoWatcher => write!(f, "no matching watch exists on target node"),
+        }
+    }
+}
+
+impl Fail for RemoveWatches {
+    fn cause(&self) -> Option<&dyn Fail> {
+        match *self {
+            RemoveWatches::NoWatcher => Some(&ZkError::NoWatcher),
+        }
+    }
+}
+
+impl RemoveWatches {
+    /// Translate the error code the server responded with, or return it if this operation does
+    /// not expect it.
+    pub(crate) fn from_zk(e: ZkError) -> Result<Self, ZkError> {
+        Ok(match e {
+            ZkError::NoWatcher => RemoveWatches::NoWatcher,
+            e => return Err(e),
+        })
+    }
+}
+
 /// The result of a failed `multi` request.
-#[derive(Clone, Copy, PartialEq, Eq, Debug, Fail)]
+#[derive(Clone, PartialEq, Eq, Debug)]
 pub enum Multi {
     /// A failed `delete` request.
-    #[fail(display = "delete failed: {}", 0)]
     Delete(Delete),
 
     /// A failed `set_data` request.
-    #[fail(display = "set_data failed: {}", 0)]
     SetData(SetData),
 
     /// A failed `create` request.
-    #[fail(display = "create failed: {}", 0)]
     Create(Create),
 
     /// A failed `check` request.
-    #[fail(display = "check failed")]
     Check(Check),
 
     /// The request would have succeeded, but a later request in the `multi`
     /// batch failed and caused this request to get rolled back.
-    #[fail(display = "request rolled back due to later failed request")]
     RolledBack,
 
-    /// The request was skipped because an earlier request in the `multi` batch
-    /// failed. It is unknown whether this request would have succeeded.
-    #[fail(display = "request failed due to earlier failed request")]
+    /// The request was skipped because an earlier request in the `multi`
+    /// batch failed. It is unknown whether this request would have succeeded.
     Skipped,
 }
 
+impl fmt::Display for Multi {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Multi::Delete(ref e) => write!(f, "delete failed: {}", e),
+            Multi::SetData(ref e) => write!(f, "set_data failed: {}", e),
+            Multi::Create(ref e) => write!(f, "create failed: {}", e),
+            Multi::Check(_) => write!(f, "check failed"),
+            Multi::RolledBack => write!(f, "request rolled back due to later failed request"),
+            Multi::Skipped => write!(f, "request failed due to earlier failed request"),
+        }
+    }
+}
+
+impl Fail for Multi {
+    fn cause(&self) -> Option<&dyn Fail> {
+        match *self {
+            Multi::Delete(ref e) => Some(e),
+            Multi::SetData(ref e) => Some(e),
+            Multi::Create(ref e) => Some(e),
+            Multi::Check(ref e) => Some(e),
+            Multi::RolledBack | Multi::Skipped => None,
+        }
+    }
+}
+
 impl From<Delete> for Multi {
     fn from(err: Delete) -> Self {
         Multi::Delete(err)
@@ -169,3 +747,295 @@ impl From<Check> for Multi {
         Multi::Check(err)
     }
 }
+
+/// The failure of a `multi` request as a whole, as returned by
+/// [`MultiResponse::all`](::MultiResponse::all).
+#[derive(Clone, PartialEq, Eq, Debug, Fail)]
+#[fail(
+    display = "operation {} of multi request failed: {}",
+    failed_index, cause
+)]
+pub struct MultiError {
+    /// The position of the operation that made the request fail, among the operations in the
+    /// order they were attached to the request.
+    pub failed_index: usize,
+    /// How that operation failed.
+    #[fail(cause)]
+    pub cause: Multi,
+}
+
+/// The server at the other end of a connection does not speak the ZooKeeper protocol.
+///
+/// This is returned when connecting if the server's response to the connection handshake does not
+/// look like a ZooKeeper connect response, which usually means that the given address points to
+/// some other kind of service.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Fail)]
+#[fail(display = "server did not respond with a ZooKeeper handshake")]
+pub struct NotAZooKeeperServer;
+
+/// A response from the server was longer than what the client decoded from it.
+///
+/// The client only checks for this if enabled with
+/// [`ZooKeeperBuilder::set_check_response_length`](::ZooKeeperBuilder::set_check_response_length).
+/// It then drops the connection and re-connects to another server, and the request the response
+/// was for fails with [`ZkError::MarshallingError`](::ZkError::MarshallingError).
+#[derive(Clone, PartialEq, Eq, Debug, Fail)]
+#[fail(
+    display = "response to {} was {} bytes long, but {} bytes were left over after decoding it",
+    operation, length, unread
+)]
+pub struct ProtocolError {
+    /// The operation the response was for (such as `GetData`).
+    pub operation: String,
+    /// The length of the response, as declared in its frame.
+    pub length: usize,
+    /// How many bytes at the end of the response were not decoded.
+    pub unread: usize,
+}
+
+/// A path was rejected before it was sent to the server.
+///
+/// This is returned by [`ZooKeeper::create`](::ZooKeeper::create),
+/// [`ZooKeeper::delete`](::ZooKeeper::delete), [`ZooKeeper::set_data`](::ZooKeeper::set_data),
+/// [`ZooKeeper::exists`](::ZooKeeper::exists), and [`ZooKeeper::get_data`](::ZooKeeper::get_data)
+/// (and their variants) if the given path is malformed. Paths of nodes to be created with a
+/// sequential [`CreateMode`](::CreateMode) may end in `/`. Unless a chroot is used, the
+/// `/zookeeper` node cannot be created or deleted, as it is reserved for the server itself. See
+/// the [ZooKeeper Programmer's Guide](https://zookeeper.apache.org/doc/current/zookeeperProgrammers.html#ch_zkDataModel)
+/// for which paths are valid.
+#[derive(Clone, PartialEq, Eq, Debug, Fail)]
+#[fail(display = "invalid path {:?}: {}", path, reason)]
+pub struct InvalidPath {
+    /// The offending path.
+    pub path: String,
+    /// Why the path is invalid.
+    pub reason: &'static str,
+}
+
+/// The data of a node was rejected before it was sent to the server.
+///
+/// This is returned by [`ZooKeeper::create`](::ZooKeeper::create) and
+/// [`ZooKeeper::set_data`](::ZooKeeper::set_data) (and their variants) if the data is larger than
+/// the limit set with
+/// [`ZooKeeperBuilder::set_max_data_size`](::ZooKeeperBuilder::set_max_data_size). The server
+/// would otherwise drop the connection upon receiving the request.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Fail)]
+#[fail(display = "data is {} bytes, but at most {} are allowed", size, limit)]
+pub struct DataTooLarge {
+    /// The size of the data in bytes.
+    pub size: usize,
+    /// The largest allowed size in bytes.
+    pub limit: usize,
+}
+
+/// An ACL was rejected before it was sent to the server.
+///
+/// This is returned by [`ZooKeeper::create`](::ZooKeeper::create) and
+/// [`ZooKeeper::set_acl`](::ZooKeeper::set_acl) (and their variants) for ACLs that the server would
+/// refuse with [`Create::InvalidAcl`] or [`SetAcl::InvalidAcl`]: lists that are empty, entries
+/// with a scheme the server does not know, and ids that are malformed for their scheme.
+#[derive(Clone, PartialEq, Debug, Fail)]
+pub struct InvalidAcl {
+    /// The offending entry, or `None` if the list as a whole is invalid.
+    pub entry: Option<Acl>,
+    /// Why the ACL is invalid.
+    pub reason: &'static str,
+}
+
+impl fmt::Display for InvalidAcl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.entry {
+            Some(ref entry) => write!(f, "invalid ACL entry {}: {}", entry, self.reason),
+            None => write!(f, "invalid ACL: {}", self.reason),
+        }
+    }
+}
+
+/// The server does not implement a request.
+///
+/// This is returned by any operation that the server answered with
+/// [`ZkError::Unimplemented`](::ZkError::Unimplemented), as happens for operations that were added
+/// in a later version of ZooKeeper, or that are disabled on the server. Once a server has done so,
+/// further requests of the same kind fail right away without being sent, until the client
+/// connects to another server. See also [`ZooKeeper::supports`](::ZooKeeper::supports).
+#[derive(Clone, PartialEq, Eq, Debug, Fail)]
+#[fail(display = "server does not support {} requests", operation)]
+pub struct Unsupported {
+    /// The kind of request that is not supported.
+    pub operation: String,
+}
+
+/// The server did not respond to a request in time.
+///
+/// This is returned by any operation if an operation timeout is set with
+/// [`ZooKeeperBuilder::set_operation_timeout`](::ZooKeeperBuilder::set_operation_timeout), and
+/// the response to the request did not arrive within that time. The request may still have been
+/// executed by the server.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Fail)]
+#[fail(display = "operation timed out")]
+pub struct Timeout;
+
+/// The client could not connect to a server in time.
+///
+/// This is returned when connecting if a server did not accept the connection within the time
+/// set with [`ZooKeeperBuilder::set_connect_timeout`](::ZooKeeperBuilder::set_connect_timeout)
+/// (as the cause of the error for the last server tried), or if no session was established
+/// within the time set with
+/// [`ZooKeeperBuilder::set_connect_deadline`](::ZooKeeperBuilder::set_connect_deadline).
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Fail)]
+#[fail(display = "connection timed out")]
+pub struct ConnectTimeout;
+
+/// The connection has not been authenticated yet.
+///
+/// This is returned by any write that is made while SASL authentication set up with
+/// [`ZooKeeperBuilder::set_sasl`](::ZooKeeperBuilder::set_sasl) is in progress, if writes are
+/// to be rejected rather than held back until then (see
+/// [`SaslWritePolicy`](::SaslWritePolicy)).
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Fail)]
+#[fail(display = "connection is not authenticated yet")]
+pub struct AuthPending;
+
+/// The request queue is full.
+///
+/// This is returned by any operation made through a handle that does not wait for a free slot
+/// (see [`ZooKeeper::wait_for_slot`](::ZooKeeper::wait_for_slot)) when as many requests as set
+/// with
+/// [`ZooKeeperBuilder::set_request_queue_capacity`](::ZooKeeperBuilder::set_request_queue_capacity)
+/// are already waiting for a response. The request is not sent.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Fail)]
+#[fail(display = "too many requests are waiting for a response")]
+pub struct Busy;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cause_is_zk_error() {
+        let err = SetData::BadVersion {
+            expected: 3,
+            actual: None,
+        };
+        let cause = err.cause().and_then(|c| c.downcast_ref::<ZkError>());
+        assert_eq!(cause, Some(&ZkError::BadVersion));
+        assert_eq!(
+            err.to_string(),
+            "target node has different version than expected (3)"
+        );
+    }
+
+    #[test]
+    fn from_zk() {
+        assert_eq!(Create::from_zk(ZkError::NoNode), Ok(Create::NoNode));
+        assert_eq!(Create::from_zk(ZkError::NodeExists), Ok(Create::NodeExists));
+        assert_eq!(Create::from_zk(ZkError::InvalidACL), Ok(Create::InvalidAcl));
+        assert_eq!(
+            Create::from_zk(ZkError::NoChildrenForEphemerals),
+            Ok(Create::NoChildrenForEphemerals)
+        );
+        assert_eq!(Delete::from_zk(ZkError::NotEmpty, 1), Ok(Delete::NotEmpty));
+        assert_eq!(
+            Delete::from_zk(ZkError::BadVersion, 1),
+            Ok(Delete::BadVersion {
+                expected: 1,
+                actual: None,
+            })
+        );
+        assert_eq!(
+            SetData::from_zk(ZkError::NoAuth, 1, "/a"),
+            Ok(SetData::NoAuth {
+                path: "/a".to_string(),
+                required: Permission::WRITE,
+            })
+        );
+        assert_eq!(
+            SetData::from_zk(ZkError::BadVersion, 2, "/a"),
+            Ok(SetData::BadVersion {
+                expected: 2,
+                actual: None,
+            })
+        );
+        assert_eq!(Exists::from_zk(ZkError::NoAuth), Ok(Exists::NoAuth));
+        assert_eq!(Exists::from_zk(ZkError::NoNode), Err(ZkError::NoNode));
+        assert_eq!(GetData::from_zk(ZkError::NoNode, "/a"), Ok(GetData::NoNode));
+        assert_eq!(
+            GetData::from_zk(ZkError::NoAuth, "/a"),
+            Ok(GetData::NoAuth {
+                path: "/a".to_string(),
+                required: Permission::READ,
+            })
+        );
+        assert_eq!(GetAcl::from_zk(ZkError::NoNode), Ok(GetAcl::NoNode));
+        assert_eq!(
+            SetAcl::from_zk(ZkError::InvalidACL, 1, "/a"),
+            Ok(SetAcl::InvalidAcl)
+        );
+        assert_eq!(Check::from_zk(ZkError::NoNode, 1), Ok(Check::NoNode));
+        assert_eq!(Auth::from_zk(ZkError::AuthFailed), Ok(Auth::AuthFailed));
+        assert_eq!(
+            Reconfig::from_zk(ZkError::BadVersion, 7),
+            Ok(Reconfig::BadVersion { expected: 7 })
+        );
+        assert_eq!(
+            Reconfig::from_zk(ZkError::ReconfigInProgress, 7),
+            Ok(Reconfig::InProgress)
+        );
+        assert_eq!(
+            RemoveWatches::from_zk(ZkError::NoWatcher),
+            Ok(RemoveWatches::NoWatcher)
+        );
+
+        // every typed error translates back into the code it came from
+        for &e in &[
+            ZkError::NoNode,
+            ZkError::NodeExists,
+            ZkError::InvalidACL,
+            ZkError::NoChildrenForEphemerals,
+            ZkError::NotReadOnly,
+        ] {
+            let err = Create::from_zk(e).unwrap();
+            assert_eq!(err.cause().unwrap().downcast_ref::<ZkError>(), Some(&e));
+        }
+
+        // codes that do not concern the operation itself are handed back
+        for &e in &[
+            ZkError::ConnectionLoss,
+            ZkError::SystemError,
+            ZkError::SessionExpired,
+        ] {
+            assert_eq!(Create::from_zk(e), Err(e));
+            assert_eq!(Delete::from_zk(e, 1), Err(e));
+            assert_eq!(SetData::from_zk(e, 1, "/a"), Err(e));
+            assert_eq!(SetAcl::from_zk(e, 1, "/a"), Err(e));
+        }
+        assert_eq!(
+            Create::from_zk(ZkError::BadVersion),
+            Err(ZkError::BadVersion)
+        );
+        assert_eq!(
+            Delete::from_zk(ZkError::NodeExists, 1),
+            Err(ZkError::NodeExists)
+        );
+    }
+
+    #[test]
+    fn multi_cause_chain() {
+        let err = Multi::from(Create::NodeExists);
+        let err: &dyn Fail = &err;
+        let chain: Vec<String> = err.iter_chain().map(|c| c.to_string()).collect();
+        assert_eq!(
+            chain,
+            vec![
+                "create failed: target node already exists",
+                "target node already exists",
+                "node already exists",
+            ]
+        );
+        assert_eq!(
+            err.find_root_cause().downcast_ref::<ZkError>(),
+            Some(&ZkError::NodeExists)
+        );
+        assert!(Multi::Skipped.cause().is_none());
+    }
+}
@@ -97,6 +97,17 @@
 //! you cannot resolve them solely using `.wait()`, but should instead use `tokio::run` or
 //! explicitly create a `tokio::Runtime` and then use `Runtime::block_on`.
 //!
+//! ## Optional features
+//!
+//! - `serde`: implements `Serialize` and `Deserialize` for [`Stat`], [`Acl`], and [`Permission`],
+//!   for example to store node metadata as JSON. A `Permission` is represented by its raw bits.
+//! - `test-util`: adds [`test_util::MockServer`], a ZooKeeper server that keeps its tree in
+//!   memory, for testing code built on this crate without running a real ZooKeeper.
+//! - `tracing`: emits [`tracing`](https://docs.rs/tracing) spans for requests, which record the
+//!   op code, path, xid, and outcome of the request, and events when the connection to the server
+//!   is established, lost, or resumed, and when a watch fires. All of them carry the id of the
+//!   session as `session_id`, to tell apart the output of multiple clients.
+//!
 //! # A somewhat silly example
 //!
 //! ```no_run
@@ -156,7 +167,7 @@
 //!                     zk.set_data("/example", Some(res.unwrap().1.version), &b"Bye world"[..])
 //!                 })
 //!                 .inspect(|(_, stat)| {
-//!                     assert_eq!(stat.unwrap().data_length as usize, "Bye world".len());
+//!                     assert_eq!(stat.as_ref().unwrap().data_length as usize, "Bye world".len());
 //!                 })
 //!                 .and_then(|(zk, _)| {
 //!                     // create a child of /example
@@ -209,6 +220,7 @@
 //!                             event_type: WatchedEventType::NodeCreated,
 //!                             keeper_state: KeeperState::SyncConnected,
 //!                             path: String::from("/example"),
+//!                             zxid: None,
 //!                         })
 //!                     );
 //!                 })
@@ -223,7 +235,9 @@
 #![deny(missing_debug_implementations)]
 #![deny(missing_copy_implementations)]
 
+extern crate base64;
 extern crate byteorder;
+extern crate bytes;
 #[macro_use]
 extern crate failure;
 #[macro_use]
@@ -231,28 +245,57 @@ extern crate futures;
 extern crate tokio;
 #[macro_use]
 extern crate lazy_static;
+extern crate rand;
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_json;
+extern crate sha1;
 #[macro_use]
 extern crate slog;
 #[cfg(test)]
 extern crate slog_async;
 #[cfg(test)]
 extern crate slog_term;
+#[cfg(feature = "tracing")]
+extern crate tracing;
 
+use failure::Fail;
 use futures::sync::oneshot;
+use rand::seq::SliceRandom;
 use std::borrow::Cow;
+use std::fmt;
+use std::mem;
 use std::net::SocketAddr;
+use std::sync::Arc;
 use std::time;
 use tokio::prelude::*;
 
+mod children;
+mod connect_string;
+mod debounce;
 /// Per-operation ZooKeeper error types.
 pub mod error;
+mod path;
 mod proto;
+pub mod recipes;
+mod sasl;
+mod select;
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util;
 mod transform;
 mod types;
 
-use proto::{Watch, ZkError};
+pub use path::sequence_number;
+use proto::Watch;
+pub use proto::{Backoff, Utf8Policy, ZkError};
+pub use sasl::{SaslClient, SaslWritePolicy};
+pub use select::{Cooldown, Ordered, ServerSelector, Shuffle};
+use types::validate_acl;
 pub use types::{
-    Acl, CreateMode, KeeperState, MultiResponse, Permission, Stat, WatchedEvent, WatchedEventType,
+    Acl, AddWatchMode, CreateMode, DataChunk, EnsembleConfig, Feature, KeeperState, Member,
+    MultiResponse, Permission, Reconfig, Stat, WatchedEvent, WatchedEventType, WatcherType,
 };
 
 /// A connection to ZooKeeper.
@@ -278,18 +321,93 @@ pub use types::{
 /// triggered, an event will be delivered to the client which left the watch at the first place.
 /// Each watch can be triggered only once. Thus, up to one event will be delivered to a client for
 /// every watch it leaves.
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub struct ZooKeeper {
     #[allow(dead_code)]
     connection: proto::Enqueuer,
     logger: slog::Logger,
+    session_id: i64,
+    password: Vec<u8>,
+    session_timeout: time::Duration,
+    /// Whether all paths are relative to a chroot, so that none of them is reserved.
+    chrooted: bool,
+    /// The servers of the ensemble, as the connection refers to them by index.
+    addrs: Vec<SocketAddr>,
+    /// The largest node data we send to the server.
+    max_data_size: usize,
+    /// Whether to find out the actual version of a node that did not have the expected one.
+    fetch_actual_version: bool,
+    /// Whether helper streams like `watch_children` set their watch again after it fired.
+    rearm_watches: bool,
+}
+
+impl fmt::Debug for ZooKeeper {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // the session password is deliberately left out
+        f.debug_struct("ZooKeeper")
+            .field("connection", &self.connection)
+            .field("session_id", &self.session_id)
+            .field("session_timeout", &self.session_timeout)
+            .finish()
+    }
+}
+
+/// The largest node data the server accepts by default (its `jute.maxbuffer` setting).
+const DEFAULT_MAX_DATA_SIZE: usize = 1024 * 1024;
+
+/// How many nodes [`ZooKeeper::delete_recursive`] deletes in a single `multi` request.
+const DELETE_BATCH_SIZE: usize = 100;
+
+/// The path of the node called `child` below the node at `parent`.
+fn child_path(parent: &str, child: &str) -> String {
+    if parent == "/" {
+        format!("/{}", child)
+    } else {
+        format!("{}/{}", parent, child)
+    }
 }
 
 /// Builder that allows customizing options for ZooKeeper connections.
 #[derive(Debug, Clone)]
 pub struct ZooKeeperBuilder {
     session_timeout: time::Duration,
+    connect_timeout: Option<time::Duration>,
+    connect_deadline: Option<time::Duration>,
+    operation_timeout: Option<time::Duration>,
+    ping_interval: Option<time::Duration>,
+    read_idle_timeout: Option<time::Duration>,
+    backoff: Backoff,
+    sasl: Option<sasl::SaslConfig>,
+    max_data_size: usize,
+    fetch_actual_version: bool,
+    rearm_watches: bool,
+    request_queue_capacity: Option<usize>,
+    check_response_length: bool,
+    utf8_policy: Utf8Policy,
+    read_only: bool,
     logger: slog::Logger,
+    resume: Option<SavedSession>,
+    chroot: Option<String>,
+    shuffle: bool,
+    selector: select::Selector,
+    max_reconnect_attempts: Option<u32>,
+}
+
+/// A previously established session that a new connection should attempt to resume.
+#[derive(Clone)]
+struct SavedSession {
+    session_id: i64,
+    password: Vec<u8>,
+    last_zxid_seen: i64,
+}
+
+impl fmt::Debug for SavedSession {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SavedSession")
+            .field("session_id", &self.session_id)
+            .field("last_zxid_seen", &self.last_zxid_seen)
+            .finish()
+    }
 }
 
 impl Default for ZooKeeperBuilder {
@@ -299,7 +417,26 @@ impl Default for ZooKeeperBuilder {
 
         ZooKeeperBuilder {
             session_timeout: time::Duration::new(0, 0),
+            connect_timeout: None,
+            connect_deadline: None,
+            operation_timeout: None,
+            ping_interval: None,
+            read_idle_timeout: None,
+            backoff: Backoff::default(),
+            sasl: None,
+            max_data_size: DEFAULT_MAX_DATA_SIZE,
+            fetch_actual_version: false,
+            rearm_watches: true,
+            request_queue_capacity: None,
+            check_response_length: false,
+            utf8_policy: Utf8Policy::Strict,
+            read_only: false,
             logger: root,
+            resume: None,
+            chroot: None,
+            shuffle: true,
+            selector: select::Selector::default(),
+            max_reconnect_attempts: None,
         }
     }
 }
@@ -314,30 +451,362 @@ impl ZooKeeperBuilder {
     ///
     /// If the connection to the server fails, the client will automatically try to re-connect.
     /// Only if re-connection fails is an error returned to the client. Requests that are in-flight
-    /// during a disconnect may fail and have to be retried.
+    /// during a disconnect fail with an error whose cause is [`ZkError::ConnectionLoss`] if the
+    /// session lives on, in which case they can be retried. They fail with
+    /// [`ZkError::SessionExpired`] instead if the session has ended, along with its ephemeral
+    /// nodes.
     pub fn connect(
         self,
         addr: &SocketAddr,
     ) -> impl Future<
         Item = (ZooKeeper, impl Stream<Item = WatchedEvent, Error = ()>),
         Error = failure::Error,
+    > {
+        self.connect_any(vec![*addr])
+    }
+
+    /// Connect to the first of the given servers that accepts a connection.
+    fn connect_any(
+        self,
+        addrs: Vec<SocketAddr>,
+    ) -> impl Future<
+        Item = (ZooKeeper, impl Stream<Item = WatchedEvent, Error = ()>),
+        Error = failure::Error,
     > {
         let (tx, rx) = futures::sync::mpsc::unbounded();
-        let addr = addr.clone();
-        tokio::net::TcpStream::connect(&addr)
-            .map_err(failure::Error::from)
-            .and_then(move |stream| self.handshake(addr, stream, tx))
-            .map(move |zk| (zk, rx))
+        let deadline = self.connect_deadline;
+        let connect =
+            proto::connect_any::<tokio::net::TcpStream>(addrs.clone(), 0, self.connect_timeout)
+                .and_then(move |(server, stream)| self.handshake(addrs, server, stream, tx))
+                .map(move |zk| (zk, rx));
+        match deadline {
+            Some(deadline) => future::Either::A(
+                tokio::timer::Timeout::new(connect, deadline)
+                    .map_err(|e| proto::timeout_error(e, || error::ConnectTimeout.into())),
+            ),
+            None => future::Either::B(connect),
+        }
+    }
+
+    /// Connect to the ZooKeeper ensemble described by the given connection string.
+    ///
+    /// The connection string has the form `host:port[,host:port...][/chroot]`. Host names are
+    /// resolved when this method is called, and every address a name resolves to is treated as a
+    /// separate server. Unless disabled with [`ZooKeeperBuilder::set_shuffle`], the servers are
+    /// shuffled to spread clients across the ensemble. The client connects to the first server
+    /// that accepts a connection, and when that connection is lost, it fails over to the next one.
+    ///
+    /// If a chroot path is given, the client behaves as if the node at that path was the root of
+    /// the tree: all paths passed to the client are relative to the chroot, and all paths
+    /// returned by the client (including those of watch events) have the chroot removed. A chroot
+    /// of `/` is the same as no chroot. The chroot node must already exist.
+    ///
+    /// See [`ZooKeeperBuilder::connect`] for the behavior of the returned future.
+    pub fn connect_string(
+        mut self,
+        connect_string: &str,
+    ) -> impl Future<
+        Item = (ZooKeeper, impl Stream<Item = WatchedEvent, Error = ()>),
+        Error = failure::Error,
+    > {
+        let cs = match connect_string.parse::<connect_string::ConnectString>() {
+            Ok(cs) => cs,
+            Err(e) => return future::Either::B(future::err(e)),
+        };
+        let mut addrs = match cs.resolve() {
+            Ok(addrs) => addrs,
+            Err(e) => return future::Either::B(future::err(e)),
+        };
+        if self.shuffle {
+            addrs.shuffle(&mut rand::thread_rng());
+        }
+        self.chroot = cs.chroot;
+        future::Either::A(self.connect_any(addrs))
+    }
+
+    /// Connect to a ZooKeeper server instance at the given address, and attempt to resume the
+    /// session identified by `session_id` and `password` rather than establishing a new one.
+    ///
+    /// This allows a process to pick up a session that was established by an earlier instance of
+    /// itself (for example, across a restart), which preserves any ephemeral nodes owned by that
+    /// session as long as the session has not yet timed out. The `session_id` and `password` can
+    /// be obtained from [`ZooKeeper::session_id`] and [`ZooKeeper::password`], and `last_zxid`
+    /// should be the last transaction id the previous instance observed (or 0 if unknown).
+    ///
+    /// If the server has already expired the session, the returned future resolves with an error
+    /// whose cause is [`ZkError::SessionExpired`].
+    ///
+    /// Note that the session password is a bearer secret: anyone who knows both it and the
+    /// session id can take over the session, including its ephemeral nodes and any
+    /// authentication that was added to it. If you persist these values, store them with the
+    /// same care as any other credential.
+    pub fn connect_with_session(
+        mut self,
+        addr: &SocketAddr,
+        session_id: i64,
+        password: Vec<u8>,
+        last_zxid: i64,
+    ) -> impl Future<
+        Item = (ZooKeeper, impl Stream<Item = WatchedEvent, Error = ()>),
+        Error = failure::Error,
+    > {
+        self.set_session(session_id, password, last_zxid);
+        self.connect(addr)
+    }
+
+    /// Attempt to resume the session identified by `session_id` and `password` when connecting,
+    /// rather than establishing a new one.
+    ///
+    /// This works like [`ZooKeeperBuilder::connect_with_session`], but applies to every way of
+    /// connecting, including [`ZooKeeperBuilder::connect_string`].
+    pub fn set_session(&mut self, session_id: i64, password: Vec<u8>, last_zxid: i64) {
+        self.resume = Some(SavedSession {
+            session_id,
+            password,
+            last_zxid_seen: last_zxid,
+        });
     }
 
     /// Set the ZooKeeper [session expiry
     /// timeout](https://zookeeper.apache.org/doc/r3.4.12/zookeeperProgrammers.html#ch_zkSessions).
     ///
-    /// The default timeout is dictated by the server.
-    pub fn set_timeout(&mut self, t: time::Duration) {
+    /// The server may adjust the requested timeout to lie within the bounds it is configured with.
+    /// The timeout that is actually used is available through [`ZooKeeper::session_timeout`] once
+    /// connected. The default timeout is dictated by the server.
+    pub fn set_session_timeout(&mut self, t: time::Duration) {
         self.session_timeout = t;
     }
 
+    /// Set the ZooKeeper session expiry timeout.
+    #[deprecated(since = "0.1.4", note = "use `set_session_timeout` instead")]
+    pub fn set_timeout(&mut self, t: time::Duration) {
+        self.set_session_timeout(t);
+    }
+
+    /// Set how long to wait for a server to accept a connection.
+    ///
+    /// A server that does not accept the connection in time is treated as unreachable, and the
+    /// next server is tried instead. This applies both when first connecting and when
+    /// re-connecting after the connection to a server was lost. By default, the client waits for
+    /// as long as the operating system does.
+    pub fn set_connect_timeout(&mut self, t: time::Duration) {
+        self.connect_timeout = Some(t);
+    }
+
+    /// Set how long connecting may take as a whole.
+    ///
+    /// This bounds the time it takes to try all servers (and all addresses their names resolve
+    /// to) in turn, each for at most the time set with [`ZooKeeperBuilder::set_connect_timeout`],
+    /// and to establish the session with the one that accepted the connection. If no session is
+    /// established in time, connecting fails with [`error::ConnectTimeout`]. This does not apply
+    /// to re-connecting after the connection to a server was lost. By default, there is no
+    /// deadline.
+    pub fn set_connect_deadline(&mut self, t: time::Duration) {
+        self.connect_deadline = Some(t);
+    }
+
+    /// Set how long to wait for the response to any single request.
+    ///
+    /// If the response has not arrived in time, the operation fails with [`error::Timeout`], and
+    /// a response that arrives later is ignored. The time spent waiting for the client to
+    /// re-connect after the connection to a server was lost counts towards this timeout. By
+    /// default, there is no timeout.
+    pub fn set_operation_timeout(&mut self, t: time::Duration) {
+        self.operation_timeout = Some(t);
+    }
+
+    /// Set how often the client sends a heartbeat to the server while otherwise idle.
+    ///
+    /// The server expires a session that it has not heard from for the session timeout, so the
+    /// interval should be well below that. If the server in turn does not respond for the whole
+    /// session timeout, the session is considered [`KeeperState::Expired`] (though the connection
+    /// is given up on well before that; see [`ZooKeeperBuilder::set_read_idle_timeout`]). By
+    /// default, the client sends a heartbeat every third of the negotiated session timeout.
+    pub fn set_ping_interval(&mut self, t: time::Duration) {
+        self.ping_interval = Some(t);
+    }
+
+    /// Set how long the client waits to hear anything from the server before it considers the
+    /// connection lost.
+    ///
+    /// A connection can silently stop working (for example, when a NAT forgets about it), and
+    /// this notices that well before the session timeout, so that the client can re-connect to
+    /// another server while the session is still alive. By default, the client waits for twice
+    /// the ping interval (see [`ZooKeeperBuilder::set_ping_interval`]).
+    pub fn set_read_idle_timeout(&mut self, t: time::Duration) {
+        self.read_idle_timeout = Some(t);
+    }
+
+    /// Set how long to wait between attempts to re-connect after the connection to a server was
+    /// lost.
+    ///
+    /// The client tries all servers in turn, and waits according to `backoff` each time none of
+    /// them accepted the connection. Re-connection is given up once the session has timed out. By
+    /// default, the wait starts at 100ms and doubles up to at most 5s; see [`Backoff`].
+    ///
+    /// # Panics
+    ///
+    /// If the `multiplier` of `backoff` is not a finite number of at least 1.
+    pub fn set_reconnect_backoff(&mut self, backoff: Backoff) {
+        assert!(
+            backoff.multiplier.is_finite() && backoff.multiplier >= 1.0,
+            "backoff multiplier must be finite and at least 1, not {}",
+            backoff.multiplier
+        );
+        self.backoff = backoff;
+    }
+
+    /// Authenticate the connection with SASL, such as with Kerberos through GSSAPI.
+    ///
+    /// Once the session is established, the client calls `new_client` to set up its side of the
+    /// SASL mechanism, and exchanges tokens with the server until the mechanism is complete.
+    /// Connecting does not wait for this; the outcome is sent to the default watcher as an event
+    /// with [`KeeperState::SaslAuthenticated`] or [`KeeperState::AuthFailed`]. Until then, writes
+    /// are held back or rejected according to `policy`, and once authentication has failed, they
+    /// fail with [`ZkError::AuthFailed`]. Authentication is not repeated when the client
+    /// re-connects to another server.
+    pub fn set_sasl<F>(&mut self, new_client: F, policy: SaslWritePolicy)
+    where
+        F: Fn() -> Box<dyn SaslClient> + Send + Sync + 'static,
+    {
+        self.sasl = Some(sasl::SaslConfig {
+            new_client: Arc::new(new_client),
+            policy,
+        });
+    }
+
+    /// Set the largest amount of data the client will store in a single node.
+    ///
+    /// Servers refuse requests larger than their `jute.maxbuffer` setting by dropping the
+    /// connection, so [`ZooKeeper::create`] and [`ZooKeeper::set_data`] instead fail with
+    /// [`error::DataTooLarge`] without contacting the server if given more data than this. This
+    /// should match the setting of the servers, which defaults to 1 MiB (1,048,576 bytes), as
+    /// does this limit.
+    pub fn set_max_data_size(&mut self, size: usize) {
+        self.max_data_size = size;
+    }
+
+    /// Set whether to find out which version a node had when a write failed because the node did
+    /// not have the expected version.
+    ///
+    /// The server does not say, so if this is enabled, [`ZooKeeper::set_data`],
+    /// [`ZooKeeper::delete`], and [`ZooKeeper::set_acl`] read the node right after such a failure,
+    /// and report its version as `actual` in their `BadVersion` errors (such as
+    /// [`error::SetData::BadVersion`]). The node may have changed again by the time the error is
+    /// returned, so this is meant for diagnosing conflicts rather than for retrying with the
+    /// reported version. Operations in a [`ZooKeeper::multi`] request never report the actual
+    /// version. This is disabled by default.
+    pub fn set_fetch_actual_version(&mut self, fetch: bool) {
+        self.fetch_actual_version = fetch;
+    }
+
+    /// Set whether streams of watched changes, such as [`ZooKeeper::watch_children`], set their
+    /// watch again each time it fires.
+    ///
+    /// Like the watches they are built on, such streams then only report the first change. This
+    /// is for callers that want to decide for themselves when to watch again. This is enabled by
+    /// default.
+    pub fn set_rearm_watches(&mut self, rearm: bool) {
+        self.rearm_watches = rearm;
+    }
+
+    /// Set how many requests may be waiting for a response at once.
+    ///
+    /// A server that falls behind would otherwise let requests pile up in the client without
+    /// bound. Once `capacity` requests are waiting for a response, further requests wait for one
+    /// of those to complete before they are sent, in the order they were made. A request that is
+    /// dropped while it waits is never sent. A request that was sent keeps its place until the
+    /// server has answered it, even if it was dropped or timed out (see
+    /// [`ZooKeeperBuilder::set_operation_timeout`]) before then. To fail with [`error::Busy`]
+    /// instead of waiting, see
+    /// [`ZooKeeper::wait_for_slot`]. By default, requests are not limited.
+    ///
+    /// # Panics
+    ///
+    /// If `capacity` is zero.
+    pub fn set_request_queue_capacity(&mut self, capacity: usize) {
+        assert!(
+            capacity > 0,
+            "request queue must have room for at least one request"
+        );
+        self.request_queue_capacity = Some(capacity);
+    }
+
+    /// Set whether to check that every response from the server is decoded down to its last byte.
+    ///
+    /// Bytes that are left over mean that the client and the server disagree on the shape of a
+    /// response, so what the client decoded may well be garbage. If this is enabled, the client
+    /// then drops the connection with an [`error::ProtocolError`] and re-connects to another
+    /// server, and the request the response was for fails with [`ZkError::MarshallingError`].
+    /// This is disabled by default, since newer servers may add fields to responses that this
+    /// client does not know about.
+    pub fn set_check_response_length(&mut self, check: bool) {
+        self.check_response_length = check;
+    }
+
+    /// Set how to decode strings in responses, such as the names of children, that are not valid
+    /// UTF-8.
+    ///
+    /// By default, such a response cannot be decoded, and the client drops the connection as it
+    /// would for any other garbled response. Tools that browse arbitrary trees may prefer
+    /// [`Utf8Policy::Lossy`], which replaces the invalid bytes instead.
+    pub fn set_utf8_policy(&mut self, policy: Utf8Policy) {
+        self.utf8_policy = policy;
+    }
+
+    /// Set how many times the client tries to connect to a server after it lost its connection,
+    /// before it gives up.
+    ///
+    /// Once the client gives up, the state stream ends with [`KeeperState::Closed`], and all
+    /// waiting and future requests fail with [`ZkError::SessionExpired`]. The count starts over
+    /// every time the client loses a connection. With `None`, which is the default, the client
+    /// keeps trying until the session times out, which suits long-lived services better than tools
+    /// that should fail fast.
+    ///
+    /// Fails, and leaves the setting as it was, if `attempts` is `Some(0)`.
+    pub fn set_max_reconnect_attempts(
+        &mut self,
+        attempts: Option<u32>,
+    ) -> Result<(), failure::Error> {
+        if attempts == Some(0) {
+            bail!("must try to reconnect at least once");
+        }
+        self.max_reconnect_attempts = attempts;
+        Ok(())
+    }
+
+    /// Set whether the client may connect to a server in read-only mode.
+    ///
+    /// A server that is partitioned from the rest of its ensemble stops serving clients, unless it
+    /// runs in read-only mode and the client allows read-only connections. On such a connection,
+    /// reads may return stale data, and all writes fail with a `NotReadOnly` error (such as
+    /// [`error::Create::NotReadOnly`]). Use [`ZooKeeper::is_read_only`] to find out whether the
+    /// client is currently connected in read-only mode. This is disabled by default.
+    pub fn set_allow_read_only(&mut self, allow: bool) {
+        self.read_only = allow;
+    }
+
+    /// Set whether the servers in a connection string are tried in a random order.
+    ///
+    /// This is enabled by default, so that the clients of an ensemble spread out over its
+    /// servers. With shuffling disabled, servers are tried in the order they were given in.
+    pub fn set_shuffle(&mut self, shuffle: bool) {
+        self.shuffle = shuffle;
+    }
+
+    /// Set how the client picks the server to re-connect to when it loses its connection.
+    ///
+    /// By default, the client tries the servers in order (see [`Ordered`]), starting with the one
+    /// after the server it lost. See [`Shuffle`] and [`Cooldown`] for other strategies, or
+    /// implement [`ServerSelector`] to, say, prefer the servers in the same datacenter. The
+    /// selector is shared by all clients built by clones of this builder.
+    pub fn set_server_selector<S>(&mut self, selector: S)
+    where
+        S: ServerSelector + 'static,
+    {
+        self.selector = select::Selector(Arc::new(std::sync::Mutex::new(selector)));
+    }
+
     /// Set the logger that should be used internally in the ZooKeeper client.
     ///
     /// By default, all logging is disabled. See also [the `slog`
@@ -348,34 +817,113 @@ impl ZooKeeperBuilder {
 
     fn handshake(
         self,
-        addr: SocketAddr,
+        addrs: Vec<SocketAddr>,
+        server: usize,
         stream: tokio::net::TcpStream,
         default_watcher: futures::sync::mpsc::UnboundedSender<WatchedEvent>,
     ) -> impl Future<Item = ZooKeeper, Error = failure::Error> {
+        let (session_id, passwd, last_zxid_seen) = match self.resume {
+            Some(SavedSession {
+                session_id,
+                ref password,
+                last_zxid_seen,
+            }) => (session_id, password.clone(), last_zxid_seen),
+            None => (0, vec![], 0),
+        };
         let request = proto::Request::Connect {
             protocol_version: 0,
-            last_zxid_seen: 0,
+            last_zxid_seen,
             timeout: (self.session_timeout.as_secs() * 1_000) as i32
                 + self.session_timeout.subsec_millis() as i32,
-            session_id: 0,
-            passwd: vec![],
-            read_only: false,
+            session_id,
+            passwd,
+            read_only: self.read_only,
         };
-        debug!(self.logger, "about to perform handshake");
+        debug!(self.logger, "about to perform handshake"; "session_id" => session_id);
 
         let plog = self.logger.clone();
-        let enqueuer = proto::Packetizer::new(addr, stream, plog, default_watcher);
-        enqueuer.enqueue(request).map(move |response| {
+        let enqueuer = proto::Packetizer::new(
+            addrs.clone(),
+            server,
+            stream,
+            proto::ConnectOptions {
+                chroot: self.chroot.clone(),
+                connect_timeout: self.connect_timeout,
+                operation_timeout: self.operation_timeout,
+                ping_interval: self.ping_interval,
+                read_idle_timeout: self.read_idle_timeout,
+                backoff: self.backoff,
+                read_only: self.read_only,
+                request_queue_capacity: self.request_queue_capacity,
+                check_response_length: self.check_response_length,
+                utf8_policy: self.utf8_policy,
+                selector: self.selector.clone(),
+                max_reconnect_attempts: self.max_reconnect_attempts,
+            },
+            plog,
+            default_watcher,
+        );
+        enqueuer.enqueue(request).and_then(move |response| {
             trace!(self.logger, "{:?}", response);
-            ZooKeeper {
-                connection: enqueuer,
-                logger: self.logger,
+            match response {
+                Ok(proto::Response::Connect { timeout, .. }) if timeout <= 0 => {
+                    // the server responds with a zero timeout if it will not resume the session
+                    Err(ZkError::SessionExpired
+                        .context("server refused to resume session")
+                        .into())
+                }
+                Ok(proto::Response::Connect {
+                    timeout,
+                    session_id,
+                    password,
+                    ..
+                }) => {
+                    let mut connection = enqueuer;
+                    if let Some(sasl) = self.sasl {
+                        connection.hold_writes(sasl.policy);
+                        let done = connection.clone();
+                        let log = self.logger.clone();
+                        tokio::spawn(
+                            sasl::authenticate(connection.clone(), (sasl.new_client)()).then(
+                                move |r| {
+                                    if let Err(ref e) = r {
+                                        info!(log, "SASL authentication failed"; "error" => %e);
+                                    }
+                                    done.authenticated(r.is_ok());
+                                    Ok(())
+                                },
+                            ),
+                        );
+                    }
+                    Ok(ZooKeeper {
+                        connection,
+                        logger: self.logger,
+                        session_id,
+                        password,
+                        session_timeout: time::Duration::from_millis(timeout as u64),
+                        chrooted: self.chroot.is_some(),
+                        addrs,
+                        max_data_size: self.max_data_size,
+                        fetch_actual_version: self.fetch_actual_version,
+                        rearm_watches: self.rearm_watches,
+                    })
+                }
+                Ok(r) => bail!("got non-connect response to handshake: {:?}", r),
+                Err(ZkError::MarshallingError) => Err(error::NotAZooKeeperServer.into()),
+                Err(e) => Err(e.context("handshake failed").into()),
             }
         })
     }
 }
 
 impl ZooKeeper {
+    /// Get a builder for customizing how to connect to ZooKeeper.
+    ///
+    /// This is the same as [`ZooKeeperBuilder::default`].
+    pub fn builder() -> ZooKeeperBuilder {
+        ZooKeeperBuilder::default()
+    }
+
     /// Connect to a ZooKeeper server instance at the given address with default parameters.
     ///
     /// See [`ZooKeeperBuilder::connect`].
@@ -386,6 +934,211 @@ impl ZooKeeper {
         ZooKeeperBuilder::default().connect(addr)
     }
 
+    /// Connect to the ZooKeeper server instance described by the given connection string with
+    /// default parameters.
+    ///
+    /// See [`ZooKeeperBuilder::connect_string`].
+    pub fn connect_string(
+        connect_string: &str,
+    ) -> impl Future<Item = (Self, impl Stream<Item = WatchedEvent, Error = ()>), Error = failure::Error>
+    {
+        ZooKeeperBuilder::default().connect_string(connect_string)
+    }
+
+    /// The id of the session this client is using.
+    ///
+    /// Together with [`ZooKeeper::password`], this can be used to resume the session from another
+    /// client through [`ZooKeeperBuilder::connect_with_session`].
+    pub fn session_id(&self) -> i64 {
+        self.session_id
+    }
+
+    /// The password of the session this client is using.
+    ///
+    /// See [`ZooKeeperBuilder::connect_with_session`] for the implications of handing this out.
+    pub fn password(&self) -> &[u8] {
+        &self.password
+    }
+
+    /// The session timeout the server agreed to when the session was established.
+    ///
+    /// This may differ from the timeout requested through
+    /// [`ZooKeeperBuilder::set_session_timeout`]. If the server does not hear from this client for
+    /// this long, it expires the session.
+    pub fn session_timeout(&self) -> time::Duration {
+        self.session_timeout
+    }
+
+    /// The address of the server the client is currently connected to, or `None` while it is not
+    /// connected to any server.
+    ///
+    /// This changes whenever the client fails over to another server of the ensemble.
+    pub fn connected_host(&self) -> Option<SocketAddr> {
+        self.connection.connected_server().map(|i| self.addrs[i])
+    }
+
+    /// The highest zxid the client has seen in any reply or watch notification from the server.
+    ///
+    /// Every change to the tree is assigned a zxid, which orders it relative to all other changes.
+    /// This can be used to reason about which writes a later read will observe, or about whether
+    /// a [`ZooKeeper::sync`] is needed. It is 0 until the first reply with a zxid has arrived.
+    pub fn last_zxid(&self) -> i64 {
+        self.connection.last_zxid()
+    }
+
+    /// How many requests have been sent to the server, and are still waiting for its response.
+    ///
+    /// Requests that are queued up to be sent, such as while the client is re-connecting, do not
+    /// count. Once the connection to the server is lost, all requests that were waiting fail, and
+    /// no longer count either.
+    pub fn outstanding_requests(&self) -> usize {
+        self.connection.outstanding()
+    }
+
+    /// Whether the server the client is connected to supports the given `feature`, or `None` if
+    /// the client has not found out yet.
+    ///
+    /// Servers do not announce what they support, so the client only finds out by using a feature.
+    /// Once the server has answered a request that uses `feature` with [`error::Unsupported`],
+    /// further such requests fail right away without being sent. What the client found out is
+    /// forgotten whenever it connects to another server, since the servers of an ensemble may run
+    /// different versions of ZooKeeper (such as during an upgrade).
+    pub fn supports(&self, feature: Feature) -> Option<bool> {
+        self.connection.supports(feature)
+    }
+
+    /// Whether the client is currently connected to a server in read-only mode.
+    ///
+    /// This can only happen if read-only connections were allowed with
+    /// [`ZooKeeperBuilder::set_allow_read_only`]. While connected in read-only mode, all writes
+    /// fail right away without being sent to the server. Use [`ZooKeeper::state_events`] to
+    /// find out when the client reconnects to a server that accepts writes.
+    pub fn is_read_only(&self) -> bool {
+        self.connection.state() == KeeperState::ConnectedReadOnly
+    }
+
+    /// Set whether requests made through this handle wait for a free slot when the request queue
+    /// is full.
+    ///
+    /// If not, they fail right away with [`error::Busy`] instead. Handles cloned from the
+    /// returned one keep this setting. This has no effect unless the request queue was limited
+    /// with [`ZooKeeperBuilder::set_request_queue_capacity`]. Requests wait by default.
+    pub fn wait_for_slot(mut self, wait: bool) -> Self {
+        self.connection.wait_for_slot(wait);
+        self
+    }
+
+    /// Get a stream of the states the session of this client goes through.
+    ///
+    /// The stream starts with the current state of the session (usually
+    /// [`KeeperState::SyncConnected`]), and then yields every transition, such as to
+    /// [`KeeperState::Disconnected`] when the connection to the server is lost, and back to
+    /// [`KeeperState::SyncConnected`] once the session has been resumed. If the session expires,
+    /// the stream yields [`KeeperState::Expired`] once, and then ends. The same goes for
+    /// [`KeeperState::Closed`] if the client gives up on reconnecting. The stream also ends once
+    /// the client has been shut down.
+    pub fn state_events(&self) -> impl Stream<Item = KeeperState, Error = ()> {
+        self.connection.state_events()
+    }
+
+    /// Get a stream of all watch events this client receives from now on.
+    ///
+    /// This includes the events of all watches, whether they were left through
+    /// [`ZooKeeper::watch`], [`ZooKeeper::with_watcher`], or [`ZooKeeper::add_watch`], which makes
+    /// it convenient for logging, or for multiplexing many watches. Each call returns a separate
+    /// stream that receives every event. The stream is not interrupted when the client reconnects
+    /// to a server, and ends once the client has been shut down or its session has expired.
+    ///
+    /// This is the same as the stream of events returned when connecting.
+    pub fn watch_events(&self) -> impl Stream<Item = WatchedEvent, Error = ()> {
+        self.connection.watch_events()
+    }
+
+    /// Get a stream of all watch events this client receives from now on, in which bursts of the
+    /// same event for the same node are coalesced.
+    ///
+    /// This is like [`ZooKeeper::watch_events`], except that an event is held back for `window`,
+    /// and only the latest of the events of the same [`WatchedEventType`] for the same path that
+    /// arrive in that time is yielded. This saves redundant reads when a node changes in quick
+    /// succession, such as with persistent watches (see [`ZooKeeper::add_watch`]). Events about
+    /// the state of the session are never held back. A `window` of zero yields every event as it
+    /// arrives.
+    pub fn debounced_watch_events(
+        &self,
+        window: time::Duration,
+    ) -> impl Stream<Item = WatchedEvent, Error = ()> {
+        debounce::Debounce::new(self.connection.watch_events(), window)
+    }
+
+    /// Get a stream of the names of the children of the node at the given `path`, which yields the
+    /// current list of children right away, and then again whenever a child is created or
+    /// deleted.
+    ///
+    /// Each list is read along with a watch for the next change, so no change goes unnoticed,
+    /// though several changes in quick succession may be reported as one. The returned lists are
+    /// not sorted. The stream ends once the node at `path` does not exist (which may be right
+    /// away). If the connection to the server is lost, the list is read again once the client has
+    /// reconnected. The stream fails if a read fails for any other reason, or if the session
+    /// expires.
+    ///
+    /// If watches are not re-armed (see [`ZooKeeperBuilder::set_rearm_watches`]), the stream
+    /// instead ends after the first change, and the list that it yields for that change is read
+    /// without leaving a watch.
+    pub fn watch_children(
+        &self,
+        path: &str,
+    ) -> impl Stream<Item = Vec<String>, Error = failure::Error> {
+        children::WatchChildren::new(self.clone(), path, self.rearm_watches)
+    }
+
+    /// Check that `path` is valid for creating or deleting a node.
+    fn validate_write_path(&self, path: &str, sequential: bool) -> Result<(), failure::Error> {
+        path::validate(path, sequential)?;
+        if !self.chrooted {
+            path::validate_unreserved(path)?;
+        }
+        Ok(())
+    }
+
+    /// Check that `data` is small enough for the server to accept it.
+    fn validate_data(&self, data: &[u8]) -> Result<(), failure::Error> {
+        if data.len() > self.max_data_size {
+            return Err(error::DataTooLarge {
+                size: data.len(),
+                limit: self.max_data_size,
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Close the session of this client, and shut the client down.
+    ///
+    /// The server removes all ephemeral nodes of the session right away, rather than once the
+    /// session times out. Requests that were sent before are answered first: the session is only
+    /// closed once no more requests are waiting for a response (see
+    /// [`ZooKeeper::outstanding_requests`]), or once the operation timeout set with
+    /// [`ZooKeeperBuilder::set_operation_timeout`] has passed, if there is one. The returned future
+    /// resolves once the server has acknowledged the close, and the connection to it has been shut
+    /// down. Any clones of this client can no longer be used afterwards. Closing a client that has
+    /// already been closed or shut down does nothing.
+    pub fn close(self) -> impl Future<Item = (), Error = failure::Error> {
+        trace!(self.logger, "close"; "outstanding" => self.outstanding_requests());
+        // ends once the client has shut down
+        let done = self.connection.state_events().for_each(|_| Ok(()));
+        let connection = self.connection.clone();
+        self.connection
+            .drained()
+            .then(move |_| connection.enqueue(proto::Request::CloseSession))
+            .then(|r| match r {
+                Ok(Ok(_)) => Ok(()),
+                Ok(Err(e)) => Err(e.context("close call failed").into()),
+                // the client has already shut down
+                Err(_) => Ok(()),
+            })
+            .and_then(move |()| done.map_err(|()| unreachable!("state events never fail")))
+    }
+
     /// Create a node with the given `path` with `data` as its contents.
     ///
     /// The `mode` argument specifies additional options for the newly created node.
@@ -399,7 +1152,8 @@ impl ZooKeeper {
     /// given `path` plus a suffix `i` where `i` is the current sequential number of the node. The
     /// sequence number is always fixed length of 10 digits, 0 padded. Once such a node is created,
     /// the sequential number will be incremented by one. The newly created node's full name is
-    /// returned when the future is resolved.
+    /// returned when the future is resolved, and [`sequence_number`] extracts the sequence number
+    /// from it.
     ///
     /// If a node with the same actual path already exists in the ZooKeeper, the returned future
     /// resolves with an error of [`error::Create::NodeExists`]. Note that since a different actual
@@ -414,7 +1168,15 @@ impl ZooKeeper {
     /// `path` left by `exists` calls, and the watches on the parent of the node by `get_children`
     /// calls.
     ///
-    /// The maximum allowable size of the data array is 1 MB (1,048,576 bytes).
+    /// If `mode` has a TTL that is out of range, the returned future resolves to
+    /// [`error::Create::InvalidTtl`] without contacting the server. Likewise, if `path` is not a
+    /// valid path, the returned future fails with [`error::InvalidPath`], and if `acl` is obviously
+    /// invalid (such as when it is empty, or has an entry with an unknown scheme), it fails with
+    /// [`error::InvalidAcl`].
+    ///
+    /// The maximum allowable size of the data array is 1 MB (1,048,576 bytes) by default. Larger
+    /// data fails with [`error::DataTooLarge`] without contacting the server (see
+    /// [`ZooKeeperBuilder::set_max_data_size`]).
     pub fn create<D, A>(
         self,
         path: &str,
@@ -427,79 +1189,455 @@ impl ZooKeeper {
         A: Into<Cow<'static, [Acl]>>,
     {
         let data = data.into();
+        let acl = acl.into();
         trace!(self.logger, "create"; "path" => path, "mode" => ?mode, "dlen" => data.len());
-        self.connection
-            .enqueue(proto::Request::Create {
-                path: path.to_string(),
-                data: data,
-                acl: acl.into(),
-                mode,
-            })
-            .and_then(transform::create)
-            .map(move |r| (self, r))
+        if let Err(e) = self
+            .validate_write_path(path, mode.is_sequential())
+            .and_then(|()| self.validate_data(&data))
+            .and_then(|()| Ok(validate_acl(&acl)?))
+        {
+            return future::Either::A(future::err(e));
+        }
+        if mode.ttl().is_some() && mode.ttl_millis().is_none() {
+            return future::Either::A(future::ok((self, Err(error::Create::InvalidTtl))));
+        }
+        future::Either::B(
+            self.connection
+                .enqueue(proto::Request::Create {
+                    path: path.to_string(),
+                    data,
+                    acl,
+                    mode,
+                })
+                .and_then(transform::create)
+                .map(move |r| (self, r)),
+        )
     }
 
-    /// Set the data for the node at the given `path`.
+    /// Create a node with the given `path` with `data` as its contents, and return its full name
+    /// along with its initial [`Stat`].
     ///
-    /// The call will succeed if such a node exists, and the given `version` matches the version of
-    /// the node (if the given `version` is `None`, it matches any version). On success, the
-    /// updated [`Stat`] of the node is returned.
-    ///
-    /// This operation, if successful, will trigger all the watches on the node of the given `path`
-    /// left by `get_data` calls.
-    ///
-    /// The maximum allowable size of the data array is 1 MB (1,048,576 bytes).
-    pub fn set_data<D>(
+    /// This behaves like [`ZooKeeper::create`], but saves a follow-up `exists` call for callers
+    /// that need the new node's `czxid` or version. It requires ZooKeeper 3.5 or newer.
+    pub fn create_with_stat<D, A>(
         self,
         path: &str,
-        version: Option<i32>,
         data: D,
-    ) -> impl Future<Item = (Self, Result<Stat, error::SetData>), Error = failure::Error>
+        acl: A,
+        mode: CreateMode,
+    ) -> impl Future<Item = (Self, Result<(String, Stat), error::Create>), Error = failure::Error>
     where
         D: Into<Cow<'static, [u8]>>,
+        A: Into<Cow<'static, [Acl]>>,
     {
         let data = data.into();
-        trace!(self.logger, "set_data"; "path" => path, "version" => ?version, "dlen" => data.len());
-        let version = version.unwrap_or(-1);
-        self.connection
-            .enqueue(proto::Request::SetData {
-                path: path.to_string(),
-                version,
-                data,
-            })
-            .and_then(move |r| transform::set_data(version, r))
-            .map(move |r| (self, r))
+        let acl = acl.into();
+        trace!(self.logger, "create2"; "path" => path, "mode" => ?mode, "dlen" => data.len());
+        if let Err(e) = self
+            .validate_write_path(path, mode.is_sequential())
+            .and_then(|()| self.validate_data(&data))
+            .and_then(|()| Ok(validate_acl(&acl)?))
+        {
+            return future::Either::A(future::err(e));
+        }
+        if mode.ttl().is_some() && mode.ttl_millis().is_none() {
+            return future::Either::A(future::ok((self, Err(error::Create::InvalidTtl))));
+        }
+        future::Either::B(
+            self.connection
+                .enqueue(proto::Request::Create2 {
+                    path: path.to_string(),
+                    data,
+                    acl,
+                    mode,
+                })
+                .and_then(transform::create2)
+                .map(move |r| (self, r)),
+        )
     }
 
-    /// Delete the node at the given `path`.
+    /// Create a node like [`ZooKeeper::create_with_stat`] does, and also return the [`Stat`] of
+    /// its parent as of right after the node was created.
     ///
-    /// The call will succeed if such a node exists, and the given `version` matches the node's
-    /// version (if the given `version` is `None`, it matches any versions).
-    ///
-    /// This operation, if successful, will trigger all the watches on the node of the given `path`
-    /// left by `exists` API calls, and the watches on the parent node left by `get_children` API
-    /// calls.
-    pub fn delete(
+    /// This is useful for keeping track of the parent's `cversion`, for example to make changes to
+    /// a directory of nodes depend on it. Since ZooKeeper cannot read within the same transaction
+    /// that it writes in, the parent is read by an `exists` request that is sent right behind the
+    /// `create`. Another client may change the parent's children in between; this happened if and
+    /// only if the parent's `pzxid` differs from the new node's `czxid`.
+    pub fn create_with_parent_stat<D, A>(
         self,
         path: &str,
-        version: Option<i32>,
-    ) -> impl Future<Item = (Self, Result<(), error::Delete>), Error = failure::Error> {
-        trace!(self.logger, "delete"; "path" => path, "version" => ?version);
-        let version = version.unwrap_or(-1);
-        self.connection
-            .enqueue(proto::Request::Delete {
+        data: D,
+        acl: A,
+        mode: CreateMode,
+    ) -> impl Future<Item = (Self, Result<(String, Stat, Stat), error::Create>), Error = failure::Error>
+    where
+        D: Into<Cow<'static, [u8]>>,
+        A: Into<Cow<'static, [Acl]>>,
+    {
+        let data = data.into();
+        let acl = acl.into();
+        trace!(self.logger, "create_with_parent_stat"; "path" => path, "mode" => ?mode);
+        if let Err(e) = self
+            .validate_write_path(path, mode.is_sequential())
+            .and_then(|()| self.validate_data(&data))
+            .and_then(|()| Ok(validate_acl(&acl)?))
+        {
+            return future::Either::A(future::err(e));
+        }
+        if mode.ttl().is_some() && mode.ttl_millis().is_none() {
+            return future::Either::A(future::ok((self, Err(error::Create::InvalidTtl))));
+        }
+        let parent = path::parent(path).to_string();
+        let create = self
+            .connection
+            .enqueue(proto::Request::Create2 {
                 path: path.to_string(),
-                version: version,
+                data,
+                acl,
+                mode,
             })
-            .and_then(move |r| transform::delete(version, r))
-            .map(move |r| (self, r))
+            .and_then(transform::create2);
+        // pipelined, so that the server reads the parent as soon after the create as it can
+        let exists = self
+            .connection
+            .enqueue(proto::Request::Exists {
+                path: parent.clone(),
+                watch: Watch::None,
+            })
+            .and_then(transform::exists);
+        future::Either::B(create.join(exists).and_then(move |(created, parent_stat)| {
+            let (name, stat) = match created {
+                Ok(created) => created,
+                Err(e) => return Ok((self, Err(e))),
+            };
+            match parent_stat {
+                Some(parent_stat) => Ok((self, Ok((name, stat, parent_stat)))),
+                None => bail!(
+                    "parent {} was deleted right after {} was created",
+                    parent,
+                    name
+                ),
+            }
+        }))
     }
 
-    /// Return the [ACL](https://zookeeper.apache.org/doc/current/zookeeperProgrammers.html#sc_ZooKeeperAccessControl)
-    /// and Stat of the node at the given `path`.
+    /// Create a node with a sequential `mode` below the given `path` prefix, and return its full
+    /// name along with its sequence number.
     ///
-    /// If no node exists for the given path, the returned future resolves with an error of
-    /// [`error::GetAcl::NoNode`].
+    /// This behaves like [`ZooKeeper::create`], and the name is relative to the chroot (if any)
+    /// just like the `path` given. Only the chroot at the start of the name the server responds
+    /// with is removed, so a node whose own name starts with the chroot keeps it.
+    ///
+    /// # Panics
+    ///
+    /// If `mode` is not a sequential [`CreateMode`].
+    pub fn create_sequential<D, A>(
+        self,
+        path: &str,
+        data: D,
+        acl: A,
+        mode: CreateMode,
+    ) -> impl Future<Item = (Self, Result<(String, u64), error::Create>), Error = failure::Error>
+    where
+        D: Into<Cow<'static, [u8]>>,
+        A: Into<Cow<'static, [Acl]>>,
+    {
+        assert!(mode.is_sequential(), "{:?} is not a sequential mode", mode);
+        self.create(path, data, acl, mode)
+            .and_then(|(zk, res)| match res {
+                Ok(name) => match sequence_number(&name) {
+                    Some(n) => Ok((zk, Ok((name, n)))),
+                    None => bail!("server did not append a sequence number to {}", name),
+                },
+                Err(e) => Ok((zk, Err(e))),
+            })
+    }
+
+    /// Create an ephemeral-sequential node below the given `path` prefix, and return its full
+    /// name, in a way that is safe to retry.
+    ///
+    /// If the connection is lost before the server has answered a `create`, the node may or may
+    /// not have been created, and creating it again could leave a duplicate behind until the
+    /// session ends. Like the "protected" create of the reference recipes, this instead puts a
+    /// GUID at the start of the node's name (as in `/locks/_c_<guid>-lock-0000000007`). When an
+    /// attempt fails because the connection was lost, the children of the parent are searched for
+    /// a node with that GUID, and the node is only created again if there is none.
+    ///
+    /// The future fails if the children cannot be read, such as when the session has expired. Any
+    /// other failure of an attempt (such as an invalid ACL, or an operation timeout, after which
+    /// the create may still be in flight) is returned as is, without trying again.
+    pub fn create_protected_ephemeral_sequential<D, A>(
+        self,
+        path: &str,
+        data: D,
+        acl: A,
+    ) -> impl Future<Item = (Self, Result<String, error::Create>), Error = failure::Error>
+    where
+        D: Into<Cow<'static, [u8]>>,
+        A: Into<Cow<'static, [Acl]>>,
+    {
+        let data = data.into();
+        let acl = acl.into();
+        trace!(self.logger, "create_protected_ephemeral_sequential"; "path" => path);
+        // requests that are never sent do not need to be looked for
+        if let Err(e) = self
+            .validate_write_path(path, true)
+            .and_then(|()| self.validate_data(&data))
+            .and_then(|()| Ok(validate_acl(&acl)?))
+        {
+            return future::Either::A(future::err(e));
+        }
+        let (parent, name) = path::protected(path);
+        let parent = parent.to_string();
+        let protected = child_path(&parent, &name);
+
+        future::Either::B(future::loop_fn(self, move |zk| {
+            let retry = zk.clone();
+            let parent = parent.clone();
+            let name = name.clone();
+            zk.create(
+                &protected,
+                data.clone(),
+                acl.clone(),
+                CreateMode::EphemeralSequential,
+            )
+            .map(future::Loop::Break)
+            .or_else(move |e| {
+                // only a lost connection leaves it open whether the node was created
+                if e.find_root_cause().downcast_ref::<ZkError>() != Some(&ZkError::ConnectionLoss) {
+                    return future::Either::A(future::err(e));
+                }
+                debug!(retry.logger, "protected create failed; looking for the node";
+                       "error" => %e);
+                future::Either::B(retry.get_children(&parent).map(move |(zk, children)| {
+                    let created = children
+                        .into_iter()
+                        .flatten()
+                        .find(|child| child.starts_with(&name));
+                    match created {
+                        Some(child) => future::Loop::Break((zk, Ok(child_path(&parent, &child)))),
+                        None => future::Loop::Continue(zk),
+                    }
+                }))
+            })
+        }))
+    }
+
+    /// Make sure that a node exists at the given `path`, creating it and any of its missing
+    /// ancestors along the way.
+    ///
+    /// Missing nodes are created one at a time, from the root down, as empty persistent nodes with
+    /// the [`Acl::open_unsafe`] ACL. Nodes that already exist are left untouched, so it is safe for
+    /// several clients to ensure the same path at the same time.
+    ///
+    /// If a node cannot be created, the returned future resolves with the error for that node
+    /// (for example, [`error::Create::NoChildrenForEphemerals`] if an ancestor is ephemeral).
+    pub fn ensure_path(
+        self,
+        path: &str,
+    ) -> impl Future<Item = (Self, Result<(), error::Create>), Error = failure::Error> {
+        let mut prefixes = Vec::new();
+        let mut prefix = String::new();
+        for component in path.split('/').filter(|c| !c.is_empty()) {
+            prefix.push('/');
+            prefix.push_str(component);
+            prefixes.push(prefix.clone());
+        }
+
+        future::loop_fn(
+            (self, prefixes.into_iter()),
+            |(zk, mut prefixes)| match prefixes.next() {
+                None => future::Either::A(future::ok(future::Loop::Break((zk, Ok(()))))),
+                Some(prefix) => future::Either::B(
+                    zk.create(&prefix, &[][..], Acl::open_unsafe(), CreateMode::Persistent)
+                        .map(move |(zk, res)| match res {
+                            Ok(_) | Err(error::Create::NodeExists) => {
+                                future::Loop::Continue((zk, prefixes))
+                            }
+                            Err(e) => future::Loop::Break((zk, Err(e))),
+                        }),
+                ),
+            },
+        )
+    }
+
+    /// Delete the node at the given `path` along with all of its descendants.
+    ///
+    /// The subtree is first discovered using [`ZooKeeper::get_children`], and then deleted from
+    /// the leaves up, in batches of [`multi`](ZooKeeper::multi) requests. Nodes that disappear
+    /// while this is going on (such as ephemeral nodes whose owner exits) are ignored. Note that
+    /// the subtree is not deleted atomically: if another client adds a node to the subtree
+    /// concurrently, the returned future may resolve with [`error::Delete::NotEmpty`] after only
+    /// part of the subtree has been deleted.
+    ///
+    /// If no node exists at the given `path`, the returned future resolves with an error of
+    /// [`error::Delete::NoNode`].
+    pub fn delete_recursive(
+        self,
+        path: &str,
+    ) -> impl Future<Item = (Self, Result<(), error::Delete>), Error = failure::Error> {
+        trace!(self.logger, "delete_recursive"; "path" => path);
+
+        // find all nodes in the subtree, with every node listed before its children
+        future::loop_fn(
+            (self, vec![path.to_string()], 0),
+            |(zk, mut nodes, next)| {
+                if next == nodes.len() {
+                    return future::Either::A(future::ok(future::Loop::Break((zk, nodes))));
+                }
+                let path = nodes[next].clone();
+                future::Either::B(zk.get_children(&path).map(move |(zk, children)| {
+                    match children {
+                        Some(children) => {
+                            nodes.extend(children.iter().map(|child| child_path(&path, child)))
+                        }
+                        None if next == 0 => return future::Loop::Break((zk, Vec::new())),
+                        None => {
+                            // already gone, which is fine
+                        }
+                    }
+                    future::Loop::Continue((zk, nodes, next + 1))
+                }))
+            },
+        ).and_then(|(zk, mut nodes)| {
+            if nodes.is_empty() {
+                return future::Either::A(future::ok((zk, Err(error::Delete::NoNode))));
+            }
+
+            // children must go before their parents
+            nodes.reverse();
+            let batches: Vec<_> = nodes
+                .chunks(DELETE_BATCH_SIZE)
+                .map(|batch| batch.to_vec())
+                .collect();
+            future::Either::B(future::loop_fn(
+                (zk, batches.into_iter()),
+                |(zk, mut batches)| match batches.next() {
+                    None => future::Either::A(future::ok(future::Loop::Break((zk, Ok(()))))),
+                    Some(batch) => {
+                        future::Either::B(zk.delete_batch(batch).map(move |(zk, res)| match res {
+                            Ok(()) => future::Loop::Continue((zk, batches)),
+                            Err(e) => future::Loop::Break((zk, Err(e))),
+                        }))
+                    }
+                },
+            ))
+        })
+    }
+
+    /// Delete the nodes at the given paths, in order, ignoring nodes that no longer exist.
+    fn delete_batch(
+        self,
+        batch: Vec<String>,
+    ) -> impl Future<Item = (Self, Result<(), error::Delete>), Error = failure::Error> {
+        let multi = batch
+            .iter()
+            .fold(self.multi(), |multi, path| multi.delete(path, None));
+        multi.run().and_then(move |(zk, results)| {
+            if results.iter().all(Result::is_ok) {
+                return future::Either::A(future::ok((zk, Ok(()))));
+            }
+
+            // the subtree changed under us, so the whole batch was rolled back. deleting the
+            // nodes one by one lets us tell which changes we can ignore.
+            future::Either::B(future::loop_fn(
+                (zk, batch.into_iter()),
+                |(zk, mut paths)| match paths.next() {
+                    None => future::Either::A(future::ok(future::Loop::Break((zk, Ok(()))))),
+                    Some(path) => {
+                        future::Either::B(zk.delete(&path, None).map(move |(zk, res)| match res {
+                            Ok(()) | Err(error::Delete::NoNode) => {
+                                future::Loop::Continue((zk, paths))
+                            }
+                            Err(e) => future::Loop::Break((zk, Err(e))),
+                        }))
+                    }
+                },
+            ))
+        })
+    }
+
+    /// Set the data for the node at the given `path`.
+    ///
+    /// The call will succeed if such a node exists, and the given `version` matches the version of
+    /// the node (if the given `version` is `None`, it matches any version). On success, the
+    /// updated [`Stat`] of the node is returned.
+    ///
+    /// This operation, if successful, will trigger all the watches on the node of the given `path`
+    /// left by `get_data` calls.
+    ///
+    /// The maximum allowable size of the data array is 1 MB (1,048,576 bytes) by default. Larger
+    /// data fails with [`error::DataTooLarge`] without contacting the server (see
+    /// [`ZooKeeperBuilder::set_max_data_size`]).
+    pub fn set_data<D>(
+        self,
+        path: &str,
+        version: Option<i32>,
+        data: D,
+    ) -> impl Future<Item = (Self, Result<Stat, error::SetData>), Error = failure::Error>
+    where
+        D: Into<Cow<'static, [u8]>>,
+    {
+        let data = data.into();
+        trace!(self.logger, "set_data"; "path" => path, "version" => ?version, "dlen" => data.len());
+        if let Err(e) = path::validate(path, false)
+            .map_err(failure::Error::from)
+            .and_then(|()| self.validate_data(&data))
+        {
+            return future::Either::A(future::err(e));
+        }
+        let version = version.unwrap_or(-1);
+        let path = path.to_string();
+        future::Either::B(
+            self.connection
+                .enqueue(proto::Request::SetData {
+                    path: path.clone(),
+                    version,
+                    data,
+                })
+                .and_then(move |r| transform::set_data(version, &path, r).map(|r| (path, r)))
+                .and_then(move |(path, r)| {
+                    self.fill_in_actual_version(path, r, |stat| stat.version)
+                }),
+        )
+    }
+
+    /// Delete the node at the given `path`.
+    ///
+    /// The call will succeed if such a node exists, and the given `version` matches the node's
+    /// version (if the given `version` is `None`, it matches any versions).
+    ///
+    /// This operation, if successful, will trigger all the watches on the node of the given `path`
+    /// left by `exists` API calls, and the watches on the parent node left by `get_children` API
+    /// calls.
+    pub fn delete(
+        self,
+        path: &str,
+        version: Option<i32>,
+    ) -> impl Future<Item = (Self, Result<(), error::Delete>), Error = failure::Error> {
+        trace!(self.logger, "delete"; "path" => path, "version" => ?version);
+        if let Err(e) = self.validate_write_path(path, false) {
+            return future::Either::A(future::err(e));
+        }
+        let version = version.unwrap_or(-1);
+        let path = path.to_string();
+        future::Either::B(
+            self.connection
+                .enqueue(proto::Request::Delete {
+                    path: path.clone(),
+                    version,
+                })
+                .and_then(move |r| transform::delete(version, r))
+                .and_then(move |r| self.fill_in_actual_version(path, r, |stat| stat.version)),
+        )
+    }
+
+    /// Return the [ACL](https://zookeeper.apache.org/doc/current/zookeeperProgrammers.html#sc_ZooKeeperAccessControl)
+    /// and Stat of the node at the given `path`.
+    ///
+    /// If no node exists for the given path, the returned future resolves with an error of
+    /// [`error::GetAcl::NoNode`].
     pub fn get_acl(
         self,
         path: &str,
@@ -523,6 +1661,9 @@ impl ZooKeeper {
     /// If no node exists for the given path, the returned future resolves with an error of
     /// [`error::SetAcl::NoNode`]. If the given `version` does not match the ACL version, the
     /// returned future resolves with an error of [`error::SetAcl::BadVersion`].
+    ///
+    /// If `acl` is obviously invalid, such as when it is empty, the returned future fails with
+    /// [`error::InvalidAcl`] without contacting the server.
     pub fn set_acl<A>(
         self,
         path: &str,
@@ -532,16 +1673,103 @@ impl ZooKeeper {
     where
         A: Into<Cow<'static, [Acl]>>,
     {
+        let acl = acl.into();
         trace!(self.logger, "set_acl"; "path" => path, "version" => ?version);
+        if let Err(e) = validate_acl(&acl) {
+            return future::Either::A(future::err(e.into()));
+        }
         let version = version.unwrap_or(-1);
-        self.connection
-            .enqueue(proto::Request::SetAcl {
-                path: path.to_string(),
-                acl: acl.into(),
-                version,
+        let path = path.to_string();
+        future::Either::B(
+            self.connection
+                .enqueue(proto::Request::SetAcl {
+                    path: path.clone(),
+                    acl,
+                    version,
+                })
+                .and_then(move |r| transform::set_acl(version, &path, r).map(|r| (path, r)))
+                .and_then(move |(path, r)| {
+                    self.fill_in_actual_version(path, r, |stat| stat.aversion)
+                }),
+        )
+    }
+
+    /// Change the [ACL](https://zookeeper.apache.org/doc/current/zookeeperProgrammers.html#sc_ZooKeeperAccessControl)
+    /// of the node at the given `path` to what `f` makes of its current ACL.
+    ///
+    /// The current ACL is read along with its version, and the ACL that `f` returns is only set if
+    /// the ACL still has that version by then. If another client changed the ACL in between, this
+    /// is tried once more with the ACL that client set, so `f` may be called twice. If the ACL
+    /// changes under this call again, the returned future resolves with an error of
+    /// [`error::SetAcl::BadVersion`].
+    ///
+    /// If no node exists for the given path, the returned future resolves with an error of
+    /// [`error::SetAcl::NoNode`]. See [`ZooKeeper::set_acl`] for the other errors.
+    pub fn update_acl<F>(
+        self,
+        path: &str,
+        f: F,
+    ) -> impl Future<Item = (Self, Result<Stat, error::SetAcl>), Error = failure::Error>
+    where
+        F: FnMut(Vec<Acl>) -> Vec<Acl>,
+    {
+        trace!(self.logger, "update_acl"; "path" => path);
+        let path = path.to_string();
+        future::loop_fn((self, f, true), move |(zk, mut f, retry)| {
+            let path = path.clone();
+            zk.get_acl(&path).and_then(move |(zk, res)| {
+                let (acl, stat) = match res {
+                    Ok(res) => res,
+                    Err(error::GetAcl::NoNode) => {
+                        return future::Either::A(future::ok(future::Loop::Break((
+                            zk,
+                            Err(error::SetAcl::NoNode),
+                        ))));
+                    }
+                };
+                let acl = f(acl);
+                future::Either::B(zk.set_acl(&path, acl, Some(stat.aversion)).map(
+                    move |(zk, res)| match res {
+                        Err(error::SetAcl::BadVersion { .. }) if retry => {
+                            future::Loop::Continue((zk, f, false))
+                        }
+                        res => future::Loop::Break((zk, res)),
+                    },
+                ))
             })
-            .and_then(move |r| transform::set_acl(version, r))
-            .map(move |r| (self, r))
+        })
+    }
+
+    /// If `res` failed because the node at `path` did not have the expected version, and we are
+    /// to find out which version it had, read `version_of` it into the error.
+    fn fill_in_actual_version<T, E>(
+        self,
+        path: String,
+        mut res: Result<T, E>,
+        version_of: fn(&Stat) -> i32,
+    ) -> impl Future<Item = (Self, Result<T, E>), Error = failure::Error>
+    where
+        T: Send + 'static,
+        E: error::ActualVersion + Send + 'static,
+    {
+        let bad_version = match res {
+            Err(ref mut e) => e.actual_version().is_some(),
+            Ok(_) => false,
+        };
+        if !self.fetch_actual_version || !bad_version {
+            return future::Either::A(future::ok((self, res)));
+        }
+        let zk = self.clone();
+        future::Either::B(self.exists(&path).then(move |r| {
+            // the write is done either way, so failing to read the node only loses the version
+            let (zk, stat) = r.unwrap_or((zk, None));
+            if let Err(ref mut e) = res {
+                if let Some(actual) = e.actual_version() {
+                    *actual = stat.as_ref().map(version_of);
+                }
+            }
+            Ok((zk, res))
+        }))
     }
 }
 
@@ -563,16 +1791,24 @@ impl ZooKeeper {
         watch: Watch,
     ) -> impl Future<Item = (Self, Option<Stat>), Error = failure::Error> {
         trace!(self.logger, "exists"; "path" => path, "watch" => ?watch);
-        self.connection
-            .enqueue(proto::Request::Exists {
-                path: path.to_string(),
-                watch,
-            })
-            .and_then(transform::exists)
-            .map(move |r| (self, r))
+        if let Err(e) = path::validate(path, false) {
+            return future::Either::A(future::err(e.into()));
+        }
+        future::Either::B(
+            self.connection
+                .enqueue(proto::Request::Exists {
+                    path: path.to_string(),
+                    watch,
+                })
+                .and_then(transform::exists)
+                .map(move |r| (self, r)),
+        )
     }
 
     /// Return the [`Stat`] of the node of the given `path`, or `None` if the node does not exist.
+    ///
+    /// If the client is not allowed to look up the node, the returned future fails with an error
+    /// whose cause is [`error::Exists::NoAuth`].
     pub fn exists(
         self,
         path: &str,
@@ -595,6 +1831,49 @@ impl ZooKeeper {
             .map(move |r| (self, r))
     }
 
+    /// Return whether the node at the given `path` is an ephemeral node owned by this client's
+    /// session.
+    ///
+    /// This is useful in recipes to check whether a lock or leadership node is still held. If no
+    /// node exists at `path`, `false` is returned.
+    pub fn owns(self, path: &str) -> impl Future<Item = (Self, bool), Error = failure::Error> {
+        self.exists(path).map(|(zk, stat)| {
+            let owned = stat.and_then(|stat| stat.owner_session_id()) == Some(zk.session_id());
+            (zk, owned)
+        })
+    }
+
+    /// Wait until a node exists at the given `path`, and return its [`Stat`].
+    ///
+    /// The returned future resolves right away if the node already exists. Otherwise, it resolves
+    /// once the node has been created. Each check uses a single watched `exists` call (see
+    /// [`WithWatcher::exists`]), so the check and the registration of the watch happen atomically
+    /// on the server, and a node that is created in between cannot be missed.
+    pub fn wait_for_creation(
+        self,
+        path: &str,
+    ) -> impl Future<Item = (Self, Stat), Error = failure::Error> {
+        trace!(self.logger, "wait_for_creation"; "path" => path);
+        let path = path.to_string();
+        future::loop_fn(self, move |zk| {
+            zk.with_watcher()
+                .exists(&path)
+                .and_then(|(zk, watch, stat)| {
+                    if let Some(stat) = stat {
+                        return future::Either::A(future::ok(future::Loop::Break((zk, stat))));
+                    }
+
+                    // check again once the watch fires, which it also does if the session is
+                    // disconnected, or if the node is deleted again before we get to it
+                    future::Either::B(
+                        watch
+                            .map(move |_| future::Loop::Continue(zk))
+                            .map_err(|e| format_err!("creation watch dropped: {:?}", e)),
+                    )
+                })
+        })
+    }
+
     /// Return the names of the children of the node at the given `path`, or `None` if the node
     /// does not exist.
     ///
@@ -607,121 +1886,456 @@ impl ZooKeeper {
         self.get_children_w(path, Watch::None)
     }
 
-    fn get_data_w(
+    fn get_children2_w(
         self,
         path: &str,
         watch: Watch,
-    ) -> impl Future<Item = (Self, Option<(Vec<u8>, Stat)>), Error = failure::Error> {
-        trace!(self.logger, "get_data"; "path" => path, "watch" => ?watch);
+    ) -> impl Future<Item = (Self, Option<(Vec<String>, Stat)>), Error = failure::Error> {
+        trace!(self.logger, "get_children2"; "path" => path, "watch" => ?watch);
         self.connection
-            .enqueue(proto::Request::GetData {
+            .enqueue(proto::Request::GetChildren2 {
                 path: path.to_string(),
                 watch,
             })
-            .and_then(transform::get_data)
+            .and_then(transform::get_children2)
             .map(move |r| (self, r))
     }
 
-    /// Return the data and the [`Stat`] of the node at the given `path`, or `None` if it does not
-    /// exist.
-    pub fn get_data(
-        self,
-        path: &str,
-    ) -> impl Future<Item = (Self, Option<(Vec<u8>, Stat)>), Error = failure::Error> {
-        self.get_data_w(path, Watch::None)
-    }
-
-    /// Start building a multi request. Multi requests batch several operations
-    /// into one atomic unit.
-    pub fn multi(self) -> MultiBuilder {
-        MultiBuilder {
-            zk: self,
-            requests: Vec::new(),
-        }
-    }
-}
-
-/// Proxy for [`ZooKeeper`] that adds watches for initiated operations.
-///
-/// Triggered watches produce events on the global watcher stream.
-#[derive(Debug, Clone)]
-pub struct WatchGlobally(ZooKeeper);
-
-impl WatchGlobally {
-    /// Return the [`Stat`] of the node of the given `path`, or `None` if the node does not exist.
+    /// Return the names of the children of the node at the given `path` along with the node's
+    /// [`Stat`], or `None` if the node does not exist.
     ///
-    /// If no errors occur, a watch is left on the node at the given `path`. The watch is triggered
-    /// by any successful operation that creates or deletes the node, or sets the node's data. When
-    /// the watch triggers, an event is sent to the global watcher stream.
-    pub fn exists(
+    /// The returned `Stat` can be used to detect concurrent modification of the set of children
+    /// (through its `cversion`) without a second round-trip, and to tell whether the children
+    /// changed since an earlier read without comparing the lists (see
+    /// [`Stat::children_changed_since`]).
+    ///
+    /// The returned list of children is not sorted and no guarantee is provided as to its natural
+    /// or lexical order.
+    pub fn get_children_with_stat(
         self,
         path: &str,
-    ) -> impl Future<Item = (ZooKeeper, Option<Stat>), Error = failure::Error> {
-        self.0.exists_w(path, Watch::Global)
+    ) -> impl Future<Item = (Self, Option<(Vec<String>, Stat)>), Error = failure::Error> {
+        self.get_children2_w(path, Watch::None)
     }
 
-    /// Return the names of the children of the node at the given `path`, or `None` if the node
-    /// does not exist.
-    ///
-    /// The returned list of children is not sorted and no guarantee is provided as to its natural
-    /// or lexical order.
-    ///
-    /// If no errors occur, a watch is left on the node at the given `path`. The watch is triggered
-    /// by any successful operation that deletes the node at the given `path`, or creates or
-    /// deletes a child of that node. When the watch triggers, an event is sent to the global
-    /// watcher stream.
-    pub fn get_children(
+    fn get_data_w(
         self,
         path: &str,
-    ) -> impl Future<Item = (ZooKeeper, Option<Vec<String>>), Error = failure::Error> {
-        self.0.get_children_w(path, Watch::Global)
+        watch: Watch,
+    ) -> impl Future<Item = (Self, Option<(Vec<u8>, Stat)>), Error = failure::Error> {
+        trace!(self.logger, "get_data"; "path" => path, "watch" => ?watch);
+        if let Err(e) = path::validate(path, false) {
+            return future::Either::A(future::err(e.into()));
+        }
+        let path = path.to_string();
+        future::Either::B(
+            self.connection
+                .enqueue(proto::Request::GetData {
+                    path: path.clone(),
+                    watch,
+                })
+                .and_then(move |r| transform::get_data(&path, r))
+                .map(move |r| (self, r)),
+        )
     }
 
     /// Return the data and the [`Stat`] of the node at the given `path`, or `None` if it does not
     /// exist.
     ///
-    /// If no errors occur, a watch is left on the node at the given `path`. The watch is triggered
-    /// by any successful operation that sets the node's data, or deletes it. When the watch
-    /// triggers, an event is sent to the global watcher stream.
+    /// If the node's ACL does not allow the client to read it, the returned future fails with an
+    /// error whose cause is [`error::GetData::NoAuth`].
+    ///
+    /// To also be notified of the next change to the node, use [`WithWatcher::get_data`] through
+    /// [`ZooKeeper::with_watcher`] instead.
     pub fn get_data(
         self,
         path: &str,
-    ) -> impl Future<Item = (ZooKeeper, Option<(Vec<u8>, Stat)>), Error = failure::Error> {
-        self.0.get_data_w(path, Watch::Global)
+    ) -> impl Future<Item = (Self, Option<(Vec<u8>, Stat)>), Error = failure::Error> {
+        self.get_data_w(path, Watch::None)
     }
-}
-
-/// Proxy for [`ZooKeeper`] that adds non-global watches for initiated operations.
-///
-/// Events from triggered watches are yielded through returned `oneshot` channels. All events are
-/// also produced on the global watcher stream.
-#[derive(Debug, Clone)]
-pub struct WithWatcher(ZooKeeper);
 
-impl WithWatcher {
-    /// Return the [`Stat`] of the node of the given `path`, or `None` if the node does not exist.
+    /// Return the data of the node at the given `path` as a stream of chunks that ends with its
+    /// [`Stat`], or `None` if it does not exist.
     ///
-    /// If no errors occur, a watch will be left on the node at the given `path`. The watch is
-    /// triggered by any successful operation that creates or deletes the node, or sets the data on
-    /// the node, and in turn causes the included `oneshot::Receiver` to resolve.
-    pub fn exists(
+    /// This suits nodes with large data, which can be processed piece by piece this way. The
+    /// response is still read from the server all at once, but unlike with
+    /// [`ZooKeeper::get_data`], the data is not copied out of it: the chunks are slices of the
+    /// buffer the response was read into, which is freed once all of them have been dropped. The
+    /// last item of the stream is always a [`DataChunk::Stat`].
+    pub fn get_data_streaming(
         self,
         path: &str,
     ) -> impl Future<
-        Item = (ZooKeeper, oneshot::Receiver<WatchedEvent>, Option<Stat>),
+        Item = (
+            Self,
+            Option<impl Stream<Item = DataChunk, Error = failure::Error>>,
+        ),
         Error = failure::Error,
     > {
-        let (tx, rx) = oneshot::channel();
-        self.0
-            .exists_w(path, Watch::Custom(tx))
-            .map(|r| (r.0, rx, r.1))
+        trace!(self.logger, "get_data_streaming"; "path" => path);
+        if let Err(e) = path::validate(path, false) {
+            return future::Either::A(future::err(e.into()));
+        }
+        let path = path.to_string();
+        future::Either::B(
+            self.connection
+                .enqueue(proto::Request::GetDataShared { path: path.clone() })
+                .and_then(move |r| transform::get_data_shared(&path, r))
+                .map(move |r| {
+                    let chunks =
+                        r.map(|(data, stat)| stream::iter_ok(DataChunk::split(data, stat)));
+                    (self, chunks)
+                }),
+        )
     }
 
-    /// Return the names of the children of the node at the given `path`, or `None` if the node
-    /// does not exist.
+    /// Return the configuration of the ensemble along with the [`Stat`] of the node it is stored
+    /// in, or `None` if the server does not support dynamic reconfiguration.
     ///
-    /// The returned list of children is not sorted and no guarantee is provided as to its natural
-    /// or lexical order.
+    /// The configuration is read from `/zookeeper/config`, regardless of any chroot. It requires
+    /// ZooKeeper 3.5 or newer.
+    pub fn get_config(
+        self,
+    ) -> impl Future<Item = (Self, Option<(EnsembleConfig, Stat)>), Error = failure::Error> {
+        trace!(self.logger, "get_config");
+        self.connection
+            .enqueue(proto::Request::GetConfig)
+            .and_then(transform::get_config)
+            .map(move |r| (self, r))
+    }
+
+    /// Change the membership of the ensemble, and return the new configuration along with the
+    /// [`Stat`] of the node it is stored in.
+    ///
+    /// The change is only made if the current configuration has the version `from_config` (if
+    /// `from_config` is `None`, any version matches). Otherwise, the returned future resolves
+    /// with [`error::Reconfig::BadVersion`]. The version of the current configuration is available
+    /// through [`ZooKeeper::get_config`].
+    ///
+    /// The server only accepts reconfigurations from suitably authenticated clients, and only if
+    /// dynamic reconfiguration is enabled. It requires ZooKeeper 3.5 or newer.
+    pub fn reconfig(
+        self,
+        change: &Reconfig,
+        from_config: Option<i64>,
+    ) -> impl Future<
+        Item = (Self, Result<(EnsembleConfig, Stat), error::Reconfig>),
+        Error = failure::Error,
+    > {
+        trace!(self.logger, "reconfig"; "change" => ?change, "from_config" => ?from_config);
+        let from_config = from_config.unwrap_or(-1);
+        self.connection
+            .enqueue(proto::Request::reconfig(change, from_config))
+            .and_then(move |r| transform::reconfig(from_config, r))
+            .map(move |r| (self, r))
+    }
+
+    /// Flush the channel between the server this client is connected to and the leader for the
+    /// node at the given `path`.
+    ///
+    /// ZooKeeper does not guarantee that every server has an up-to-date view of the tree at all
+    /// times. Once the returned future resolves, reads issued after it are guaranteed to observe
+    /// all writes that were committed before the sync started. The future resolves with the path
+    /// as echoed back by the server, which may differ from the given `path` if the server
+    /// translated it (for example under a chroot).
+    pub fn sync(self, path: &str) -> impl Future<Item = (Self, String), Error = failure::Error> {
+        trace!(self.logger, "sync"; "path" => path);
+        self.connection
+            .enqueue(proto::Request::Sync {
+                path: path.to_string(),
+            })
+            .and_then(transform::sync)
+            .map(move |r| (self, r))
+    }
+
+    /// Make sure that reads issued after the returned future resolves observe the write that was
+    /// assigned `zxid`, such as one made by another client.
+    ///
+    /// If the client has already seen `zxid` (see [`ZooKeeper::last_zxid`]), the future resolves
+    /// right away without contacting the server. Otherwise, the client issues a
+    /// [`ZooKeeper::sync`] for `path`, and the future fails if the client has still not seen
+    /// `zxid` once the sync has completed, which means that no such write has been committed.
+    pub fn sync_to(
+        self,
+        path: &str,
+        zxid: i64,
+    ) -> impl Future<Item = Self, Error = failure::Error> {
+        trace!(self.logger, "sync_to"; "path" => path, "zxid" => zxid);
+        if self.last_zxid() >= zxid {
+            return future::Either::A(future::ok(self));
+        }
+        future::Either::B(self.sync(path).and_then(move |(zk, _)| {
+            let last_zxid = zk.last_zxid();
+            if last_zxid < zxid {
+                bail!(
+                    "zxid {} not seen after sync (last seen {})",
+                    zxid,
+                    last_zxid
+                );
+            }
+            Ok(zk)
+        }))
+    }
+
+    /// Return the paths of all ephemeral nodes owned by this client's session whose path starts
+    /// with `prefix`.
+    ///
+    /// If `prefix` is empty (or `/`), every ephemeral node owned by the session is returned. This
+    /// requires ZooKeeper 3.6 or newer.
+    pub fn get_ephemerals(
+        self,
+        prefix: &str,
+    ) -> impl Future<Item = (Self, Vec<String>), Error = failure::Error> {
+        trace!(self.logger, "get_ephemerals"; "prefix" => prefix);
+        self.connection
+            .enqueue(proto::Request::GetEphemerals {
+                prefix: prefix.to_string(),
+            })
+            .and_then(transform::get_ephemerals)
+            .map(move |r| (self, r))
+    }
+
+    /// Return the number of descendants (children, grandchildren, and so on) of the node at the
+    /// given `path`, or `None` if the node does not exist.
+    ///
+    /// This is much cheaper than walking the tree with `get_children` when only the size of a
+    /// subtree is needed. It requires ZooKeeper 3.6 or newer.
+    pub fn get_all_children_number(
+        self,
+        path: &str,
+    ) -> impl Future<Item = (Self, Option<i32>), Error = failure::Error> {
+        trace!(self.logger, "get_all_children_number"; "path" => path);
+        self.connection
+            .enqueue(proto::Request::GetAllChildrenNumber {
+                path: path.to_string(),
+            })
+            .and_then(transform::get_all_children_number)
+            .map(move |r| (self, r))
+    }
+
+    /// Return the `(scheme, id)` pairs this client's connection is authenticated as.
+    ///
+    /// This is mostly useful for debugging ACL problems. It requires ZooKeeper 3.7 or newer.
+    pub fn who_am_i(
+        self,
+    ) -> impl Future<Item = (Self, Vec<(String, String)>), Error = failure::Error> {
+        trace!(self.logger, "who_am_i");
+        self.connection
+            .enqueue(proto::Request::WhoAmI)
+            .and_then(transform::who_am_i)
+            .map(move |r| (self, r))
+    }
+
+    /// Add the given authentication credentials to this client's connection.
+    ///
+    /// The `auth` bytes are interpreted according to `scheme`. For the `digest` scheme, they are
+    /// `user:password`, and nodes can be protected with an [`Acl`] whose id is
+    /// [`Acl::digest_id`]`(user, password)`. Credentials apply to the connection as a whole, and
+    /// all successfully added credentials are used when checking ACLs. When the client re-connects
+    /// to another server, it adds them again, in the order they were added.
+    ///
+    /// Note that the server closes the connection if authentication fails.
+    pub fn add_auth<A>(
+        self,
+        scheme: &str,
+        auth: A,
+    ) -> impl Future<Item = (Self, Result<(), error::Auth>), Error = failure::Error>
+    where
+        A: Into<Cow<'static, [u8]>>,
+    {
+        trace!(self.logger, "add_auth"; "scheme" => scheme);
+        self.connection
+            .enqueue(proto::Request::Auth {
+                scheme: scheme.to_string(),
+                auth: auth.into(),
+            })
+            .and_then(transform::add_auth)
+            .map(move |r| (self, r))
+    }
+
+    /// Set a watch on the given `path` that stays in place after it has been triggered.
+    ///
+    /// Events for the watch are sent to the global watcher stream. With
+    /// [`AddWatchMode::PersistentRecursive`], events are also sent for nodes below `path`, and
+    /// the event's `path` is that of the node that changed. The node at `path` does not need to
+    /// exist. This requires ZooKeeper 3.6 or newer.
+    pub fn add_watch(
+        self,
+        path: &str,
+        mode: AddWatchMode,
+    ) -> impl Future<Item = Self, Error = failure::Error> {
+        trace!(self.logger, "add_watch"; "path" => path, "mode" => ?mode);
+        self.connection
+            .enqueue(proto::Request::AddWatch {
+                path: path.to_string(),
+                mode,
+            })
+            .and_then(transform::add_watch)
+            .map(move |()| self)
+    }
+
+    /// Remove the watches of the given `watcher_type` on the node at the given `path`.
+    ///
+    /// Watches set with [`ZooKeeper::with_watcher`] that are removed resolve with a
+    /// [`WatchedEventType::DataWatchRemoved`] or [`WatchedEventType::ChildWatchRemoved`] event.
+    /// If no matching watch exists, the returned future resolves to
+    /// [`error::RemoveWatches::NoWatcher`].
+    ///
+    /// If `local` is `true`, the server is not contacted, and only the watches set with
+    /// [`ZooKeeper::with_watcher`] are removed. The server-side watch stays in place, and when it
+    /// triggers, its event is only sent to the global watcher stream. This requires ZooKeeper 3.5
+    /// or newer unless `local` is `true`.
+    pub fn remove_watches(
+        self,
+        path: &str,
+        watcher_type: WatcherType,
+        local: bool,
+    ) -> impl Future<Item = (Self, Result<(), error::RemoveWatches>), Error = failure::Error> {
+        trace!(self.logger, "remove_watches"; "path" => path, "type" => ?watcher_type, "local" => local);
+        self.connection
+            .enqueue(proto::Request::RemoveWatches {
+                path: path.to_string(),
+                watcher_type,
+                local,
+            })
+            .and_then(transform::remove_watches)
+            .map(move |r| (self, r))
+    }
+
+    /// Send a request with the given `opcode` and serialized `body`, and return the body of the
+    /// server's response as it is.
+    ///
+    /// This is an escape hatch for operations this client does not model yet. The error code in
+    /// the reply header is still interpreted, so a failed operation resolves to the [`ZkError`]
+    /// the server responded with. The client knows nothing about the operation, so it does not
+    /// move paths in `body` below the chroot, does not hold the request back while the
+    /// connection is being authenticated, and does not refuse it on a read-only connection.
+    /// Watches cannot be left this way.
+    pub fn raw_request(
+        self,
+        opcode: i32,
+        body: Vec<u8>,
+    ) -> impl Future<Item = (Self, Result<Vec<u8>, ZkError>), Error = failure::Error> {
+        trace!(self.logger, "raw_request"; "opcode" => opcode, "blen" => body.len());
+        self.connection
+            .enqueue(proto::Request::Raw { opcode, body })
+            .and_then(transform::raw)
+            .map(move |r| (self, r))
+    }
+
+    /// Start building a multi request. Multi requests batch several operations
+    /// into one atomic unit.
+    pub fn multi(self) -> MultiBuilder {
+        MultiBuilder {
+            zk: self,
+            requests: Vec::new(),
+        }
+    }
+}
+
+/// Proxy for [`ZooKeeper`] that adds watches for initiated operations.
+///
+/// Triggered watches produce events on the global watcher stream.
+#[derive(Debug, Clone)]
+pub struct WatchGlobally(ZooKeeper);
+
+impl WatchGlobally {
+    /// Return the [`Stat`] of the node of the given `path`, or `None` if the node does not exist.
+    ///
+    /// If no errors occur, a watch is left on the node at the given `path`. The watch is triggered
+    /// by any successful operation that creates or deletes the node, or sets the node's data. When
+    /// the watch triggers, an event is sent to the global watcher stream.
+    pub fn exists(
+        self,
+        path: &str,
+    ) -> impl Future<Item = (ZooKeeper, Option<Stat>), Error = failure::Error> {
+        self.0.exists_w(path, Watch::Global)
+    }
+
+    /// Return the names of the children of the node at the given `path`, or `None` if the node
+    /// does not exist.
+    ///
+    /// The returned list of children is not sorted and no guarantee is provided as to its natural
+    /// or lexical order.
+    ///
+    /// If no errors occur, a watch is left on the node at the given `path`. The watch is triggered
+    /// by any successful operation that deletes the node at the given `path`, or creates or
+    /// deletes a child of that node. When the watch triggers, an event is sent to the global
+    /// watcher stream.
+    pub fn get_children(
+        self,
+        path: &str,
+    ) -> impl Future<Item = (ZooKeeper, Option<Vec<String>>), Error = failure::Error> {
+        self.0.get_children_w(path, Watch::Global)
+    }
+
+    /// Return the names of the children of the node at the given `path` along with the node's
+    /// [`Stat`], or `None` if the node does not exist.
+    ///
+    /// The returned list of children is not sorted and no guarantee is provided as to its natural
+    /// or lexical order. See [`Stat::children_changed_since`] for how to tell whether the
+    /// children changed since an earlier read without comparing the lists.
+    ///
+    /// If no errors occur, a watch is left on the node at the given `path`. The watch is triggered
+    /// by any successful operation that deletes the node at the given `path`, or creates or
+    /// deletes a child of that node. When the watch triggers, an event is sent to the global
+    /// watcher stream.
+    pub fn get_children_with_stat(
+        self,
+        path: &str,
+    ) -> impl Future<Item = (ZooKeeper, Option<(Vec<String>, Stat)>), Error = failure::Error> {
+        self.0.get_children2_w(path, Watch::Global)
+    }
+
+    /// Return the data and the [`Stat`] of the node at the given `path`, or `None` if it does not
+    /// exist.
+    ///
+    /// If no errors occur, a watch is left on the node at the given `path`. The watch is triggered
+    /// by any successful operation that sets the node's data, or deletes it. When the watch
+    /// triggers, an event is sent to the global watcher stream.
+    pub fn get_data(
+        self,
+        path: &str,
+    ) -> impl Future<Item = (ZooKeeper, Option<(Vec<u8>, Stat)>), Error = failure::Error> {
+        self.0.get_data_w(path, Watch::Global)
+    }
+}
+
+/// Proxy for [`ZooKeeper`] that adds non-global watches for initiated operations.
+///
+/// Events from triggered watches are yielded through returned `oneshot` channels. All events are
+/// also produced on the global watcher stream.
+#[derive(Debug, Clone)]
+pub struct WithWatcher(ZooKeeper);
+
+/// What a read through [`WithWatcher`] resolves to: the client, and if the node exists, the
+/// receiver for the watch event along with what was read.
+pub type Watched<T> = (ZooKeeper, Option<T>);
+
+impl WithWatcher {
+    /// Return the [`Stat`] of the node of the given `path`, or `None` if the node does not exist.
+    ///
+    /// If no errors occur, a watch will be left on the node at the given `path`. The watch is
+    /// triggered by any successful operation that creates or deletes the node, or sets the data on
+    /// the node, and in turn causes the included `oneshot::Receiver` to resolve.
+    pub fn exists(
+        self,
+        path: &str,
+    ) -> impl Future<
+        Item = (ZooKeeper, oneshot::Receiver<WatchedEvent>, Option<Stat>),
+        Error = failure::Error,
+    > {
+        let (tx, rx) = oneshot::channel();
+        self.0
+            .exists_w(path, Watch::Custom(tx))
+            .map(|r| (r.0, rx, r.1))
+    }
+
+    /// Return the names of the children of the node at the given `path`, or `None` if the node
+    /// does not exist.
+    ///
+    /// The returned list of children is not sorted and no guarantee is provided as to its natural
+    /// or lexical order.
     ///
     /// If no errors occur, a watch is left on the node at the given `path`. The watch is triggered
     /// by any successful operation that deletes the node at the given `path`, or creates or
@@ -731,10 +2345,7 @@ impl WithWatcher {
         self,
         path: &str,
     ) -> impl Future<
-        Item = (
-            ZooKeeper,
-            Option<(oneshot::Receiver<WatchedEvent>, Vec<String>)>,
-        ),
+        Item = Watched<(oneshot::Receiver<WatchedEvent>, Vec<String>)>,
         Error = failure::Error,
     > {
         let (tx, rx) = oneshot::channel();
@@ -743,20 +2354,45 @@ impl WithWatcher {
             .map(|r| (r.0, r.1.map(move |c| (rx, c))))
     }
 
+    /// Return the names of the children of the node at the given `path` along with the node's
+    /// [`Stat`], or `None` if the node does not exist.
+    ///
+    /// The returned list of children is not sorted and no guarantee is provided as to its natural
+    /// or lexical order. See [`Stat::children_changed_since`] for how to tell whether the
+    /// children changed since an earlier read without comparing the lists.
+    ///
+    /// If no errors occur, a watch is left on the node at the given `path`. The watch is triggered
+    /// by any successful operation that deletes the node at the given `path`, or creates or
+    /// deletes a child of that node, and in turn causes the included `oneshot::Receiver` to
+    /// resolve.
+    pub fn get_children_with_stat(
+        self,
+        path: &str,
+    ) -> impl Future<
+        Item = Watched<(oneshot::Receiver<WatchedEvent>, Vec<String>, Stat)>,
+        Error = failure::Error,
+    > {
+        let (tx, rx) = oneshot::channel();
+        self.0
+            .get_children2_w(path, Watch::Custom(tx))
+            .map(|r| (r.0, r.1.map(move |(c, stat)| (rx, c, stat))))
+    }
+
     /// Return the data and the [`Stat`] of the node at the given `path`, or `None` if it does not
     /// exist.
     ///
     /// If no errors occur, a watch is left on the node at the given `path`. The watch is triggered
     /// by any successful operation that sets the node's data, or deletes it, and in turn causes
     /// the included `oneshot::Receiver` to resolve.
+    ///
+    /// The data is read and the watch is left by the same request, so no change to the node can
+    /// go unnoticed between the two. This makes it a good fit for keeping a cached copy of the
+    /// node up to date.
     pub fn get_data(
         self,
         path: &str,
     ) -> impl Future<
-        Item = (
-            ZooKeeper,
-            Option<(oneshot::Receiver<WatchedEvent>, Vec<u8>, Stat)>,
-        ),
+        Item = Watched<(oneshot::Receiver<WatchedEvent>, Vec<u8>, Stat)>,
         Error = failure::Error,
     > {
         let (tx, rx) = oneshot::channel();
@@ -767,6 +2403,9 @@ impl WithWatcher {
 }
 
 /// Proxy for [`ZooKeeper`] that batches operations into an atomic "multi" request.
+///
+/// Nothing is sent to the server until [`MultiBuilder::run`] is called. A builder with operations
+/// attached that is dropped without being run logs so at the debug level.
 #[derive(Debug)]
 pub struct MultiBuilder {
     zk: ZooKeeper,
@@ -829,46 +2468,228 @@ impl MultiBuilder {
         self
     }
 
+    /// Attach a get data operation to this multi request.
+    ///
+    /// Reads cannot be mixed with writes or checks in the same multi request. A batch of reads is
+    /// not atomic; each read observes the tree independently, and a missing node does not fail
+    /// the other reads.
+    ///
+    /// See [`ZooKeeper::get_data`] for details.
+    pub fn get_data(mut self, path: &str) -> Self {
+        self.requests.push(proto::Request::GetData {
+            path: path.to_string(),
+            watch: Watch::None,
+        });
+        self
+    }
+
+    /// Attach a get children operation to this multi request.
+    ///
+    /// The same restrictions as for [`MultiBuilder::get_data`] apply.
+    ///
+    /// See [`ZooKeeper::get_children`] for details.
+    pub fn get_children(mut self, path: &str) -> Self {
+        self.requests.push(proto::Request::GetChildren {
+            path: path.to_string(),
+            watch: Watch::None,
+        });
+        self
+    }
+
+    /// Check the attached operations the way the client checks them when they are not part of a
+    /// multi request, without contacting the server.
+    ///
+    /// Fails with the error of the first operation whose path, data, or ACL is invalid (with the
+    /// index of the operation as context), or if reads and writes were attached to the same
+    /// request. The server may still refuse a request that passes this check, for example because
+    /// a node does not exist.
+    pub fn dry_run(&self) -> Result<(), failure::Error> {
+        for (i, request) in self.requests.iter().enumerate() {
+            let valid = match *request {
+                proto::Request::Create {
+                    ref path,
+                    ref data,
+                    ref acl,
+                    mode,
+                } => self
+                    .zk
+                    .validate_write_path(path, mode.is_sequential())
+                    .and_then(|()| self.zk.validate_data(data))
+                    .and_then(|()| Ok(validate_acl(acl)?)),
+                proto::Request::SetData {
+                    ref path, ref data, ..
+                } => path::validate(path, false)
+                    .map_err(failure::Error::from)
+                    .and_then(|()| self.zk.validate_data(data)),
+                proto::Request::Delete { ref path, .. } => self.zk.validate_write_path(path, false),
+                proto::Request::Check { ref path, .. }
+                | proto::Request::GetData { ref path, .. }
+                | proto::Request::GetChildren { ref path, .. } => {
+                    path::validate(path, false).map_err(failure::Error::from)
+                }
+                _ => unreachable!("{:?} cannot be attached to a multi request", request),
+            };
+            if let Err(e) = valid {
+                return Err(e
+                    .context(format!("operation {} of multi request is invalid", i))
+                    .into());
+            }
+        }
+        check_multi_kinds(&self.markers())
+    }
+
+    /// The markers of the attached operations, which their responses are matched up with.
+    fn markers(&self) -> Vec<transform::RequestMarker> {
+        self.requests.iter().map(|r| r.into()).collect()
+    }
+
     /// Run executes the attached requests in one atomic unit.
+    ///
+    /// Fails without contacting the server if reads and writes were attached to the same request
+    /// (see also [`MultiBuilder::dry_run`]). Use [`MultiResponse::all`] to find out whether the
+    /// request as a whole succeeded.
     pub fn run(
-        self,
+        mut self,
     ) -> impl Future<Item = (ZooKeeper, Vec<Result<MultiResponse, error::Multi>>), Error = failure::Error>
     {
-        let (zk, requests) = (self.zk, self.requests);
-        let reqs_lite: Vec<transform::RequestMarker> = requests.iter().map(|r| r.into()).collect();
-        zk.connection
-            .enqueue(proto::Request::Multi(requests))
-            .and_then(move |r| match r {
-                Ok(proto::Response::Multi(responses)) => reqs_lite
-                    .iter()
-                    .zip(responses)
-                    .map(|(req, res)| transform::multi(req, res))
-                    .collect(),
-                Ok(r) => bail!("got non-multi response to multi: {:?}", r),
-                Err(e) => Err(format_err!("multi call failed: {:?}", e)),
-            })
-            .map(move |r| (zk, r))
+        let reqs_lite = self.markers();
+        // taking the requests marks the builder as run for `Drop`
+        let zk = self.zk.clone();
+        let requests = mem::take(&mut self.requests);
+        if let Err(e) = check_multi_kinds(&reqs_lite) {
+            return future::Either::A(future::err(e));
+        }
+        future::Either::B(
+            zk.connection
+                .enqueue(proto::Request::Multi(requests))
+                .and_then(move |r| match r {
+                    Ok(proto::Response::Multi(responses)) => reqs_lite
+                        .iter()
+                        .zip(responses)
+                        .map(|(req, res)| transform::multi(req, res))
+                        .collect(),
+                    Ok(r) => bail!("got non-multi response to multi: {:?}", r),
+                    Err(e) => Err(e.context("multi call failed").into()),
+                })
+                .map(move |r| (zk, r)),
+        )
+    }
+}
+
+impl Drop for MultiBuilder {
+    fn drop(&mut self) {
+        if !self.requests.is_empty() {
+            debug!(self.zk.logger, "multi request dropped without being run";
+                   "ops" => self.requests.len());
+        }
+    }
+}
+
+/// Check that the operations of a multi request are either all reads or all writes.
+fn check_multi_kinds(markers: &[transform::RequestMarker]) -> Result<(), failure::Error> {
+    let reads = markers.iter().filter(|r| r.is_read()).count();
+    if reads != 0 && reads != markers.len() {
+        bail!("multi request cannot mix reads and writes");
     }
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
     use slog::Drain;
+    use std::io::{Read, Write};
+    use std::{net, thread};
 
-    #[test]
-    fn it_works() {
-        let mut rt = tokio::runtime::Runtime::new().unwrap();
-        let mut builder = ZooKeeperBuilder::default();
-        let decorator = slog_term::TermDecorator::new().build();
-        let drain = slog_term::FullFormat::new(decorator).build().fuse();
-        let drain = slog_async::Async::new(drain).build().fuse();
-        builder.set_logger(slog::Logger::root(drain, o!()));
+    /// Spawn a scripted server on a local port, and return its address.
+    ///
+    /// `f` is handed the first (and only) connection made to the server.
+    fn fake_server<F>(f: F) -> SocketAddr
+    where
+        F: FnOnce(net::TcpStream) + Send + 'static,
+    {
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            f(stream)
+        });
+        addr
+    }
 
-        let (zk, w): (ZooKeeper, _) =
-            rt.block_on(
-                builder
+    /// Read a single length-prefixed frame sent by the client.
+    fn read_frame(stream: &mut net::TcpStream) -> Vec<u8> {
+        let len = stream.read_i32::<BigEndian>().unwrap();
+        let mut buf = vec![0; len as usize];
+        stream.read_exact(&mut buf).unwrap();
+        buf
+    }
+
+    /// Write `body` as a single length-prefixed frame.
+    fn write_frame(stream: &mut net::TcpStream, body: &[u8]) {
+        stream.write_i32::<BigEndian>(body.len() as i32).unwrap();
+        stream.write_all(body).unwrap();
+    }
+
+    /// Perform the server side of the connection handshake.
+    fn accept_session(stream: &mut net::TcpStream, session_id: i64) {
+        let _ = read_frame(stream);
+        write_frame(stream, &connect_response(30_000, session_id, &[0; 16]));
+    }
+
+    /// Read a request frame, returning its xid, opcode, and body.
+    fn read_request(stream: &mut net::TcpStream) -> (i32, i32, Vec<u8>) {
+        let frame = read_frame(stream);
+        let mut header = &frame[..8];
+        let xid = header.read_i32::<BigEndian>().unwrap();
+        let opcode = header.read_i32::<BigEndian>().unwrap();
+        (xid, opcode, frame[8..].to_vec())
+    }
+
+    /// Write a reply frame with the given header fields and body.
+    fn write_reply(stream: &mut net::TcpStream, xid: i32, zxid: i64, err: i32, body: &[u8]) {
+        let mut frame = Vec::new();
+        frame.write_i32::<BigEndian>(xid).unwrap();
+        frame.write_i64::<BigEndian>(zxid).unwrap();
+        frame.write_i32::<BigEndian>(err).unwrap();
+        frame.extend(body);
+        write_frame(stream, &frame);
+    }
+
+    /// Serialize a string the way ZooKeeper does.
+    fn jute_string(s: &str) -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.write_i32::<BigEndian>(s.len() as i32).unwrap();
+        buf.extend(s.as_bytes());
+        buf
+    }
+
+    /// Serialize a connect response.
+    fn connect_response(timeout: i32, session_id: i64, password: &[u8]) -> Vec<u8> {
+        let mut body = Vec::new();
+        body.write_i32::<BigEndian>(0).unwrap();
+        body.write_i32::<BigEndian>(timeout).unwrap();
+        body.write_i64::<BigEndian>(session_id).unwrap();
+        body.write_i32::<BigEndian>(password.len() as i32).unwrap();
+        body.extend(password);
+        body.push(0);
+        body
+    }
+
+    #[test]
+    fn it_works() {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let mut builder = ZooKeeperBuilder::default();
+        let decorator = slog_term::TermDecorator::new().build();
+        let drain = slog_term::FullFormat::new(decorator).build().fuse();
+        let drain = slog_async::Async::new(drain).build().fuse();
+        builder.set_logger(slog::Logger::root(drain, o!()));
+
+        let (zk, w): (ZooKeeper, _) =
+            rt.block_on(
+                builder
                     .connect(&"127.0.0.1:2181".parse().unwrap())
                     .and_then(|(zk, w)| {
                         zk.with_watcher()
@@ -903,6 +2724,7 @@ mod tests {
                                         event_type: WatchedEventType::NodeCreated,
                                         keeper_state: KeeperState::SyncConnected,
                                         path: String::from("/foo"),
+                                        zxid: None,
                                     }
                                 );
                             })
@@ -926,7 +2748,10 @@ mod tests {
                                 zk.set_data("/foo", Some(res.unwrap().1.version), &b"Bye world"[..])
                             })
                             .inspect(|(_, stat)| {
-                                assert_eq!(stat.unwrap().data_length as usize, "Bye world".len());
+                                assert_eq!(
+                                    stat.as_ref().unwrap().data_length as usize,
+                                    "Bye world".len()
+                                );
                             })
                             .and_then(|(zk, _)| zk.get_data("/foo"))
                             .inspect(|(_, res)| {
@@ -981,6 +2806,7 @@ mod tests {
                                         event_type: WatchedEventType::NodeCreated,
                                         keeper_state: KeeperState::SyncConnected,
                                         path: String::from("/foo"),
+                                        zxid: None,
                                     })
                                 );
                             })
@@ -996,6 +2822,7 @@ mod tests {
                                         event_type: WatchedEventType::NodeDataChanged,
                                         keeper_state: KeeperState::SyncConnected,
                                         path: String::from("/foo"),
+                                        zxid: None,
                                     })
                                 );
 
@@ -1010,6 +2837,7 @@ mod tests {
                                         event_type: WatchedEventType::NodeDeleted,
                                         keeper_state: KeeperState::SyncConnected,
                                         path: String::from("/foo"),
+                                        zxid: None,
                                     })
                                 );
                             })
@@ -1071,7 +2899,10 @@ mod tests {
                             zk.set_data("/example", Some(res.unwrap().1.version), &b"Bye world"[..])
                         })
                         .inspect(|(_, stat)| {
-                            assert_eq!(stat.unwrap().data_length as usize, "Bye world".len());
+                            assert_eq!(
+                                stat.as_ref().unwrap().data_length as usize,
+                                "Bye world".len()
+                            );
                         })
                         .and_then(|(zk, _)| {
                             // create a child of /example
@@ -1124,6 +2955,7 @@ mod tests {
                                     event_type: WatchedEventType::NodeCreated,
                                     keeper_state: KeeperState::SyncConnected,
                                     path: String::from("/example"),
+                                    zxid: None,
                                 })
                             );
                         })
@@ -1171,7 +3003,7 @@ mod tests {
                             .and_then(|(zk, _)| zk.set_acl("/acl_test", Acl::read_unsafe(), None))
                             .inspect(|(_, stat)| {
                                 // successfully change node acl to `read_unsafe`
-                                assert_eq!(stat.unwrap().data_length as usize, b"foo".len())
+                                assert_eq!(stat.as_ref().unwrap().data_length as usize, b"foo".len())
                             })
                             .and_then(|(zk, _)| zk.get_acl("/acl_test"))
                             .inspect(|(_, res)| {
@@ -1181,12 +3013,24 @@ mod tests {
                             .and_then(|(zk, _)| zk.set_data("/acl_test", None, &b"bar"[..]))
                             .inspect(|(_, res)| {
                                 // cannot set data on a read only node
-                                assert_eq!(res, &Err(error::SetData::NoAuth))
+                                assert_eq!(
+                                    res,
+                                    &Err(error::SetData::NoAuth {
+                                        path: "/acl_test".to_string(),
+                                        required: Permission::WRITE,
+                                    })
+                                )
                             })
                             .and_then(|(zk, _)| zk.set_acl("/acl_test", Acl::open_unsafe(), None))
                             .inspect(|(_, res)| {
                                 // cannot change a read only node's acl
-                                assert_eq!(res, &Err(error::SetAcl::NoAuth))
+                                assert_eq!(
+                                    res,
+                                    &Err(error::SetAcl::NoAuth {
+                                        path: "/acl_test".to_string(),
+                                        required: Permission::ADMIN,
+                                    })
+                                )
                             })
                     }),
             ).unwrap();
@@ -1313,4 +3157,3019 @@ mod tests {
         drop(zk); // make Packetizer idle
         rt.shutdown_on_idle().wait().unwrap();
     }
+
+    #[test]
+    fn resume_session() {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let addr = "127.0.0.1:2181".parse().unwrap();
+        let (zk, _) = rt.block_on(ZooKeeper::connect(&addr)).unwrap();
+        let (zk, path) = rt
+            .block_on(zk.create(
+                "/resume_session",
+                &b""[..],
+                Acl::open_unsafe(),
+                CreateMode::Ephemeral,
+            ))
+            .unwrap();
+        assert_eq!(path.as_ref().map(String::as_str), Ok("/resume_session"));
+
+        let (zk2, _) = rt
+            .block_on(ZooKeeperBuilder::default().connect_with_session(
+                &addr,
+                zk.session_id(),
+                zk.password().to_vec(),
+                0,
+            ))
+            .unwrap();
+        assert_eq!(zk2.session_id(), zk.session_id());
+
+        // the ephemeral node is owned by the resumed session
+        let (zk2, stat) = rt.block_on(zk2.exists("/resume_session")).unwrap();
+        assert_eq!(stat.unwrap().ephemeral_owner, zk.session_id());
+
+        drop(zk);
+        let (zk2, res) = rt.block_on(zk2.delete("/resume_session", None)).unwrap();
+        assert_eq!(res, Ok(()));
+        drop(zk2);
+        rt.shutdown_on_idle().wait().unwrap();
+    }
+
+    #[test]
+    fn resume_expired_session() {
+        let addr = fake_server(|mut stream| {
+            let mut req = &read_frame(&mut stream)[..];
+            assert_eq!(req.read_i32::<BigEndian>().unwrap(), 0); // protocol version
+            assert_eq!(req.read_i64::<BigEndian>().unwrap(), 7); // last zxid
+            assert_eq!(req.read_i32::<BigEndian>().unwrap(), 0); // timeout
+            assert_eq!(req.read_i64::<BigEndian>().unwrap(), 42); // session id
+            assert_eq!(req.read_i32::<BigEndian>().unwrap(), 3); // password
+            // an expired session is signalled with a zero timeout
+            write_frame(&mut stream, &connect_response(0, 0, &[0; 16]));
+        });
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let err = rt
+            .block_on(ZooKeeperBuilder::default().connect_with_session(
+                &addr,
+                42,
+                vec![1, 2, 3],
+                7,
+            ))
+            .map(|_| ())
+            .unwrap_err();
+        assert_eq!(
+            err.find_root_cause().downcast_ref::<ZkError>(),
+            Some(&ZkError::SessionExpired)
+        );
+        rt.shutdown_on_idle().wait().unwrap();
+    }
+
+    #[test]
+    fn resume_session_from_connect_string() {
+        let addr = fake_server(|mut stream| {
+            let mut req = &read_frame(&mut stream)[..];
+            assert_eq!(req.read_i32::<BigEndian>().unwrap(), 0); // protocol version
+            assert_eq!(req.read_i64::<BigEndian>().unwrap(), 7); // last zxid
+            let _ = req.read_i32::<BigEndian>().unwrap(); // timeout
+            assert_eq!(req.read_i64::<BigEndian>().unwrap(), 42); // session id
+            assert_eq!(req.read_i32::<BigEndian>().unwrap(), 3);
+            assert_eq!(&req[..3], &[1, 2, 3]); // password
+            write_frame(&mut stream, &connect_response(30_000, 42, &[1, 2, 3]));
+            let _ = read_frame(&mut stream); // close session
+        });
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let mut builder = ZooKeeperBuilder::default();
+        builder.set_session(42, vec![1, 2, 3], 7);
+        let (zk, _) = rt
+            .block_on(builder.connect_string(&addr.to_string()))
+            .unwrap();
+        assert_eq!(zk.session_id(), 42);
+        assert_eq!(zk.password(), &[1, 2, 3]);
+        drop(zk);
+    }
+
+    #[test]
+    fn connect_to_non_zookeeper() {
+        let addr = fake_server(|mut stream| {
+            let _ = read_frame(&mut stream);
+            stream
+                .write_all(b"HTTP/1.1 400 Bad Request\r\nConnection: keep-alive\r\n\r\n")
+                .unwrap();
+            // keep the connection open so that a client waiting for more bytes would hang
+            thread::sleep(time::Duration::from_secs(5));
+        });
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let start = time::Instant::now();
+        let err = rt
+            .block_on(ZooKeeper::connect(&addr))
+            .map(|_| ())
+            .unwrap_err();
+        assert!(start.elapsed() < time::Duration::from_secs(1));
+        assert_eq!(
+            err.downcast_ref::<error::NotAZooKeeperServer>(),
+            Some(&error::NotAZooKeeperServer)
+        );
+    }
+
+    #[test]
+    fn connect_with_bad_protocol_version() {
+        let addr = fake_server(|mut stream| {
+            let _ = read_frame(&mut stream);
+            let mut response = connect_response(30_000, 1, &[0; 16]);
+            response[3] = 0x7f;
+            write_frame(&mut stream, &response);
+            thread::sleep(time::Duration::from_secs(5));
+        });
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let err = rt
+            .block_on(ZooKeeper::connect(&addr))
+            .map(|_| ())
+            .unwrap_err();
+        assert!(err.downcast_ref::<error::NotAZooKeeperServer>().is_some());
+    }
+
+    #[test]
+    fn owns() {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let addr = "127.0.0.1:2181".parse().unwrap();
+        let (zk, _) = rt.block_on(ZooKeeper::connect(&addr)).unwrap();
+        let (other, _) = rt.block_on(ZooKeeper::connect(&addr)).unwrap();
+        assert_ne!(zk.session_id(), other.session_id());
+
+        let (zk, owned) = rt.block_on(zk.owns("/owns_test")).unwrap();
+        assert!(!owned, "a missing node is not owned by anyone");
+
+        let (zk, _) = rt
+            .block_on(zk.create(
+                "/owns_test",
+                &b""[..],
+                Acl::open_unsafe(),
+                CreateMode::Ephemeral,
+            ))
+            .unwrap();
+        let (zk, owned) = rt.block_on(zk.owns("/owns_test")).unwrap();
+        assert!(owned);
+        let (other, owned) = rt.block_on(other.owns("/owns_test")).unwrap();
+        assert!(!owned);
+
+        drop((zk, other));
+        rt.shutdown_on_idle().wait().unwrap();
+    }
+
+    #[test]
+    fn sync_returns_echoed_path() {
+        let addr = fake_server(|mut stream| {
+            accept_session(&mut stream, 1);
+            let (xid, opcode, body) = read_request(&mut stream);
+            assert_eq!(opcode, 9);
+            assert_eq!(body, jute_string("/foo"));
+            // pretend the server translated the path
+            write_reply(&mut stream, xid, 1, 0, &jute_string("/app/foo"));
+            let _ = read_frame(&mut stream); // close session
+        });
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let (zk, _) = rt.block_on(ZooKeeper::connect(&addr)).unwrap();
+        let (zk, path) = rt.block_on(zk.sync("/foo")).unwrap();
+        assert_eq!(path, "/app/foo");
+        drop(zk);
+    }
+
+    #[test]
+    fn sync_to_zxid() {
+        let addr = fake_server(|mut stream| {
+            accept_session(&mut stream, 1);
+            let (xid, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, 3);
+            write_reply(&mut stream, xid, 5, 0, &[0; 68]);
+            // zxids up to 5 have been seen already, so only 7 and 10 need a sync
+            let (xid, opcode, body) = read_request(&mut stream);
+            assert_eq!(opcode, 9);
+            assert_eq!(body, jute_string("/"));
+            write_reply(&mut stream, xid, 8, 0, &jute_string("/"));
+            let (xid, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, 9);
+            write_reply(&mut stream, xid, 9, 0, &jute_string("/"));
+            let (_, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, -11);
+        });
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let (zk, _) = rt.block_on(ZooKeeper::connect(&addr)).unwrap();
+        let (zk, _) = rt.block_on(zk.exists("/a")).unwrap();
+        assert_eq!(zk.last_zxid(), 5);
+        let zk = rt.block_on(zk.sync_to("/", 3)).unwrap();
+        let zk = rt.block_on(zk.sync_to("/", 5)).unwrap();
+        let zk = rt.block_on(zk.sync_to("/", 7)).unwrap();
+        assert_eq!(zk.last_zxid(), 8);
+        // the sync did not catch up to a write that was never committed
+        let err = rt.block_on(zk.clone().sync_to("/", 10)).unwrap_err();
+        assert_eq!(err.to_string(), "zxid 10 not seen after sync (last seen 9)");
+        rt.block_on(zk.close()).unwrap();
+    }
+
+    #[test]
+    fn get_data_streaming() {
+        let data: Vec<u8> = (0..300 * 1024).map(|i| i as u8).collect();
+        let expected = data.clone();
+        let addr = fake_server(move |mut stream| {
+            accept_session(&mut stream, 1);
+            let (xid, opcode, body) = read_request(&mut stream);
+            assert_eq!(opcode, 4);
+            assert!(body.starts_with(&jute_string("/big")));
+            let mut reply = Vec::new();
+            reply.write_i32::<BigEndian>(data.len() as i32).unwrap();
+            reply.extend(&data);
+            reply.extend(&[0; 32][..]); // czxid, mzxid, ctime, mtime
+            reply.write_i32::<BigEndian>(3).unwrap(); // version
+            reply.extend(&[0; 16][..]); // cversion, aversion, ephemeral owner
+            reply.write_i32::<BigEndian>(data.len() as i32).unwrap();
+            reply.extend(&[0; 12][..]); // children, pzxid
+            write_reply(&mut stream, xid, 1, 0, &reply);
+            let (xid, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, 4);
+            write_reply(&mut stream, xid, 1, -101, &[]);
+            let (_, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, -11);
+        });
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let (zk, _) = rt.block_on(ZooKeeper::connect(&addr)).unwrap();
+        let (zk, chunks) = rt.block_on(zk.get_data_streaming("/big")).unwrap();
+        let mut chunks = rt.block_on(chunks.unwrap().collect()).unwrap();
+        assert!(chunks.len() > 2);
+        let stat = match chunks.pop() {
+            Some(DataChunk::Stat(stat)) => stat,
+            c => panic!("expected the stat last, got {:?}", c),
+        };
+        assert_eq!(stat.version, 3);
+        assert_eq!(stat.data_length as usize, expected.len());
+        let mut read = Vec::new();
+        for chunk in chunks {
+            match chunk {
+                DataChunk::Data(bytes) => read.extend_from_slice(&bytes),
+                c => panic!("expected data, got {:?}", c),
+            }
+        }
+        assert!(read == expected);
+
+        // a missing node has neither data nor a stat
+        let (zk, chunks) = rt.block_on(zk.get_data_streaming("/missing")).unwrap();
+        assert!(chunks.is_none());
+        rt.block_on(zk.close()).unwrap();
+    }
+
+    #[test]
+    fn create_container() {
+        let addr = fake_server(|mut stream| {
+            accept_session(&mut stream, 1);
+            let (xid, opcode, body) = read_request(&mut stream);
+            assert_eq!(opcode, 19);
+            // the create flags are the last field of the request
+            let mut flags = &body[body.len() - 4..];
+            assert_eq!(
+                flags.read_i32::<BigEndian>().unwrap(),
+                CreateMode::Container.flag()
+            );
+            assert_eq!(flags.len(), 0);
+            let mut reply = jute_string("/locks");
+            reply.extend(&[0; 68][..]); // stat
+            write_reply(&mut stream, xid, 1, 0, &reply);
+            let _ = read_frame(&mut stream); // close session
+        });
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let (zk, _) = rt.block_on(ZooKeeper::connect(&addr)).unwrap();
+        let (zk, res) = rt
+            .block_on(zk.create(
+                "/locks",
+                &[][..],
+                Acl::open_unsafe(),
+                CreateMode::Container,
+            ))
+            .unwrap();
+        assert_eq!(res, Ok("/locks".to_string()));
+        drop(zk);
+    }
+
+    #[test]
+    fn create_with_ttl() {
+        let addr = fake_server(|mut stream| {
+            accept_session(&mut stream, 1);
+            let (xid, opcode, body) = read_request(&mut stream);
+            assert_eq!(opcode, 21);
+            // the TTL follows the create flags at the end of the request
+            let mut tail = &body[body.len() - 12..];
+            assert_eq!(tail.read_i32::<BigEndian>().unwrap(), 6);
+            assert_eq!(tail.read_i64::<BigEndian>().unwrap(), 90_000);
+            let mut reply = jute_string("/ttl0000000001");
+            reply.extend(&[0; 68][..]); // stat
+            write_reply(&mut stream, xid, 1, 0, &reply);
+            let _ = read_frame(&mut stream); // close session
+        });
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let (zk, _) = rt.block_on(ZooKeeper::connect(&addr)).unwrap();
+
+        // invalid TTLs never make it to the server
+        for &ttl in &[
+            time::Duration::from_millis(0),
+            time::Duration::from_millis(0x100_0000_0000),
+        ] {
+            let mode = CreateMode::PersistentWithTtl(ttl);
+            let (_, res) = rt
+                .block_on(zk.clone().create("/ttl", &[][..], Acl::open_unsafe(), mode))
+                .unwrap();
+            assert_eq!(res, Err(error::Create::InvalidTtl));
+        }
+
+        let mode = CreateMode::PersistentSequentialWithTtl(time::Duration::from_secs(90));
+        let (zk, res) = rt
+            .block_on(zk.create("/ttl", &[][..], Acl::open_unsafe(), mode))
+            .unwrap();
+        assert_eq!(res, Ok("/ttl0000000001".to_string()));
+        drop(zk);
+    }
+
+    #[test]
+    fn add_watch() {
+        let addr = fake_server(|mut stream| {
+            accept_session(&mut stream, 1);
+            for &mode in &[0, 1] {
+                let (xid, opcode, body) = read_request(&mut stream);
+                assert_eq!(opcode, 106);
+                let mut expected = jute_string("/app");
+                expected.write_i32::<BigEndian>(mode).unwrap();
+                assert_eq!(body, expected);
+                write_reply(&mut stream, xid, 1, 0, &[0, 0, 0, 0]);
+            }
+
+            // a recursive watch fires for a descendant of the watched path
+            let mut event = Vec::new();
+            event.write_i32::<BigEndian>(3).unwrap(); // NodeDataChanged
+            event.write_i32::<BigEndian>(3).unwrap(); // SyncConnected
+            event.extend(jute_string("/app/a/b"));
+            write_reply(&mut stream, -1, -1, 0, &event);
+            let _ = read_frame(&mut stream); // close session
+        });
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let (zk, w) = rt.block_on(ZooKeeper::connect(&addr)).unwrap();
+        let zk = rt
+            .block_on(zk.add_watch("/app", AddWatchMode::Persistent))
+            .unwrap();
+        let zk = rt
+            .block_on(zk.add_watch("/app", AddWatchMode::PersistentRecursive))
+            .unwrap();
+
+        let (event, _) = rt.block_on(w.into_future()).map_err(|_| ()).unwrap();
+        assert_eq!(
+            event,
+            Some(WatchedEvent {
+                event_type: WatchedEventType::NodeDataChanged,
+                keeper_state: KeeperState::SyncConnected,
+                path: "/app/a/b".to_string(),
+                zxid: None,
+            })
+        );
+        drop(zk);
+    }
+
+    #[test]
+    fn remove_watches() {
+        let addr = fake_server(|mut stream| {
+            accept_session(&mut stream, 1);
+            let (xid, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, 3);
+            write_reply(&mut stream, xid, 1, -101, &[]); // NoNode; the watch is still set
+
+            // local removals never reach the server
+            for &(wtype, err) in &[(2, 0), (1, -121), (3, 0)] {
+                let (xid, opcode, body) = read_request(&mut stream);
+                assert_eq!(opcode, 18);
+                let mut expected = jute_string("/w");
+                expected.write_i32::<BigEndian>(wtype).unwrap();
+                assert_eq!(body, expected);
+                write_reply(&mut stream, xid, 1, err, &[]);
+            }
+            let _ = read_frame(&mut stream); // close session
+        });
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let (zk, _) = rt.block_on(ZooKeeper::connect(&addr)).unwrap();
+        let (zk, w, stat) = rt.block_on(zk.with_watcher().exists("/w")).unwrap();
+        assert_eq!(stat, None);
+
+        // an exists watch is not a child watch
+        let (zk, res) = rt
+            .block_on(zk.remove_watches("/w", WatcherType::Children, true))
+            .unwrap();
+        assert_eq!(res, Err(error::RemoveWatches::NoWatcher));
+
+        let (zk, res) = rt
+            .block_on(zk.remove_watches("/w", WatcherType::Data, false))
+            .unwrap();
+        assert_eq!(res, Ok(()));
+        assert_eq!(
+            rt.block_on(w).unwrap().event_type,
+            WatchedEventType::DataWatchRemoved
+        );
+
+        let (zk, res) = rt
+            .block_on(zk.remove_watches("/w", WatcherType::Children, false))
+            .unwrap();
+        assert_eq!(res, Err(error::RemoveWatches::NoWatcher));
+        let (zk, res) = rt
+            .block_on(zk.remove_watches("/w", WatcherType::Any, false))
+            .unwrap();
+        assert_eq!(res, Ok(()));
+        drop(zk);
+    }
+
+    #[test]
+    fn chroot() {
+        let addr = fake_server(|mut stream| {
+            accept_session(&mut stream, 1);
+            let (xid, opcode, body) = read_request(&mut stream);
+            assert_eq!(opcode, 1);
+            assert!(body.starts_with(&jute_string("/app/lock-")));
+            write_reply(&mut stream, xid, 1, 0, &jute_string("/app/lock-0000000003"));
+
+            // the root of the chroot is the chroot itself
+            let (xid, opcode, body) = read_request(&mut stream);
+            assert_eq!(opcode, 3);
+            assert_eq!(&body[..body.len() - 1], &jute_string("/app")[..]);
+            write_reply(&mut stream, xid, 1, -101, &[]);
+
+            let mut event = Vec::new();
+            event.write_i32::<BigEndian>(1).unwrap(); // NodeCreated
+            event.write_i32::<BigEndian>(3).unwrap(); // SyncConnected
+            event.extend(jute_string("/app"));
+            write_reply(&mut stream, -1, -1, 0, &event);
+            let _ = read_frame(&mut stream); // close session
+        });
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let (zk, _) = rt
+            .block_on(ZooKeeper::connect_string(&format!("{}/app", addr)))
+            .unwrap();
+        let (zk, res) = rt
+            .block_on(zk.create(
+                "/lock-",
+                &[][..],
+                Acl::open_unsafe(),
+                CreateMode::EphemeralSequential,
+            ))
+            .unwrap();
+        assert_eq!(res, Ok("/lock-0000000003".to_string()));
+
+        let (zk, w, stat) = rt.block_on(zk.with_watcher().exists("/")).unwrap();
+        assert_eq!(stat, None);
+        let event = rt.block_on(w).unwrap();
+        assert_eq!(event.event_type, WatchedEventType::NodeCreated);
+        assert_eq!(event.path, "/");
+        drop(zk);
+    }
+
+    /// An address that nothing is listening on.
+    fn dead_address() -> SocketAddr {
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap()
+    }
+
+    #[test]
+    fn connect_string_fails_over() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let second = fake_server(move |mut stream| {
+            // the client resumes its session here after losing the first server
+            let mut connect = &read_frame(&mut stream)[..];
+            let _protocol_version = connect.read_i32::<BigEndian>().unwrap();
+            let _last_zxid = connect.read_i64::<BigEndian>().unwrap();
+            let _timeout = connect.read_i32::<BigEndian>().unwrap();
+            tx.send(connect.read_i64::<BigEndian>().unwrap()).unwrap();
+        });
+        let first = fake_server(|mut stream| {
+            accept_session(&mut stream, 42);
+            let (xid, _, _) = read_request(&mut stream);
+            write_reply(&mut stream, xid, 1, 0, &jute_string("/"));
+            // and now the server goes away
+        });
+
+        let mut builder = ZooKeeperBuilder::default();
+        builder.set_shuffle(false);
+        let connect_string = format!("{},{},{}", dead_address(), first, second);
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let (zk, _) = rt
+            .block_on(builder.connect_string(&connect_string))
+            .unwrap();
+        assert_eq!(zk.session_id(), 42);
+        let (zk, _) = rt.block_on(zk.sync("/")).unwrap();
+
+        let resumed = rx.recv_timeout(time::Duration::from_secs(5)).unwrap();
+        assert_eq!(resumed, 42);
+        drop(zk);
+    }
+
+    #[test]
+    fn connected_host() {
+        let second = fake_server(|mut stream| {
+            accept_session(&mut stream, 42);
+            let _ = read_frame(&mut stream); // close session
+        });
+        let first = fake_server(|mut stream| {
+            accept_session(&mut stream, 42);
+            let (xid, _, _) = read_request(&mut stream);
+            write_reply(&mut stream, xid, 1, 0, &jute_string("/"));
+            // and now the server goes away
+        });
+
+        let mut builder = ZooKeeperBuilder::default();
+        builder.set_shuffle(false);
+        let connect_string = format!("{},{}", first, second);
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let (zk, _) = rt
+            .block_on(builder.connect_string(&connect_string))
+            .unwrap();
+        assert_eq!(zk.connected_host(), Some(first));
+        let events = zk.state_events();
+        let (zk, _) = rt.block_on(zk.sync("/")).unwrap();
+
+        // wait for the client to fail over
+        let states = rt
+            .block_on(
+                events
+                    .skip(1)
+                    .take_while(|s| Ok(*s != KeeperState::SyncConnected))
+                    .collect(),
+            )
+            .unwrap();
+        assert_eq!(states, vec![KeeperState::Disconnected]);
+        assert_eq!(zk.connected_host(), Some(second));
+        drop(zk);
+    }
+
+    #[test]
+    fn wait_for_creation() {
+        let server = test_util::MockServer::start().unwrap();
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let (zk, _) = rt.block_on(ZooKeeper::connect(&server.addr())).unwrap();
+        let (other, _) = rt.block_on(ZooKeeper::connect(&server.addr())).unwrap();
+
+        // the watch left by a check for an absent node fires when the node is created
+        let (zk, watch, stat) = rt.block_on(zk.with_watcher().exists("/a")).unwrap();
+        assert_eq!(stat, None);
+        let (other, res) = rt
+            .block_on(other.create("/a", &b""[..], Acl::open_unsafe(), CreateMode::Persistent))
+            .unwrap();
+        assert!(res.is_ok());
+        let event = rt.block_on(watch).unwrap();
+        assert_eq!(event.event_type, WatchedEventType::NodeCreated);
+        assert_eq!(event.path, "/a");
+
+        let (zk, stat) = rt.block_on(zk.wait_for_creation("/a")).unwrap();
+        assert_eq!(stat.version, 0);
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        rt.spawn(
+            zk.wait_for_creation("/b")
+                .then(move |r| tx.send(r.map(|(_, stat)| stat)).map_err(|_| ())),
+        );
+        let (other, res) = rt
+            .block_on(other.create("/b", &b"b"[..], Acl::open_unsafe(), CreateMode::Persistent))
+            .unwrap();
+        assert!(res.is_ok());
+        let stat = rx
+            .recv_timeout(time::Duration::from_secs(5))
+            .unwrap()
+            .unwrap();
+        assert_eq!(stat.data_length, 1);
+        drop(other);
+    }
+
+    #[test]
+    fn server_selector_picks_reconnect_target() {
+        /// Always picks the same server, and remembers which one the client lost.
+        struct Pinned(SocketAddr, Arc<std::sync::Mutex<Vec<Option<SocketAddr>>>>);
+        impl ServerSelector for Pinned {
+            fn next(&mut self, _: &[SocketAddr], last_failed: Option<SocketAddr>) -> SocketAddr {
+                self.1.lock().unwrap().push(last_failed);
+                self.0
+            }
+        }
+
+        let third = fake_server(|mut stream| {
+            accept_session(&mut stream, 42);
+            let _ = read_frame(&mut stream); // close session
+        });
+        // the client would fail over here if it went in order
+        let second = fake_server(|mut stream| {
+            accept_session(&mut stream, 42);
+            let _ = read_frame(&mut stream); // close session
+        });
+        let first = fake_server(|mut stream| {
+            accept_session(&mut stream, 42);
+            let (xid, _, _) = read_request(&mut stream);
+            write_reply(&mut stream, xid, 1, 0, &jute_string("/"));
+            // and now the server goes away
+        });
+
+        let asked = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut builder = ZooKeeperBuilder::default();
+        builder.set_shuffle(false);
+        builder.set_server_selector(Pinned(third, asked.clone()));
+        let connect_string = format!("{},{},{}", first, second, third);
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let (zk, _) = rt
+            .block_on(builder.connect_string(&connect_string))
+            .unwrap();
+        assert_eq!(zk.connected_host(), Some(first));
+        let events = zk.state_events();
+        let (zk, _) = rt.block_on(zk.sync("/")).unwrap();
+
+        let states = rt
+            .block_on(
+                events
+                    .skip(1)
+                    .take_while(|s| Ok(*s != KeeperState::SyncConnected))
+                    .collect(),
+            )
+            .unwrap();
+        assert_eq!(states, vec![KeeperState::Disconnected]);
+        assert_eq!(zk.connected_host(), Some(third));
+        assert_eq!(*asked.lock().unwrap(), vec![Some(first)]);
+        drop(zk);
+    }
+
+    /// Serialize a list of strings the way ZooKeeper does.
+    fn jute_strings(ss: &[&str]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.write_i32::<BigEndian>(ss.len() as i32).unwrap();
+        for s in ss {
+            buf.extend(jute_string(s));
+        }
+        buf
+    }
+
+    /// Serialize a stat with the given fields, and all others zero.
+    fn stat(czxid: i64, cversion: i32, pzxid: i64) -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.write_i64::<BigEndian>(czxid).unwrap();
+        buf.extend(&[0; 24][..]); // mzxid, ctime, mtime
+        buf.write_i32::<BigEndian>(0).unwrap(); // version
+        buf.write_i32::<BigEndian>(cversion).unwrap();
+        buf.extend(&[0; 20][..]); // aversion, ephemeral owner, data length, children
+        buf.write_i64::<BigEndian>(pzxid).unwrap();
+        buf
+    }
+
+    #[test]
+    fn reconnect_resumes_session() {
+        let second = fake_server(|mut stream| {
+            let mut connect = &read_frame(&mut stream)[..];
+            assert_eq!(connect.read_i32::<BigEndian>().unwrap(), 0); // protocol version
+            assert_eq!(connect.read_i64::<BigEndian>().unwrap(), 7); // last zxid seen
+            assert_eq!(connect.read_i32::<BigEndian>().unwrap(), 30_000); // timeout
+            assert_eq!(connect.read_i64::<BigEndian>().unwrap(), 42); // session id
+            write_frame(&mut stream, &connect_response(30_000, 42, &[0; 16]));
+
+            // the client then restores its watches
+            let (xid, opcode, body) = read_request(&mut stream);
+            assert_eq!((xid, opcode), (-8, 101));
+            let mut expected = Vec::new();
+            expected.write_i64::<BigEndian>(7).unwrap();
+            expected.extend(jute_strings(&["/d"])); // data
+            expected.extend(jute_strings(&["/w"])); // exist
+            expected.extend(jute_strings(&[])); // child
+            assert_eq!(body, expected);
+            write_reply(&mut stream, -8, 7, 0, &[]);
+
+            let mut event = Vec::new();
+            event.write_i32::<BigEndian>(1).unwrap(); // NodeCreated
+            event.write_i32::<BigEndian>(3).unwrap(); // SyncConnected
+            event.extend(jute_string("/w"));
+            write_reply(&mut stream, -1, -1, 0, &event);
+
+            let (xid, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, 9);
+            write_reply(&mut stream, xid, 8, 0, &jute_string("/"));
+            let _ = read_frame(&mut stream); // close session
+        });
+        let first = fake_server(|mut stream| {
+            accept_session(&mut stream, 42);
+            let (xid, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, 3);
+            write_reply(&mut stream, xid, 6, -101, &[]); // NoNode
+            let (xid, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, 4);
+            let mut reply = jute_string("data");
+            reply.extend(&[0; 68][..]); // stat
+            write_reply(&mut stream, xid, 7, 0, &reply);
+            // and now the connection drops
+        });
+
+        let mut builder = ZooKeeperBuilder::default();
+        builder.set_shuffle(false);
+        let connect_string = format!("{},{}", first, second);
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let (zk, _) = rt
+            .block_on(builder.connect_string(&connect_string))
+            .unwrap();
+        let (zk, w, _) = rt.block_on(zk.with_watcher().exists("/w")).unwrap();
+        let (zk, _) = rt.block_on(zk.watch().get_data("/d")).unwrap();
+
+        // the custom watch survives the reconnect
+        let event = rt.block_on(w).unwrap();
+        assert_eq!(event.event_type, WatchedEventType::NodeCreated);
+        assert_eq!(event.path, "/w");
+
+        let (zk, _) = rt.block_on(zk.sync("/")).unwrap();
+        assert_eq!(zk.session_id(), 42);
+        drop(zk);
+    }
+
+    /// Serialize a watch event for `path`.
+    fn watch_event(event_type: i32, path: &str) -> Vec<u8> {
+        let mut event = Vec::new();
+        event.write_i32::<BigEndian>(event_type).unwrap();
+        event.write_i32::<BigEndian>(3).unwrap(); // SyncConnected
+        event.extend(jute_string(path));
+        event
+    }
+
+    #[test]
+    fn debounced_watch_events() {
+        let (go, next) = ::std::sync::mpsc::channel();
+        let addr = fake_server(move |mut stream| {
+            accept_session(&mut stream, 1);
+            next.recv().unwrap();
+            for zxid in 2..5 {
+                write_reply(&mut stream, -1, zxid, 0, &watch_event(3, "/d"));
+            }
+            next.recv().unwrap();
+            write_reply(&mut stream, -1, 5, 0, &watch_event(3, "/e"));
+            let (_, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, -11);
+        });
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let (zk, _) = rt.block_on(ZooKeeper::connect(&addr)).unwrap();
+        let all = zk.debounced_watch_events(time::Duration::from_secs(0));
+        let events = zk.debounced_watch_events(time::Duration::from_millis(200));
+        go.send(()).unwrap();
+
+        // a window of zero lets every event through
+        let all = rt.block_on(all.take(3).collect()).unwrap();
+        assert!(all.iter().all(|e| e.path == "/d"));
+
+        let (event, events) = rt.block_on(events.into_future()).ok().unwrap();
+        let event = event.unwrap();
+        assert_eq!(event.event_type, WatchedEventType::NodeDataChanged);
+        assert_eq!(event.path, "/d");
+        go.send(()).unwrap();
+        // the other two events for the same node were coalesced into the first
+        let (event, _) = rt.block_on(events.into_future()).ok().unwrap();
+        assert_eq!(event.unwrap().path, "/e");
+        drop(zk);
+    }
+
+    #[test]
+    fn watch_children() {
+        let addr = fake_server(|mut stream| {
+            accept_session(&mut stream, 1);
+            let mut expected = jute_string("/d");
+            expected.push(1); // watch
+            for (i, children) in [&[][..], &["x"][..], &[][..]].iter().enumerate() {
+                let (xid, opcode, body) = read_request(&mut stream);
+                assert_eq!(opcode, 8);
+                assert_eq!(body, expected);
+                write_reply(&mut stream, xid, i as i64 + 1, 0, &jute_strings(children));
+                write_reply(&mut stream, -1, -1, 0, &watch_event(4, "/d")); // NodeChildrenChanged
+            }
+            let (xid, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, 8);
+            write_reply(&mut stream, xid, 4, -101, &[]); // NoNode
+            let _ = read_frame(&mut stream); // close session
+        });
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let (zk, _) = rt.block_on(ZooKeeper::connect(&addr)).unwrap();
+        let lists = rt.block_on(zk.watch_children("/d").collect()).unwrap();
+        assert_eq!(lists, vec![vec![], vec!["x".to_string()], vec![]]);
+        drop(zk);
+    }
+
+    #[test]
+    fn close_waits_for_outstanding_requests() {
+        let addr = fake_server(|mut stream| {
+            accept_session(&mut stream, 1);
+            let xids: Vec<_> = (0..3)
+                .map(|_| {
+                    let (xid, opcode, _) = read_request(&mut stream);
+                    assert_eq!(opcode, 9); // sync
+                    xid
+                })
+                .collect();
+            // nothing else is sent while the requests wait for their responses
+            stream
+                .set_read_timeout(Some(time::Duration::from_millis(300)))
+                .unwrap();
+            assert!(stream.read(&mut [0]).is_err());
+            stream.set_read_timeout(None).unwrap();
+            for (i, xid) in xids.into_iter().enumerate() {
+                write_reply(&mut stream, xid, i as i64 + 1, 0, &jute_string("/"));
+                thread::sleep(time::Duration::from_millis(50));
+            }
+            let (xid, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, -11); // close session
+            write_reply(&mut stream, xid, 4, 0, &[]);
+        });
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let (zk, _) = rt.block_on(ZooKeeper::connect(&addr)).unwrap();
+        assert_eq!(zk.outstanding_requests(), 0);
+        let (tx, rx) = std::sync::mpsc::channel();
+        for _ in 0..3 {
+            let tx = tx.clone();
+            rt.spawn(
+                zk.clone()
+                    .sync("/")
+                    .then(move |r| tx.send(r.map(|(_, path)| path)).map_err(|_| ())),
+            );
+        }
+        while zk.outstanding_requests() < 3 {
+            thread::sleep(time::Duration::from_millis(10));
+        }
+
+        rt.block_on(zk.clone().close()).unwrap();
+        assert_eq!(zk.outstanding_requests(), 0);
+        for _ in 0..3 {
+            assert_eq!(rx.try_recv().unwrap().unwrap(), "/");
+        }
+    }
+
+    #[test]
+    fn watch_children_without_rearm() {
+        let addr = fake_server(|mut stream| {
+            accept_session(&mut stream, 1);
+            let (xid, opcode, body) = read_request(&mut stream);
+            assert_eq!(opcode, 8);
+            assert_eq!(body.last(), Some(&1)); // watch
+            write_reply(&mut stream, xid, 1, 0, &jute_strings(&["x"]));
+            write_reply(&mut stream, -1, -1, 0, &watch_event(4, "/d")); // NodeChildrenChanged
+            let (xid, opcode, body) = read_request(&mut stream);
+            assert_eq!(opcode, 8);
+            assert_eq!(body.last(), Some(&0)); // no watch
+            write_reply(&mut stream, xid, 2, 0, &jute_strings(&["x", "y"]));
+            let (_, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, -11); // close session
+        });
+
+        let mut builder = ZooKeeperBuilder::default();
+        builder.set_rearm_watches(false);
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let (zk, _) = rt.block_on(builder.connect(&addr)).unwrap();
+        let lists = rt.block_on(zk.watch_children("/d").collect()).unwrap();
+        assert_eq!(
+            lists,
+            vec![
+                vec!["x".to_string()],
+                vec!["x".to_string(), "y".to_string()]
+            ]
+        );
+        drop(zk);
+    }
+
+    #[test]
+    fn watch_children_across_reconnect() {
+        let second = fake_server(|mut stream| {
+            let _ = read_frame(&mut stream);
+            write_frame(&mut stream, &connect_response(30_000, 42, &[0; 16]));
+            // the read that was lost is made again
+            let (xid, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, 8);
+            write_reply(&mut stream, xid, 3, 0, &jute_strings(&["a", "b"]));
+            write_reply(&mut stream, -1, -1, 0, &watch_event(2, "/d")); // NodeDeleted
+            let (xid, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, 8);
+            write_reply(&mut stream, xid, 4, -101, &[]); // NoNode
+            let _ = read_frame(&mut stream); // close session
+        });
+        let first = fake_server(|mut stream| {
+            accept_session(&mut stream, 42);
+            let (xid, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, 8);
+            write_reply(&mut stream, xid, 1, 0, &jute_strings(&["a"]));
+            write_reply(&mut stream, -1, -1, 0, &watch_event(4, "/d")); // NodeChildrenChanged
+            let (_, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, 8);
+            // and now the connection drops before the server answers
+        });
+
+        let mut builder = ZooKeeperBuilder::default();
+        builder.set_shuffle(false);
+        let connect_string = format!("{},{}", first, second);
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let (zk, _) = rt
+            .block_on(builder.connect_string(&connect_string))
+            .unwrap();
+        let lists = rt.block_on(zk.watch_children("/d").collect()).unwrap();
+        assert_eq!(
+            lists,
+            vec![
+                vec!["a".to_string()],
+                vec!["a".to_string(), "b".to_string()]
+            ]
+        );
+        assert_eq!(zk.connected_host(), Some(second));
+        drop(zk);
+    }
+
+    #[test]
+    fn reconnect_gives_up_after_session_timeout() {
+        let first = fake_server(|mut stream| {
+            let _ = read_frame(&mut stream);
+            write_frame(&mut stream, &connect_response(300, 42, &[0; 16]));
+            // and now the connection drops, and no other server is reachable
+        });
+
+        let mut builder = ZooKeeperBuilder::default();
+        builder.set_shuffle(false);
+        let connect_string = format!("{},{}", first, dead_address());
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let (zk, _) = rt
+            .block_on(builder.connect_string(&connect_string))
+            .unwrap();
+        let start = time::Instant::now();
+        assert!(rt.block_on(zk.sync("/")).is_err());
+        assert!(start.elapsed() < time::Duration::from_secs(5));
+    }
+
+    #[test]
+    fn reconnect_gives_up_after_max_attempts() {
+        let first = fake_server(|mut stream| {
+            let _ = read_frame(&mut stream);
+            write_frame(&mut stream, &connect_response(30_000, 42, &[0; 16]));
+            // and now the connection drops, and the server refuses any new ones
+        });
+
+        let mut builder = ZooKeeperBuilder::default();
+        assert!(builder.set_max_reconnect_attempts(Some(0)).is_err());
+        builder.set_max_reconnect_attempts(Some(2)).unwrap();
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let (zk, _) = rt.block_on(builder.connect(&first)).unwrap();
+        let events = zk.state_events();
+        let start = time::Instant::now();
+        // the stream ends once the client gives up
+        let states = rt.block_on(events.collect()).unwrap();
+        assert_eq!(states.last(), Some(&KeeperState::Closed));
+        assert!(!states.contains(&KeeperState::Expired));
+        // well before the session would have timed out
+        assert!(start.elapsed() < time::Duration::from_secs(5));
+        assert!(rt.block_on(zk.sync("/")).is_err());
+    }
+
+    #[test]
+    fn connect_deadline() {
+        let hung = fake_server(|mut stream| {
+            // accept the connection, but never answer the handshake
+            let _ = read_frame(&mut stream);
+            let _ = stream.read(&mut [0]);
+        });
+
+        let mut builder = ZooKeeperBuilder::default();
+        builder.set_shuffle(false);
+        builder.set_connect_deadline(time::Duration::from_millis(300));
+        let connect_string = format!("{},{}", dead_address(), hung);
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let start = time::Instant::now();
+        let err = rt
+            .block_on(builder.connect_string(&connect_string))
+            .map(|_| ())
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<error::ConnectTimeout>(),
+            Some(&error::ConnectTimeout)
+        );
+        assert!(start.elapsed() < time::Duration::from_secs(5));
+    }
+
+    #[test]
+    fn session_timeout() {
+        let addr = fake_server(|mut stream| {
+            let mut connect = &read_frame(&mut stream)[..];
+            assert_eq!(connect.read_i32::<BigEndian>().unwrap(), 0); // protocol version
+            assert_eq!(connect.read_i64::<BigEndian>().unwrap(), 0); // last zxid seen
+            assert_eq!(connect.read_i32::<BigEndian>().unwrap(), 5_500); // timeout
+
+            // the server picks a timeout within its own bounds
+            write_frame(&mut stream, &connect_response(4_000, 1, &[0; 16]));
+            let _ = read_frame(&mut stream); // close session
+        });
+
+        let mut builder = ZooKeeper::builder();
+        builder.set_session_timeout(time::Duration::from_millis(5_500));
+        builder.set_connect_timeout(time::Duration::from_secs(5));
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let (zk, _) = rt.block_on(builder.connect(&addr)).unwrap();
+        assert_eq!(zk.session_timeout(), time::Duration::from_secs(4));
+        drop(zk);
+    }
+
+    #[test]
+    fn read_only() {
+        let addr = fake_server(|mut stream| {
+            let mut connect = &read_frame(&mut stream)[..];
+            assert_eq!(connect.read_i32::<BigEndian>().unwrap(), 0); // protocol version
+            assert_eq!(connect.read_i64::<BigEndian>().unwrap(), 0); // last zxid seen
+            assert_eq!(connect.read_i32::<BigEndian>().unwrap(), 0); // timeout
+            assert_eq!(connect.read_i64::<BigEndian>().unwrap(), 0); // session id
+            assert_eq!(connect.read_i32::<BigEndian>().unwrap(), 0); // password
+            assert_eq!(connect, &[1]); // read-only allowed
+
+            let mut response = connect_response(30_000, 1, &[0; 16]);
+            *response.last_mut().unwrap() = 1; // read-only
+            write_frame(&mut stream, &response);
+
+            // the write never makes it to the server
+            let (xid, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, 9);
+            write_reply(&mut stream, xid, 1, 0, &jute_string("/"));
+            let _ = read_frame(&mut stream); // close session
+        });
+
+        let mut builder = ZooKeeper::builder();
+        builder.set_allow_read_only(true);
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let (zk, _) = rt.block_on(builder.connect(&addr)).unwrap();
+        assert!(zk.is_read_only());
+        let states = zk.state_events().into_future();
+        let (state, _) = rt.block_on(states).ok().unwrap();
+        assert_eq!(state, Some(KeeperState::ConnectedReadOnly));
+
+        let (zk, res) = rt
+            .block_on(zk.create("/foo", &b""[..], Acl::open_unsafe(), CreateMode::Persistent))
+            .unwrap();
+        assert_eq!(res, Err(error::Create::NotReadOnly));
+        let (zk, _) = rt.block_on(zk.sync("/")).unwrap();
+        drop(zk);
+    }
+
+    #[test]
+    fn ensure_path() {
+        let addr = fake_server(|mut stream| {
+            accept_session(&mut stream, 1);
+            let mut nodes = vec!["/a".to_string()];
+            loop {
+                let (xid, opcode, body) = read_request(&mut stream);
+                if opcode == -11 {
+                    // close session
+                    break;
+                }
+                assert_eq!(opcode, 1);
+                let mut body = &body[..];
+                let len = body.read_i32::<BigEndian>().unwrap() as usize;
+                let path = String::from_utf8(body[..len].to_vec()).unwrap();
+                if nodes.contains(&path) {
+                    write_reply(&mut stream, xid, 1, -110, &[]); // NodeExists
+                } else {
+                    write_reply(&mut stream, xid, 1, 0, &jute_string(&path));
+                    nodes.push(path);
+                }
+            }
+            assert_eq!(nodes, vec!["/a", "/a/b", "/a/b/c"]);
+        });
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let (zk, _) = rt.block_on(ZooKeeper::connect(&addr)).unwrap();
+        // both callers race on the same path, and both succeed
+        let ((zk, a), (_, b)) = rt
+            .block_on(
+                zk.clone()
+                    .ensure_path("/a/b/c")
+                    .join(zk.ensure_path("/a/b/c")),
+            )
+            .unwrap();
+        assert_eq!(a, Ok(()));
+        assert_eq!(b, Ok(()));
+        drop(zk);
+    }
+
+    #[test]
+    fn delete_recursive() {
+        let (tx, rx) = ::std::sync::mpsc::channel();
+        let addr = fake_server(move |mut stream| {
+            accept_session(&mut stream, 1);
+            let mut nodes: Vec<String> = ["/", "/t", "/t/a", "/t/a/x", "/t/b", "/other"]
+                .iter()
+                .map(|s| s.to_string())
+                .collect();
+            let read_path = |body: &mut &[u8]| {
+                let len = body.read_i32::<BigEndian>().unwrap() as usize;
+                let path = String::from_utf8(body[..len].to_vec()).unwrap();
+                *body = &body[len..];
+                path
+            };
+            loop {
+                let (xid, opcode, body) = read_request(&mut stream);
+                let mut body = &body[..];
+                match opcode {
+                    8 => {
+                        // get children
+                        let path = read_path(&mut body);
+                        if !nodes.contains(&path) {
+                            write_reply(&mut stream, xid, 1, -101, &[]);
+                            continue;
+                        }
+                        let prefix = format!("{}/", path.trim_end_matches('/'));
+                        let mut children: Vec<_> = nodes
+                            .iter()
+                            .filter(|n| n.starts_with(&prefix) && n.len() > prefix.len())
+                            .map(|n| &n[prefix.len()..])
+                            .filter(|n| !n.contains('/'))
+                            .collect();
+                        if path == "/t/b" {
+                            // an ephemeral node that goes away before it can be deleted
+                            children.push("gone");
+                        }
+                        write_reply(&mut stream, xid, 1, 0, &jute_strings(&children));
+                    }
+                    14 => {
+                        // multi, with only deletes
+                        let mut paths = Vec::new();
+                        while body.read_i32::<BigEndian>().unwrap() == 2 {
+                            body.read_u8().unwrap();
+                            body.read_i32::<BigEndian>().unwrap();
+                            paths.push(read_path(&mut body));
+                            body.read_i32::<BigEndian>().unwrap(); // version
+                        }
+                        let mut reply = Vec::new();
+                        if paths.iter().all(|p| nodes.contains(p)) {
+                            nodes.retain(|n| !paths.contains(n));
+                            for _ in &paths {
+                                reply.write_i32::<BigEndian>(2).unwrap();
+                                reply.write_u8(0).unwrap();
+                                reply.write_i32::<BigEndian>(-1).unwrap();
+                            }
+                        } else {
+                            for p in &paths {
+                                let err = if nodes.contains(p) { -2 } else { -101 };
+                                reply.write_i32::<BigEndian>(-1).unwrap();
+                                reply.write_u8(0).unwrap();
+                                reply.write_i32::<BigEndian>(err).unwrap();
+                                reply.write_i32::<BigEndian>(err).unwrap();
+                            }
+                        }
+                        reply.write_i32::<BigEndian>(-1).unwrap();
+                        reply.write_u8(1).unwrap();
+                        reply.write_i32::<BigEndian>(-1).unwrap();
+                        write_reply(&mut stream, xid, 1, 0, &reply);
+                    }
+                    2 => {
+                        // delete
+                        let path = read_path(&mut body);
+                        let err = if nodes.contains(&path) { 0 } else { -101 };
+                        nodes.retain(|n| *n != path);
+                        write_reply(&mut stream, xid, 1, err, &[]);
+                    }
+                    -11 => break,
+                    _ => unreachable!("unexpected opcode {}", opcode),
+                }
+            }
+            tx.send(nodes).unwrap();
+        });
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let (zk, _) = rt.block_on(ZooKeeper::connect(&addr)).unwrap();
+        let (zk, res) = rt.block_on(zk.delete_recursive("/t")).unwrap();
+        assert_eq!(res, Ok(()));
+        let (zk, res) = rt.block_on(zk.delete_recursive("/t")).unwrap();
+        assert_eq!(res, Err(error::Delete::NoNode));
+        drop(zk);
+
+        let nodes = rx.recv_timeout(time::Duration::from_secs(5)).unwrap();
+        assert_eq!(nodes, vec!["/", "/other"]);
+    }
+
+    #[test]
+    fn large_data_is_not_sent() {
+        let addr = fake_server(|mut stream| {
+            accept_session(&mut stream, 1);
+            let (xid, opcode, body) = read_request(&mut stream);
+            assert_eq!(opcode, 1);
+            assert!(body.starts_with(&jute_string("/a")));
+            write_reply(&mut stream, xid, 1, 0, &jute_string("/a"));
+            let (xid, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, 5);
+            write_reply(&mut stream, xid, 2, 0, &[0; 68]);
+            let (_, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, -11);
+        });
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let mut builder = ZooKeeperBuilder::default();
+        builder.set_max_data_size(4);
+        let (zk, _) = rt.block_on(builder.connect(&addr)).unwrap();
+        let too_large = error::DataTooLarge { size: 5, limit: 4 };
+
+        let err = rt
+            .block_on(zk.clone().create(
+                "/a",
+                vec![0; 5],
+                Acl::open_unsafe(),
+                CreateMode::Persistent,
+            ))
+            .unwrap_err();
+        assert_eq!(err.downcast_ref(), Some(&too_large));
+        let (zk, res) = rt
+            .block_on(zk.create("/a", vec![0; 4], Acl::open_unsafe(), CreateMode::Persistent))
+            .unwrap();
+        assert_eq!(res, Ok("/a".to_string()));
+
+        let err = rt
+            .block_on(zk.clone().set_data("/a", None, vec![0; 5]))
+            .unwrap_err();
+        assert_eq!(err.downcast_ref(), Some(&too_large));
+        let (zk, res) = rt.block_on(zk.set_data("/a", None, vec![0; 4])).unwrap();
+        assert!(res.is_ok());
+        drop(zk);
+
+        // the default matches the default of the server
+        let addr = fake_server(|mut stream| {
+            accept_session(&mut stream, 1);
+            let (_, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, -11);
+        });
+        let (zk, _) = rt.block_on(ZooKeeper::connect(&addr)).unwrap();
+        let err = rt
+            .block_on(zk.set_data("/a", None, vec![0; 1024 * 1024 + 1]))
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref(),
+            Some(&error::DataTooLarge {
+                size: 1024 * 1024 + 1,
+                limit: 1024 * 1024,
+            })
+        );
+    }
+
+    #[test]
+    fn config() {
+        let addr = fake_server(|mut stream| {
+            let config = "server.1=a:2888:3888;2181\nversion=100000002\n";
+            let mut reply = jute_string(config);
+            reply.extend(&[0; 68][..]);
+
+            accept_session(&mut stream, 1);
+            // the configuration node is not subject to the chroot
+            let (xid, opcode, body) = read_request(&mut stream);
+            assert_eq!(opcode, 4);
+            assert_eq!(
+                &body[..body.len() - 1],
+                &jute_string("/zookeeper/config")[..]
+            );
+            write_reply(&mut stream, xid, 1, 0, &reply);
+
+            let (xid, opcode, body) = read_request(&mut stream);
+            assert_eq!(opcode, 16);
+            let mut expected = jute_string("server.2=b:2888:3888;2181");
+            expected.write_i32::<BigEndian>(-1).unwrap();
+            expected.write_i32::<BigEndian>(-1).unwrap();
+            expected.write_i64::<BigEndian>(0x1_0000_0002).unwrap();
+            assert_eq!(body, expected);
+            write_reply(&mut stream, xid, 2, 0, &reply);
+
+            let (xid, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, 16);
+            write_reply(&mut stream, xid, 2, -103, &[]);
+            let _ = read_frame(&mut stream); // close session
+        });
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let (zk, _) = rt
+            .block_on(ZooKeeper::connect_string(&format!("{}/app", addr)))
+            .unwrap();
+        let (zk, res) = rt.block_on(zk.get_config()).unwrap();
+        let (config, _) = res.unwrap();
+        assert_eq!(config.members, vec![Member::new(1, "a:2888:3888;2181")]);
+        assert_eq!(config.version, 0x1_0000_0002);
+
+        let change = Reconfig::Incremental {
+            joining: vec![Member::new(2, "b:2888:3888;2181")],
+            leaving: vec![],
+        };
+        let (zk, res) = rt
+            .block_on(zk.reconfig(&change, Some(config.version)))
+            .unwrap();
+        assert_eq!(res.unwrap().0, config);
+        let (zk, res) = rt.block_on(zk.reconfig(&change, Some(1))).unwrap();
+        assert_eq!(res, Err(error::Reconfig::BadVersion { expected: 1 }));
+        drop(zk);
+    }
+
+    #[test]
+    fn unsupported() {
+        let addr = fake_server(|mut stream| {
+            accept_session(&mut stream, 1);
+            let (xid, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, 21);
+            write_reply(&mut stream, xid, 1, -6, &[]);
+            // the second TTL node is never asked for
+            let (xid, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, 15);
+            let mut body = jute_string("/b");
+            body.extend(&[0; 68][..]);
+            write_reply(&mut stream, xid, 2, 0, &body);
+            let (_, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, -11);
+        });
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let (zk, _) = rt.block_on(ZooKeeper::connect(&addr)).unwrap();
+        assert_eq!(zk.supports(Feature::Ttl), None);
+        let ttl = CreateMode::PersistentWithTtl(time::Duration::from_secs(1));
+        let unsupported = error::Unsupported {
+            operation: "CreateTtl".to_string(),
+        };
+        for _ in 0..2 {
+            let err = rt
+                .block_on(zk.clone().create("/a", &[][..], Acl::open_unsafe(), ttl))
+                .unwrap_err();
+            assert_eq!(err.downcast_ref(), Some(&unsupported));
+            assert_eq!(zk.supports(Feature::Ttl), Some(false));
+        }
+
+        let (zk, res) = rt
+            .block_on(zk.create_with_stat(
+                "/b",
+                &[][..],
+                Acl::open_unsafe(),
+                CreateMode::Persistent,
+            ))
+            .unwrap();
+        assert_eq!(res.unwrap().0, "/b");
+        assert_eq!(zk.supports(Feature::CreateWithStat), Some(true));
+        assert_eq!(zk.supports(Feature::WhoAmI), None);
+        drop(zk);
+    }
+
+    #[test]
+    fn invalid_paths_are_not_sent() {
+        let addr = fake_server(|mut stream| {
+            accept_session(&mut stream, 1);
+            let (xid, opcode, body) = read_request(&mut stream);
+            assert_eq!(opcode, 1);
+            assert!(body.starts_with(&jute_string("/a/")));
+            write_reply(&mut stream, xid, 1, 0, &jute_string("/a/0000000001"));
+            let (_, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, -11);
+        });
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let (zk, _) = rt.block_on(ZooKeeper::connect(&addr)).unwrap();
+        fn reason<T>(r: Result<T, failure::Error>) -> &'static str {
+            match r {
+                Ok(_) => panic!("invalid path was accepted"),
+                Err(e) => e.downcast::<error::InvalidPath>().unwrap().reason,
+            }
+        }
+        assert_eq!(
+            reason(rt.block_on(zk.clone().get_data("a"))),
+            "path must start with /"
+        );
+        assert_eq!(
+            reason(rt.block_on(zk.clone().exists("/a/"))),
+            "path must not end with /"
+        );
+        assert_eq!(
+            reason(rt.block_on(zk.clone().set_data("/a/../b", None, &b""[..]))),
+            "path must not contain relative node names"
+        );
+        assert_eq!(
+            reason(rt.block_on(zk.clone().delete("/zookeeper", None))),
+            "path is reserved for the server"
+        );
+        assert_eq!(
+            reason(rt.block_on(zk.clone().create(
+                "/a/",
+                &b""[..],
+                Acl::open_unsafe(),
+                CreateMode::Persistent
+            ))),
+            "path must not end with /"
+        );
+
+        // the sequence number completes the name of a sequential node
+        let (zk, res) = rt
+            .block_on(zk.create(
+                "/a/",
+                &b""[..],
+                Acl::open_unsafe(),
+                CreateMode::PersistentSequential,
+            ))
+            .unwrap();
+        assert_eq!(res, Ok("/a/0000000001".to_string()));
+        drop(zk);
+    }
+
+    #[test]
+    fn create_sequential_in_chroot() {
+        let addr = fake_server(|mut stream| {
+            accept_session(&mut stream, 1);
+            let (xid, opcode, body) = read_request(&mut stream);
+            assert_eq!(opcode, 1);
+            assert!(body.starts_with(&jute_string("/app/app/queue/item-")));
+            let name = jute_string("/app/app/queue/item-0000000005");
+            write_reply(&mut stream, xid, 1, 0, &name);
+            let (_, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, -11);
+        });
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let (zk, _) = rt
+            .block_on(ZooKeeper::connect_string(&format!("{}/app", addr)))
+            .unwrap();
+        let (zk, res) = rt
+            .block_on(zk.create_sequential(
+                "/app/queue/item-",
+                &b""[..],
+                Acl::open_unsafe(),
+                CreateMode::PersistentSequential,
+            ))
+            .unwrap();
+        assert_eq!(res, Ok(("/app/queue/item-0000000005".to_string(), 5)));
+        drop(zk);
+    }
+
+    #[test]
+    fn raw_request() {
+        let addr = fake_server(|mut stream| {
+            accept_session(&mut stream, 1);
+            let (xid, opcode, typed) = read_request(&mut stream);
+            assert_eq!(opcode, 3);
+            write_reply(&mut stream, xid, 3, 0, &stat(1, 2, 3));
+            let (xid, opcode, raw) = read_request(&mut stream);
+            assert_eq!((opcode, raw), (3, typed));
+            write_reply(&mut stream, xid, 3, 0, &stat(1, 2, 3));
+            // errors in the reply header are not the caller's to decode
+            let (xid, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, 3);
+            write_reply(&mut stream, xid, 3, -101, &[]);
+            // nor are operations the client has never heard of
+            let (xid, opcode, body) = read_request(&mut stream);
+            assert_eq!((opcode, body), (200, vec![1, 2, 3]));
+            write_reply(&mut stream, xid, 3, 0, &[4, 5]);
+            let (_, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, -11);
+        });
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let (zk, _) = rt.block_on(ZooKeeper::connect(&addr)).unwrap();
+        let (zk, typed) = rt.block_on(zk.exists("/d")).unwrap();
+        let mut body = jute_string("/d");
+        body.push(0); // no watch
+        let (zk, raw) = rt.block_on(zk.raw_request(3, body.clone())).unwrap();
+        // the same bytes the typed call decoded
+        assert_eq!(raw, Ok(stat(1, 2, 3)));
+        let typed = typed.unwrap();
+        assert_eq!((typed.czxid, typed.cversion, typed.pzxid), (1, 2, 3));
+
+        let (zk, res) = rt.block_on(zk.raw_request(3, body)).unwrap();
+        assert_eq!(res, Err(ZkError::NoNode));
+        let (zk, res) = rt.block_on(zk.raw_request(200, vec![1, 2, 3])).unwrap();
+        assert_eq!(res, Ok(vec![4, 5]));
+        drop(zk);
+    }
+
+    #[test]
+    fn protected_create_after_lost_ack() {
+        let (created, name) = ::std::sync::mpsc::channel();
+        let second = fake_server(move |mut stream| {
+            let _ = read_frame(&mut stream); // connect
+            write_frame(&mut stream, &connect_response(30_000, 42, &[0; 16]));
+            // the node is found, rather than created again
+            let (xid, opcode, body) = read_request(&mut stream);
+            assert_eq!(opcode, 8);
+            assert!(body.starts_with(&jute_string("/locks")));
+            let name: String = name.recv().unwrap();
+            let children = jute_strings(&["lock-0000000006", &name]);
+            write_reply(&mut stream, xid, 8, 0, &children);
+            let (_, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, -11);
+        });
+        let first = fake_server(move |mut stream| {
+            accept_session(&mut stream, 42);
+            let (_, opcode, body) = read_request(&mut stream);
+            assert_eq!(opcode, 1);
+            let len = (&body[..4]).read_i32::<BigEndian>().unwrap() as usize;
+            let path = String::from_utf8(body[4..4 + len].to_vec()).unwrap();
+            assert!(path.starts_with("/locks/_c_"), "{}", path);
+            assert!(path.ends_with("-lock-"), "{}", path);
+            // the server creates the node, but the connection drops before the reply is sent
+            created.send(format!("{}0000000007", &path[7..])).unwrap();
+        });
+
+        let mut builder = ZooKeeperBuilder::default();
+        builder.set_shuffle(false);
+        let connect_string = format!("{},{}", first, second);
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let (zk, _) = rt
+            .block_on(builder.connect_string(&connect_string))
+            .unwrap();
+        let (zk, res) = rt
+            .block_on(zk.create_protected_ephemeral_sequential(
+                "/locks/lock-",
+                &b""[..],
+                Acl::open_unsafe(),
+            ))
+            .unwrap();
+        let path = res.unwrap();
+        assert!(path.starts_with("/locks/_c_"), "{}", path);
+        assert!(path.ends_with("-lock-0000000007"), "{}", path);
+        drop(zk);
+    }
+
+    #[test]
+    fn protected_create_with_empty_acl() {
+        let addr = fake_server(|mut stream| {
+            accept_session(&mut stream, 1);
+            // neither the create nor a search for the node is sent
+            let (_, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, -11);
+        });
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let (zk, _) = rt.block_on(ZooKeeper::connect(&addr)).unwrap();
+        let err = rt
+            .block_on(zk.clone().create_protected_ephemeral_sequential(
+                "/locks/lock-",
+                &b""[..],
+                vec![],
+            ))
+            .map(|_| ())
+            .unwrap_err();
+        assert!(err.downcast_ref::<error::InvalidAcl>().is_some(), "{}", err);
+        rt.block_on(zk.close()).unwrap();
+    }
+
+    #[test]
+    fn acl_round_trip() {
+        let acl = vec![
+            Acl::digest("user", "password", Permission::READ | Permission::WRITE),
+            Acl::new(Permission::ALL, "ip", "127.0.0.1"),
+        ];
+        // perms, scheme, and id of each entry, in the order the server reads them back
+        let mut encoded = Vec::new();
+        encoded.write_i32::<BigEndian>(2).unwrap();
+        encoded.write_u32::<BigEndian>(3).unwrap();
+        encoded.extend(jute_string("digest"));
+        encoded.extend(jute_string(&Acl::digest_id("user", "password")));
+        encoded.write_u32::<BigEndian>(31).unwrap();
+        encoded.extend(jute_string("ip"));
+        encoded.extend(jute_string("127.0.0.1"));
+
+        let addr = fake_server(move |mut stream| {
+            accept_session(&mut stream, 1);
+            let (xid, opcode, body) = read_request(&mut stream);
+            assert_eq!(opcode, 1);
+            let mut expected = jute_string("/a");
+            expected.extend(jute_string("data"));
+            expected.extend(&encoded);
+            expected.write_i32::<BigEndian>(0).unwrap(); // persistent
+            assert_eq!(body, expected);
+            write_reply(&mut stream, xid, 1, 0, &jute_string("/a"));
+            // the ACL that is obviously invalid never gets here
+            let (xid, opcode, body) = read_request(&mut stream);
+            assert_eq!(opcode, 6);
+            assert_eq!(body, jute_string("/a"));
+            let mut reply = encoded.clone();
+            reply.extend(&stat(1, 0, 1));
+            write_reply(&mut stream, xid, 1, 0, &reply);
+            let (_, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, -11);
+        });
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let (zk, _) = rt.block_on(ZooKeeper::connect(&addr)).unwrap();
+        let (zk, res) = rt
+            .block_on(zk.create("/a", &b"data"[..], acl.clone(), CreateMode::Persistent))
+            .unwrap();
+        assert_eq!(res, Ok("/a".to_string()));
+        let empty = zk
+            .clone()
+            .create("/b", &b""[..], vec![], CreateMode::Persistent);
+        let err = rt.block_on(empty).unwrap_err();
+        assert_eq!(
+            err.downcast_ref(),
+            Some(&error::InvalidAcl {
+                entry: None,
+                reason: "ACL must not be empty",
+            })
+        );
+        let (zk, res) = rt.block_on(zk.get_acl("/a")).unwrap();
+        assert_eq!(res.unwrap().0, acl);
+        drop(zk);
+    }
+
+    #[test]
+    fn connection_loss_fails_pending_requests() {
+        let second = fake_server(|mut stream| {
+            let _ = read_frame(&mut stream); // connect
+            write_frame(&mut stream, &connect_response(30_000, 42, &[0; 16]));
+            let (xid, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, 3);
+            write_reply(&mut stream, xid, 2, 0, &stat(1, 0, 1));
+            let _ = read_frame(&mut stream); // close session
+        });
+        let first = fake_server(|mut stream| {
+            accept_session(&mut stream, 42);
+            let (_, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, 3);
+            // and now the connection drops, before the request is answered
+        });
+
+        let mut builder = ZooKeeperBuilder::default();
+        builder.set_shuffle(false);
+        let connect_string = format!("{},{}", first, second);
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let (zk, _) = rt
+            .block_on(builder.connect_string(&connect_string))
+            .unwrap();
+        let err = rt.block_on(zk.clone().exists("/a")).unwrap_err();
+        assert_eq!(
+            err.find_root_cause().downcast_ref(),
+            Some(&ZkError::ConnectionLoss)
+        );
+        // the session survived, so the request can be retried
+        let (zk, res) = rt.block_on(zk.exists("/a")).unwrap();
+        assert!(res.is_some());
+        assert_eq!(zk.session_id(), 42);
+        drop(zk);
+    }
+
+    #[test]
+    fn session_expiry_fails_pending_requests() {
+        let second = fake_server(|mut stream| {
+            // the server no longer knows the session
+            let _ = read_frame(&mut stream);
+            write_frame(&mut stream, &connect_response(0, 0, &[0; 16]));
+        });
+        let first = fake_server(|mut stream| {
+            accept_session(&mut stream, 42);
+            let (_, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, 3);
+        });
+
+        let mut builder = ZooKeeperBuilder::default();
+        builder.set_shuffle(false);
+        let connect_string = format!("{},{}", first, second);
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let (zk, _) = rt
+            .block_on(builder.connect_string(&connect_string))
+            .unwrap();
+        let err = rt.block_on(zk.clone().exists("/a")).unwrap_err();
+        assert_eq!(
+            err.find_root_cause().downcast_ref(),
+            Some(&ZkError::ConnectionLoss)
+        );
+        // this one is sent while re-connecting, and so learns that the session is gone
+        let err = rt.block_on(zk.exists("/a")).unwrap_err();
+        assert_eq!(
+            err.find_root_cause().downcast_ref(),
+            Some(&ZkError::SessionExpired)
+        );
+    }
+
+    #[test]
+    fn lossy_utf8_policy() {
+        let addr = fake_server(|mut stream| {
+            accept_session(&mut stream, 1);
+            let (xid, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, 8);
+            let mut children = Vec::new();
+            children.write_i32::<BigEndian>(2).unwrap();
+            children.extend(jute_string("ok"));
+            children.write_i32::<BigEndian>(2).unwrap();
+            children.extend(&[b'x', 0xff]);
+            write_reply(&mut stream, xid, 1, 0, &children);
+            let (_, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, -11); // close session
+        });
+
+        let mut builder = ZooKeeperBuilder::default();
+        builder.set_utf8_policy(Utf8Policy::Lossy);
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let (zk, _) = rt.block_on(builder.connect(&addr)).unwrap();
+        let (zk, children) = rt.block_on(zk.get_children("/")).unwrap();
+        assert_eq!(
+            children,
+            Some(vec!["ok".to_string(), "x\u{fffd}".to_string()])
+        );
+        drop(zk);
+    }
+
+    #[test]
+    fn check_response_length() {
+        let second = fake_server(|mut stream| {
+            let _ = read_frame(&mut stream); // connect
+            write_frame(&mut stream, &connect_response(30_000, 42, &[0; 16]));
+            let (xid, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, 3);
+            write_reply(&mut stream, xid, 2, 0, &stat(1, 0, 1));
+            let _ = read_frame(&mut stream); // close session
+        });
+        let first = fake_server(|mut stream| {
+            accept_session(&mut stream, 42);
+            let (xid, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, 3);
+            let mut reply = stat(1, 0, 1);
+            reply.extend(&[1, 2, 3]);
+            write_reply(&mut stream, xid, 1, 0, &reply);
+            let _ = read_frame(&mut stream);
+        });
+
+        let mut builder = ZooKeeperBuilder::default();
+        builder.set_shuffle(false);
+        builder.set_check_response_length(true);
+        let connect_string = format!("{},{}", first, second);
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let (zk, _) = rt
+            .block_on(builder.connect_string(&connect_string))
+            .unwrap();
+        let err = rt.block_on(zk.clone().exists("/a")).unwrap_err();
+        assert_eq!(
+            err.find_root_cause().downcast_ref(),
+            Some(&ZkError::MarshallingError)
+        );
+        // the connection could not be trusted anymore, so the client moved on to the next server
+        let (zk, res) = rt.block_on(zk.exists("/a")).unwrap();
+        assert!(res.is_some());
+        assert_eq!(zk.session_id(), 42);
+        drop(zk);
+
+        let err = error::ProtocolError {
+            operation: "Exists".to_string(),
+            length: 87,
+            unread: 3,
+        };
+        assert_eq!(
+            err.to_string(),
+            "response to Exists was 87 bytes long, but 3 bytes were left over after decoding it"
+        );
+    }
+
+    #[test]
+    fn bad_version_reports_actual() {
+        let addr = fake_server(|mut stream| {
+            accept_session(&mut stream, 1);
+            let (xid, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, 5);
+            write_reply(&mut stream, xid, 1, -103, &[]);
+            // the client looks up the version the node has now
+            let (xid, opcode, body) = read_request(&mut stream);
+            assert_eq!(opcode, 3);
+            assert!(body.starts_with(&jute_string("/v")));
+            let mut stat = stat(1, 0, 1);
+            stat[32..36].copy_from_slice(&[0, 0, 0, 5]); // version
+            write_reply(&mut stream, xid, 1, 0, &stat);
+            // and does not bother if the node is gone by then
+            let (xid, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, 2);
+            write_reply(&mut stream, xid, 2, -103, &[]);
+            let (xid, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, 3);
+            write_reply(&mut stream, xid, 2, -101, &[]);
+            let (_, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, -11);
+        });
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let mut builder = ZooKeeperBuilder::default();
+        builder.set_fetch_actual_version(true);
+        let (zk, _) = rt.block_on(builder.connect(&addr)).unwrap();
+        let (zk, res) = rt.block_on(zk.set_data("/v", Some(2), &b""[..])).unwrap();
+        let err = res.unwrap_err();
+        assert_eq!(
+            err,
+            error::SetData::BadVersion {
+                expected: 2,
+                actual: Some(5),
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "target node has version 5 rather than the expected 2"
+        );
+        let (zk, res) = rt.block_on(zk.delete("/v", Some(2))).unwrap();
+        assert_eq!(
+            res,
+            Err(error::Delete::BadVersion {
+                expected: 2,
+                actual: None,
+            })
+        );
+        drop(zk);
+    }
+
+    #[test]
+    fn update_acl_retries_after_concurrent_change() {
+        let addr = fake_server(|mut stream| {
+            accept_session(&mut stream, 1);
+            for &aversion in &[1u8, 2] {
+                let (xid, opcode, body) = read_request(&mut stream);
+                assert_eq!(opcode, 6); // get_acl
+                assert_eq!(body, jute_string("/a"));
+                let mut reply = Vec::new();
+                reply.write_i32::<BigEndian>(1).unwrap();
+                reply.write_i32::<BigEndian>(aversion as i32).unwrap(); // perms
+                reply.extend(jute_string("world"));
+                reply.extend(jute_string("anyone"));
+                let mut stat = stat(1, 0, 1);
+                stat[40..44].copy_from_slice(&[0, 0, 0, aversion]);
+                reply.extend(&stat);
+                let zxid = i64::from(aversion) * 2;
+                write_reply(&mut stream, xid, zxid - 1, 0, &reply);
+
+                let (xid, opcode, body) = read_request(&mut stream);
+                assert_eq!(opcode, 7); // set_acl
+                assert_eq!(body[body.len() - 4..], [0, 0, 0, aversion]);
+                if aversion == 1 {
+                    // another client changed the ACL in the meantime
+                    write_reply(&mut stream, xid, zxid, -103, &[]);
+                } else {
+                    write_reply(&mut stream, xid, zxid, 0, &stat);
+                }
+            }
+            let (_, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, -11);
+        });
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let (zk, _) = rt.block_on(ZooKeeper::connect(&addr)).unwrap();
+        let (seen, applied) = std::sync::mpsc::channel();
+        let (zk, res) = rt
+            .block_on(zk.update_acl("/a", move |mut acl| {
+                seen.send(acl[0].perms.to_raw()).unwrap();
+                acl[0].perms = Permission::READ;
+                acl
+            }))
+            .unwrap();
+        assert_eq!(res.map(|stat| stat.aversion), Ok(2));
+        // the transform was applied to the ACL that was current at each attempt
+        assert_eq!(applied.try_iter().collect::<Vec<_>>(), vec![1, 2]);
+        drop(zk);
+    }
+
+    #[test]
+    fn close() {
+        let addr = fake_server(|mut stream| {
+            accept_session(&mut stream, 1);
+            let (xid, opcode, body) = read_request(&mut stream);
+            assert_eq!(opcode, -11);
+            assert!(body.is_empty());
+            write_reply(&mut stream, xid, 1, 0, &[]);
+            // the server hangs up once the session is closed
+        });
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let (zk, _) = rt.block_on(ZooKeeper::connect(&addr)).unwrap();
+        let other = zk.clone();
+        let events = zk.state_events();
+        rt.block_on(zk.close()).unwrap();
+        let states = rt.block_on(events.collect()).unwrap();
+        assert_eq!(states, vec![KeeperState::SyncConnected]);
+
+        // the client is gone, but closing it again is harmless
+        assert!(rt.block_on(other.clone().exists("/")).is_err());
+        rt.block_on(other.close()).unwrap();
+    }
+
+    #[test]
+    fn last_zxid() {
+        let addr = fake_server(|mut stream| {
+            accept_session(&mut stream, 1);
+            for &zxid in &[5, 9] {
+                let (xid, opcode, _) = read_request(&mut stream);
+                assert_eq!(opcode, 3);
+                write_reply(&mut stream, xid, zxid, 0, &[0; 68]);
+            }
+            let (xid, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, 3);
+            let mut event = Vec::new();
+            event.write_i32::<BigEndian>(3).unwrap(); // NodeDataChanged
+            event.write_i32::<BigEndian>(3).unwrap(); // SyncConnected
+            event.extend(jute_string("/n"));
+            write_reply(&mut stream, -1, 12, 0, &event);
+            write_reply(&mut stream, xid, 12, 0, &[0; 68]);
+            let (_, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, -11);
+        });
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let (zk, _) = rt.block_on(ZooKeeper::connect(&addr)).unwrap();
+        assert_eq!(zk.last_zxid(), 0);
+        let (zk, _) = rt.block_on(zk.exists("/n")).unwrap();
+        assert_eq!(zk.last_zxid(), 5);
+        let (zk, _) = rt.block_on(zk.exists("/n")).unwrap();
+        assert_eq!(zk.last_zxid(), 9);
+        let (zk, _) = rt.block_on(zk.exists("/n")).unwrap();
+        assert_eq!(zk.last_zxid(), 12);
+        drop(zk);
+    }
+
+    #[test]
+    fn operation_timeout() {
+        let addr = fake_server(|mut stream| {
+            accept_session(&mut stream, 1);
+            let (slow, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, 4);
+            // only answer the first request once the client has given up on it
+            let (xid, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, 4);
+            let mut reply = jute_string("slow");
+            reply.extend(&[0; 68][..]); // stat
+            write_reply(&mut stream, slow, 1, 0, &reply);
+            let mut reply = jute_string("fast");
+            reply.extend(&[0; 68][..]); // stat
+            write_reply(&mut stream, xid, 1, 0, &reply);
+            let (_, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, -11);
+        });
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let mut builder = ZooKeeperBuilder::default();
+        builder.set_operation_timeout(time::Duration::from_millis(200));
+        let (zk, _) = rt.block_on(builder.connect(&addr)).unwrap();
+        let err = rt.block_on(zk.clone().get_data("/slow")).unwrap_err();
+        assert_eq!(err.downcast_ref::<error::Timeout>(), Some(&error::Timeout));
+
+        // the late response is not mistaken for the response to the next request
+        let (zk, res) = rt.block_on(zk.get_data("/fast")).unwrap();
+        assert_eq!(res.unwrap().0, b"fast");
+        drop(zk);
+    }
+
+    #[test]
+    fn request_queue_capacity() {
+        let (stalled, is_stalled) = ::std::sync::mpsc::channel();
+        let addr = fake_server(move |mut stream| {
+            accept_session(&mut stream, 1);
+            let (first, _, _) = read_request(&mut stream);
+            let (second, _, _) = read_request(&mut stream);
+            // the third request waits for one of the first two to complete
+            stream
+                .set_read_timeout(Some(time::Duration::from_millis(300)))
+                .unwrap();
+            assert!(stream.peek(&mut [0]).is_err());
+            stream.set_read_timeout(None).unwrap();
+            stalled.send(()).unwrap();
+
+            for &xid in &[first, second] {
+                let mut reply = jute_string("data");
+                reply.extend(&[0; 68][..]); // stat
+                write_reply(&mut stream, xid, 1, 0, &reply);
+            }
+            let (third, opcode, body) = read_request(&mut stream);
+            assert_eq!(opcode, 4);
+            assert!(body.starts_with(&jute_string("/3")));
+            let mut reply = jute_string("data");
+            reply.extend(&[0; 68][..]); // stat
+            write_reply(&mut stream, third, 1, 0, &reply);
+            let (_, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, -11);
+        });
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let mut builder = ZooKeeperBuilder::default();
+        builder.set_request_queue_capacity(2);
+        let (zk, _) = rt.block_on(builder.connect(&addr)).unwrap();
+        let first = zk.clone().get_data("/1");
+        let second = zk.clone().get_data("/2");
+        let third = zk.clone().get_data("/3");
+        is_stalled.recv().unwrap();
+
+        // a handle that does not wait gives up right away
+        let err = rt
+            .block_on(zk.clone().wait_for_slot(false).get_data("/4"))
+            .unwrap_err();
+        assert_eq!(err.downcast_ref::<error::Busy>(), Some(&error::Busy));
+
+        let ((_, first), (_, second), (_, third)) =
+            rt.block_on(first.join3(second, third)).unwrap();
+        for res in &[first, second, third] {
+            assert_eq!(res.as_ref().unwrap().0, b"data");
+        }
+        drop(zk);
+    }
+
+    #[test]
+    fn timed_out_request_keeps_its_slot() {
+        let (answered, is_answered) = ::std::sync::mpsc::channel();
+        let addr = fake_server(move |mut stream| {
+            accept_session(&mut stream, 1);
+            let (first, _, _) = read_request(&mut stream);
+            // the first request timed out, but the server has yet to answer it
+            stream
+                .set_read_timeout(Some(time::Duration::from_millis(300)))
+                .unwrap();
+            assert!(stream.peek(&mut [0]).is_err());
+            stream.set_read_timeout(None).unwrap();
+
+            let mut reply = jute_string("data");
+            reply.extend(&[0; 68][..]); // stat
+            write_reply(&mut stream, first, 1, 0, &reply);
+            answered.send(()).unwrap();
+            // the second request was given up on while it waited, so it is never sent
+            let (_, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, -11);
+        });
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let mut builder = ZooKeeperBuilder::default();
+        builder.set_request_queue_capacity(1);
+        builder.set_operation_timeout(time::Duration::from_millis(100));
+        let (zk, _) = rt.block_on(builder.connect(&addr)).unwrap();
+        for path in &["/1", "/2"] {
+            let err = rt.block_on(zk.clone().get_data(path)).unwrap_err();
+            assert_eq!(err.downcast_ref::<error::Timeout>(), Some(&error::Timeout));
+        }
+        is_answered.recv().unwrap();
+        drop(zk);
+        rt.shutdown_on_idle().wait().unwrap();
+    }
+
+    #[test]
+    fn ping_interval() {
+        let (tx, rx) = ::std::sync::mpsc::channel();
+        let addr = fake_server(move |mut stream| {
+            let _ = read_frame(&mut stream);
+            // by default, the client pings every third of this
+            write_frame(&mut stream, &connect_response(900, 1, &[0; 16]));
+            let mut sent = vec![time::Instant::now()];
+            while sent.len() <= 3 {
+                let (xid, opcode, body) = read_request(&mut stream);
+                assert_eq!((xid, opcode, body), (-2, 11, vec![]));
+                sent.push(time::Instant::now());
+                write_reply(&mut stream, xid, 1, 0, &[]);
+            }
+            tx.send(sent).unwrap();
+            loop {
+                let (_, opcode, _) = read_request(&mut stream);
+                if opcode == -11 {
+                    break;
+                }
+            }
+        });
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let (zk, _) = rt.block_on(ZooKeeper::connect(&addr)).unwrap();
+        let sent = rx.recv().unwrap();
+        for pair in sent.windows(2) {
+            let gap = pair[1] - pair[0];
+            assert!(gap >= time::Duration::from_millis(250), "{:?}", gap);
+            assert!(gap < time::Duration::from_millis(600), "{:?}", gap);
+        }
+        drop(zk);
+
+        let (tx, rx) = ::std::sync::mpsc::channel();
+        let addr = fake_server(move |mut stream| {
+            accept_session(&mut stream, 1);
+            let start = time::Instant::now();
+            for _ in 0..3 {
+                let (xid, opcode, _) = read_request(&mut stream);
+                assert_eq!(opcode, 11);
+                write_reply(&mut stream, xid, 1, 0, &[]);
+            }
+            tx.send(start.elapsed()).unwrap();
+            loop {
+                let (_, opcode, _) = read_request(&mut stream);
+                if opcode == -11 {
+                    break;
+                }
+            }
+        });
+
+        let mut builder = ZooKeeperBuilder::default();
+        builder.set_ping_interval(time::Duration::from_millis(50));
+        let (zk, _) = rt.block_on(builder.connect(&addr)).unwrap();
+        let elapsed = rx.recv().unwrap();
+        assert!(elapsed >= time::Duration::from_millis(150), "{:?}", elapsed);
+        assert!(elapsed < time::Duration::from_secs(1), "{:?}", elapsed);
+        drop(zk);
+    }
+
+    #[test]
+    fn unanswered_pings_expire_session() {
+        let addr = fake_server(|mut stream| {
+            let _ = read_frame(&mut stream);
+            write_frame(&mut stream, &connect_response(300, 1, &[0; 16]));
+            // keep reading, but never answer
+            while stream.read_i32::<BigEndian>().is_ok() {}
+        });
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let mut builder = ZooKeeperBuilder::default();
+        // outlast the session, so that the connection is not given up on first
+        builder.set_read_idle_timeout(time::Duration::from_secs(1));
+        let (zk, _) = rt.block_on(builder.connect(&addr)).unwrap();
+        let states = rt.block_on(zk.state_events().collect()).unwrap();
+        assert_eq!(
+            states,
+            vec![KeeperState::SyncConnected, KeeperState::Expired]
+        );
+        assert!(rt.block_on(zk.sync("/")).is_err());
+    }
+
+    #[test]
+    fn idle_connection_reconnects() {
+        let second = fake_server(|mut stream| {
+            let _ = read_frame(&mut stream);
+            write_frame(&mut stream, &connect_response(30_000, 42, &[0; 16]));
+            let _ = read_frame(&mut stream); // close session
+        });
+        let first = fake_server(|mut stream| {
+            accept_session(&mut stream, 42);
+            // the connection stays open, but nothing ever comes back
+            while stream.read_i32::<BigEndian>().is_ok() {}
+        });
+
+        let mut builder = ZooKeeperBuilder::default();
+        builder.set_shuffle(false);
+        builder.set_ping_interval(time::Duration::from_millis(50));
+        let connect_string = format!("{},{}", first, second);
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let start = time::Instant::now();
+        let (zk, _) = rt
+            .block_on(builder.connect_string(&connect_string))
+            .unwrap();
+        let states: Vec<_> = rt.block_on(zk.state_events().take(3).collect()).unwrap();
+        assert_eq!(
+            states,
+            vec![
+                KeeperState::SyncConnected,
+                KeeperState::Disconnected,
+                KeeperState::SyncConnected
+            ]
+        );
+        // well within the 30s session timeout
+        assert!(start.elapsed() < time::Duration::from_secs(5));
+        drop(zk);
+    }
+
+    #[test]
+    fn multi_read() {
+        let addr = fake_server(|mut stream| {
+            accept_session(&mut stream, 1);
+            let (xid, opcode, body) = read_request(&mut stream);
+            assert_eq!(opcode, 22);
+            let mut expected = Vec::new();
+            expected.write_i32::<BigEndian>(4).unwrap();
+            expected.write_u8(0).unwrap();
+            expected.write_i32::<BigEndian>(-1).unwrap();
+            expected.extend(jute_string("/a"));
+            expected.push(0); // watch
+            expected.write_i32::<BigEndian>(8).unwrap();
+            expected.write_u8(0).unwrap();
+            expected.write_i32::<BigEndian>(-1).unwrap();
+            expected.extend(jute_string("/b"));
+            expected.push(0); // watch
+            expected.write_i32::<BigEndian>(-1).unwrap();
+            expected.write_u8(1).unwrap();
+            expected.write_i32::<BigEndian>(-1).unwrap();
+            assert_eq!(body, expected);
+
+            let mut reply = Vec::new();
+            reply.write_i32::<BigEndian>(4).unwrap();
+            reply.write_u8(0).unwrap();
+            reply.write_i32::<BigEndian>(0).unwrap();
+            reply.extend(jute_string("data"));
+            reply.extend(&[0; 68][..]); // stat
+            // reads fail independently of each other
+            reply.write_i32::<BigEndian>(-1).unwrap();
+            reply.write_u8(0).unwrap();
+            reply.write_i32::<BigEndian>(-101).unwrap();
+            reply.write_i32::<BigEndian>(-101).unwrap();
+            reply.write_i32::<BigEndian>(-1).unwrap();
+            reply.write_u8(1).unwrap();
+            reply.write_i32::<BigEndian>(-1).unwrap();
+            write_reply(&mut stream, xid, 1, 0, &reply);
+            let (_, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, -11);
+        });
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let (zk, _) = rt.block_on(ZooKeeper::connect(&addr)).unwrap();
+        let (zk, res) = rt
+            .block_on(zk.multi().get_data("/a").get_children("/b").run())
+            .unwrap();
+        match res[0] {
+            Ok(MultiResponse::GetData(Some((ref data, _)))) => assert_eq!(data, b"data"),
+            ref r => panic!("unexpected response {:?}", r),
+        }
+        assert_eq!(res[1], Ok(MultiResponse::GetChildren(None)));
+
+        let err = rt
+            .block_on(zk.clone().multi().get_data("/a").delete("/a", None).run())
+            .unwrap_err();
+        assert_eq!(err.to_string(), "multi request cannot mix reads and writes");
+        drop(zk);
+    }
+
+    #[test]
+    fn create_with_parent_stat() {
+        let addr = fake_server(|mut stream| {
+            accept_session(&mut stream, 1);
+            let (xid, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, 3);
+            write_reply(&mut stream, xid, 6, 0, &stat(1, 3, 6));
+            // both requests are sent before the server answers either
+            let (create, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, 15);
+            let (exists, opcode, body) = read_request(&mut stream);
+            assert_eq!(opcode, 3);
+            assert_eq!(body[..body.len() - 1], jute_string("/d")[..]);
+            let mut reply = jute_string("/d/n");
+            reply.extend(stat(7, 0, 7));
+            write_reply(&mut stream, create, 7, 0, &reply);
+            write_reply(&mut stream, exists, 7, 0, &stat(1, 4, 7));
+            let (_, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, -11);
+        });
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let (zk, _) = rt.block_on(ZooKeeper::connect(&addr)).unwrap();
+        let (zk, before) = rt.block_on(zk.exists("/d")).unwrap();
+        let (zk, res) = rt
+            .block_on(zk.create_with_parent_stat(
+                "/d/n",
+                &b""[..],
+                Acl::open_unsafe(),
+                CreateMode::Persistent,
+            ))
+            .unwrap();
+        let (name, stat, parent) = res.unwrap();
+        assert_eq!(name, "/d/n");
+        assert_eq!(parent.cversion, before.unwrap().cversion + 1);
+        // nothing else happened to the children in between
+        assert_eq!(parent.pzxid, stat.czxid);
+        drop(zk);
+    }
+
+    #[test]
+    fn children_changed_since() {
+        let addr = fake_server(|mut stream| {
+            accept_session(&mut stream, 1);
+            // a child is created and deleted in between, which leaves the list as it was
+            for (i, &(cversion, watch)) in [(1, true), (3, false), (3, false)].iter().enumerate() {
+                let (xid, opcode, body) = read_request(&mut stream);
+                assert_eq!(opcode, 12);
+                assert_eq!(body[body.len() - 1] == 1, watch);
+                let mut reply = jute_strings(&["a"]);
+                reply.extend(stat(1, cversion, 2 + i as i64));
+                write_reply(&mut stream, xid, 2 + i as i64, 0, &reply);
+                if watch {
+                    write_reply(&mut stream, -1, 3, 0, &watch_event(4, "/d"));
+                }
+            }
+            let (_, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, -11);
+        });
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let (zk, _) = rt.block_on(ZooKeeper::connect(&addr)).unwrap();
+        let (zk, res) = rt
+            .block_on(zk.with_watcher().get_children_with_stat("/d"))
+            .unwrap();
+        let (changed, children, before) = res.unwrap();
+        assert_eq!(children, vec!["a"]);
+        let event = rt.block_on(changed).unwrap();
+        assert_eq!(event.event_type, WatchedEventType::NodeChildrenChanged);
+
+        let (zk, res) = rt.block_on(zk.get_children_with_stat("/d")).unwrap();
+        let (children, after) = res.unwrap();
+        assert_eq!(children, vec!["a"]);
+        assert!(after.children_changed_since(before.cversion));
+
+        let (zk, res) = rt.block_on(zk.get_children_with_stat("/d")).unwrap();
+        assert!(!res.unwrap().1.children_changed_since(after.cversion));
+        drop(zk);
+    }
+
+    /// Serialize the header of a result in a multi response.
+    fn result_header(opcode: i32, done: bool, err: i32) -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.write_i32::<BigEndian>(opcode).unwrap();
+        buf.write_u8(done as u8).unwrap();
+        buf.write_i32::<BigEndian>(err).unwrap();
+        buf
+    }
+
+    /// Serialize a failed result in a multi response.
+    fn result_err(err: i32) -> Vec<u8> {
+        let mut buf = result_header(-1, false, err);
+        buf.write_i32::<BigEndian>(err).unwrap();
+        buf
+    }
+
+    #[test]
+    fn multi_create_then_check() {
+        let done = result_header(-1, true, -1);
+
+        let addr = fake_server(move |mut stream| {
+            accept_session(&mut stream, 1);
+            let (xid, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, 14);
+            let mut reply = result_header(1, false, 0);
+            reply.extend(jute_string("/a"));
+            reply.extend(result_header(13, false, 0));
+            reply.extend(&done);
+            write_reply(&mut stream, xid, 1, 0, &reply);
+
+            // a failed transaction is reported through the results of its operations
+            let (xid, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, 14);
+            let mut reply = result_err(0);
+            reply.extend(result_err(-103)); // BadVersion
+            reply.extend(&done);
+            write_reply(&mut stream, xid, 2, 0, &reply);
+            let (_, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, -11);
+        });
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let (zk, _) = rt.block_on(ZooKeeper::connect(&addr)).unwrap();
+        let (zk, res) = rt
+            .block_on(
+                zk.multi()
+                    .create("/a", &b""[..], Acl::open_unsafe(), CreateMode::Persistent)
+                    .check("/a", 0)
+                    .run(),
+            )
+            .unwrap();
+        assert_eq!(
+            res,
+            vec![
+                Ok(MultiResponse::Create("/a".to_string())),
+                Ok(MultiResponse::Check)
+            ]
+        );
+
+        let (zk, res) = rt
+            .block_on(
+                zk.multi()
+                    .create("/b", &b""[..], Acl::open_unsafe(), CreateMode::Persistent)
+                    .check("/a", 1)
+                    .run(),
+            )
+            .unwrap();
+        assert_eq!(
+            res,
+            vec![
+                Err(error::Multi::RolledBack),
+                Err(error::Multi::Check(error::Check::BadVersion {
+                    expected: 1
+                })),
+            ]
+        );
+        drop(zk);
+    }
+
+    #[test]
+    fn multi_error_points_at_failed_operation() {
+        let addr = fake_server(move |mut stream| {
+            accept_session(&mut stream, 1);
+            let (xid, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, 14);
+            let mut reply = result_err(0); // rolled back
+            reply.extend(result_err(-101)); // NoNode
+            reply.extend(result_err(-2)); // skipped
+            reply.extend(result_header(-1, true, -1));
+            write_reply(&mut stream, xid, 1, 0, &reply);
+            let (_, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, -11);
+        });
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let (zk, _) = rt.block_on(ZooKeeper::connect(&addr)).unwrap();
+        let (zk, res) = rt
+            .block_on(
+                zk.multi()
+                    .create("/a", &b""[..], Acl::open_unsafe(), CreateMode::Persistent)
+                    .delete("/b", None)
+                    .set_data("/c", None, &b""[..])
+                    .run(),
+            )
+            .unwrap();
+        let err = MultiResponse::all(res).unwrap_err();
+        assert_eq!(err.failed_index, 1);
+        assert_eq!(err.cause, error::Multi::Delete(error::Delete::NoNode));
+        drop(zk);
+    }
+
+    #[test]
+    fn multi_dry_run() {
+        let addr = fake_server(|mut stream| {
+            accept_session(&mut stream, 1);
+            // dry runs never reach the server
+            let (_, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, -11);
+        });
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let (zk, _) = rt.block_on(ZooKeeper::connect(&addr)).unwrap();
+        let valid = zk
+            .clone()
+            .multi()
+            .create("/a", &b""[..], Acl::open_unsafe(), CreateMode::Persistent)
+            .create(
+                "/a/",
+                &b""[..],
+                Acl::open_unsafe(),
+                CreateMode::PersistentSequential,
+            )
+            .set_data("/b", None, &b""[..])
+            .check("/b", 1)
+            .delete("/c", None);
+        assert!(valid.dry_run().is_ok());
+        drop(valid);
+
+        let invalid = zk
+            .clone()
+            .multi()
+            .create("/a", &b""[..], Acl::open_unsafe(), CreateMode::Persistent)
+            .delete("b", None);
+        let err = invalid.dry_run().unwrap_err();
+        assert_eq!(err.to_string(), "operation 1 of multi request is invalid");
+        assert_eq!(
+            err.find_root_cause().downcast_ref(),
+            Some(&error::InvalidPath {
+                path: "b".to_string(),
+                reason: "path must start with /",
+            })
+        );
+        drop(invalid);
+
+        let mixed = zk.clone().multi().get_data("/a").delete("/a", None);
+        assert!(mixed.dry_run().is_err());
+        drop(mixed);
+        rt.block_on(zk.close()).unwrap();
+    }
+
+    #[test]
+    fn get_data_with_watcher() {
+        let addr = fake_server(|mut stream| {
+            accept_session(&mut stream, 1);
+            let (xid, opcode, body) = read_request(&mut stream);
+            assert_eq!(opcode, 4);
+            let mut expected = jute_string("/n");
+            expected.push(1); // watch
+            assert_eq!(body, expected);
+            let mut reply = jute_string("old");
+            reply.extend(&[0; 68][..]); // stat
+            write_reply(&mut stream, xid, 1, 0, &reply);
+
+            let (xid, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, 5);
+            write_reply(&mut stream, xid, 2, 0, &[0; 68]);
+            let mut event = Vec::new();
+            event.write_i32::<BigEndian>(3).unwrap(); // NodeDataChanged
+            event.write_i32::<BigEndian>(3).unwrap(); // SyncConnected
+            event.extend(jute_string("/n"));
+            write_reply(&mut stream, -1, 2, 0, &event);
+            let _ = read_frame(&mut stream); // close session
+        });
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let (zk, _) = rt.block_on(ZooKeeper::connect(&addr)).unwrap();
+        let (zk, res) = rt.block_on(zk.with_watcher().get_data("/n")).unwrap();
+        let (w, data, _) = res.unwrap();
+        assert_eq!(data, b"old");
+
+        let (zk, res) = rt.block_on(zk.set_data("/n", None, &b"new"[..])).unwrap();
+        assert!(res.is_ok());
+        let event = rt.block_on(w).unwrap();
+        assert_eq!(event.event_type, WatchedEventType::NodeDataChanged);
+        assert_eq!(event.path, "/n");
+        drop(zk);
+    }
+
+    #[test]
+    fn watch_events() {
+        let second = fake_server(|mut stream| {
+            let _ = read_frame(&mut stream);
+            write_frame(&mut stream, &connect_response(30_000, 42, &[0; 16]));
+            let (xid, opcode, _) = read_request(&mut stream);
+            assert_eq!((xid, opcode), (-8, 101));
+            write_reply(&mut stream, -8, 2, 0, &[]);
+
+            let mut event = Vec::new();
+            event.write_i32::<BigEndian>(3).unwrap(); // NodeDataChanged
+            event.write_i32::<BigEndian>(3).unwrap(); // SyncConnected
+            event.extend(jute_string("/d"));
+            write_reply(&mut stream, -1, 3, 0, &event);
+            let _ = read_frame(&mut stream); // close session
+        });
+        let first = fake_server(|mut stream| {
+            accept_session(&mut stream, 42);
+            let (xid, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, 4);
+            let mut reply = jute_string("data");
+            reply.extend(&[0; 68][..]); // stat
+            write_reply(&mut stream, xid, 2, 0, &reply);
+            // and now the connection drops
+        });
+
+        let mut builder = ZooKeeperBuilder::default();
+        builder.set_shuffle(false);
+        let connect_string = format!("{},{}", first, second);
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let (zk, _) = rt
+            .block_on(builder.connect_string(&connect_string))
+            .unwrap();
+        let events = zk.watch_events();
+        let (zk, _) = rt.block_on(zk.watch().get_data("/d")).unwrap();
+
+        // the event arrives from the server we failed over to
+        let (event, _) = rt.block_on(events.into_future()).ok().unwrap();
+        let event = event.unwrap();
+        assert_eq!(event.event_type, WatchedEventType::NodeDataChanged);
+        assert_eq!(event.path, "/d");
+        drop(zk);
+    }
+
+    #[test]
+    fn state_events() {
+        let second = fake_server(|mut stream| {
+            let _ = read_frame(&mut stream);
+            // the session is gone
+            write_frame(&mut stream, &connect_response(0, 0, &[0; 16]));
+        });
+        let first = fake_server(|mut stream| {
+            accept_session(&mut stream, 42);
+            let _ = read_frame(&mut stream);
+            // and now the connection drops
+        });
+
+        let mut builder = ZooKeeperBuilder::default();
+        builder.set_shuffle(false);
+        let connect_string = format!("{},{}", first, second);
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let (zk, _) = rt
+            .block_on(builder.connect_string(&connect_string))
+            .unwrap();
+        let states = zk.state_events();
+        // the in-flight request is lost along with the connection
+        assert!(rt.block_on(zk.clone().sync("/")).is_err());
+        let states = rt.block_on(states.collect()).unwrap();
+        assert_eq!(
+            states,
+            vec![
+                KeeperState::SyncConnected,
+                KeeperState::Disconnected,
+                KeeperState::Expired,
+            ]
+        );
+
+        // requests fail once the session has expired
+        assert!(rt.block_on(zk.sync("/")).is_err());
+    }
+
+    #[test]
+    fn connect_string_all_dead() {
+        let mut builder = ZooKeeperBuilder::default();
+        builder.set_shuffle(false);
+        let connect_string = format!("{},{}", dead_address(), dead_address());
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let res = rt.block_on(builder.connect_string(&connect_string));
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn add_auth() {
+        let addr = fake_server(|mut stream| {
+            accept_session(&mut stream, 1);
+            let mut expected = Vec::new();
+            expected.write_i32::<BigEndian>(0).unwrap();
+            expected.extend(jute_string("digest"));
+            expected.extend(jute_string("user:password"));
+            let (xid, opcode, body) = read_request(&mut stream);
+            assert_eq!((xid, opcode), (-4, 100));
+            assert_eq!(body, expected);
+            write_reply(&mut stream, -4, 0, 0, &[]);
+
+            let (xid, opcode, _) = read_request(&mut stream);
+            assert_eq!((xid, opcode), (-4, 100));
+            write_reply(&mut stream, -4, 0, -115, &[]);
+            let _ = read_frame(&mut stream); // close session
+        });
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let (zk, _) = rt.block_on(ZooKeeper::connect(&addr)).unwrap();
+        let (zk, res) = rt
+            .block_on(zk.add_auth("digest", &b"user:password"[..]))
+            .unwrap();
+        assert_eq!(res, Ok(()));
+        let (zk, res) = rt
+            .block_on(zk.add_auth("digest", &b"user:wrong"[..]))
+            .unwrap();
+        assert_eq!(res, Err(error::Auth::AuthFailed));
+        drop(zk);
+    }
+
+    #[test]
+    fn add_auth_again_after_reconnect() {
+        fn auth_body(scheme: &str, auth: &str) -> Vec<u8> {
+            let mut body = Vec::new();
+            body.write_i32::<BigEndian>(0).unwrap();
+            body.extend(jute_string(scheme));
+            body.extend(jute_string(auth));
+            body
+        }
+
+        let second = fake_server(|mut stream| {
+            let _ = read_frame(&mut stream); // connect
+            write_frame(&mut stream, &connect_response(30_000, 42, &[0; 16]));
+
+            // both credentials come back, in the order they were added
+            let (xid, opcode, body) = read_request(&mut stream);
+            assert_eq!((xid, opcode), (-4, 100));
+            assert_eq!(body, auth_body("digest", "user:password"));
+            write_reply(&mut stream, -4, 7, 0, &[]);
+            let (xid, opcode, body) = read_request(&mut stream);
+            assert_eq!((xid, opcode), (-4, 100));
+            assert_eq!(body, auth_body("ip", "127.0.0.1"));
+            write_reply(&mut stream, -4, 7, 0, &[]);
+
+            let (xid, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, 9);
+            write_reply(&mut stream, xid, 7, 0, &jute_string("/"));
+            let _ = read_frame(&mut stream); // close session
+        });
+        let first = fake_server(|mut stream| {
+            accept_session(&mut stream, 42);
+            let (_, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, 100);
+            write_reply(&mut stream, -4, 5, 0, &[]);
+            // credentials the server rejected are not added again
+            let (_, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, 100);
+            write_reply(&mut stream, -4, 6, -115, &[]);
+            let (_, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, 100);
+            write_reply(&mut stream, -4, 7, 0, &[]);
+            // and now the connection drops
+        });
+
+        let mut builder = ZooKeeperBuilder::default();
+        builder.set_shuffle(false);
+        let connect_string = format!("{},{}", first, second);
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let (zk, _) = rt
+            .block_on(builder.connect_string(&connect_string))
+            .unwrap();
+        let (zk, res) = rt
+            .block_on(zk.add_auth("digest", &b"user:password"[..]))
+            .unwrap();
+        assert_eq!(res, Ok(()));
+        let (zk, res) = rt
+            .block_on(zk.add_auth("digest", &b"user:wrong"[..]))
+            .unwrap();
+        assert_eq!(res, Err(error::Auth::AuthFailed));
+        let (zk, res) = rt.block_on(zk.add_auth("ip", &b"127.0.0.1"[..])).unwrap();
+        assert_eq!(res, Ok(()));
+
+        let (zk, _) = rt.block_on(zk.sync("/")).unwrap();
+        assert_eq!(zk.session_id(), 42);
+        drop(zk);
+    }
+
+    /// A SASL client that answers the server's challenges from a script.
+    struct ScriptedSasl {
+        script: Vec<(&'static str, Option<&'static str>)>,
+    }
+
+    impl SaslClient for ScriptedSasl {
+        fn initial_response(&mut self) -> Result<Vec<u8>, failure::Error> {
+            Ok(b"hello".to_vec())
+        }
+
+        fn evaluate_challenge(
+            &mut self,
+            challenge: &[u8],
+        ) -> Result<Option<Vec<u8>>, failure::Error> {
+            let (expected, response) = self.script.remove(0);
+            assert_eq!(challenge, expected.as_bytes());
+            Ok(response.map(|r| r.as_bytes().to_vec()))
+        }
+
+        fn is_complete(&self) -> bool {
+            self.script.is_empty()
+        }
+    }
+
+    #[test]
+    fn sasl() {
+        let addr = fake_server(|mut stream| {
+            accept_session(&mut stream, 1);
+            let (xid, opcode, body) = read_request(&mut stream);
+            assert_eq!(opcode, 102);
+            assert_eq!(body, jute_string("hello"));
+            write_reply(&mut stream, xid, 0, 0, &jute_string("challenge"));
+            let (xid, opcode, body) = read_request(&mut stream);
+            assert_eq!(opcode, 102);
+            assert_eq!(body, jute_string("response"));
+            write_reply(&mut stream, xid, 0, 0, &jute_string("welcome"));
+            // the write was held back until now
+            let (xid, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, 1);
+            write_reply(&mut stream, xid, 1, 0, &jute_string("/a"));
+            let (_, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, -11);
+        });
+
+        let mut builder = ZooKeeperBuilder::default();
+        builder.set_sasl(
+            || {
+                Box::new(ScriptedSasl {
+                    script: vec![("challenge", Some("response")), ("welcome", None)],
+                })
+            },
+            SaslWritePolicy::Queue,
+        );
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let (zk, w) = rt.block_on(builder.connect(&addr)).unwrap();
+        let (zk, res) = rt
+            .block_on(zk.create("/a", &b""[..], Acl::open_unsafe(), CreateMode::Persistent))
+            .unwrap();
+        assert_eq!(res, Ok("/a".to_string()));
+        let (event, _) = rt
+            .block_on(
+                w.skip_while(|e| Ok(e.keeper_state != KeeperState::SaslAuthenticated))
+                    .into_future(),
+            )
+            .map_err(|_| ())
+            .unwrap();
+        assert_eq!(event.unwrap().event_type, WatchedEventType::None);
+        drop(zk);
+    }
+
+    #[test]
+    fn sasl_failure() {
+        let (reply, replied) = std::sync::mpsc::channel();
+        let addr = fake_server(move |mut stream| {
+            accept_session(&mut stream, 1);
+            let (xid, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, 102);
+            replied.recv().unwrap();
+            write_reply(&mut stream, xid, 0, -115, &[]); // AuthFailed
+            let (_, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, -11);
+        });
+
+        let mut builder = ZooKeeperBuilder::default();
+        builder.set_sasl(
+            || {
+                Box::new(ScriptedSasl {
+                    script: vec![("welcome", None)],
+                })
+            },
+            SaslWritePolicy::Reject,
+        );
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let (zk, w) = rt.block_on(builder.connect(&addr)).unwrap();
+        let create = |zk: &ZooKeeper| {
+            zk.clone()
+                .create("/a", &b""[..], Acl::open_unsafe(), CreateMode::Persistent)
+        };
+        let err = rt.block_on(create(&zk)).unwrap_err();
+        assert_eq!(err.downcast_ref(), Some(&error::AuthPending));
+
+        reply.send(()).unwrap();
+        let (event, _) = rt
+            .block_on(
+                w.skip_while(|e| Ok(e.keeper_state != KeeperState::AuthFailed))
+                    .into_future(),
+            )
+            .map_err(|_| ())
+            .unwrap();
+        assert!(event.is_some());
+        let err = rt.block_on(create(&zk)).unwrap_err();
+        assert_eq!(
+            err.find_root_cause().downcast_ref::<ZkError>(),
+            Some(&ZkError::AuthFailed)
+        );
+        drop(zk);
+    }
+
+    #[test]
+    fn error_header() {
+        // the body of a successful get_data: empty data, and a zeroed stat
+        let mut data = Vec::new();
+        data.write_i32::<BigEndian>(0).unwrap();
+        data.extend(&[0; 68][..]);
+        let data2 = data.clone();
+
+        let second = fake_server(move |mut stream| {
+            let _ = read_frame(&mut stream);
+            write_frame(&mut stream, &connect_response(30_000, 42, &[0; 16]));
+            let (xid, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, 4);
+            write_reply(&mut stream, xid, 3, -112, &[]); // SessionExpired
+            let _ = read_frame(&mut stream);
+        });
+        let first = fake_server(move |mut stream| {
+            accept_session(&mut stream, 42);
+            let (xid, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, 4);
+            write_reply(&mut stream, xid, 1, -7, &[]); // OperationTimeout
+            let (xid, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, 4);
+            write_reply(&mut stream, xid, 2, 0, &data2);
+            let (xid, opcode, _) = read_request(&mut stream);
+            assert_eq!(opcode, 4);
+            write_reply(&mut stream, xid, 2, -4, &[]); // ConnectionLoss
+            let _ = read_frame(&mut stream);
+        });
+
+        let mut builder = ZooKeeperBuilder::default();
+        builder.set_shuffle(false);
+        let connect_string = format!("{},{}", first, second);
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let (zk, _) = rt
+            .block_on(builder.connect_string(&connect_string))
+            .unwrap();
+        let states = zk.state_events();
+        let root_cause =
+            |err: failure::Error| *err.find_root_cause().downcast_ref::<ZkError>().unwrap();
+
+        // a timed out operation fails on its own
+        let err = rt.block_on(zk.clone().get_data("/a")).unwrap_err();
+        assert_eq!(root_cause(err), ZkError::OperationTimeout);
+        let (_, res) = rt.block_on(zk.clone().get_data("/a")).unwrap();
+        assert_eq!(res.unwrap().0, Vec::<u8>::new());
+
+        // a lost connection makes us reconnect to the next server
+        let err = rt.block_on(zk.clone().get_data("/a")).unwrap_err();
+        assert_eq!(root_cause(err), ZkError::ConnectionLoss);
+
+        // and an expired session ends the client
+        let err = rt.block_on(zk.clone().get_data("/a")).unwrap_err();
+        assert_eq!(root_cause(err), ZkError::SessionExpired);
+        drop(zk);
+        let states = rt.block_on(states.collect()).unwrap();
+        assert_eq!(
+            states,
+            vec![
+                KeeperState::SyncConnected,
+                KeeperState::Disconnected,
+                KeeperState::SyncConnected,
+                KeeperState::Expired,
+            ]
+        );
+    }
+
+    #[cfg(feature = "tracing")]
+    mod instrumentation {
+        use super::*;
+        use std::collections::HashMap;
+        use std::sync::{Arc, Mutex};
+
+        /// The values of the fields of a span or event, by field name.
+        type Values = HashMap<&'static str, String>;
+
+        /// A `tracing` subscriber that remembers the fields of every span and event.
+        #[derive(Clone, Default)]
+        struct Recorder {
+            spans: Arc<Mutex<Vec<(&'static str, Values)>>>,
+            events: Arc<Mutex<Vec<Values>>>,
+        }
+
+        struct Fields<'a>(&'a mut Values);
+
+        impl<'a> tracing::field::Visit for Fields<'a> {
+            fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+                self.0.insert(field.name(), value.to_string());
+            }
+
+            fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn fmt::Debug) {
+                self.0.insert(field.name(), format!("{:?}", value));
+            }
+        }
+
+        impl tracing::Subscriber for Recorder {
+            fn enabled(&self, _: &tracing::Metadata) -> bool {
+                true
+            }
+
+            fn new_span(&self, span: &tracing::span::Attributes) -> tracing::span::Id {
+                let mut fields = HashMap::new();
+                span.record(&mut Fields(&mut fields));
+                let mut spans = self.spans.lock().unwrap();
+                spans.push((span.metadata().name(), fields));
+                tracing::span::Id::from_u64(spans.len() as u64)
+            }
+
+            fn record(&self, span: &tracing::span::Id, values: &tracing::span::Record) {
+                let mut spans = self.spans.lock().unwrap();
+                values.record(&mut Fields(&mut spans[span.into_u64() as usize - 1].1));
+            }
+
+            fn record_follows_from(&self, _: &tracing::span::Id, _: &tracing::span::Id) {}
+
+            fn event(&self, event: &tracing::Event) {
+                let mut fields = HashMap::new();
+                event.record(&mut Fields(&mut fields));
+                self.events.lock().unwrap().push(fields);
+            }
+
+            fn enter(&self, _: &tracing::span::Id) {}
+
+            fn exit(&self, _: &tracing::span::Id) {}
+        }
+
+        #[test]
+        fn tracing_spans() {
+            let addr = fake_server(|mut stream| {
+                accept_session(&mut stream, 0x42);
+                let (xid, opcode, _) = read_request(&mut stream);
+                assert_eq!(opcode, 1);
+                write_reply(&mut stream, xid, 1, 0, &jute_string("/app/a"));
+                let (xid, opcode, _) = read_request(&mut stream);
+                assert_eq!(opcode, 1);
+                write_reply(&mut stream, xid, 1, -110, &[]);
+                let _ = read_frame(&mut stream); // close session
+            });
+
+            let recorder = Recorder::default();
+            tracing::subscriber::with_default(recorder.clone(), || {
+                // everything has to happen on this thread for the subscriber to see it
+                let mut rt = tokio::runtime::current_thread::Runtime::new().unwrap();
+                let (zk, _) = rt
+                    .block_on(ZooKeeper::connect_string(&format!("{}/app", addr)))
+                    .unwrap();
+                let (zk, res) = rt
+                    .block_on(zk.create(
+                        "/a",
+                        &b"x"[..],
+                        Acl::open_unsafe(),
+                        CreateMode::Persistent,
+                    ))
+                    .unwrap();
+                assert_eq!(res, Ok("/a".to_string()));
+                let (zk, res) = rt
+                    .block_on(zk.create(
+                        "/a",
+                        &b"x"[..],
+                        Acl::open_unsafe(),
+                        CreateMode::Persistent,
+                    ))
+                    .unwrap();
+                assert_eq!(res, Err(error::Create::NodeExists));
+                drop(zk);
+            });
+
+            let spans = recorder.spans.lock().unwrap();
+            let requests: Vec<_> = spans
+                .iter()
+                .filter(|span| span.0 == "request")
+                .map(|span| &span.1)
+                .collect();
+            assert_eq!(requests.len(), 3);
+            assert_eq!(requests[0]["opcode"], "CreateSession");
+            assert_eq!(requests[0]["session_id"], "66");
+            assert!(!requests[0].contains_key("path"));
+            for (create, outcome) in requests[1..].iter().zip(&["ok", "NodeExists"]) {
+                assert_eq!(create["opcode"], "Create");
+                // the path as the user sees it, without the chroot
+                assert_eq!(create["path"], "/a");
+                assert_eq!(create["session_id"], "66");
+                assert_eq!(create["outcome"], *outcome);
+            }
+            assert_ne!(requests[1]["xid"], requests[2]["xid"]);
+
+            let events = recorder.events.lock().unwrap();
+            let connected = events
+                .iter()
+                .find(|e| e["message"] == "connected to server")
+                .unwrap();
+            assert_eq!(connected["session_id"], "66");
+            assert_eq!(connected["resumed"], "false");
+        }
+    }
 }
@@ -0,0 +1,242 @@
+use error;
+use rand;
+
+/// The node the server keeps its own bookkeeping (such as quotas and its configuration) under.
+const RESERVED: &str = "/zookeeper";
+
+/// The number of digits in the sequence number the server appends to sequential nodes.
+const SEQUENCE_DIGITS: usize = 10;
+
+/// What the names of protected nodes start with, as in the reference recipes.
+const PROTECTED_PREFIX: &str = "_c_";
+
+/// Check that `path` is a valid path of a node, before sending it to the server.
+///
+/// The rules are the same as the ones the server enforces. If the node is to be `sequential`,
+/// the path may end in `/`, as the server will append the sequence number to it.
+pub(crate) fn validate(path: &str, sequential: bool) -> Result<(), error::InvalidPath> {
+    let invalid = |reason| {
+        Err(error::InvalidPath {
+            path: path.to_string(),
+            reason,
+        })
+    };
+
+    if path.is_empty() {
+        return invalid("path must not be empty");
+    }
+    if !path.starts_with('/') {
+        return invalid("path must start with /");
+    }
+    if path.len() > 1 && path.ends_with('/') && !sequential {
+        return invalid("path must not end with /");
+    }
+
+    let mut components: Vec<_> = path[1..].split('/').collect();
+    if sequential && path.ends_with('/') {
+        // the sequence number will make up the name of the node
+        components.pop();
+    }
+    for component in components {
+        if path.len() > 1 && component.is_empty() {
+            return invalid("path must not contain empty node names");
+        }
+        if component == "." || component == ".." {
+            return invalid("path must not contain relative node names");
+        }
+        if component.contains('\0') {
+            return invalid("path must not contain null characters");
+        }
+        if component.chars().any(is_disallowed) {
+            return invalid("path must not contain control or private use characters");
+        }
+    }
+    Ok(())
+}
+
+/// Whether the server refuses `c` in paths, as it is a control or private use character.
+fn is_disallowed(c: char) -> bool {
+    ('\u{1}'..='\u{1f}').contains(&c)
+        || ('\u{7f}'..='\u{9f}').contains(&c)
+        || ('\u{e000}'..='\u{f8ff}').contains(&c)
+        || ('\u{fff0}'..='\u{ffff}').contains(&c)
+}
+
+/// Check that the node at `path` is not the one reserved for the server itself, which clients
+/// may not create or delete.
+pub(crate) fn validate_unreserved(path: &str) -> Result<(), error::InvalidPath> {
+    if path == RESERVED {
+        return Err(error::InvalidPath {
+            path: path.to_string(),
+            reason: "path is reserved for the server",
+        });
+    }
+    Ok(())
+}
+
+/// The path of the parent of the node at the valid `path` (which is `/` for the root itself).
+pub(crate) fn parent(path: &str) -> &str {
+    match path.rfind('/') {
+        Some(0) | None => "/",
+        Some(i) => &path[..i],
+    }
+}
+
+/// Split the valid `path` prefix of a sequential node into its parent, and a name for a protected
+/// node in it that starts with a new GUID (such as `_c_<guid>-lock-` for `/locks/lock-`).
+///
+/// The server appends the sequence number to the name, so the node can be told apart from the
+/// other children of the parent by this name alone.
+pub(crate) fn protected(path: &str) -> (&str, String) {
+    let name = &path[path.rfind('/').map_or(0, |i| i + 1)..];
+    let protected = format!("{}{}-{}", PROTECTED_PREFIX, guid(), name);
+    (parent(path), protected)
+}
+
+/// A random (version 4) UUID.
+fn guid() -> String {
+    let mut bytes: [u8; 16] = rand::random();
+    bytes[6] = bytes[6] & 0x0f | 0x40;
+    bytes[8] = bytes[8] & 0x3f | 0x80;
+    let mut guid = String::with_capacity(36);
+    for (i, b) in bytes.iter().enumerate() {
+        if i == 4 || i == 6 || i == 8 || i == 10 {
+            guid.push('-');
+        }
+        guid.push_str(&format!("{:02x}", b));
+    }
+    guid
+}
+
+/// Extract the sequence number from the `path` of a node created with a sequential
+/// [`CreateMode`](::CreateMode), as returned by [`ZooKeeper::create`](::ZooKeeper::create).
+///
+/// The server appends a zero-padded counter of 10 digits to the names of such nodes, so that
+/// `/queue/item-0000000042` has the sequence number 42. Returns `None` if `path` does not end in
+/// such a counter.
+///
+/// ```
+/// # use tokio_zookeeper::sequence_number;
+/// assert_eq!(sequence_number("/queue/item-0000000042"), Some(42));
+/// assert_eq!(sequence_number("/queue/item"), None);
+/// ```
+pub fn sequence_number(path: &str) -> Option<u64> {
+    let len = path.len();
+    if len < SEQUENCE_DIGITS || !path.is_char_boundary(len - SEQUENCE_DIGITS) {
+        return None;
+    }
+    let counter = &path[len - SEQUENCE_DIGITS..];
+    if !counter.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    counter.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reason(path: &str, sequential: bool) -> &'static str {
+        validate(path, sequential).unwrap_err().reason
+    }
+
+    #[test]
+    fn valid() {
+        for path in &["/", "/a", "/a/b", "/a.b/..c/.d.", "/zookeeper/quota", "/ü"] {
+            assert_eq!(validate(path, false), Ok(()), "{}", path);
+        }
+        assert_eq!(validate("/a/", true), Ok(()));
+        assert_eq!(validate("/a/lock-", true), Ok(()));
+        assert_eq!(validate("/", true), Ok(()));
+    }
+
+    #[test]
+    fn parents() {
+        assert_eq!(parent("/a/b"), "/a");
+        assert_eq!(parent("/a"), "/");
+        assert_eq!(parent("/"), "/");
+        // the prefix of a sequential node
+        assert_eq!(parent("/a/"), "/a");
+    }
+
+    #[test]
+    fn invalid() {
+        assert_eq!(reason("", false), "path must not be empty");
+        assert_eq!(reason("a/b", false), "path must start with /");
+        assert_eq!(reason("/a/", false), "path must not end with /");
+        assert_eq!(
+            reason("/a//b", false),
+            "path must not contain empty node names"
+        );
+        assert_eq!(
+            reason("/a//", true),
+            "path must not contain empty node names"
+        );
+        assert_eq!(
+            reason("/a/./b", false),
+            "path must not contain relative node names"
+        );
+        assert_eq!(
+            reason("/..", false),
+            "path must not contain relative node names"
+        );
+        assert_eq!(
+            reason("/a\0b", false),
+            "path must not contain null characters"
+        );
+        assert_eq!(
+            reason("/a\u{7}", false),
+            "path must not contain control or private use characters"
+        );
+        assert_eq!(
+            reason("/a\u{fff5}", true),
+            "path must not contain control or private use characters"
+        );
+    }
+
+    #[test]
+    fn sequence_numbers() {
+        assert_eq!(sequence_number("/a/lock-0000000003"), Some(3));
+        assert_eq!(sequence_number("/a/0000000000"), Some(0));
+        assert_eq!(sequence_number("/0000000100"), Some(100));
+        assert_eq!(sequence_number("/a/n_2147483647"), Some(2_147_483_647));
+        // only the last 10 digits are the counter
+        assert_eq!(sequence_number("/a/job-420000000007"), Some(7));
+
+        assert_eq!(sequence_number("/a/lock"), None);
+        assert_eq!(sequence_number("/a/lock-"), None);
+        assert_eq!(sequence_number("/a/lock-123"), None);
+        assert_eq!(sequence_number("/a/lock-00000000x3"), None);
+        assert_eq!(sequence_number("/a/lock-+000000003"), None);
+        assert_eq!(sequence_number("/000000001"), None);
+        assert_eq!(sequence_number("/lock-ü00000003"), None);
+        assert_eq!(sequence_number(""), None);
+    }
+
+    #[test]
+    fn protected_names() {
+        let (parent, name) = protected("/locks/lock-");
+        assert_eq!(parent, "/locks");
+        assert!(name.starts_with("_c_"), "{}", name);
+        assert!(name.ends_with("-lock-"), "{}", name);
+        assert_eq!(name.len(), "_c_".len() + 36 + "-lock-".len());
+        assert_eq!(validate(&format!("{}/{}", parent, name), true), Ok(()));
+        // every node gets a name of its own
+        assert_ne!(protected("/locks/lock-").1, name);
+
+        let (parent, name) = protected("/");
+        assert_eq!(parent, "/");
+        assert_eq!(name.len(), "_c_".len() + 36 + "-".len());
+    }
+
+    #[test]
+    fn reserved() {
+        assert!(validate_unreserved("/zookeeper").is_err());
+        assert_eq!(validate_unreserved("/zookeeper/quota"), Ok(()));
+        assert_eq!(validate_unreserved("/zoo"), Ok(()));
+        assert_eq!(
+            validate_unreserved("/zookeeper").unwrap_err().to_string(),
+            "invalid path \"/zookeeper\": path is reserved for the server"
+        );
+    }
+}
@@ -1,13 +1,52 @@
-use super::{request, watch::WatchType, Request, Response};
+use super::{
+    broadcast::Broadcast,
+    chroot,
+    instrument::{self, RequestSpan},
+    limit::Responder,
+    request,
+    response::{self, Lossy, Utf8Policy},
+    state::SessionState,
+    watch::{WatchRegistry, WatchType},
+    Request, Response,
+};
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
-use failure;
-use futures::sync::{mpsc, oneshot};
+use bytes::BytesMut;
+use failure::{self, Fail};
+use futures::sync::oneshot;
 use slog;
-use std::collections::HashMap;
-use std::{mem, time};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time;
 use tokio;
 use tokio::prelude::*;
-use {WatchedEvent, WatchedEventType, ZkError};
+use {error, AddWatchMode, KeeperState, WatchedEvent, WatcherType, ZkError};
+
+/// The smallest possible response to a connect request (with an empty password, and without the
+/// trailing read-only flag sent by newer servers).
+const MIN_CONNECT_RESPONSE: i32 = 4 + 4 + 8 + 4;
+
+/// An upper bound on the size of a response to a connect request. Servers send a 16 byte password,
+/// so anything close to this is already suspicious.
+const MAX_CONNECT_RESPONSE: i32 = MIN_CONNECT_RESPONSE + 1 + 1024;
+
+/// The xid the server uses for responses to auth packets.
+const AUTH_XID: i32 = -4;
+
+/// Check that the response of `length` bytes to `operation` was decoded completely, with only
+/// `rest` left over.
+fn check_decoded(operation: &str, length: usize, rest: &[u8]) -> Result<(), error::ProtocolError> {
+    if rest.is_empty() {
+        return Ok(());
+    }
+    Err(error::ProtocolError {
+        operation: operation.to_string(),
+        length,
+        unread: rest.len(),
+    })
+}
+
+/// The scheme and bytes of credentials added with `add_auth`.
+pub(super) type Credentials = (String, Cow<'static, [u8]>);
 
 pub(super) struct ActivePacketizer<S> {
     stream: S,
@@ -16,58 +55,132 @@ pub(super) struct ActivePacketizer<S> {
     timer: tokio::timer::Delay,
     timeout: time::Duration,
 
+    /// Heartbeat interval requested by the user, if any.
+    ping_interval: Option<time::Duration>,
+
+    /// Fires when the server has not sent us anything for the whole session timeout.
+    read_timer: tokio::timer::Delay,
+
+    /// Fires when the server has not sent us anything for `idle_timeout`.
+    idle_timer: tokio::timer::Delay,
+    idle_timeout: time::Duration,
+
+    /// Read idle timeout requested by the user, if any.
+    read_idle_timeout: Option<time::Duration>,
+
+    /// Whether responses must be decoded down to their last byte.
+    check_response_length: bool,
+
+    /// How to decode strings in responses that are not valid UTF-8.
+    utf8_policy: Utf8Policy,
+
     /// Bytes we have not yet set.
     pub(super) outbox: Vec<u8>,
 
     /// Prefix of outbox that has been sent.
     outstart: usize,
 
-    /// Bytes we have not yet deserialized.
-    inbox: Vec<u8>,
-
-    /// Prefix of inbox that has been sent.
-    instart: usize,
+    /// Bytes we have not yet deserialized, which each response is split off from.
+    inbox: BytesMut,
 
     /// What operation are we waiting for a response for?
-    reply: HashMap<i32, (request::OpCode, oneshot::Sender<Result<Response, ZkError>>)>,
+    reply: HashMap<i32, (request::OpCode, Responder, RequestSpan)>,
+
+    /// Auth requests waiting for a response (in the order they were sent)
+    pending_auth: VecDeque<(Credentials, Responder)>,
+
+    /// Credentials the server has accepted (in the order they were added)
+    pub(super) auth: Vec<Credentials>,
+
+    /// Registered watchers (path -> watcher)
+    pub(super) watchers: WatchRegistry,
+
+    /// Registered watchers (xid -> watcher to add when ok)
+    pub(super) pending_watchers:
+        HashMap<i32, (String, Option<oneshot::Sender<WatchedEvent>>, WatchType)>,
+
+    /// Persistent watches sent to the server (xid -> watch to add when ok)
+    pending_persistent: HashMap<i32, (String, AddWatchMode)>,
 
-    /// Custom registered watchers (path -> watcher)
-    watchers: HashMap<String, Vec<(oneshot::Sender<WatchedEvent>, WatchType)>>,
+    /// Watch removals sent to the server (xid -> watchers to remove when ok)
+    pending_removals: HashMap<i32, (String, WatcherType)>,
 
-    /// Custom registered watchers (xid -> watcher to add when ok)
-    pub(super) pending_watchers: HashMap<i32, (String, oneshot::Sender<WatchedEvent>, WatchType)>,
+    /// Reads whose data is sliced out of the response rather than copied (xids)
+    shared_data: HashSet<i32>,
 
-    first: bool,
+    /// Prefix of all server paths (client paths are used everywhere else, including for watchers)
+    chroot: Option<String>,
+
+    pub(super) first: bool,
+
+    /// Set if the server refused to establish or resume our session.
+    pub(super) session_expired: bool,
+
+    /// Set if we are connected to a server in read-only mode, which will refuse all writes.
+    read_only: bool,
+
+    /// Where to report the outcome of the handshake
+    session_state: SessionState,
 
     /// Fields for re-connection
     pub(super) last_zxid_seen: i64,
     pub(super) session_id: i64,
     pub(super) password: Vec<u8>,
+    /// The negotiated session timeout in milliseconds.
+    pub(super) session_timeout: i32,
 }
 
 impl<S> ActivePacketizer<S>
 where
     S: AsyncRead + AsyncWrite,
 {
-    pub(super) fn new(stream: S) -> Self {
+    pub(super) fn new(
+        stream: S,
+        chroot: Option<String>,
+        ping_interval: Option<time::Duration>,
+        read_idle_timeout: Option<time::Duration>,
+        check_response_length: bool,
+        utf8_policy: Utf8Policy,
+        session_state: SessionState,
+    ) -> Self {
         ActivePacketizer {
             stream,
             timer: tokio::timer::Delay::new(
                 time::Instant::now() + time::Duration::from_secs(86_400),
             ),
             timeout: time::Duration::new(86_400, 0),
+            ping_interval,
+            read_timer: tokio::timer::Delay::new(
+                time::Instant::now() + time::Duration::from_secs(86_400),
+            ),
+            idle_timer: tokio::timer::Delay::new(
+                time::Instant::now() + time::Duration::from_secs(86_400),
+            ),
+            idle_timeout: time::Duration::new(86_400, 0),
+            read_idle_timeout,
+            check_response_length,
+            utf8_policy,
             outbox: Vec::new(),
             outstart: 0,
-            inbox: Vec::new(),
-            instart: 0,
+            inbox: BytesMut::new(),
             reply: Default::default(),
+            pending_auth: Default::default(),
+            auth: Default::default(),
             watchers: Default::default(),
             pending_watchers: Default::default(),
+            pending_persistent: Default::default(),
+            pending_removals: Default::default(),
+            shared_data: Default::default(),
+            chroot,
             first: true,
+            session_expired: false,
+            read_only: false,
+            session_state,
 
             last_zxid_seen: 0,
             session_id: 0,
             password: Vec::new(),
+            session_timeout: 0,
         }
     }
 
@@ -76,15 +189,49 @@ where
     }
 
     fn inlen(&self) -> usize {
-        self.inbox.len() - self.instart
+        self.inbox.len()
     }
 
-    pub(super) fn enqueue(
-        &mut self,
-        xid: i32,
-        item: Request,
-        tx: oneshot::Sender<Result<Response, ZkError>>,
-    ) {
+    pub(super) fn enqueue(&mut self, xid: i32, mut item: Request, tx: Responder) {
+        if self.read_only && item.is_write() {
+            // the server would refuse it anyway
+            let _ = tx.send(Err(ZkError::NotReadOnly));
+            return;
+        }
+
+        if let Request::RemoveWatches {
+            ref path,
+            watcher_type,
+            local,
+        } = item
+        {
+            if local {
+                // never goes to the server, so we can answer right away
+                let _ = tx.send(match self.watchers.remove(path, watcher_type) {
+                    0 => Err(ZkError::NoWatcher),
+                    _ => Ok(Response::Empty),
+                });
+                return;
+            }
+            self.pending_removals
+                .insert(xid, (path.clone(), watcher_type));
+        }
+
+        if let Request::AddWatch { ref path, mode } = item {
+            self.pending_persistent.insert(xid, (path.clone(), mode));
+        }
+
+        if let Request::GetDataShared { .. } = item {
+            self.shared_data.insert(xid);
+        }
+
+        // the span shows the path as the user knows it
+        let span = RequestSpan::new(self.session_id, xid, &item);
+
+        if let Some(ref chroot) = self.chroot {
+            item.prepend_chroot(chroot);
+        }
+
         let lengthi = self.outbox.len();
         // dummy length
         self.outbox.push(0);
@@ -92,8 +239,21 @@ where
         self.outbox.push(0);
         self.outbox.push(0);
 
-        let old = self.reply.insert(xid, (item.opcode(), tx));
-        assert!(old.is_none());
+        // auth packets are answered with a fixed xid, and in the order they were sent
+        let xid = if let Request::Auth {
+            ref scheme,
+            ref auth,
+        } = item
+        {
+            self.pending_auth
+                .push_back(((scheme.clone(), auth.clone()), tx));
+            AUTH_XID
+        } else {
+            let old = self.reply.insert(xid, (item.opcode(), tx, span));
+            assert!(old.is_none());
+            self.session_state.set_outstanding(self.reply.len());
+            xid
+        };
 
         if let Request::Connect { .. } = item {
         } else {
@@ -103,7 +263,7 @@ where
                 .expect("Vec::write should never fail");
             // opcode
             self.outbox
-                .write_i32::<BigEndian>(item.opcode() as i32)
+                .write_i32::<BigEndian>(item.opcode().code())
                 .expect("Vec::write should never fail");
         }
 
@@ -118,6 +278,20 @@ where
             .expect("Vec::write should never fail");
     }
 
+    /// Fail every request that is still waiting for a response with `e`, since the connection is
+    /// gone and the responses will never arrive.
+    pub(super) fn fail_pending(&mut self, e: ZkError) {
+        for (_, (_, tx, span)) in self.reply.drain() {
+            let r = Err(e);
+            span.finish(&r);
+            let _ = tx.send(r);
+        }
+        self.session_state.set_outstanding(0);
+        for (_, tx) in self.pending_auth.drain(..) {
+            let _ = tx.send(Err(e));
+        }
+    }
+
     fn poll_write(
         &mut self,
         exiting: bool,
@@ -153,9 +327,32 @@ where
         Ok(Async::Ready(()))
     }
 
+    /// Read the length prefix of the next frame in the inbox.
+    ///
+    /// The very first frame on a connection is the response to our connect request, which has a
+    /// small, fixed layout. If its length is implausible, the peer is most likely not a ZooKeeper
+    /// server at all, and we give up right away rather than waiting for (or trying to parse)
+    /// whatever it sends us.
+    fn next_frame_length(&mut self) -> Result<usize, failure::Error> {
+        let length = (&mut &self.inbox[..]).read_i32::<BigEndian>()?;
+        if self.first && !(MIN_CONNECT_RESPONSE..=MAX_CONNECT_RESPONSE).contains(&length) {
+            return Err(self.reject_handshake());
+        }
+        Ok(length as usize)
+    }
+
+    /// Fail the pending connect request because the peer does not speak the ZooKeeper protocol.
+    fn reject_handshake(&mut self) -> failure::Error {
+        for (_, (_, tx, _)) in self.reply.drain() {
+            let _ = tx.send(Err(ZkError::MarshallingError));
+        }
+        self.session_state.set_outstanding(0);
+        error::NotAZooKeeperServer.into()
+    }
+
     fn poll_read(
         &mut self,
-        default_watcher: &mut mpsc::UnboundedSender<WatchedEvent>,
+        events: &Broadcast<WatchedEvent>,
         logger: &mut slog::Logger,
     ) -> Result<Async<()>, failure::Error>
     where
@@ -163,7 +360,7 @@ where
     {
         loop {
             let mut need = if self.inlen() >= 4 {
-                let length = (&mut &self.inbox[self.instart..]).read_i32::<BigEndian>()? as usize;
+                let length = self.next_frame_length()?;
                 length + 4
             } else {
                 4
@@ -172,16 +369,25 @@ where
 
             while self.inlen() < need {
                 let read_from = self.inbox.len();
-                self.inbox.resize(self.instart + need, 0);
+                self.inbox.resize(need, 0);
                 match self.stream.poll_read(&mut self.inbox[read_from..])? {
                     Async::Ready(n) => {
                         self.inbox.truncate(read_from + n);
+                        if n != 0 && self.session_timeout > 0 {
+                            // the server is still there
+                            self.read_timer.reset(
+                                time::Instant::now()
+                                    + time::Duration::from_millis(self.session_timeout as u64),
+                            );
+                            self.idle_timer
+                                .reset(time::Instant::now() + self.idle_timeout);
+                        }
                         if n == 0 {
                             if self.inlen() != 0 {
                                 bail!(
                                     "connection closed with {} bytes left in buffer: {:x?}",
                                     self.inlen(),
-                                    &self.inbox[self.instart..]
+                                    &self.inbox[..]
                                 );
                             } else {
                                 // Server closed session with no bytes left in buffer
@@ -191,8 +397,7 @@ where
                         }
 
                         if self.inlen() >= 4 && need == 4 {
-                            let length = (&mut &self.inbox[self.instart..]).read_i32::<BigEndian>()?
-                                as usize;
+                            let length = self.next_frame_length()?;
                             need += length;
                         }
                     }
@@ -205,8 +410,9 @@ where
 
             {
                 let mut err = None;
-                let mut buf = &self.inbox[self.instart + 4..self.instart + need];
-                self.instart += need;
+                // responses may keep slices of their frame around
+                let frame = self.inbox.split_to(need).freeze();
+                let mut buf = &frame[4..];
 
                 let xid = if self.first {
                     0
@@ -223,6 +429,7 @@ where
 
                         assert!(zxid >= self.last_zxid_seen);
                         self.last_zxid_seen = zxid;
+                        self.session_state.saw_zxid(zxid);
                     }
                     let zk_err: ZkError = buf.read_i32::<BigEndian>()?.into();
                     if zk_err != ZkError::Ok {
@@ -240,65 +447,52 @@ where
                     }
                 } else if xid == -1 {
                     // watch event
-                    use super::response::ReadFrom;
-                    let e = WatchedEvent::read_from(&mut buf)?;
-                    trace!(logger, "got watcher event {:?}", e);
-
-                    let mut remove = false;
-                    if let Some(watchers) = self.watchers.get_mut(&e.path) {
-                        // custom watchers were set by the user -- notify them
-                        let mut i = (watchers.len() - 1) as isize;
-                        trace!(logger,
-                               "found potentially waiting custom watchers";
-                               "n" => watchers.len()
-                        );
-
-                        while i >= 0 {
-                            let triggers = match (&watchers[i as usize].1, e.event_type) {
-                                (WatchType::Child, WatchedEventType::NodeDeleted)
-                                | (WatchType::Child, WatchedEventType::NodeChildrenChanged) => true,
-                                (WatchType::Child, _) => false,
-                                (WatchType::Data, WatchedEventType::NodeDeleted)
-                                | (WatchType::Data, WatchedEventType::NodeDataChanged) => true,
-                                (WatchType::Data, _) => false,
-                                (WatchType::Exist, WatchedEventType::NodeChildrenChanged) => false,
-                                (WatchType::Exist, _) => true,
-                            };
-
-                            if triggers {
-                                // this watcher is no longer active
-                                let w = watchers.swap_remove(i as usize);
-                                // NOTE: ignore the case where the receiver has been dropped
-                                let _ = w.0.send(e.clone());
-                            }
-                            i -= 1;
-                        }
-
-                        if watchers.is_empty() {
-                            remove = true;
-                        }
+                    let mut e = response::read_watched_event(&mut buf, self.utf8_policy)?;
+                    if self.check_response_length {
+                        check_decoded("watch event", need - 4, buf)?;
                     }
-
-                    if remove {
-                        self.watchers
-                            .remove(&e.path)
-                            .expect("tried to remove watcher that didn't exist");
+                    if let Some(ref chroot) = self.chroot {
+                        chroot::strip(chroot, &mut e.path);
                     }
+                    trace!(logger, "got watcher event {:?}", e);
+                    instrument::watch_fired(self.session_id, &e);
 
-                    // NOTE: ignoring error, because the user may not care about events
-                    let _ = default_watcher.unbounded_send(e);
+                    // custom watchers may have been set by the user -- notify them
+                    let notified = self.watchers.fire(&e);
+                    trace!(logger, "notified custom watchers"; "n" => notified);
+
+                    events.send(&e);
                 } else if xid == -2 {
                     // response to ping -- empty response
                     trace!(logger, "got response to heartbeat");
                     if let Some(e) = err {
                         bail!("bad response to ping: {:?}", e);
                     }
+                } else if xid == AUTH_XID {
+                    // response to add_auth -- empty response
+                    trace!(logger, "got response to auth"; "err" => ?err);
+                    if let Some((credentials, tx)) = self.pending_auth.pop_front() {
+                        // credentials that are added again after re-connecting are already known
+                        if err.is_none() && !self.auth.contains(&credentials) {
+                            self.auth.push(credentials);
+                        }
+                        let _ = tx.send(match err {
+                            Some(e) => Err(e),
+                            None => Ok(Response::Empty),
+                        });
+                    }
                 } else {
                     // response to user request
                     self.first = false;
 
                     // find the waiting request future
-                    let (opcode, tx) = self.reply.remove(&xid).unwrap(); // TODO: return an error if xid was unknown
+                    let (opcode, tx, span) = self.reply.remove(&xid).unwrap(); // TODO: return an error if xid was unknown
+                    let shared = self.shared_data.remove(&xid);
+                    self.session_state.set_outstanding(self.reply.len());
+                    if opcode != request::OpCode::CreateSession {
+                        self.session_state
+                            .learn(opcode, err != Some(ZkError::Unimplemented));
+                    }
 
                     if let Some(w) = self.pending_watchers.remove(&xid) {
                         // normally, watches are *only* added for successful operations
@@ -307,10 +501,14 @@ where
                             || (opcode == request::OpCode::Exists && err == Some(ZkError::NoNode))
                         {
                             trace!(logger, "pending watcher turned into real watcher"; "xid" => xid);
-                            self.watchers
-                                .entry(w.0)
-                                .or_insert_with(Vec::new)
-                                .push((w.1, w.2));
+                            // the server tracks an exists watch on an existing node as a data
+                            // watch, which matters when the watch is re-registered
+                            let wtype = if w.2 == WatchType::Exist && err.is_none() {
+                                WatchType::Data
+                            } else {
+                                w.2
+                            };
+                            self.watchers.add(w.0, w.1, wtype);
                         } else {
                             trace!(logger,
                                    "pending watcher not turned into real watcher: {:?}",
@@ -320,14 +518,84 @@ where
                         }
                     }
 
+                    if let Some((path, mode)) = self.pending_persistent.remove(&xid) {
+                        if err.is_none() {
+                            self.watchers.add_persistent(path, mode);
+                        }
+                    }
+
+                    if let Some((path, wtype)) = self.pending_removals.remove(&xid) {
+                        if err.is_none() {
+                            let removed = self.watchers.remove(&path, wtype);
+                            trace!(logger, "removed custom watchers"; "n" => removed, "xid" => xid);
+                        }
+                    }
+
                     if let Some(e) = err {
                         info!(logger,
                                "handling server error response: {:?}", e;
                                "xid" => xid, "opcode" => ?opcode);
 
-                        tx.send(Err(e)).is_ok();
+                        let r = Err(e);
+                        span.finish(&r);
+                        tx.send(r).is_ok();
+
+                        match e {
+                            ZkError::SessionExpired => {
+                                // there is no body to read, and no session to keep using
+                                info!(logger, "session expired"; "session_id" => self.session_id);
+                                instrument::expired(self.session_id);
+                                self.session_expired = true;
+                                self.session_state.transition(KeeperState::Expired);
+                                return Err(e
+                                    .context("server reported that the session expired")
+                                    .into());
+                            }
+                            ZkError::ConnectionLoss | ZkError::RuntimeInconsistency => {
+                                // the server can no longer serve this connection, so move on to
+                                // another one (which fails the other in-flight requests)
+                                return Err(e
+                                    .context("server reported a broken connection")
+                                    .into());
+                            }
+                            _ => {}
+                        }
                     } else {
-                        let mut r = Response::parse(opcode, &mut buf)?;
+                        let parsed = if shared {
+                            Response::parse_shared_data(&frame, &mut buf)
+                        } else {
+                            match self.utf8_policy {
+                                Utf8Policy::Strict => Response::parse(opcode, &mut buf),
+                                Utf8Policy::Lossy => Response::parse(opcode, &mut Lossy(&mut buf)),
+                            }
+                        };
+                        let mut r = match parsed {
+                            Ok(Response::Connect {
+                                protocol_version, ..
+                            }) if protocol_version != 0 =>
+                            {
+                                // we only speak protocol version 0
+                                let _ = tx.send(Err(ZkError::MarshallingError));
+                                return Err(self.reject_handshake());
+                            }
+                            Err(_) if opcode == request::OpCode::CreateSession => {
+                                let _ = tx.send(Err(ZkError::MarshallingError));
+                                return Err(self.reject_handshake());
+                            }
+                            r => r?,
+                        };
+                        if self.check_response_length {
+                            let operation = format!("{:?}", opcode);
+                            if let Err(e) = check_decoded(&operation, need - 4, buf) {
+                                // what is left may well be the start of the next response
+                                let _ = tx.send(Err(ZkError::MarshallingError));
+                                return Err(e.into());
+                            }
+                        }
+
+                        if let Some(ref chroot) = self.chroot {
+                            r.strip_chroot(opcode, chroot);
+                        }
 
                         debug!(logger,
                                "handling server response: {:?}", r;
@@ -336,30 +604,60 @@ where
                         if let Response::Connect {
                             timeout,
                             session_id,
-                            ref mut password,
+                            ref password,
+                            read_only,
                             ..
                         } = r
                         {
-                            assert!(timeout >= 0);
-                            trace!(logger, "negotiated session timeout: {}ms", timeout);
-
-                            self.timeout = time::Duration::from_millis(2 * timeout as u64 / 3);
-                            self.timer.reset(time::Instant::now() + self.timeout);
-
-                            // keep track of these for consistent re-connect
-                            self.session_id = session_id;
-                            mem::swap(&mut self.password, password);
+                            if timeout <= 0 {
+                                // the server refused to establish or resume the session, and
+                                // will close the connection after this response.
+                                info!(logger, "session expired"; "session_id" => self.session_id);
+                                instrument::expired(self.session_id);
+                                self.session_expired = true;
+                                self.session_state.transition(KeeperState::Expired);
+                            } else {
+                                trace!(logger, "negotiated session timeout: {}ms", timeout);
+
+                                // like the reference client, ping three times per session
+                                // timeout unless told otherwise
+                                self.timeout = self
+                                    .ping_interval
+                                    .unwrap_or(time::Duration::from_millis(timeout as u64 / 3));
+                                self.timer.reset(time::Instant::now() + self.timeout);
+                                self.read_timer.reset(
+                                    time::Instant::now()
+                                        + time::Duration::from_millis(timeout as u64),
+                                );
+                                // every ping should be answered well before the next one is due,
+                                // so missing two in a row means the connection is likely dead
+                                self.idle_timeout =
+                                    self.read_idle_timeout.unwrap_or(self.timeout * 2);
+                                self.idle_timer
+                                    .reset(time::Instant::now() + self.idle_timeout);
+
+                                // the session id is only known up front if we are resuming
+                                instrument::connected(session_id, self.session_id != 0, read_only);
+
+                                // keep track of these for consistent re-connect
+                                self.session_id = session_id;
+                                self.session_timeout = timeout;
+                                self.password = password.clone();
+                                self.read_only = read_only;
+                                self.session_state.transition(if read_only {
+                                    KeeperState::ConnectedReadOnly
+                                } else {
+                                    KeeperState::SyncConnected
+                                });
+                            }
                         }
 
-                        tx.send(Ok(r)).is_ok(); // if receiver doesn't care, we don't either
+                        let r = Ok(r);
+                        span.finish(&r);
+                        tx.send(r).is_ok(); // if receiver doesn't care, we don't either
                     }
                 }
             }
-
-            if self.instart == self.inbox.len() {
-                self.inbox.clear();
-                self.instart = 0;
-            }
         }
     }
 
@@ -367,10 +665,32 @@ where
         &mut self,
         exiting: bool,
         logger: &mut slog::Logger,
-        default_watcher: &mut mpsc::UnboundedSender<WatchedEvent>,
+        events: &Broadcast<WatchedEvent>,
     ) -> Result<Async<()>, failure::Error> {
         trace!(logger, "poll_read");
-        let r = self.poll_read(default_watcher, logger)?;
+        let r = self.poll_read(events, logger)?;
+
+        if let Async::Ready(()) = self.read_timer.poll()? {
+            // the server has not answered our pings for as long as it keeps a session alive
+            // without hearing from us, so it will have expired the session by now
+            info!(logger, "server stopped responding"; "session_id" => self.session_id);
+            instrument::expired(self.session_id);
+            self.session_expired = true;
+            self.session_state.transition(KeeperState::Expired);
+            return Err(ZkError::SessionExpired
+                .context("no response from server within the session timeout")
+                .into());
+        }
+
+        if let Async::Ready(()) = self.idle_timer.poll()? {
+            // the session may well still be alive, but this connection is not; a half-open TCP
+            // connection would otherwise only be noticed once the session has timed out
+            info!(logger, "server idle for too long"; "idle" => ?self.idle_timeout);
+            bail!(
+                "no response from server for {:?}; connection assumed lost",
+                self.idle_timeout
+            );
+        }
 
         if let Async::Ready(()) = self.timer.poll()? {
             if self.outbox.is_empty() {
@@ -385,7 +705,7 @@ where
                     .expect("Vec::write should never fail");
                 // opcode
                 self.outbox
-                    .write_i32::<BigEndian>(request::OpCode::Ping as i32)
+                    .write_i32::<BigEndian>(request::OpCode::Ping.code())
                     .expect("Vec::write should never fail");
                 trace!(logger, "sending heartbeat");
             } else {
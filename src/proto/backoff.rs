@@ -0,0 +1,169 @@
+use failure;
+use rand::{self, Rng};
+use std::{cmp, time};
+use tokio;
+use tokio::prelude::*;
+
+/// How long to wait between attempts to re-connect to the ensemble.
+///
+/// Every time the client has tried all servers without success, it waits before trying them
+/// again. The first wait lasts for `initial`, and every following one is `multiplier` times
+/// longer than the one before, up to at most `max`. Once a connection succeeds, the next loss of
+/// connection starts over from `initial`.
+///
+/// By default, the client waits for 100ms, doubling the wait up to at most 5s.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Backoff {
+    /// How long to wait after the first unsuccessful attempt.
+    pub initial: time::Duration,
+    /// The longest to ever wait between attempts.
+    pub max: time::Duration,
+    /// How much longer each wait is than the one before.
+    pub multiplier: f64,
+    /// Whether to wait for a random duration of between half of and the full wait, so that
+    /// clients that lost their connection at the same time do not all retry at the same time.
+    pub jitter: bool,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff {
+            initial: time::Duration::from_millis(100),
+            max: time::Duration::from_secs(5),
+            multiplier: 2.0,
+            jitter: false,
+        }
+    }
+}
+
+impl Backoff {
+    /// The successive waits between attempts.
+    pub(crate) fn delays(&self) -> Delays {
+        Delays {
+            next: cmp::min(self.initial, self.max),
+            backoff: *self,
+        }
+    }
+}
+
+/// An endless sequence of waits according to a [`Backoff`].
+#[derive(Debug)]
+pub(crate) struct Delays {
+    next: time::Duration,
+    backoff: Backoff,
+}
+
+impl Iterator for Delays {
+    type Item = time::Duration;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let delay = self.next;
+        // multiply in floating point, as the product may not fit in a `Duration`
+        let next = delay.as_secs_f64() * self.backoff.multiplier;
+        self.next = if next < self.backoff.max.as_secs_f64() {
+            time::Duration::from_secs_f64(next)
+        } else {
+            self.backoff.max
+        };
+        if self.backoff.jitter {
+            Some(delay.mul_f64(rand::thread_rng().gen_range(0.5, 1.0)))
+        } else {
+            Some(delay)
+        }
+    }
+}
+
+/// The source of time used when re-connecting, so that tests can control it.
+pub(crate) trait Clock: Clone + Send + 'static {
+    /// The current time.
+    fn now(&self) -> time::Instant;
+
+    /// A future that resolves at `deadline`.
+    fn sleep_until(
+        &self,
+        deadline: time::Instant,
+    ) -> Box<dyn Future<Item = (), Error = failure::Error> + Send>;
+}
+
+/// The real time, as kept by the tokio runtime.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct TokioClock;
+
+impl Clock for TokioClock {
+    fn now(&self) -> time::Instant {
+        time::Instant::now()
+    }
+
+    fn sleep_until(
+        &self,
+        deadline: time::Instant,
+    ) -> Box<dyn Future<Item = (), Error = failure::Error> + Send> {
+        Box::new(tokio::timer::Delay::new(deadline).map_err(failure::Error::from))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> time::Duration {
+        time::Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn exponential() {
+        let delays: Vec<_> = Backoff::default().delays().take(8).collect();
+        assert_eq!(
+            delays,
+            vec![
+                ms(100),
+                ms(200),
+                ms(400),
+                ms(800),
+                ms(1600),
+                ms(3200),
+                ms(5000),
+                ms(5000)
+            ]
+        );
+
+        let backoff = Backoff {
+            initial: ms(10),
+            max: ms(100),
+            multiplier: 3.0,
+            jitter: false,
+        };
+        let delays: Vec<_> = backoff.delays().take(4).collect();
+        assert_eq!(delays, vec![ms(10), ms(30), ms(90), ms(100)]);
+
+        // the cap applies from the start
+        let backoff = Backoff {
+            initial: ms(200),
+            max: ms(100),
+            ..Backoff::default()
+        };
+        assert_eq!(backoff.delays().next(), Some(ms(100)));
+    }
+
+    #[test]
+    fn huge_multiplier() {
+        let backoff = Backoff {
+            multiplier: 1e20,
+            ..Backoff::default()
+        };
+        let delays: Vec<_> = backoff.delays().take(3).collect();
+        assert_eq!(delays, vec![ms(100), ms(5000), ms(5000)]);
+    }
+
+    #[test]
+    fn jitter() {
+        let backoff = Backoff {
+            jitter: true,
+            ..Backoff::default()
+        };
+        let delays = backoff.delays().zip(Backoff::default().delays()).take(10);
+        for (jittered, delay) in delays {
+            assert!(jittered >= delay / 2 && jittered <= delay);
+        }
+    }
+}
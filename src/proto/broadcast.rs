@@ -0,0 +1,81 @@
+use futures::sync::mpsc;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+/// Hands out a copy of every value it is given to each of its subscribers.
+///
+/// Clones share the same set of subscribers.
+pub(crate) struct Broadcast<T>(Arc<Mutex<Option<Vec<mpsc::UnboundedSender<T>>>>>);
+
+impl<T> Clone for Broadcast<T> {
+    fn clone(&self) -> Self {
+        Broadcast(Arc::clone(&self.0))
+    }
+}
+
+impl<T> Default for Broadcast<T> {
+    fn default() -> Self {
+        Broadcast(Arc::new(Mutex::new(Some(Vec::new()))))
+    }
+}
+
+impl<T> fmt::Debug for Broadcast<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let subscribers = self.0.lock().unwrap().as_ref().map(Vec::len);
+        f.debug_tuple("Broadcast").field(&subscribers).finish()
+    }
+}
+
+impl<T: Clone> Broadcast<T> {
+    /// Get a stream of all values sent from now on.
+    ///
+    /// The stream ends once the broadcast is closed.
+    pub(crate) fn subscribe(&self) -> mpsc::UnboundedReceiver<T> {
+        let (tx, rx) = mpsc::unbounded();
+        self.add(tx);
+        rx
+    }
+
+    /// Send all values from now on to `tx`.
+    pub(crate) fn add(&self, tx: mpsc::UnboundedSender<T>) {
+        if let Some(ref mut subscribers) = *self.0.lock().unwrap() {
+            subscribers.push(tx);
+        }
+    }
+
+    /// Send a copy of `value` to every subscriber, and forget the ones that have gone away.
+    pub(crate) fn send(&self, value: &T) {
+        if let Some(ref mut subscribers) = *self.0.lock().unwrap() {
+            subscribers.retain(|tx| tx.unbounded_send(value.clone()).is_ok());
+        }
+    }
+
+    /// End the streams of all subscribers, including those that subscribe later.
+    pub(crate) fn close(&self) {
+        *self.0.lock().unwrap() = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::Stream;
+
+    #[test]
+    fn broadcast() {
+        let b = Broadcast::default();
+        let early = b.subscribe();
+        b.send(&1);
+        let late = b.subscribe();
+        let gone = b.subscribe();
+        drop(gone);
+        b.send(&2);
+        b.close();
+        b.send(&3);
+        let closed = b.subscribe();
+
+        assert_eq!(early.wait().collect::<Result<Vec<_>, _>>(), Ok(vec![1, 2]));
+        assert_eq!(late.wait().collect::<Result<Vec<_>, _>>(), Ok(vec![2]));
+        assert_eq!(closed.wait().collect::<Result<Vec<_>, _>>(), Ok(vec![]));
+    }
+}
@@ -0,0 +1,72 @@
+//! Translation between the paths a client uses and the paths the server sees when the client is
+//! confined to a chroot.
+//!
+//! A chroot is stored without a trailing slash, and a chroot of `/` is represented by not having
+//! a chroot at all.
+
+/// Turn a client path into the server path below `chroot`.
+pub(super) fn prepend(chroot: &str, path: &str) -> String {
+    if path == "/" {
+        chroot.to_string()
+    } else {
+        format!("{}{}", chroot, path)
+    }
+}
+
+/// Turn a server path below `chroot` back into a client path.
+///
+/// Paths that are not below `chroot` are left untouched.
+pub(super) fn strip(chroot: &str, path: &mut String) {
+    if path == chroot {
+        *path = "/".to_string();
+    } else if path.starts_with(chroot) && path[chroot.len()..].starts_with('/') {
+        path.drain(..chroot.len());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        for &(client, server) in &[
+            ("/", "/app"),
+            ("/foo", "/app/foo"),
+            ("/foo/bar", "/app/foo/bar"),
+        ] {
+            assert_eq!(prepend("/app", client), server);
+            let mut path = server.to_string();
+            strip("/app", &mut path);
+            assert_eq!(path, client);
+        }
+    }
+
+    #[test]
+    fn strip_leaves_foreign_paths() {
+        for &path in &["/other", "/application", "/ap"] {
+            let mut p = path.to_string();
+            strip("/app", &mut p);
+            assert_eq!(p, path);
+        }
+    }
+
+    #[test]
+    fn strip_sequential_name() {
+        let mut path = "/app/locks/lock-0000000007".to_string();
+        strip("/app", &mut path);
+        assert_eq!(path, "/locks/lock-0000000007");
+    }
+
+    #[test]
+    fn strip_chroot_in_name() {
+        // only the chroot at the start is removed, not where the node's own name repeats it
+        let mut path = "/app/app/queue/item-0000000005".to_string();
+        strip("/app", &mut path);
+        assert_eq!(path, "/app/queue/item-0000000005");
+
+        let mut path = "/app/app-0000000005".to_string();
+        strip("/app", &mut path);
+        assert_eq!(path, "/app-0000000005");
+    }
+}
@@ -1,59 +1,156 @@
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
-#[repr(i32)]
+/// Error codes returned by the ZooKeeper server.
+///
+/// The per-operation errors in [`error`](../error/index.html) expose the `ZkError` they were
+/// mapped from through `Fail::cause`. Codes this client does not know about are kept as
+/// `ZkError::Unknown`, and [`ZkError::code`] gives back the raw code of any error.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Fail)]
 pub enum ZkError {
     /// This code is never returned from the server. It should not be used other than to indicate a
     /// range. Specifically error codes greater than this value are API errors (while values less
     /// than this indicate a system error.
-    APIError = -100,
+    #[fail(display = "API error range marker")]
+    APIError,
     /// Client authentication failed.
-    AuthFailed = -115,
+    #[fail(display = "client authentication failed")]
+    AuthFailed,
     /// Invalid arguments.
-    BadArguments = -8,
+    #[fail(display = "invalid arguments")]
+    BadArguments,
     /// Version conflict in `set` operation. In case of reconfiguration: reconfig requested from
     /// config version X but last seen config has a different version Y.
-    BadVersion = -103,
+    #[fail(display = "version conflict")]
+    BadVersion,
     /// Connection to the server has been lost.
-    ConnectionLoss = -4,
+    #[fail(display = "connection to the server has been lost")]
+    ConnectionLoss,
     /// A data inconsistency was found.
-    DataInconsistency = -3,
+    #[fail(display = "a data inconsistency was found")]
+    DataInconsistency,
     /// Attempt to create ephemeral node on a local session.
-    EphemeralOnLocalSession = -120,
+    #[fail(display = "attempt to create ephemeral node on a local session")]
+    EphemeralOnLocalSession,
     /// Invalid `Acl` specified.
-    InvalidACL = -114,
+    #[fail(display = "invalid ACL specified")]
+    InvalidACL,
     /// Invalid callback specified.
-    InvalidCallback = -113,
+    #[fail(display = "invalid callback specified")]
+    InvalidCallback,
     /// Error while marshalling or unmarshalling data.
-    MarshallingError = -5,
+    #[fail(display = "error while marshalling or unmarshalling data")]
+    MarshallingError,
+    /// The new configuration of an ensemble has no quorum of servers that are connected and up to
+    /// date with the leader.
+    #[fail(display = "no quorum of new config is connected and up-to-date with the leader")]
+    NewConfigNoQuorum,
     /// Not authenticated.
-    NoAuth = -102,
+    #[fail(display = "not authenticated")]
+    NoAuth,
     /// Ephemeral nodes may not have children.
-    NoChildrenForEphemerals = -108,
+    #[fail(display = "ephemeral nodes may not have children")]
+    NoChildrenForEphemerals,
     /// Request to create node that already exists.
-    NodeExists = -110,
+    #[fail(display = "node already exists")]
+    NodeExists,
     /// Attempted to read a node that does not exist.
-    NoNode = -101,
+    #[fail(display = "node does not exist")]
+    NoNode,
     /// The node has children.
-    NotEmpty = -111,
+    #[fail(display = "node has children")]
+    NotEmpty,
     /// State-changing request is passed to read-only server.
-    NotReadOnly = -119,
+    #[fail(display = "state-changing request is passed to read-only server")]
+    NotReadOnly,
     /// Attempt to remove a non-existing watcher.
-    NoWatcher = -121,
+    #[fail(display = "attempt to remove a non-existing watcher")]
+    NoWatcher,
     /// No error occurred.
-    Ok = 0,
+    #[fail(display = "no error occurred")]
+    Ok,
     /// Operation timeout.
-    OperationTimeout = -7,
+    #[fail(display = "operation timeout")]
+    OperationTimeout,
+    /// The quota of the node has been exceeded.
+    #[fail(display = "quota has been exceeded")]
+    QuotaExceeded,
+    /// Dynamic reconfiguration is disabled on the server.
+    #[fail(display = "reconfiguration is disabled")]
+    ReconfigDisabled,
+    /// Another reconfiguration of the ensemble is in progress.
+    #[fail(display = "another reconfiguration is in progress")]
+    ReconfigInProgress,
+    /// The request timed out on the server, for example while it waited for a quorum.
+    #[fail(display = "request timed out")]
+    RequestTimeout,
     /// A runtime inconsistency was found.
-    RuntimeInconsistency = -2,
+    #[fail(display = "a runtime inconsistency was found")]
+    RuntimeInconsistency,
+    /// The server closed the session because it requires SASL authentication.
+    #[fail(display = "the session was closed because SASL authentication is required")]
+    SessionClosedRequireSaslAuth,
     /// The session has been expired by the server.
-    SessionExpired = -112,
+    #[fail(display = "the session has been expired by the server")]
+    SessionExpired,
     /// Session moved to another server, so operation is ignored.
-    SessionMoved = -118,
+    #[fail(display = "session moved to another server, so operation is ignored")]
+    SessionMoved,
     /// System and server-side errors. This is never thrown by the server, it shouldn't be used
     /// other than to indicate a range. Specifically error codes greater than this value, but lesser
     /// than `APIError`, are system errors.
-    SystemError = -1,
+    #[fail(display = "system error range marker")]
+    SystemError,
+    /// The server is overloaded and throttled the operation.
+    #[fail(display = "operation was throttled by the server")]
+    ThrottledOp,
     /// Operation is unimplemented.
-    Unimplemented = -6,
+    #[fail(display = "operation is unimplemented")]
+    Unimplemented,
+    /// The session is not known to the server.
+    #[fail(display = "unknown session")]
+    UnknownSession,
+    /// An error code this client does not know about, with the raw code the server sent.
+    #[fail(display = "unknown error code {}", _0)]
+    Unknown(i32),
+}
+
+impl ZkError {
+    /// The error code the server uses for this error.
+    pub fn code(&self) -> i32 {
+        match *self {
+            ZkError::APIError => -100,
+            ZkError::AuthFailed => -115,
+            ZkError::BadArguments => -8,
+            ZkError::BadVersion => -103,
+            ZkError::ConnectionLoss => -4,
+            ZkError::DataInconsistency => -3,
+            ZkError::EphemeralOnLocalSession => -120,
+            ZkError::InvalidACL => -114,
+            ZkError::InvalidCallback => -113,
+            ZkError::MarshallingError => -5,
+            ZkError::NewConfigNoQuorum => -13,
+            ZkError::NoAuth => -102,
+            ZkError::NoChildrenForEphemerals => -108,
+            ZkError::NodeExists => -110,
+            ZkError::NoNode => -101,
+            ZkError::NotEmpty => -111,
+            ZkError::NotReadOnly => -119,
+            ZkError::NoWatcher => -121,
+            ZkError::Ok => 0,
+            ZkError::OperationTimeout => -7,
+            ZkError::QuotaExceeded => -125,
+            ZkError::ReconfigDisabled => -123,
+            ZkError::ReconfigInProgress => -14,
+            ZkError::RequestTimeout => -122,
+            ZkError::RuntimeInconsistency => -2,
+            ZkError::SessionClosedRequireSaslAuth => -124,
+            ZkError::SessionExpired => -112,
+            ZkError::SessionMoved => -118,
+            ZkError::SystemError => -1,
+            ZkError::ThrottledOp => -127,
+            ZkError::Unimplemented => -6,
+            ZkError::UnknownSession => -12,
+            ZkError::Unknown(code) => code,
+        }
+    }
 }
 
 impl From<i32> for ZkError {
@@ -69,6 +166,7 @@ impl From<i32> for ZkError {
             -114 => ZkError::InvalidACL,
             -113 => ZkError::InvalidCallback,
             -5 => ZkError::MarshallingError,
+            -13 => ZkError::NewConfigNoQuorum,
             -102 => ZkError::NoAuth,
             -108 => ZkError::NoChildrenForEphemerals,
             -110 => ZkError::NodeExists,
@@ -78,12 +176,47 @@ impl From<i32> for ZkError {
             -121 => ZkError::NoWatcher,
             0 => ZkError::Ok,
             -7 => ZkError::OperationTimeout,
+            -125 => ZkError::QuotaExceeded,
+            -123 => ZkError::ReconfigDisabled,
+            -14 => ZkError::ReconfigInProgress,
+            -122 => ZkError::RequestTimeout,
             -2 => ZkError::RuntimeInconsistency,
+            -124 => ZkError::SessionClosedRequireSaslAuth,
             -112 => ZkError::SessionExpired,
             -118 => ZkError::SessionMoved,
             -1 => ZkError::SystemError,
+            -127 => ZkError::ThrottledOp,
             -6 => ZkError::Unimplemented,
-            _ => panic!("unknown error code {}", code),
+            -12 => ZkError::UnknownSession,
+            code => ZkError::Unknown(code),
+        }
+    }
+}
+
+impl From<ZkError> for i32 {
+    fn from(e: ZkError) -> Self {
+        e.code()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn code_round_trip() {
+        let known = [
+            -127, -125, -124, -123, -122, -121, -120, -119, -118, -115, -114, -113, -112, -111,
+            -110, -108, -103, -102, -101, -100, -14, -13, -12, -8, -7, -6, -5, -4, -3, -2, -1, 0,
+        ];
+        for &code in &known {
+            let e = ZkError::from(code);
+            assert_ne!(e, ZkError::Unknown(code), "code {} is not mapped", code);
+            assert_eq!(e.code(), code);
+            assert_eq!(i32::from(e), code);
         }
+        assert_eq!(ZkError::from(-9), ZkError::Unknown(-9));
+        assert_eq!(ZkError::from(-9).code(), -9);
+        assert_eq!(ZkError::from(42).to_string(), "unknown error code 42");
     }
 }
@@ -0,0 +1,90 @@
+use super::limit::Responder;
+use super::Request;
+use failure;
+use futures::sync::mpsc;
+use std::mem;
+use std::sync::{Arc, Mutex};
+use {error, SaslWritePolicy, ZkError};
+
+type Queued = (Request, Responder);
+
+/// Holds back writes while the connection is being authenticated.
+#[derive(Clone, Debug)]
+pub(super) struct WriteGate(Arc<Mutex<GateState>>);
+
+#[derive(Debug)]
+enum GateState {
+    /// Authentication is in progress, and these writes are waiting for it.
+    Pending(SaslWritePolicy, Vec<Queued>),
+    /// Authentication succeeded, so writes are sent right away.
+    Open,
+    /// Authentication failed, so writes would only be refused by the server.
+    Failed,
+}
+
+impl WriteGate {
+    pub(super) fn new(policy: SaslWritePolicy) -> Self {
+        WriteGate(Arc::new(Mutex::new(GateState::Pending(policy, Vec::new()))))
+    }
+
+    /// Send `request` through `queue`, unless it is a write that has to wait for authentication.
+    pub(super) fn submit(
+        &self,
+        queue: &mpsc::UnboundedSender<Queued>,
+        request: Request,
+        tx: Responder,
+    ) -> Result<(), failure::Error> {
+        // the lock is held while sending, so that nothing overtakes the writes held back so far
+        let mut state = self.0.lock().unwrap();
+        if request.is_write() {
+            match *state {
+                GateState::Pending(SaslWritePolicy::Queue, ref mut held) => {
+                    held.push((request, tx));
+                    return Ok(());
+                }
+                GateState::Pending(SaslWritePolicy::Reject, _) => {
+                    return Err(error::AuthPending.into());
+                }
+                GateState::Failed => {
+                    // giving up the slot of the request may send another one, which needs the lock
+                    drop(state);
+                    let _ = tx.send(Err(ZkError::AuthFailed));
+                    return Ok(());
+                }
+                GateState::Open => {}
+            }
+        }
+        let sent = queue.unbounded_send((request, tx));
+        drop(state);
+        sent.map_err(|e| format_err!("failed to enqueue new request: {:?}", e))
+    }
+
+    /// Let the held back writes through if the connection was `authenticated`, or fail them.
+    pub(super) fn finish(&self, queue: &mpsc::UnboundedSender<Queued>, authenticated: bool) {
+        let mut state = self.0.lock().unwrap();
+        let next = if authenticated {
+            GateState::Open
+        } else {
+            GateState::Failed
+        };
+        let (mut unsent, mut failed) = (Vec::new(), Vec::new());
+        if let GateState::Pending(_, held) = mem::replace(&mut *state, next) {
+            for (request, tx) in held {
+                if authenticated {
+                    // if this fails, the receiver learns of it through the dropped sender
+                    if let Err(e) = queue.unbounded_send((request, tx)) {
+                        unsent.push(e.into_inner());
+                    }
+                } else {
+                    failed.push(tx);
+                }
+            }
+        }
+        // giving up the slots of the requests may send others, which needs the lock
+        drop(state);
+        drop(unsent);
+        for tx in failed {
+            let _ = tx.send(Err(ZkError::AuthFailed));
+        }
+    }
+}
@@ -0,0 +1,107 @@
+//! Instrumentation of the connection with [`tracing`](https://docs.rs/tracing).
+//!
+//! Without the `tracing` feature, all of this compiles down to nothing.
+
+use super::{Request, Response};
+use failure;
+#[cfg(feature = "tracing")]
+use tracing::{self, field};
+use {WatchedEvent, ZkError};
+
+/// The span of a single request, from when it is sent until its response arrives.
+pub(super) struct RequestSpan {
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+}
+
+#[cfg(feature = "tracing")]
+impl RequestSpan {
+    /// Open the span for `request`, which is sent as `xid` on the session `session_id` (which is 0
+    /// if we do not have a session yet).
+    pub(super) fn new(session_id: i64, xid: i32, request: &Request) -> Self {
+        let span = tracing::info_span!(
+            "request",
+            session_id = field::Empty,
+            xid,
+            opcode = ?request.opcode(),
+            path = field::Empty,
+            outcome = field::Empty,
+        );
+        if session_id != 0 {
+            span.record("session_id", session_id);
+        }
+        if let Some(path) = request.path() {
+            span.record("path", path);
+        }
+        RequestSpan { span }
+    }
+
+    /// Record the `result` of the request, and close the span.
+    pub(super) fn finish(self, result: &Result<Response, ZkError>) {
+        match *result {
+            Ok(Response::Connect { session_id, .. }) => {
+                // we only now know which session we are on
+                self.span.record("session_id", session_id);
+                self.span.record("outcome", "ok");
+            }
+            Ok(_) => {
+                self.span.record("outcome", "ok");
+            }
+            Err(e) => {
+                self.span.record("outcome", field::debug(e));
+            }
+        }
+    }
+}
+
+/// Note that we have established (or `resumed`) the session `session_id`.
+#[cfg(feature = "tracing")]
+pub(super) fn connected(session_id: i64, resumed: bool, read_only: bool) {
+    tracing::info!(session_id, resumed, read_only, "connected to server");
+}
+
+/// Note that the server has expired the session `session_id`.
+#[cfg(feature = "tracing")]
+pub(super) fn expired(session_id: i64) {
+    tracing::info!(session_id, "session expired");
+}
+
+/// Note that the connection of the session `session_id` was lost due to `error`, and that we are
+/// trying to reconnect.
+#[cfg(feature = "tracing")]
+pub(super) fn disconnected(session_id: i64, error: &failure::Error) {
+    tracing::info!(session_id, error = %error, "connection lost; reconnecting");
+}
+
+/// Note that the server has triggered a watch of the session `session_id`.
+#[cfg(feature = "tracing")]
+pub(super) fn watch_fired(session_id: i64, event: &WatchedEvent) {
+    tracing::debug!(
+        session_id,
+        path = %event.path,
+        event_type = ?event.event_type,
+        keeper_state = ?event.keeper_state,
+        "watch fired"
+    );
+}
+
+#[cfg(not(feature = "tracing"))]
+impl RequestSpan {
+    pub(super) fn new(_: i64, _: i32, _: &Request) -> Self {
+        RequestSpan {}
+    }
+
+    pub(super) fn finish(self, _: &Result<Response, ZkError>) {}
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(super) fn connected(_: i64, _: bool, _: bool) {}
+
+#[cfg(not(feature = "tracing"))]
+pub(super) fn expired(_: i64) {}
+
+#[cfg(not(feature = "tracing"))]
+pub(super) fn disconnected(_: i64, _: &failure::Error) {}
+
+#[cfg(not(feature = "tracing"))]
+pub(super) fn watch_fired(_: i64, _: &WatchedEvent) {}
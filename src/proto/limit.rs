@@ -0,0 +1,207 @@
+use super::Response;
+use error;
+use failure;
+use futures::sync::oneshot;
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use ZkError;
+
+/// Sends a request that had to wait for a slot, which the request then holds on to.
+type Deferred = Box<dyn FnOnce(Slot) -> Result<(), failure::Error> + Send>;
+
+/// Bounds how many requests have been sent to the connection, but not answered yet.
+#[derive(Clone)]
+pub(super) struct RequestLimit {
+    shared: Arc<Mutex<LimitState>>,
+    /// Whether a request waits for a free slot, rather than failing with `error::Busy`.
+    wait: bool,
+}
+
+struct LimitState {
+    capacity: usize,
+    /// How many requests hold a slot.
+    taken: usize,
+    /// Whether a request is being sent, in which case it also sends the requests that wait once
+    /// it is done, so that nothing overtakes them.
+    sending: bool,
+    /// Requests that wait for a free slot, in the order they were made.
+    waiting: VecDeque<Deferred>,
+}
+
+/// The slot of a request that was given one, which is given up when this is dropped.
+pub(super) struct Slot {
+    shared: Arc<Mutex<LimitState>>,
+}
+
+/// Where the response to a request goes, along with the slot the request holds until then.
+#[derive(Debug)]
+pub(super) struct Responder {
+    tx: oneshot::Sender<Result<Response, ZkError>>,
+    _slot: Option<Slot>,
+}
+
+impl RequestLimit {
+    pub(super) fn new(capacity: usize) -> Self {
+        RequestLimit {
+            shared: Arc::new(Mutex::new(LimitState {
+                capacity,
+                taken: 0,
+                sending: false,
+                waiting: VecDeque::new(),
+            })),
+            wait: true,
+        }
+    }
+
+    pub(super) fn set_wait(&mut self, wait: bool) {
+        self.wait = wait;
+    }
+
+    /// Call `send` with a slot once one is free, which is right away unless other requests are
+    /// waiting.
+    ///
+    /// If `send` has to wait, but this limit does not, this fails with `error::Busy`. If `send`
+    /// fails later on, its error is lost, so it should tell the caller in some other way. The slot
+    /// is given back once `send` drops it, so it should hold on to it until the request has been
+    /// answered.
+    pub(super) fn submit<F>(&self, send: F) -> Result<(), failure::Error>
+    where
+        F: FnOnce(Slot) -> Result<(), failure::Error> + Send + 'static,
+    {
+        let mut state = self.shared.lock().unwrap();
+        let free = state.taken < state.capacity && state.waiting.is_empty();
+        if free && !state.sending {
+            state.taken += 1;
+            state.sending = true;
+            drop(state);
+            // the lock is not held while sending, as a failed request gives its slot back
+            let sent = send(Slot {
+                shared: self.shared.clone(),
+            });
+            send_waiting(&self.shared);
+            sent
+        } else if free || self.wait {
+            // whoever is sending right now sends this once it is done
+            state.waiting.push_back(Box::new(send));
+            Ok(())
+        } else {
+            Err(error::Busy.into())
+        }
+    }
+}
+
+/// Send the requests that wait for as long as there are free slots, and then note that nothing
+/// is being sent anymore.
+fn send_waiting(shared: &Arc<Mutex<LimitState>>) {
+    loop {
+        let send = {
+            let mut state = shared.lock().unwrap();
+            if state.taken >= state.capacity || state.waiting.is_empty() {
+                state.sending = false;
+                return;
+            }
+            state.taken += 1;
+            state.waiting.pop_front().unwrap()
+        };
+        // a request that cannot be sent gives its slot back right away
+        let _ = send(Slot {
+            shared: shared.clone(),
+        });
+    }
+}
+
+impl fmt::Debug for RequestLimit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let state = self.shared.lock().unwrap();
+        f.debug_struct("RequestLimit")
+            .field("capacity", &state.capacity)
+            .field("taken", &state.taken)
+            .field("waiting", &state.waiting.len())
+            .field("wait", &self.wait)
+            .finish()
+    }
+}
+
+impl fmt::Debug for Slot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Slot").finish()
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock().unwrap();
+        state.taken -= 1;
+        if state.sending || state.waiting.is_empty() {
+            return;
+        }
+        state.sending = true;
+        drop(state);
+        send_waiting(&self.shared);
+    }
+}
+
+impl Responder {
+    /// Where the response to a request that holds `slot` goes.
+    pub(super) fn new(tx: oneshot::Sender<Result<Response, ZkError>>, slot: Slot) -> Self {
+        Responder {
+            tx,
+            _slot: Some(slot),
+        }
+    }
+
+    /// Send the response, and give up the slot of the request.
+    pub(super) fn send(
+        self,
+        r: Result<Response, ZkError>,
+    ) -> Result<(), Result<Response, ZkError>> {
+        self.tx.send(r)
+    }
+}
+
+impl From<oneshot::Sender<Result<Response, ZkError>>> for Responder {
+    fn from(tx: oneshot::Sender<Result<Response, ZkError>>) -> Self {
+        Responder { tx, _slot: None }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn taken(limit: &RequestLimit) -> usize {
+        limit.shared.lock().unwrap().taken
+    }
+
+    #[test]
+    fn waiting_request_that_cannot_be_sent() {
+        let limit = RequestLimit::new(1);
+        let slots = Arc::new(Mutex::new(Vec::new()));
+        let hold = |slots: &Arc<Mutex<Vec<Slot>>>| {
+            let slots = slots.clone();
+            move |slot| {
+                slots.lock().unwrap().push(slot);
+                Ok(())
+            }
+        };
+
+        limit.submit(hold(&slots)).unwrap();
+        // this one has to wait, and then fails to be sent
+        limit.submit(|_| bail!("cannot send")).unwrap();
+        limit.submit(hold(&slots)).unwrap();
+        assert_eq!(taken(&limit), 1);
+        assert_eq!(slots.lock().unwrap().len(), 1);
+
+        // the failed request gives its slot back, so the third one gets it
+        let first = slots.lock().unwrap().remove(0);
+        drop(first);
+        assert_eq!(taken(&limit), 1);
+        assert_eq!(slots.lock().unwrap().len(), 1);
+
+        slots.lock().unwrap().clear();
+        assert_eq!(taken(&limit), 0);
+        assert!(limit.submit(|_| bail!("cannot send")).is_err());
+        assert_eq!(taken(&limit), 0);
+    }
+}
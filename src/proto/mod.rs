@@ -1,23 +1,35 @@
 use failure;
+use futures::future::{self, Loop};
+use std::fmt;
 use std::net::SocketAddr;
+use std::time;
 use tokio;
 use tokio::prelude::*;
 
 mod active_packetizer;
+mod backoff;
+mod broadcast;
+mod chroot;
 mod error;
+mod gate;
+mod instrument;
+mod limit;
 mod packetizer;
 mod request;
 mod response;
+mod state;
 mod watch;
 
-pub(crate) use self::error::ZkError;
-pub(crate) use self::packetizer::{Enqueuer, Packetizer};
+pub use self::backoff::Backoff;
+pub use self::error::ZkError;
+pub(crate) use self::packetizer::{ConnectOptions, Enqueuer, Packetizer};
 pub(crate) use self::request::Request;
 pub(crate) use self::response::Response;
+pub use self::response::Utf8Policy;
 pub(crate) use self::watch::Watch;
 
-pub trait ZooKeeperTransport: AsyncRead + AsyncWrite + Sized + Send {
-    type Addr: Send;
+pub trait ZooKeeperTransport: AsyncRead + AsyncWrite + Sized + Send + 'static {
+    type Addr: Send + Clone + fmt::Debug + 'static;
     type ConnectError: Into<failure::Error>;
     type ConnectFut: Future<Item = Self, Error = Self::ConnectError> + Send + 'static;
     fn connect(&Self::Addr) -> Self::ConnectFut;
@@ -31,3 +43,66 @@ impl ZooKeeperTransport for tokio::net::TcpStream {
         tokio::net::TcpStream::connect(addr)
     }
 }
+
+/// Connect to the first of the given servers that accepts a connection.
+///
+/// Servers are tried in order, starting at index `start` and wrapping around, and each server is
+/// tried once. A server that has not accepted the connection within `timeout` (if given) counts
+/// as unreachable. On success, the index of the server that was connected to is returned along
+/// with the connection.
+pub(crate) fn connect_any<S>(
+    addrs: Vec<S::Addr>,
+    start: usize,
+    timeout: Option<time::Duration>,
+) -> impl Future<Item = (usize, S), Error = failure::Error>
+where
+    S: ZooKeeperTransport,
+{
+    assert!(!addrs.is_empty());
+    future::loop_fn(0, move |attempt| {
+        let i = (start + attempt) % addrs.len();
+        let last = attempt + 1 == addrs.len();
+        let addr = addrs[i].clone();
+        connect_one::<S>(&addr, timeout).then(move |r| match r {
+            Ok(stream) => Ok(Loop::Break((i, stream))),
+            Err(e) if last => Err(e.context(format!("failed to connect to {:?}", addr)).into()),
+            Err(_) => Ok(Loop::Continue(attempt + 1)),
+        })
+    })
+}
+
+/// Connect to the server at `addr`, giving up after `timeout` if there is one.
+pub(crate) fn connect_one<S>(
+    addr: &S::Addr,
+    timeout: Option<time::Duration>,
+) -> impl Future<Item = S, Error = failure::Error>
+where
+    S: ZooKeeperTransport,
+{
+    let connect = S::connect(addr).map_err(Into::into);
+    match timeout {
+        Some(timeout) => future::Either::A(
+            tokio::timer::Timeout::new(connect, timeout)
+                .map_err(|e| timeout_error(e, || ::error::ConnectTimeout.into())),
+        ),
+        None => future::Either::B(connect),
+    }
+}
+
+/// Turn the error of a future that was bounded by `tokio::timer::Timeout` back into the error of
+/// that future, or into `elapsed()` if it did not complete in time.
+pub(crate) fn timeout_error<F>(
+    e: tokio::timer::timeout::Error<failure::Error>,
+    elapsed: F,
+) -> failure::Error
+where
+    F: FnOnce() -> failure::Error,
+{
+    if e.is_elapsed() {
+        elapsed()
+    } else if e.is_inner() {
+        e.into_inner().expect("is_inner")
+    } else {
+        e.into_timer().expect("neither elapsed nor inner").into()
+    }
+}
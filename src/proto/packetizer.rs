@@ -1,38 +1,113 @@
 use super::{
-    active_packetizer::ActivePacketizer, request, watch::WatchType, Request, Response,
-    ZooKeeperTransport,
+    active_packetizer::ActivePacketizer,
+    backoff::{Backoff, Clock, TokioClock},
+    broadcast::Broadcast,
+    gate::WriteGate,
+    instrument,
+    limit::{RequestLimit, Responder},
+    request,
+    response::Utf8Policy,
+    state::SessionState,
+    watch::WatchType,
+    Request, Response, ZooKeeperTransport,
 };
 use byteorder::{BigEndian, WriteBytesExt};
 use failure;
+use failure::Fail;
 use futures::{
-    future::Either,
+    future::{self, Either, Loop},
     sync::{mpsc, oneshot},
 };
+use select::Selector;
 use slog;
-use std::mem;
+use std::net::SocketAddr;
+use std::{cmp, mem, time};
 use tokio;
 use tokio::prelude::*;
-use {Watch, WatchedEvent, ZkError};
+use {
+    error, Feature, KeeperState, SaslWritePolicy, Watch, WatchedEvent, WatchedEventType, ZkError,
+};
+
+/// The xid the server uses for responses to SetWatches requests.
+const SET_WATCHES_XID: i32 = -8;
+
+/// Reconnecting was given up after the given number of attempts.
+#[derive(Debug, Fail)]
+#[fail(display = "gave up reconnecting after {} attempts", _0)]
+struct GaveUp(usize);
+
+/// Settings that apply to every connection to a server.
+#[derive(Clone, Debug)]
+pub(crate) struct ConnectOptions {
+    /// Prefix of all server paths
+    pub(crate) chroot: Option<String>,
+
+    /// How long to wait for a server to accept a connection
+    pub(crate) connect_timeout: Option<time::Duration>,
+
+    /// Whether we accept a connection to a server in read-only mode
+    pub(crate) read_only: bool,
+
+    /// How long to wait for the response to a request
+    pub(crate) operation_timeout: Option<time::Duration>,
+
+    /// How often to send a heartbeat, if not derived from the session timeout
+    pub(crate) ping_interval: Option<time::Duration>,
+
+    /// How long to wait for anything from the server, if not derived from the ping interval
+    pub(crate) read_idle_timeout: Option<time::Duration>,
+
+    /// How long to wait before trying all servers again when reconnecting
+    pub(crate) backoff: Backoff,
+
+    /// How many requests may be waiting for a response at once, if they are limited at all
+    pub(crate) request_queue_capacity: Option<usize>,
+
+    /// Whether to drop a connection whose responses are longer than what they decode to
+    pub(crate) check_response_length: bool,
+
+    /// How to decode strings in responses that are not valid UTF-8
+    pub(crate) utf8_policy: Utf8Policy,
+
+    /// Which server to try next when reconnecting
+    pub(crate) selector: Selector,
+
+    /// How many times to try to connect to a server when reconnecting, if not until the session
+    /// times out
+    pub(crate) max_reconnect_attempts: Option<u32>,
+}
 
 pub(crate) struct Packetizer<S>
 where
     S: ZooKeeperTransport,
 {
-    /// ZooKeeper address
-    addr: S::Addr,
+    /// ZooKeeper server addresses
+    addrs: Vec<S::Addr>,
+
+    /// Index of the server in `addrs` we are connected to (or are connecting to)
+    server: usize,
+
+    /// Settings for re-connection
+    options: ConnectOptions,
 
     /// Current state
     state: PacketizerState<S>,
 
-    /// Watcher to send watch events to.
-    default_watcher: mpsc::UnboundedSender<WatchedEvent>,
+    /// Where to send all watch events to.
+    events: Broadcast<WatchedEvent>,
 
     /// Incoming requests
-    rx: mpsc::UnboundedReceiver<(Request, oneshot::Sender<Result<Response, ZkError>>)>,
+    rx: mpsc::UnboundedReceiver<(Request, Responder)>,
 
     /// Next xid to issue
     xid: i32,
 
+    /// When we lost the connection to the server, if we have not re-established it since
+    disconnected_at: Option<time::Instant>,
+
+    /// State of our session, as seen by the user
+    session_state: SessionState,
+
     logger: slog::Logger,
 
     exiting: bool,
@@ -40,11 +115,13 @@ where
 
 impl<S> Packetizer<S>
 where
-    S: ZooKeeperTransport,
+    S: ZooKeeperTransport<Addr = SocketAddr>,
 {
     pub(crate) fn new(
-        addr: S::Addr,
+        addrs: Vec<S::Addr>,
+        server: usize,
         stream: S,
+        options: ConnectOptions,
         log: slog::Logger,
         default_watcher: mpsc::UnboundedSender<WatchedEvent>,
     ) -> Enqueuer
@@ -52,14 +129,32 @@ where
         S: Send + 'static + AsyncRead + AsyncWrite,
     {
         let (tx, rx) = mpsc::unbounded();
+        let session_state = SessionState::default();
+        session_state.set_server(server);
+        let events = Broadcast::default();
+        events.add(default_watcher);
 
         let exitlogger = log.clone();
+        let operation_timeout = options.operation_timeout;
+        let limit = options.request_queue_capacity.map(RequestLimit::new);
         tokio::spawn(
             Packetizer {
-                addr,
-                state: PacketizerState::Connected(ActivePacketizer::new(stream)),
+                addrs,
+                server,
+                state: PacketizerState::Connected(ActivePacketizer::new(
+                    stream,
+                    options.chroot.clone(),
+                    options.ping_interval,
+                    options.read_idle_timeout,
+                    options.check_response_length,
+                    options.utf8_policy,
+                    session_state.clone(),
+                )),
+                options,
                 xid: 0,
-                default_watcher,
+                disconnected_at: None,
+                session_state: session_state.clone(),
+                events: events.clone(),
                 rx: rx,
                 logger: log,
                 exiting: false,
@@ -69,13 +164,19 @@ where
             }),
         );
 
-        Enqueuer(tx)
+        Enqueuer(tx, session_state, events, operation_timeout, None, limit)
     }
 }
 
 enum PacketizerState<S> {
     Connected(ActivePacketizer<S>),
-    Reconnecting(Box<Future<Item = ActivePacketizer<S>, Error = failure::Error> + Send + 'static>),
+    Reconnecting(
+        Box<
+            dyn Future<Item = (usize, ActivePacketizer<S>), Error = failure::Error>
+                + Send
+                + 'static,
+        >,
+    ),
 }
 
 impl<S> PacketizerState<S>
@@ -85,19 +186,19 @@ where
     fn poll(
         &mut self,
         exiting: bool,
+        server: &mut usize,
         logger: &mut slog::Logger,
-        default_watcher: &mut mpsc::UnboundedSender<WatchedEvent>,
+        events: &Broadcast<WatchedEvent>,
     ) -> Result<Async<()>, failure::Error> {
-        let ap = match *self {
-            PacketizerState::Connected(ref mut ap) => {
-                return ap.poll(exiting, logger, default_watcher)
-            }
+        let (connected, ap) = match *self {
+            PacketizerState::Connected(ref mut ap) => return ap.poll(exiting, logger, events),
             PacketizerState::Reconnecting(ref mut c) => try_ready!(c.poll()),
         };
 
         // we are now connected!
+        *server = connected;
         mem::replace(self, PacketizerState::Connected(ap));
-        self.poll(exiting, logger, default_watcher)
+        self.poll(exiting, server, logger, events)
     }
 }
 
@@ -124,48 +225,72 @@ where
                     ref mut watch,
                     ..
                 }
+                | Request::GetChildren2 {
+                    ref path,
+                    ref mut watch,
+                    ..
+                }
                 | Request::Exists {
                     ref path,
                     ref mut watch,
                     ..
                 } => {
-                    if let Watch::Custom(_) = *watch {
+                    if let Watch::None = *watch {
+                    } else {
                         // set to Global so that watch will be sent as 1u8
-                        let w = mem::replace(watch, Watch::Global);
-                        if let Watch::Custom(w) = w {
-                            let wtype = match item {
-                                Request::GetData { .. } => WatchType::Data,
-                                Request::GetChildren { .. } => WatchType::Child,
-                                Request::Exists { .. } => WatchType::Exist,
-                                _ => unreachable!(),
-                            };
-                            trace!(
-                                self.logger,
-                                "adding pending watcher";
-                                "xid" => self.xid,
-                                "path" => path,
-                                "wtype" => ?wtype
-                            );
-                            ap.pending_watchers
-                                .insert(self.xid, (path.to_string(), w, wtype));
-                        } else {
-                            unreachable!();
-                        }
+                        let w = match mem::replace(watch, Watch::Global) {
+                            Watch::Custom(w) => Some(w),
+                            _ => None,
+                        };
+                        let wtype = match item {
+                            Request::GetData { .. } => WatchType::Data,
+                            Request::GetChildren { .. } | Request::GetChildren2 { .. } => {
+                                WatchType::Child
+                            }
+                            Request::Exists { .. } => WatchType::Exist,
+                            _ => unreachable!(),
+                        };
+                        trace!(
+                            self.logger,
+                            "adding pending watcher";
+                            "xid" => self.xid,
+                            "path" => path,
+                            "wtype" => ?wtype,
+                            "custom" => w.is_some()
+                        );
+                        ap.pending_watchers
+                            .insert(self.xid, (path.to_string(), w, wtype));
                     }
                 }
                 _ => {}
             }
 
+            let closing = item.opcode() == request::OpCode::CloseSession;
             ap.enqueue(self.xid, item, tx);
             self.xid += 1;
+            if closing {
+                // the server hangs up once it has answered, so nothing else can be sent
+                self.exiting = true;
+                return Ok(Async::Ready(()));
+            }
         }
         Ok(Async::NotReady)
     }
 }
 
-impl<S> Future for Packetizer<S>
+impl<S> Drop for Packetizer<S>
 where
     S: ZooKeeperTransport,
+{
+    fn drop(&mut self) {
+        // no more events will arrive
+        self.events.close();
+    }
+}
+
+impl<S> Future for Packetizer<S>
+where
+    S: ZooKeeperTransport<Addr = SocketAddr>,
 {
     type Item = ();
     type Error = failure::Error;
@@ -192,7 +317,7 @@ where
                             .expect("Vec::write should never fail");
                         // opcode
                         ap.outbox
-                            .write_i32::<BigEndian>(request::OpCode::CloseSession as i32)
+                            .write_i32::<BigEndian>(request::OpCode::CloseSession.code())
                             .expect("Vec::write should never fail");
                     } else {
                         unreachable!("poll_enqueue will never return Err() if not connected");
@@ -201,83 +326,277 @@ where
             }
         }
 
-        match self
-            .state
-            .poll(self.exiting, &mut self.logger, &mut self.default_watcher)
-        {
-            Ok(v) => Ok(v),
+        match self.state.poll(
+            self.exiting,
+            &mut self.server,
+            &mut self.logger,
+            &self.events,
+        ) {
+            Ok(Async::Ready(())) => {
+                self.session_state.close();
+                Ok(Async::Ready(()))
+            }
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(e) if self.exiting => {
+                // the session is being closed, so there is nothing to reconnect for
+                self.session_state.close();
+                Err(e)
+            }
             Err(e) => {
                 // if e is disconnect, then purge state and reconnect
                 // for now, assume all errors are disconnects
-                // TODO: test this!
+                let now = time::Instant::now();
+                let (session_id, password, last_zxid_seen, session_timeout, watchers, auth) =
+                    if let PacketizerState::Connected(ref mut ap) = self.state {
+                        if ap.session_expired || ap.session_id == 0 {
+                            // there is no session to re-establish
+                            let cause = if ap.session_expired {
+                                ZkError::SessionExpired
+                            } else {
+                                ZkError::ConnectionLoss
+                            };
+                            ap.fail_pending(cause);
+                            fail_queued(&mut self.rx, cause);
+                            self.session_state.close();
+                            return Err(e);
+                        }
+                        // the session lives on, so these requests can be retried once we have
+                        // re-connected
+                        ap.fail_pending(ZkError::ConnectionLoss);
+                        if !ap.first || self.disconnected_at.is_none() {
+                            // we had a working connection until now
+                            self.disconnected_at = Some(now);
+                        }
+                        (
+                            ap.session_id,
+                            ap.password.split_off(0),
+                            ap.last_zxid_seen,
+                            ap.session_timeout,
+                            mem::take(&mut ap.watchers),
+                            mem::take(&mut ap.auth),
+                        )
+                    } else {
+                        // we could not reconnect before the session timed out, or we gave up
+                        fail_queued(&mut self.rx, ZkError::SessionExpired);
+                        let state = if e.find_root_cause().downcast_ref::<GaveUp>().is_some() {
+                            KeeperState::Closed
+                        } else {
+                            KeeperState::Expired
+                        };
+                        self.session_state.transition(state);
+                        return Err(e);
+                    };
+                self.session_state.transition(KeeperState::Disconnected);
 
-                let password = if let PacketizerState::Connected(ActivePacketizer {
-                    ref mut password,
-                    ..
-                }) = self.state
-                {
-                    password.split_off(0)
-                } else {
-                    // XXX: error while connecting -- don't recurse (for now)
-                    return Err(e);
-                };
-
-                if let PacketizerState::Connected(ActivePacketizer {
-                    last_zxid_seen,
-                    session_id,
-                    ..
-                }) = self.state
-                {
-                    info!(self.logger, "connection lost; reconnecting";
-                          "session_id" => session_id,
-                          "last_zxid" => last_zxid_seen
-                    );
+                info!(self.logger, "connection lost; reconnecting";
+                      "session_id" => session_id,
+                      "last_zxid" => last_zxid_seen,
+                      "error" => %e
+                );
+                instrument::disconnected(session_id, &e);
 
-                    let xid = self.xid;
-                    self.xid += 1;
-
-                    let log = self.logger.clone();
-                    let retry = S::connect(&self.addr)
-                        .map_err(|e| e.into())
-                        .map(move |stream| {
-                            let request = Request::Connect {
-                                protocol_version: 0,
-                                last_zxid_seen,
-                                timeout: 0,
-                                session_id,
-                                passwd: password,
-                                read_only: false,
-                            };
-                            trace!(log, "about to handshake (again)");
-
-                            let (tx, rx) = oneshot::channel();
-                            tokio::spawn(rx.then(move |r| {
-                                trace!(log, "re-connection response: {:?}", r);
-                                Ok(())
-                            }));
-
-                            let mut ap = ActivePacketizer::new(stream);
-                            ap.enqueue(xid, request, tx);
-                            ap
-                        });
-
-                    // dropping the old state will also cancel in-flight requests
-                    mem::replace(
-                        &mut self.state,
-                        PacketizerState::Reconnecting(Box::new(retry)),
+                // the server expires the session once it has not heard from us for the session
+                // timeout, so there is no point in trying to resume it after that
+                let deadline = self.disconnected_at.unwrap_or(now)
+                    + time::Duration::from_millis(session_timeout as u64);
+
+                let log = self.logger.clone();
+                let chroot = self.options.chroot.clone();
+                let session_state = self.session_state.clone();
+                let read_only = self.options.read_only;
+                let ping_interval = self.options.ping_interval;
+                let read_idle_timeout = self.options.read_idle_timeout;
+                let check_response_length = self.options.check_response_length;
+                let utf8_policy = self.options.utf8_policy;
+                let retry = reconnect::<S, _>(
+                    self.addrs.clone(),
+                    self.server,
+                    &self.options,
+                    deadline,
+                    TokioClock,
+                    log.clone(),
+                ).map(move |(server, stream)| {
+                    trace!(log, "about to handshake (again)"; "server" => server);
+                    session_state.set_server(server);
+                    let mut ap = ActivePacketizer::new(
+                        stream,
+                        chroot,
+                        ping_interval,
+                        read_idle_timeout,
+                        check_response_length,
+                        utf8_policy,
+                        session_state,
                     );
-                    self.poll()
-                } else {
-                    unreachable!();
-                }
+                    ap.session_id = session_id;
+                    ap.password = password.clone();
+                    ap.last_zxid_seen = last_zxid_seen;
+                    ap.session_timeout = session_timeout;
+                    ap.watchers = watchers;
+
+                    let request = Request::Connect {
+                        protocol_version: 0,
+                        last_zxid_seen,
+                        timeout: session_timeout,
+                        session_id,
+                        passwd: password,
+                        read_only,
+                    };
+                    // the response to the connect request is always read as xid 0
+                    ap.enqueue(0, request, log_response(&log, "re-connection"));
+
+                    // add our credentials again, in the order they were added, so that the same
+                    // ACLs match on the new connection
+                    for credentials in &auth {
+                        let request = Request::Auth {
+                            scheme: credentials.0.clone(),
+                            auth: credentials.1.clone(),
+                        };
+                        ap.enqueue(0, request, log_response(&log, "auth"));
+                    }
+                    ap.auth = auth;
+
+                    // restore our watches. any watch that would have triggered while we were
+                    // disconnected is triggered right away by the server.
+                    let watches = ap.watchers.paths();
+                    if !watches.is_empty() {
+                        let request = Request::SetWatches {
+                            relative_zxid: last_zxid_seen,
+                            watches,
+                        };
+                        ap.enqueue(SET_WATCHES_XID, request, log_response(&log, "set-watches"));
+                    }
+                    (server, ap)
+                });
+
+                // dropping the old state will also cancel in-flight requests
+                mem::replace(
+                    &mut self.state,
+                    PacketizerState::Reconnecting(Box::new(retry)),
+                );
+                self.poll()
             }
         }
     }
 }
 
+/// Connect to one of the given servers, retrying until one accepts a connection, until `deadline`
+/// (as told by `clock`) has passed, or until we made as many attempts as `options` allow.
+///
+/// The selector in `options` picks which server to try next, starting after the server at index
+/// `last_failed`. Between rounds of as many attempts as there are servers, we wait for the
+/// successive delays of its backoff.
+fn reconnect<S, C>(
+    addrs: Vec<SocketAddr>,
+    last_failed: usize,
+    options: &ConnectOptions,
+    deadline: time::Instant,
+    clock: C,
+    log: slog::Logger,
+) -> impl Future<Item = (usize, S), Error = failure::Error>
+where
+    S: ZooKeeperTransport<Addr = SocketAddr>,
+    C: Clock,
+{
+    let selector = options.selector.clone();
+    let connect_timeout = options.connect_timeout;
+    let max_attempts = options.max_reconnect_attempts.map(|n| n as usize);
+    future::loop_fn(
+        (options.backoff.delays(), 0, last_failed),
+        move |(mut delays, attempts, failed)| {
+            let log = log.clone();
+            let clock = clock.clone();
+            let server = selector.next(&addrs, failed);
+            let addr = addrs[server];
+            let attempts = attempts + 1;
+            let last = attempts % addrs.len() == 0;
+            super::connect_one::<S>(&addr, connect_timeout).then(move |r| match r {
+                Ok(stream) => Either::A(future::ok(Loop::Break((server, stream)))),
+                Err(ref e) if max_attempts == Some(attempts) => {
+                    info!(log, "giving up on reconnecting"; "error" => %e);
+                    Either::A(future::err(GaveUp(attempts).into()))
+                }
+                Err(e) if !last => {
+                    trace!(log, "failed to connect"; "server" => server, "error" => %e);
+                    Either::A(future::ok(Loop::Continue((delays, attempts, server))))
+                }
+                Err(ref e) if clock.now() >= deadline => {
+                    info!(log, "giving up on reconnecting"; "error" => %e);
+                    Either::A(future::err(
+                        ZkError::SessionExpired
+                            .context("could not reconnect before the session timed out")
+                            .into(),
+                    ))
+                }
+                Err(e) => {
+                    let e = e.context(format!("failed to connect to {:?}", addr));
+                    let delay = delays.next().expect("there is always another delay");
+                    debug!(log, "failed to reconnect; retrying";
+                           "error" => %e,
+                           "delay" => ?delay
+                    );
+                    let retry_at = cmp::min(clock.now() + delay, deadline);
+                    Either::B(
+                        clock
+                            .sleep_until(retry_at)
+                            .map(move |_| Loop::Continue((delays, attempts, server))),
+                    )
+                }
+            })
+        },
+    )
+}
+
+/// Fail every request that is waiting in `rx` to be sent with `e`, as no more requests will be sent
+/// once the packetizer exits.
+fn fail_queued(rx: &mut mpsc::UnboundedReceiver<(Request, Responder)>, e: ZkError) {
+    rx.close();
+    while let Ok(Async::Ready(Some((_, tx)))) = rx.poll() {
+        let _ = tx.send(Err(e));
+    }
+}
+
+/// Create a response channel for an internal request that just logs the response.
+fn log_response(log: &slog::Logger, what: &'static str) -> Responder {
+    let log = log.clone();
+    let (tx, rx) = oneshot::channel();
+    tokio::spawn(rx.then(move |r| {
+        trace!(log, "{} response: {:?}", what, r);
+        Ok(())
+    }));
+    tx.into()
+}
+
+/// The error for a request with the given `opcode` that the server does not implement.
+fn unsupported(opcode: request::OpCode) -> failure::Error {
+    error::Unsupported {
+        operation: format!("{:?}", opcode),
+    }
+    .into()
+}
+
+/// Send `request` to the packetizer, unless it is a write that `gate` holds back.
+fn send(
+    queue: &mpsc::UnboundedSender<(Request, Responder)>,
+    gate: Option<&WriteGate>,
+    request: Request,
+    tx: Responder,
+) -> Result<(), failure::Error> {
+    match gate {
+        Some(gate) => gate.submit(queue, request, tx),
+        None => queue
+            .unbounded_send((request, tx))
+            .map_err(|e| format_err!("failed to enqueue new request: {:?}", e)),
+    }
+}
+
 #[derive(Clone, Debug)]
 pub(crate) struct Enqueuer(
-    mpsc::UnboundedSender<(Request, oneshot::Sender<Result<Response, ZkError>>)>,
+    mpsc::UnboundedSender<(Request, Responder)>,
+    SessionState,
+    Broadcast<WatchedEvent>,
+    Option<time::Duration>,
+    Option<WriteGate>,
+    Option<RequestLimit>,
 );
 
 impl Enqueuer {
@@ -285,14 +604,214 @@ impl Enqueuer {
         &self,
         request: Request,
     ) -> impl Future<Item = Result<Response, ZkError>, Error = failure::Error> {
+        let opcode = request.opcode();
+        if self.1.supports(opcode) == Some(false) {
+            // the server told us before that it will not do this
+            return Either::B(Err(unsupported(opcode)).into_future());
+        }
+
         let (tx, rx) = oneshot::channel();
-        match self.0.unbounded_send((request, tx)) {
-            Ok(()) => {
-                Either::A(rx.map_err(|e| format_err!("failed to enqueue new request: {:?}", e)))
+        let sent = match self.5 {
+            Some(ref limit) => {
+                let (queue, gate) = (self.0.clone(), self.4.clone());
+                // the request holds its slot until the packetizer is done with it, even if the
+                // caller gives up on the response before that
+                limit.submit(move |slot| {
+                    if tx.is_canceled() {
+                        // nobody is interested in the response anymore, so it is not sent at all
+                        return Ok(());
+                    }
+                    // if the request has to wait, and cannot be sent after all, dropping `tx`
+                    // tells the caller
+                    send(&queue, gate.as_ref(), request, Responder::new(tx, slot))
+                })
             }
-            Err(e) => {
-                Either::B(Err(format_err!("failed to enqueue new request: {:?}", e)).into_future())
+            None => send(&self.0, self.4.as_ref(), request, tx.into()),
+        };
+        if let Err(e) = sent {
+            return Either::B(Err(e).into_future());
+        }
+
+        let rx = rx
+            .map_err(|e| format_err!("failed to enqueue new request: {:?}", e))
+            .and_then(move |r| match r {
+                Err(ZkError::Unimplemented) => Err(unsupported(opcode)),
+                r => Ok(r),
+            });
+        Either::A(match self.3 {
+            // a response that arrives after we gave up is dropped along with its receiver
+            Some(timeout) => Either::A(
+                tokio::timer::Timeout::new(rx, timeout)
+                    .map_err(|e| super::timeout_error(e, || error::Timeout.into())),
+            ),
+            None => Either::B(rx),
+        })
+    }
+
+    /// Set whether a request waits for a free slot if the request queue is full, rather than
+    /// failing with [`error::Busy`].
+    pub(crate) fn wait_for_slot(&mut self, wait: bool) {
+        if let Some(ref mut limit) = self.5 {
+            limit.set_wait(wait);
+        }
+    }
+
+    /// Hold back writes according to `policy` until [`Enqueuer::authenticated`] is called.
+    pub(crate) fn hold_writes(&mut self, policy: SaslWritePolicy) {
+        self.4 = Some(WriteGate::new(policy));
+    }
+
+    /// Note whether the connection was authenticated, which releases (or fails) the writes held
+    /// back until now, and tell the default watcher about it.
+    pub(crate) fn authenticated(&self, authenticated: bool) {
+        if let Some(ref gate) = self.4 {
+            gate.finish(&self.0, authenticated);
+        }
+        self.2.send(&WatchedEvent {
+            event_type: WatchedEventType::None,
+            keeper_state: if authenticated {
+                KeeperState::SaslAuthenticated
+            } else {
+                KeeperState::AuthFailed
+            },
+            path: String::new(),
+            zxid: None,
+        });
+    }
+
+    pub(crate) fn watch_events(&self) -> mpsc::UnboundedReceiver<WatchedEvent> {
+        self.2.subscribe()
+    }
+
+    pub(crate) fn connected_server(&self) -> Option<usize> {
+        self.1.connected_server()
+    }
+
+    pub(crate) fn last_zxid(&self) -> i64 {
+        self.1.last_zxid()
+    }
+
+    pub(crate) fn outstanding(&self) -> usize {
+        self.1.outstanding()
+    }
+
+    /// Wait until no requests are waiting for a response from the server, or until the operation
+    /// timeout has passed, if there is one.
+    pub(crate) fn drained(&self) -> impl Future<Item = (), Error = ()> {
+        // the waiters are also let go once the client has shut down
+        let drained = self.1.drained().then(|_| Ok(()));
+        match self.3 {
+            Some(timeout) => {
+                Either::A(tokio::timer::Timeout::new(drained, timeout).then(|_| Ok(())))
             }
+            None => Either::B(drained),
         }
     }
+
+    pub(crate) fn supports(&self, feature: Feature) -> Option<bool> {
+        self.1.supports(match feature {
+            Feature::CreateWithStat => request::OpCode::Create2,
+            Feature::Container => request::OpCode::CreateContainer,
+            Feature::Ttl => request::OpCode::CreateTtl,
+            Feature::GetEphemerals => request::OpCode::GetEphemerals,
+            Feature::PersistentWatches => request::OpCode::AddWatch,
+            Feature::WhoAmI => request::OpCode::WhoAmI,
+        })
+    }
+
+    pub(crate) fn state(&self) -> KeeperState {
+        self.1.current()
+    }
+
+    pub(crate) fn state_events(&self) -> mpsc::UnboundedReceiver<KeeperState> {
+        self.1.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net;
+    use std::sync::{Arc, Mutex};
+
+    /// A clock that only moves when slept on, and remembers how long each sleep was.
+    #[derive(Clone)]
+    struct MockClock(Arc<Mutex<(time::Instant, Vec<time::Duration>)>>);
+
+    impl Clock for MockClock {
+        fn now(&self) -> time::Instant {
+            self.0.lock().unwrap().0
+        }
+
+        fn sleep_until(
+            &self,
+            deadline: time::Instant,
+        ) -> Box<dyn Future<Item = (), Error = failure::Error> + Send> {
+            let mut inner = self.0.lock().unwrap();
+            let slept = deadline - inner.0;
+            inner.0 = deadline;
+            inner.1.push(slept);
+            Box::new(future::ok(()))
+        }
+    }
+
+    #[test]
+    fn reconnect_backs_off() {
+        // nothing listens on this address anymore, so connecting fails right away
+        let addr = net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let clock = MockClock(Arc::new(Mutex::new((time::Instant::now(), Vec::new()))));
+        let backoff = Backoff {
+            initial: time::Duration::from_millis(100),
+            max: time::Duration::from_millis(500),
+            multiplier: 2.0,
+            jitter: false,
+        };
+        let options = ConnectOptions {
+            chroot: None,
+            connect_timeout: None,
+            read_only: false,
+            operation_timeout: None,
+            ping_interval: None,
+            read_idle_timeout: None,
+            backoff,
+            request_queue_capacity: None,
+            check_response_length: false,
+            utf8_policy: Utf8Policy::Strict,
+            selector: Selector::default(),
+            max_reconnect_attempts: None,
+        };
+        let deadline = clock.now() + time::Duration::from_secs(2);
+        let log = slog::Logger::root(slog::Discard, o!());
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let err = rt
+            .block_on(reconnect::<tokio::net::TcpStream, _>(
+                vec![addr],
+                0,
+                &options,
+                deadline,
+                clock.clone(),
+                log,
+            ))
+            .unwrap_err();
+        assert_eq!(
+            err.find_root_cause().downcast_ref::<ZkError>(),
+            Some(&ZkError::SessionExpired)
+        );
+
+        // the last wait is cut short by the deadline
+        let slept: Vec<_> = clock
+            .0
+            .lock()
+            .unwrap()
+            .1
+            .iter()
+            .map(|d| d.as_millis())
+            .collect();
+        assert_eq!(slept, vec![100, 200, 400, 500, 500, 300]);
+        assert_eq!(clock.now(), deadline);
+    }
 }
@@ -1,9 +1,12 @@
+use super::chroot;
+use super::watch::WatchPaths;
 use super::Watch;
 use super::ZkError;
 use byteorder::{BigEndian, WriteBytesExt};
 use std::borrow::Cow;
 use std::io::{self, Write};
-use {Acl, CreateMode};
+use types::CONFIG_NODE;
+use {Acl, AddWatchMode, CreateMode, Permission, Reconfig, WatcherType};
 
 #[derive(Debug)]
 pub(crate) enum Request {
@@ -34,14 +37,28 @@ pub(crate) enum Request {
         acl: Cow<'static, [Acl]>,
         mode: CreateMode,
     },
+    Create2 {
+        path: String,
+        data: Cow<'static, [u8]>,
+        acl: Cow<'static, [Acl]>,
+        mode: CreateMode,
+    },
     GetChildren {
         path: String,
         watch: Watch,
     },
+    GetChildren2 {
+        path: String,
+        watch: Watch,
+    },
     GetData {
         path: String,
         watch: Watch,
     },
+    /// Read the data of a node without copying it out of the response it arrives in.
+    GetDataShared {
+        path: String,
+    },
     GetAcl {
         path: String,
     },
@@ -54,33 +71,134 @@ pub(crate) enum Request {
         path: String,
         version: i32,
     },
+    Sync {
+        path: String,
+    },
+    GetEphemerals {
+        prefix: String,
+    },
+    GetAllChildrenNumber {
+        path: String,
+    },
+    /// Read the ensemble configuration, which is not subject to the chroot.
+    GetConfig,
+    Reconfig {
+        /// Comma-separated server specifications to add.
+        joining: Option<String>,
+        /// Comma-separated IDs of servers to remove.
+        leaving: Option<String>,
+        /// Comma-separated server specifications of a new ensemble.
+        new_members: Option<String>,
+        /// The expected configuration version, or -1 for any.
+        from_config: i64,
+    },
+    WhoAmI,
+    AddWatch {
+        path: String,
+        mode: AddWatchMode,
+    },
+    RemoveWatches {
+        path: String,
+        watcher_type: WatcherType,
+        /// Only remove local watchers, and never send the request to the server.
+        local: bool,
+    },
+    Auth {
+        scheme: String,
+        auth: Cow<'static, [u8]>,
+    },
+    Sasl {
+        token: Vec<u8>,
+    },
+    SetWatches {
+        relative_zxid: i64,
+        watches: WatchPaths,
+    },
     Multi(Vec<Request>),
+    CloseSession,
+    /// An operation the client does not model, whose `body` is sent as it is.
+    Raw {
+        opcode: i32,
+        body: Vec<u8>,
+    },
 }
 
 #[derive(Clone, Copy, Debug, Hash, Eq, PartialEq, Ord, PartialOrd)]
-#[repr(i32)]
 #[allow(dead_code)]
 pub(super) enum OpCode {
-    Notification = 0,
-    Create = 1,
-    Delete = 2,
-    Exists = 3,
-    GetData = 4,
-    SetData = 5,
-    GetACL = 6,
-    SetACL = 7,
-    GetChildren = 8,
-    Synchronize = 9,
-    Ping = 11,
-    GetChildren2 = 12,
-    Check = 13,
-    Multi = 14,
-    Auth = 100,
-    SetWatches = 101,
-    Sasl = 102,
-    CreateSession = -10,
-    CloseSession = -11,
-    Error = -1,
+    Notification,
+    Create,
+    Delete,
+    Exists,
+    GetData,
+    SetData,
+    GetACL,
+    SetACL,
+    GetChildren,
+    Synchronize,
+    Ping,
+    GetChildren2,
+    Check,
+    Multi,
+    Create2,
+    Reconfig,
+    RemoveWatches,
+    CreateContainer,
+    CreateTtl,
+    MultiRead,
+    Auth,
+    SetWatches,
+    Sasl,
+    GetEphemerals,
+    GetAllChildrenNumber,
+    SetWatches2,
+    AddWatch,
+    WhoAmI,
+    CreateSession,
+    CloseSession,
+    Error,
+    /// An operation the client does not model, whose response is handed back as it is.
+    Raw(i32),
+}
+
+impl OpCode {
+    /// The code the server knows this operation by.
+    pub(super) fn code(self) -> i32 {
+        match self {
+            OpCode::Notification => 0,
+            OpCode::Create => 1,
+            OpCode::Delete => 2,
+            OpCode::Exists => 3,
+            OpCode::GetData => 4,
+            OpCode::SetData => 5,
+            OpCode::GetACL => 6,
+            OpCode::SetACL => 7,
+            OpCode::GetChildren => 8,
+            OpCode::Synchronize => 9,
+            OpCode::Ping => 11,
+            OpCode::GetChildren2 => 12,
+            OpCode::Check => 13,
+            OpCode::Multi => 14,
+            OpCode::Create2 => 15,
+            OpCode::Reconfig => 16,
+            OpCode::RemoveWatches => 18,
+            OpCode::CreateContainer => 19,
+            OpCode::CreateTtl => 21,
+            OpCode::MultiRead => 22,
+            OpCode::Auth => 100,
+            OpCode::SetWatches => 101,
+            OpCode::Sasl => 102,
+            OpCode::GetEphemerals => 103,
+            OpCode::GetAllChildrenNumber => 104,
+            OpCode::SetWatches2 => 105,
+            OpCode::AddWatch => 106,
+            OpCode::WhoAmI => 107,
+            OpCode::CreateSession => -10,
+            OpCode::CloseSession => -11,
+            OpCode::Error => -1,
+            OpCode::Raw(code) => code,
+        }
+    }
 }
 
 impl From<i32> for OpCode {
@@ -100,9 +218,20 @@ impl From<i32> for OpCode {
             12 => OpCode::GetChildren2,
             13 => OpCode::Check,
             14 => OpCode::Multi,
+            15 => OpCode::Create2,
+            16 => OpCode::Reconfig,
+            18 => OpCode::RemoveWatches,
+            19 => OpCode::CreateContainer,
+            21 => OpCode::CreateTtl,
+            22 => OpCode::MultiRead,
             100 => OpCode::Auth,
             101 => OpCode::SetWatches,
             102 => OpCode::Sasl,
+            103 => OpCode::GetEphemerals,
+            104 => OpCode::GetAllChildrenNumber,
+            105 => OpCode::SetWatches2,
+            106 => OpCode::AddWatch,
+            107 => OpCode::WhoAmI,
             -10 => OpCode::CreateSession,
             -11 => OpCode::CloseSession,
             -1 => OpCode::Error,
@@ -121,19 +250,49 @@ pub trait WriteTo {
     fn write_to<W: Write>(&self, writer: W) -> io::Result<()>;
 }
 
+impl WriteTo for [String] {
+    fn write_to<W: Write>(&self, writer: W) -> io::Result<()> {
+        write_list(writer, self)
+    }
+}
+
+impl WriteTo for Vec<String> {
+    fn write_to<W: Write>(&self, writer: W) -> io::Result<()> {
+        self.as_slice().write_to(writer)
+    }
+}
+
+impl WriteTo for [Acl] {
+    fn write_to<W: Write>(&self, writer: W) -> io::Result<()> {
+        write_list(writer, self)
+    }
+}
+
+impl WriteTo for Vec<Acl> {
+    fn write_to<W: Write>(&self, writer: W) -> io::Result<()> {
+        self.as_slice().write_to(writer)
+    }
+}
+
 impl WriteTo for Acl {
     fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
-        writer.write_u32::<BigEndian>(self.perms.code())?;
+        self.perms.write_to(&mut writer)?;
         self.scheme.write_to(&mut writer)?;
         self.id.write_to(writer)
     }
 }
 
+impl WriteTo for Permission {
+    fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_u32::<BigEndian>(self.to_raw())
+    }
+}
+
 impl WriteTo for MultiHeader {
     fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
         match *self {
             MultiHeader::NextOk(opcode) => {
-                writer.write_i32::<BigEndian>(opcode as i32)?;
+                writer.write_i32::<BigEndian>(opcode.code())?;
                 writer.write_u8(false as u8)?;
                 writer.write_i32::<BigEndian>(-1)
             }
@@ -163,6 +322,22 @@ impl WriteTo for str {
     }
 }
 
+impl WriteTo for String {
+    fn write_to<W: Write>(&self, writer: W) -> io::Result<()> {
+        self.as_str().write_to(writer)
+    }
+}
+
+impl<T: WriteTo + ?Sized> WriteTo for Option<&T> {
+    fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        match *self {
+            Some(t) => t.write_to(writer),
+            // a null string or buffer
+            None => writer.write_i32::<BigEndian>(-1),
+        }
+    }
+}
+
 impl WriteTo for [u8] {
     fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
         try!(writer.write_i32::<BigEndian>(self.len() as i32));
@@ -182,7 +357,38 @@ where
     Ok(())
 }
 
+/// Join the items of `list` with commas, or return `None` if there are none.
+fn comma_separated<T: ToString>(list: &[T]) -> Option<String> {
+    if list.is_empty() {
+        return None;
+    }
+    let items: Vec<_> = list.iter().map(ToString::to_string).collect();
+    Some(items.join(","))
+}
+
 impl Request {
+    /// Build the request for the given change to the ensemble, which is expected to currently be
+    /// at configuration version `from_config` (or at any version if it is -1).
+    pub(crate) fn reconfig(change: &Reconfig, from_config: i64) -> Self {
+        match *change {
+            Reconfig::Incremental {
+                ref joining,
+                ref leaving,
+            } => Request::Reconfig {
+                joining: comma_separated(joining),
+                leaving: comma_separated(leaving),
+                new_members: None,
+                from_config,
+            },
+            Reconfig::NonIncremental { ref members } => Request::Reconfig {
+                joining: None,
+                leaving: None,
+                new_members: comma_separated(members),
+                from_config,
+            },
+        }
+    }
+
     pub(super) fn serialize_into(&self, buffer: &mut Vec<u8>) -> Result<(), io::Error> {
         match *self {
             Request::Connect {
@@ -209,6 +415,10 @@ impl Request {
                 ref path,
                 ref watch,
             }
+            | Request::GetChildren2 {
+                ref path,
+                ref watch,
+            }
             | Request::Exists {
                 ref path,
                 ref watch,
@@ -234,13 +444,26 @@ impl Request {
                 ref data,
                 mode,
                 ref acl,
+            }
+            | Request::Create2 {
+                ref path,
+                ref data,
+                mode,
+                ref acl,
             } => {
                 path.write_to(&mut *buffer)?;
                 data.write_to(&mut *buffer)?;
-                write_list(&mut *buffer, acl)?;
-                buffer.write_i32::<BigEndian>(mode as i32)?;
+                acl.write_to(&mut *buffer)?;
+                buffer.write_i32::<BigEndian>(mode.flag())?;
+                if let Some(ttl) = mode.ttl() {
+                    // out-of-range TTLs are rejected by the server
+                    buffer.write_i64::<BigEndian>(ttl.as_millis() as i64)?;
+                }
             }
-            Request::GetAcl { ref path } => {
+            Request::GetAcl { ref path }
+            | Request::Sync { ref path }
+            | Request::GetEphemerals { prefix: ref path }
+            | Request::GetAllChildrenNumber { ref path } => {
                 path.write_to(&mut *buffer)?;
             }
             Request::SetAcl {
@@ -249,13 +472,72 @@ impl Request {
                 version,
             } => {
                 path.write_to(&mut *buffer)?;
-                write_list(&mut *buffer, acl)?;
+                acl.write_to(&mut *buffer)?;
                 buffer.write_i32::<BigEndian>(version)?;
             }
             Request::Check { ref path, version } => {
                 path.write_to(&mut *buffer)?;
                 buffer.write_i32::<BigEndian>(version)?;
             }
+            Request::GetDataShared { ref path } => {
+                path.write_to(&mut *buffer)?;
+                // watch
+                buffer.write_u8(0)?;
+            }
+            Request::GetConfig => {
+                CONFIG_NODE.write_to(&mut *buffer)?;
+                // watch
+                buffer.write_u8(0)?;
+            }
+            Request::Reconfig {
+                ref joining,
+                ref leaving,
+                ref new_members,
+                from_config,
+            } => {
+                joining.as_ref().write_to(&mut *buffer)?;
+                leaving.as_ref().write_to(&mut *buffer)?;
+                new_members.as_ref().write_to(&mut *buffer)?;
+                buffer.write_i64::<BigEndian>(from_config)?;
+            }
+            Request::WhoAmI | Request::CloseSession => {}
+            Request::AddWatch { ref path, mode } => {
+                path.write_to(&mut *buffer)?;
+                buffer.write_i32::<BigEndian>(mode as i32)?;
+            }
+            Request::RemoveWatches {
+                ref path,
+                watcher_type,
+                ..
+            } => {
+                path.write_to(&mut *buffer)?;
+                buffer.write_i32::<BigEndian>(watcher_type as i32)?;
+            }
+            Request::Auth {
+                ref scheme,
+                ref auth,
+            } => {
+                // auth type; always 0 in current servers
+                buffer.write_i32::<BigEndian>(0)?;
+                scheme.write_to(&mut *buffer)?;
+                auth.write_to(&mut *buffer)?;
+            }
+            Request::Sasl { ref token } => {
+                token[..].write_to(&mut *buffer)?;
+            }
+            Request::SetWatches {
+                relative_zxid,
+                ref watches,
+            } => {
+                buffer.write_i64::<BigEndian>(relative_zxid)?;
+                watches.data.write_to(&mut *buffer)?;
+                watches.exist.write_to(&mut *buffer)?;
+                watches.child.write_to(&mut *buffer)?;
+                if self.opcode() == OpCode::SetWatches2 {
+                    watches.persistent.write_to(&mut *buffer)?;
+                    watches.persistent_recursive.write_to(&mut *buffer)?;
+                }
+            }
             Request::Multi(ref requests) => {
                 for r in requests {
                     MultiHeader::NextOk(r.opcode()).write_to(&mut *buffer)?;
@@ -263,23 +545,338 @@ impl Request {
                 }
                 MultiHeader::Done.write_to(&mut *buffer)?;
             }
+            Request::Raw { ref body, .. } => {
+                buffer.write_all(body)?;
+            }
         }
         Ok(())
     }
 
+    /// The path of the node this request operates on, if it operates on a single node.
+    #[cfg(feature = "tracing")]
+    pub(super) fn path(&self) -> Option<&str> {
+        match *self {
+            Request::Exists { ref path, .. }
+            | Request::Delete { ref path, .. }
+            | Request::SetData { ref path, .. }
+            | Request::Create { ref path, .. }
+            | Request::Create2 { ref path, .. }
+            | Request::GetChildren { ref path, .. }
+            | Request::GetChildren2 { ref path, .. }
+            | Request::GetData { ref path, .. }
+            | Request::GetDataShared { ref path }
+            | Request::GetAcl { ref path }
+            | Request::SetAcl { ref path, .. }
+            | Request::Check { ref path, .. }
+            | Request::Sync { ref path }
+            | Request::GetEphemerals { prefix: ref path }
+            | Request::GetAllChildrenNumber { ref path }
+            | Request::AddWatch { ref path, .. }
+            | Request::RemoveWatches { ref path, .. } => Some(path),
+            Request::GetConfig => Some(CONFIG_NODE),
+            _ => None,
+        }
+    }
+
+    /// Move all paths in this request below `chroot`.
+    pub(super) fn prepend_chroot(&mut self, chroot: &str) {
+        match *self {
+            Request::Exists { ref mut path, .. }
+            | Request::Delete { ref mut path, .. }
+            | Request::SetData { ref mut path, .. }
+            | Request::Create { ref mut path, .. }
+            | Request::Create2 { ref mut path, .. }
+            | Request::GetChildren { ref mut path, .. }
+            | Request::GetChildren2 { ref mut path, .. }
+            | Request::GetData { ref mut path, .. }
+            | Request::GetDataShared { ref mut path }
+            | Request::GetAcl { ref mut path }
+            | Request::SetAcl { ref mut path, .. }
+            | Request::Check { ref mut path, .. }
+            | Request::Sync { ref mut path }
+            | Request::GetEphemerals {
+                prefix: ref mut path,
+            }
+            | Request::GetAllChildrenNumber { ref mut path }
+            | Request::AddWatch { ref mut path, .. }
+            | Request::RemoveWatches { ref mut path, .. } => {
+                *path = chroot::prepend(chroot, path);
+            }
+            Request::SetWatches {
+                ref mut watches, ..
+            } => {
+                for path in watches
+                    .data
+                    .iter_mut()
+                    .chain(&mut watches.exist)
+                    .chain(&mut watches.child)
+                    .chain(&mut watches.persistent)
+                    .chain(&mut watches.persistent_recursive)
+                {
+                    *path = chroot::prepend(chroot, path);
+                }
+            }
+            Request::Multi(ref mut requests) => {
+                for r in requests {
+                    r.prepend_chroot(chroot);
+                }
+            }
+            Request::Connect { .. }
+            | Request::GetConfig
+            | Request::Reconfig { .. }
+            | Request::WhoAmI
+            | Request::Auth { .. }
+            | Request::Sasl { .. }
+            | Request::CloseSession
+            | Request::Raw { .. } => {}
+        }
+    }
+
     pub(super) fn opcode(&self) -> OpCode {
         match *self {
             Request::Connect { .. } => OpCode::CreateSession,
             Request::Exists { .. } => OpCode::Exists,
             Request::Delete { .. } => OpCode::Delete,
+            // container nodes have their own opcode, and are always answered with a stat
+            Request::Create { mode, .. } | Request::Create2 { mode, .. }
+                if mode == CreateMode::Container =>
+            {
+                OpCode::CreateContainer
+            }
+            Request::Create { mode, .. } | Request::Create2 { mode, .. }
+                if mode.ttl().is_some() =>
+            {
+                OpCode::CreateTtl
+            }
             Request::Create { .. } => OpCode::Create,
+            Request::Create2 { .. } => OpCode::Create2,
             Request::GetChildren { .. } => OpCode::GetChildren,
+            Request::GetChildren2 { .. } => OpCode::GetChildren2,
             Request::SetData { .. } => OpCode::SetData,
-            Request::GetData { .. } => OpCode::GetData,
+            Request::GetData { .. } | Request::GetDataShared { .. } | Request::GetConfig => {
+                OpCode::GetData
+            }
+            Request::Reconfig { .. } => OpCode::Reconfig,
             Request::GetAcl { .. } => OpCode::GetACL,
             Request::SetAcl { .. } => OpCode::SetACL,
+            // a batch of reads is not a transaction, and servers expect it under its own opcode
+            Request::Multi(ref requests)
+                if !requests.is_empty() && requests.iter().all(|r| !r.is_write()) =>
+            {
+                OpCode::MultiRead
+            }
             Request::Multi { .. } => OpCode::Multi,
             Request::Check { .. } => OpCode::Check,
+            Request::Sync { .. } => OpCode::Synchronize,
+            Request::GetEphemerals { .. } => OpCode::GetEphemerals,
+            Request::GetAllChildrenNumber { .. } => OpCode::GetAllChildrenNumber,
+            Request::WhoAmI => OpCode::WhoAmI,
+            Request::CloseSession => OpCode::CloseSession,
+            Request::AddWatch { .. } => OpCode::AddWatch,
+            Request::RemoveWatches { .. } => OpCode::RemoveWatches,
+            Request::Auth { .. } => OpCode::Auth,
+            Request::Sasl { .. } => OpCode::Sasl,
+            Request::Raw { opcode, .. } => OpCode::Raw(opcode),
+            // persistent watches can only be restored by servers that know about them
+            Request::SetWatches { ref watches, .. }
+                if watches.persistent.is_empty() && watches.persistent_recursive.is_empty() =>
+            {
+                OpCode::SetWatches
+            }
+            Request::SetWatches { .. } => OpCode::SetWatches2,
+        }
+    }
+
+    /// Whether this request modifies the tree, and so cannot be served by a read-only server.
+    pub(super) fn is_write(&self) -> bool {
+        match *self {
+            Request::Create { .. }
+            | Request::Create2 { .. }
+            | Request::Delete { .. }
+            | Request::SetData { .. }
+            | Request::SetAcl { .. }
+            | Request::Check { .. }
+            | Request::Reconfig { .. } => true,
+            Request::Multi(ref requests) => requests.iter().any(Request::is_write),
+            Request::Connect { .. }
+            | Request::Exists { .. }
+            | Request::GetChildren { .. }
+            | Request::GetChildren2 { .. }
+            | Request::GetData { .. }
+            | Request::GetDataShared { .. }
+            | Request::GetAcl { .. }
+            | Request::Sync { .. }
+            | Request::GetEphemerals { .. }
+            | Request::GetAllChildrenNumber { .. }
+            | Request::GetConfig
+            | Request::WhoAmI
+            | Request::AddWatch { .. }
+            | Request::RemoveWatches { .. }
+            | Request::Auth { .. }
+            | Request::Sasl { .. }
+            | Request::SetWatches { .. }
+            | Request::CloseSession
+            | Request::Raw { .. } => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::response::{BufferReader, ReadFrom, StringReader};
+    use super::*;
+    use Member;
+
+    fn strings(buf: &mut Vec<u8>, ss: &[&str]) {
+        buf.write_i32::<BigEndian>(ss.len() as i32).unwrap();
+        for s in ss {
+            buf.write_i32::<BigEndian>(s.len() as i32).unwrap();
+            buf.extend(s.as_bytes());
+        }
+    }
+
+    #[test]
+    fn serialize_set_watches() {
+        let mut request = Request::SetWatches {
+            relative_zxid: 7,
+            watches: WatchPaths {
+                data: vec!["/d".to_string()],
+                exist: vec!["/e1".to_string(), "/e2".to_string()],
+                child: vec!["/c".to_string()],
+                ..Default::default()
+            },
+        };
+        assert_eq!(request.opcode(), OpCode::SetWatches);
+
+        let mut expected = Vec::new();
+        expected.write_i64::<BigEndian>(7).unwrap();
+        strings(&mut expected, &["/d"]);
+        strings(&mut expected, &["/e1", "/e2"]);
+        strings(&mut expected, &["/c"]);
+        let mut buf = Vec::new();
+        request.serialize_into(&mut buf).unwrap();
+        assert_eq!(buf, expected);
+
+        // persistent watches need the newer request, which appends them
+        if let Request::SetWatches {
+            ref mut watches, ..
+        } = request
+        {
+            watches.persistent_recursive.push("/r".to_string());
         }
+        assert_eq!(request.opcode(), OpCode::SetWatches2);
+        strings(&mut expected, &[]);
+        strings(&mut expected, &["/r"]);
+        let mut buf = Vec::new();
+        request.serialize_into(&mut buf).unwrap();
+        assert_eq!(buf, expected);
+    }
+
+    #[test]
+    fn serialize_reconfig() {
+        fn string(buf: &mut Vec<u8>, s: Option<&str>) {
+            match s {
+                Some(s) => {
+                    buf.write_i32::<BigEndian>(s.len() as i32).unwrap();
+                    buf.extend(s.as_bytes());
+                }
+                None => buf.write_i32::<BigEndian>(-1).unwrap(),
+            }
+        }
+
+        let change = Reconfig::Incremental {
+            joining: vec![
+                Member::new(4, "10.0.0.4:2888:3888;2181"),
+                Member::new(5, "10.0.0.5:2888:3888:observer;2181"),
+            ],
+            leaving: vec![1, 2],
+        };
+        let request = Request::reconfig(&change, 0x1_0000_0000);
+        assert_eq!(request.opcode(), OpCode::Reconfig);
+        assert!(request.is_write());
+        let mut expected = Vec::new();
+        string(
+            &mut expected,
+            Some("server.4=10.0.0.4:2888:3888;2181,server.5=10.0.0.5:2888:3888:observer;2181"),
+        );
+        string(&mut expected, Some("1,2"));
+        string(&mut expected, None);
+        expected.write_i64::<BigEndian>(0x1_0000_0000).unwrap();
+        let mut buf = Vec::new();
+        request.serialize_into(&mut buf).unwrap();
+        assert_eq!(buf, expected);
+
+        // leaving out either half of an incremental change
+        let change = Reconfig::Incremental {
+            joining: vec![],
+            leaving: vec![3],
+        };
+        let mut expected = Vec::new();
+        string(&mut expected, None);
+        string(&mut expected, Some("3"));
+        string(&mut expected, None);
+        expected.write_i64::<BigEndian>(-1).unwrap();
+        let mut buf = Vec::new();
+        Request::reconfig(&change, -1)
+            .serialize_into(&mut buf)
+            .unwrap();
+        assert_eq!(buf, expected);
+
+        let change = Reconfig::NonIncremental {
+            members: vec![Member::new(1, "a:1:2"), Member::new(2, "b:1:2")],
+        };
+        let mut expected = Vec::new();
+        string(&mut expected, None);
+        string(&mut expected, None);
+        string(&mut expected, Some("server.1=a:1:2,server.2=b:1:2"));
+        expected.write_i64::<BigEndian>(7).unwrap();
+        let mut buf = Vec::new();
+        Request::reconfig(&change, 7)
+            .serialize_into(&mut buf)
+            .unwrap();
+        assert_eq!(buf, expected);
+    }
+
+    fn round_trip<T>(t: &T) -> T
+    where
+        T: WriteTo + ReadFrom,
+    {
+        let mut buf = Vec::new();
+        t.write_to(&mut buf).unwrap();
+        let mut read = &buf[..];
+        let back = T::read_from(&mut read).unwrap();
+        assert!(read.is_empty(), "{} bytes left over", read.len());
+        back
+    }
+
+    #[test]
+    fn write_then_read() {
+        for &p in &[
+            Permission::NONE,
+            Permission::READ | Permission::ADMIN,
+            Permission::ALL,
+        ] {
+            assert_eq!(round_trip(&p), p);
+        }
+
+        let acl = Acl::new(Permission::READ, "ip", "10.0.0.0/8");
+        assert_eq!(round_trip(&acl), acl);
+
+        let acls = vec![acl, Acl::digest("user", "password", Permission::ALL)];
+        assert_eq!(round_trip(&acls), acls);
+        assert_eq!(round_trip(&Vec::<Acl>::new()), vec![]);
+
+        let strings = vec!["/a".to_string(), String::new(), "ü".to_string()];
+        assert_eq!(round_trip(&strings), strings);
+
+        let mut buf = Vec::new();
+        "/path".write_to(&mut buf).unwrap();
+        b"\x00\x01"[..].write_to(&mut buf).unwrap();
+        b""[..].write_to(&mut buf).unwrap();
+        let mut read = &buf[..];
+        assert_eq!(read.read_string().unwrap(), "/path");
+        assert_eq!(read.read_buffer().unwrap(), vec![0, 1]);
+        assert_eq!(read.read_nullable_buffer().unwrap(), Some(vec![]));
+        assert!(read.is_empty());
     }
 }
@@ -1,6 +1,8 @@
+use super::chroot;
 use super::error::ZkError;
 use super::request::{MultiHeader, OpCode};
 use byteorder::{BigEndian, ReadBytesExt};
+use bytes::Bytes;
 use failure;
 use std::io::{self, Read};
 use {Acl, KeeperState, Permission, Stat, WatchedEvent, WatchedEventType};
@@ -19,22 +21,53 @@ pub(crate) enum Response {
         bytes: Vec<u8>,
         stat: Stat,
     },
+    /// Data that shares the buffer of the response it arrived in.
+    SharedData {
+        bytes: Bytes,
+        stat: Stat,
+    },
     GetAcl {
         acl: Vec<Acl>,
         stat: Stat,
     },
     Empty,
+    Count(i32),
+    AuthInfo(Vec<(String, String)>),
     Strings(Vec<String>),
+    StringsStat {
+        children: Vec<String>,
+        stat: Stat,
+    },
     String(String),
+    CreateStat {
+        path: String,
+        stat: Stat,
+    },
     Multi(Vec<Result<Response, ZkError>>),
+    Sasl(Vec<u8>),
+    Raw(Vec<u8>),
+}
+
+/// How to decode strings in responses, such as the names of nodes, that are not valid UTF-8.
+///
+/// The server does not check that the names of nodes are valid UTF-8, so other clients may have
+/// created nodes whose names are not.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Utf8Policy {
+    /// Fail to decode the response, which drops the connection to the server.
+    Strict,
+    /// Replace invalid sequences with `U+FFFD REPLACEMENT CHARACTER`, as
+    /// [`String::from_utf8_lossy`] does. Note that the decoded name then no longer refers to the
+    /// node.
+    Lossy,
 }
 
 pub trait ReadFrom: Sized {
-    fn read_from<R: Read>(read: &mut R) -> io::Result<Self>;
+    fn read_from<R: StringReader>(read: &mut R) -> io::Result<Self>;
 }
 
 impl ReadFrom for Vec<String> {
-    fn read_from<R: Read>(read: &mut R) -> io::Result<Self> {
+    fn read_from<R: StringReader>(read: &mut R) -> io::Result<Self> {
         let len = try!(read.read_i32::<BigEndian>());
         let mut items = Vec::with_capacity(len as usize);
         for _ in 0..len {
@@ -44,8 +77,21 @@ impl ReadFrom for Vec<String> {
     }
 }
 
+impl ReadFrom for Vec<(String, String)> {
+    fn read_from<R: StringReader>(read: &mut R) -> io::Result<Self> {
+        let len = read.read_i32::<BigEndian>()?;
+        let mut items = Vec::with_capacity(len as usize);
+        for _ in 0..len {
+            let scheme = read.read_string()?;
+            let id = read.read_string()?;
+            items.push((scheme, id));
+        }
+        Ok(items)
+    }
+}
+
 impl ReadFrom for Stat {
-    fn read_from<R: Read>(read: &mut R) -> io::Result<Stat> {
+    fn read_from<R: StringReader>(read: &mut R) -> io::Result<Stat> {
         Ok(Stat {
             czxid: try!(read.read_i64::<BigEndian>()),
             mzxid: try!(read.read_i64::<BigEndian>()),
@@ -63,7 +109,7 @@ impl ReadFrom for Stat {
 }
 
 impl ReadFrom for WatchedEvent {
-    fn read_from<R: Read>(read: &mut R) -> io::Result<WatchedEvent> {
+    fn read_from<R: StringReader>(read: &mut R) -> io::Result<WatchedEvent> {
         let wtype = read.read_i32::<BigEndian>()?;
         let state = read.read_i32::<BigEndian>()?;
         let path = read.read_string()?;
@@ -71,12 +117,28 @@ impl ReadFrom for WatchedEvent {
             event_type: WatchedEventType::from(wtype),
             keeper_state: KeeperState::from(state),
             path,
+            zxid: None,
         })
     }
 }
 
+/// Read the watch event in the rest of a notification `frame`.
+///
+/// Newer servers append the zxid of the change to the event, while older ones end it with the
+/// path. Since both speak the same protocol version, the length of the frame tells them apart.
+pub(crate) fn read_watched_event(frame: &mut &[u8], utf8: Utf8Policy) -> io::Result<WatchedEvent> {
+    let mut e = match utf8 {
+        Utf8Policy::Strict => WatchedEvent::read_from(frame)?,
+        Utf8Policy::Lossy => WatchedEvent::read_from(&mut Lossy(frame))?,
+    };
+    if frame.len() >= 8 {
+        e.zxid = Some(frame.read_i64::<BigEndian>()?);
+    }
+    Ok(e)
+}
+
 impl ReadFrom for Vec<Acl> {
-    fn read_from<R: Read>(read: &mut R) -> io::Result<Self> {
+    fn read_from<R: StringReader>(read: &mut R) -> io::Result<Self> {
         let len = try!(read.read_i32::<BigEndian>());
         let mut items = Vec::with_capacity(len as usize);
         for _ in 0..len {
@@ -87,7 +149,7 @@ impl ReadFrom for Vec<Acl> {
 }
 
 impl ReadFrom for Acl {
-    fn read_from<R: Read>(read: &mut R) -> io::Result<Self> {
+    fn read_from<R: StringReader>(read: &mut R) -> io::Result<Self> {
         let perms = try!(Permission::read_from(read));
         let scheme = try!(read.read_string());
         let id = try!(read.read_string());
@@ -96,13 +158,13 @@ impl ReadFrom for Acl {
 }
 
 impl ReadFrom for Permission {
-    fn read_from<R: Read>(read: &mut R) -> io::Result<Self> {
+    fn read_from<R: StringReader>(read: &mut R) -> io::Result<Self> {
         Ok(Permission::from_raw(try!(read.read_u32::<BigEndian>())))
     }
 }
 
 impl ReadFrom for MultiHeader {
-    fn read_from<R: Read>(read: &mut R) -> io::Result<Self> {
+    fn read_from<R: StringReader>(read: &mut R) -> io::Result<Self> {
         let opcode = read.read_i32::<BigEndian>()?;
         let done = read.read_u8()? != 0;
         let err = read.read_i32::<BigEndian>()?;
@@ -117,63 +179,142 @@ impl ReadFrom for MultiHeader {
 }
 
 pub trait BufferReader: Read {
+    /// Read a buffer, treating a null buffer as an empty one.
     fn read_buffer(&mut self) -> io::Result<Vec<u8>>;
+
+    /// Read a buffer, or `None` if the buffer is null (has a negative length on the wire).
+    fn read_nullable_buffer(&mut self) -> io::Result<Option<Vec<u8>>>;
 }
 
 impl<R: Read> BufferReader for R {
     fn read_buffer(&mut self) -> io::Result<Vec<u8>> {
+        Ok(self.read_nullable_buffer()?.unwrap_or_default())
+    }
+
+    fn read_nullable_buffer(&mut self) -> io::Result<Option<Vec<u8>>> {
         let len = try!(self.read_i32::<BigEndian>());
-        let len = if len < 0 { 0 } else { len as usize };
-        let mut buf = vec![0; len];
-        let read = try!(self.read(&mut buf));
-        if read == len {
-            Ok(buf)
-        } else {
-            Err(io::Error::new(
-                io::ErrorKind::WouldBlock,
-                "read_buffer failed",
-            ))
+        if len < 0 {
+            return Ok(None);
         }
+        let mut buf = vec![0; len as usize];
+        try!(self.read_exact(&mut buf));
+        Ok(Some(buf))
     }
 }
 
-trait StringReader: Read {
-    fn read_string(&mut self) -> io::Result<String>;
-}
+pub trait StringReader: Read + Sized {
+    /// Whether to replace invalid UTF-8 in strings, rather than fail to read them.
+    fn lossy(&self) -> bool {
+        false
+    }
 
-impl<R: Read> StringReader for R {
     fn read_string(&mut self) -> io::Result<String> {
         let raw = try!(self.read_buffer());
-        Ok(String::from_utf8(raw).unwrap())
+        if self.lossy() {
+            return Ok(String::from_utf8_lossy(&raw).into_owned());
+        }
+        String::from_utf8(raw).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+impl StringReader for &[u8] {}
+
+/// A reader of a frame that decodes strings according to [`Utf8Policy::Lossy`].
+pub(super) struct Lossy<'a, 'b: 'a>(pub(super) &'a mut &'b [u8]);
+
+impl<'a, 'b> Read for Lossy<'a, 'b> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl<'a, 'b> StringReader for Lossy<'a, 'b> {
+    fn lossy(&self) -> bool {
+        true
     }
 }
 
 impl Response {
-    pub(super) fn parse(opcode: OpCode, reader: &mut &[u8]) -> Result<Self, failure::Error> {
+    /// Make all paths in this response to a request with the given `opcode` relative to `chroot`.
+    ///
+    /// Note that the names returned by `get_children` are not paths, and are left alone.
+    pub(super) fn strip_chroot(&mut self, opcode: OpCode, chroot: &str) {
+        match (opcode, self) {
+            (OpCode::Multi, &mut Response::Multi(ref mut responses)) => {
+                // the only paths a multi responds with are those of created nodes
+                for r in responses {
+                    if let Ok(ref mut r) = *r {
+                        r.strip_chroot(OpCode::Create, chroot);
+                    }
+                }
+            }
+            (OpCode::GetEphemerals, &mut Response::Strings(ref mut paths)) => {
+                for path in paths {
+                    chroot::strip(chroot, path);
+                }
+            }
+            (_, &mut Response::String(ref mut path))
+            | (_, &mut Response::CreateStat { ref mut path, .. }) => {
+                chroot::strip(chroot, path);
+            }
+            _ => {}
+        }
+    }
+
+    pub(super) fn parse<R: StringReader>(
+        opcode: OpCode,
+        reader: &mut R,
+    ) -> Result<Self, failure::Error> {
         match opcode {
             OpCode::CreateSession => Ok(Response::Connect {
                 protocol_version: reader.read_i32::<BigEndian>()?,
                 timeout: reader.read_i32::<BigEndian>()?,
                 session_id: reader.read_i64::<BigEndian>()?,
                 password: reader.read_buffer()?,
-                read_only: reader.read_u8()? != 0,
+                // servers that do not support read-only mode leave this out
+                read_only: reader.read_u8().unwrap_or(0) != 0,
             }),
             OpCode::Exists | OpCode::SetData | OpCode::SetACL => {
                 Ok(Response::Stat(Stat::read_from(reader)?))
             }
-            OpCode::GetData => Ok(Response::GetData {
+            // reconfig answers with the new configuration
+            OpCode::GetData | OpCode::Reconfig => Ok(Response::GetData {
                 bytes: reader.read_buffer()?,
                 stat: Stat::read_from(reader)?,
             }),
             OpCode::Delete => Ok(Response::Empty),
-            OpCode::GetChildren => Ok(Response::Strings(Vec::<String>::read_from(reader)?)),
-            OpCode::Create => Ok(Response::String(reader.read_string()?)),
+            OpCode::GetChildren | OpCode::GetEphemerals => {
+                Ok(Response::Strings(Vec::<String>::read_from(reader)?))
+            }
+            OpCode::GetChildren2 => Ok(Response::StringsStat {
+                children: Vec::<String>::read_from(reader)?,
+                stat: Stat::read_from(reader)?,
+            }),
+            OpCode::Create | OpCode::Synchronize => Ok(Response::String(reader.read_string()?)),
+            OpCode::Create2 | OpCode::CreateContainer | OpCode::CreateTtl => Ok(Response::CreateStat {
+                path: reader.read_string()?,
+                stat: Stat::read_from(reader)?,
+            }),
             OpCode::GetACL => Ok(Response::GetAcl {
                 acl: Vec::<Acl>::read_from(reader)?,
                 stat: Stat::read_from(reader)?,
             }),
-            OpCode::Check => Ok(Response::Empty),
-            OpCode::Multi => {
+            OpCode::Check
+            | OpCode::RemoveWatches
+            | OpCode::SetWatches
+            | OpCode::SetWatches2
+            | OpCode::CloseSession => Ok(Response::Empty),
+            OpCode::AddWatch => {
+                // the body is an error code that duplicates the one in the reply header
+                let _ = reader.read_i32::<BigEndian>()?;
+                Ok(Response::Empty)
+            }
+            OpCode::GetAllChildrenNumber => Ok(Response::Count(reader.read_i32::<BigEndian>()?)),
+            OpCode::Sasl => Ok(Response::Sasl(reader.read_buffer()?)),
+            OpCode::WhoAmI => Ok(Response::AuthInfo(Vec::<(String, String)>::read_from(
+                reader,
+            )?)),
+            OpCode::Multi | OpCode::MultiRead => {
                 let mut responses = Vec::new();
                 loop {
                     match MultiHeader::read_from(reader)? {
@@ -189,7 +330,375 @@ impl Response {
                 }
                 Ok(Response::Multi(responses))
             }
-            _ => panic!("got unexpected response opcode {:?}", opcode),
+            OpCode::Raw(_) => {
+                let mut body = Vec::new();
+                reader.read_to_end(&mut body)?;
+                Ok(Response::Raw(body))
+            }
+            _ => bail!("got unexpected response opcode {:?}", opcode),
+        }
+    }
+
+    /// Parse the response to a `GetDataShared` request from `reader`, which holds what is left of
+    /// `frame`, slicing the data out of `frame` rather than copying it.
+    pub(super) fn parse_shared_data(
+        frame: &Bytes,
+        reader: &mut &[u8],
+    ) -> Result<Self, failure::Error> {
+        let len = reader.read_i32::<BigEndian>()?;
+        // a null buffer is an empty one
+        let len = if len < 0 { 0 } else { len as usize };
+        if reader.len() < len {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        let start = frame.len() - reader.len();
+        *reader = &reader[len..];
+        Ok(Response::SharedData {
+            bytes: frame.slice(start, start + len),
+            stat: Stat::read_from(reader)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use byteorder::WriteBytesExt;
+
+    /// A reader that hands out at most 64 bytes per `read` call.
+    struct Chunked<'a>(&'a [u8]);
+
+    impl<'a> Read for Chunked<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = buf.len().min(self.0.len()).min(64);
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
         }
     }
+
+    impl<'a> StringReader for Chunked<'a> {}
+
+    fn write_stat(buf: &mut Vec<u8>, stat: &Stat) {
+        buf.write_i64::<BigEndian>(stat.czxid).unwrap();
+        buf.write_i64::<BigEndian>(stat.mzxid).unwrap();
+        buf.write_i64::<BigEndian>(stat.ctime).unwrap();
+        buf.write_i64::<BigEndian>(stat.mtime).unwrap();
+        buf.write_i32::<BigEndian>(stat.version).unwrap();
+        buf.write_i32::<BigEndian>(stat.cversion).unwrap();
+        buf.write_i32::<BigEndian>(stat.aversion).unwrap();
+        buf.write_i64::<BigEndian>(stat.ephemeral_owner).unwrap();
+        buf.write_i32::<BigEndian>(stat.data_length).unwrap();
+        buf.write_i32::<BigEndian>(stat.num_children).unwrap();
+        buf.write_i64::<BigEndian>(stat.pzxid).unwrap();
+    }
+
+    fn stat() -> Stat {
+        Stat {
+            czxid: 1,
+            mzxid: 2,
+            ctime: 3,
+            mtime: 4,
+            version: 5,
+            cversion: 6,
+            aversion: 7,
+            ephemeral_owner: 8,
+            data_length: 9,
+            num_children: 10,
+            pzxid: 11,
+        }
+    }
+
+    #[test]
+    fn read_buffer_across_short_reads() {
+        let data: Vec<u8> = (0..200 * 1024).map(|i| i as u8).collect();
+        let mut frame = Vec::new();
+        frame.write_i32::<BigEndian>(data.len() as i32).unwrap();
+        frame.extend(&data);
+        write_stat(&mut frame, &stat());
+
+        let mut reader = Chunked(&frame);
+        assert_eq!(reader.read_buffer().unwrap(), data);
+        assert_eq!(Stat::read_from(&mut reader).unwrap(), stat());
+
+        // the same frame also decodes as a get_data response
+        match Response::parse(OpCode::GetData, &mut &frame[..]).unwrap() {
+            Response::GetData { bytes, stat: s } => {
+                assert_eq!(bytes, data);
+                assert_eq!(s, stat());
+            }
+            r => panic!("unexpected response {:?}", r),
+        }
+    }
+
+    #[test]
+    fn parse_shared_data() {
+        let data: Vec<u8> = (0..200 * 1024).map(|i| i as u8).collect();
+        let mut frame = vec![0; 16]; // xid, zxid, err
+        frame.write_i32::<BigEndian>(data.len() as i32).unwrap();
+        frame.extend(&data);
+        write_stat(&mut frame, &stat());
+        let frame = Bytes::from(frame);
+
+        let mut reader = &frame[16..];
+        match Response::parse_shared_data(&frame, &mut reader).unwrap() {
+            Response::SharedData { bytes, stat: s } => {
+                assert_eq!(bytes, data);
+                // the data was not copied out of the frame
+                assert_eq!(bytes.as_ptr(), frame[20..].as_ptr());
+                assert_eq!(s, stat());
+            }
+            r => panic!("unexpected response {:?}", r),
+        }
+        assert!(reader.is_empty());
+
+        // the data cannot reach past the end of the frame
+        let mut reader = &frame[16..1000];
+        assert!(Response::parse_shared_data(&frame, &mut reader).is_err());
+    }
+
+    #[test]
+    fn read_buffer_truncated() {
+        let mut frame = Vec::new();
+        frame.write_i32::<BigEndian>(16).unwrap();
+        frame.extend(&[0; 8]);
+        let err = Chunked(&frame).read_buffer().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn read_null_buffer() {
+        let mut frame = Vec::new();
+        frame.write_i32::<BigEndian>(-1).unwrap();
+        assert_eq!(Chunked(&frame).read_buffer().unwrap(), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn read_nullable_buffer() {
+        let mut frame = Vec::new();
+        frame.write_i32::<BigEndian>(-1).unwrap();
+        frame.write_i32::<BigEndian>(0).unwrap();
+        frame.write_i32::<BigEndian>(3).unwrap();
+        frame.extend(b"abc");
+
+        let mut reader = Chunked(&frame);
+        assert_eq!(reader.read_nullable_buffer().unwrap(), None);
+        assert_eq!(reader.read_nullable_buffer().unwrap(), Some(Vec::new()));
+        assert_eq!(
+            reader.read_nullable_buffer().unwrap(),
+            Some(b"abc".to_vec())
+        );
+        assert!(reader.0.is_empty());
+    }
+
+    #[test]
+    fn read_string_invalid_utf8() {
+        let mut frame = Vec::new();
+        frame.write_i32::<BigEndian>(3).unwrap();
+        frame.extend(&[b'/', 0xff, 0xfe]);
+        let err = (&mut &frame[..]).read_string().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // the error propagates through composite decoders as well
+        let mut children = Vec::new();
+        children.write_i32::<BigEndian>(1).unwrap();
+        children.extend(&frame);
+        assert!(Response::parse(OpCode::GetChildren, &mut &children[..]).is_err());
+    }
+
+    #[test]
+    fn read_invalid_utf8_by_policy() {
+        let mut frame = Vec::new();
+        frame.write_i32::<BigEndian>(1).unwrap();
+        frame.write_i32::<BigEndian>(4).unwrap();
+        frame.extend(&[b'a', 0xff, 0xfe, b'b']);
+
+        let mut strict = &frame[..];
+        assert!(Response::parse(OpCode::GetChildren, &mut strict).is_err());
+
+        let mut lossy = &frame[..];
+        match Response::parse(OpCode::GetChildren, &mut Lossy(&mut lossy)).unwrap() {
+            Response::Strings(children) => assert_eq!(children, vec!["a\u{fffd}\u{fffd}b"]),
+            r => panic!("unexpected response {:?}", r),
+        }
+        assert!(lossy.is_empty());
+    }
+
+    #[test]
+    fn parse_unexpected_opcode() {
+        let frame = [0u8; 16];
+        let err = Response::parse(OpCode::Ping, &mut &frame[..]).unwrap_err();
+        assert_eq!(err.to_string(), "got unexpected response opcode Ping");
+    }
+
+    #[test]
+    fn parse_get_children2() {
+        let mut frame = Vec::new();
+        frame.write_i32::<BigEndian>(2).unwrap();
+        for child in &["a", "bc"] {
+            frame.write_i32::<BigEndian>(child.len() as i32).unwrap();
+            frame.extend(child.as_bytes());
+        }
+        write_stat(&mut frame, &stat());
+
+        match Response::parse(OpCode::GetChildren2, &mut &frame[..]).unwrap() {
+            Response::StringsStat { children, stat: s } => {
+                assert_eq!(children, vec!["a".to_string(), "bc".to_string()]);
+                // every field lines up with its position on the wire
+                assert_eq!(s, stat());
+            }
+            r => panic!("unexpected response {:?}", r),
+        }
+    }
+
+    #[test]
+    fn parse_multi_with_read() {
+        let mut frame = Vec::new();
+        frame.write_i32::<BigEndian>(1).unwrap();
+        frame.write_u8(0).unwrap();
+        frame.write_i32::<BigEndian>(-1).unwrap();
+        frame.write_i32::<BigEndian>(2).unwrap();
+        frame.extend(b"/a");
+        frame.write_i32::<BigEndian>(4).unwrap();
+        frame.write_u8(0).unwrap();
+        frame.write_i32::<BigEndian>(-1).unwrap();
+        frame.write_i32::<BigEndian>(3).unwrap();
+        frame.extend(b"abc");
+        write_stat(&mut frame, &stat());
+        frame.write_i32::<BigEndian>(-1).unwrap();
+        frame.write_u8(1).unwrap();
+        frame.write_i32::<BigEndian>(-1).unwrap();
+
+        for &opcode in &[OpCode::Multi, OpCode::MultiRead] {
+            match Response::parse(opcode, &mut &frame[..]).unwrap() {
+                Response::Multi(responses) => {
+                    assert_eq!(responses.len(), 2);
+                    match responses[0] {
+                        Ok(Response::String(ref path)) => assert_eq!(path, "/a"),
+                        ref r => panic!("unexpected response {:?}", r),
+                    }
+                    match responses[1] {
+                        Ok(Response::GetData { ref bytes, stat: s }) => {
+                            assert_eq!(bytes, b"abc");
+                            assert_eq!(s, stat());
+                        }
+                        ref r => panic!("unexpected response {:?}", r),
+                    }
+                }
+                r => panic!("unexpected response {:?}", r),
+            }
+        }
+    }
+
+    #[test]
+    fn parse_create2() {
+        let mut frame = Vec::new();
+        frame.write_i32::<BigEndian>(5).unwrap();
+        frame.extend(b"/node");
+        write_stat(&mut frame, &stat());
+
+        match Response::parse(OpCode::Create2, &mut &frame[..]).unwrap() {
+            Response::CreateStat { path, stat: s } => {
+                assert_eq!(path, "/node");
+                assert_eq!(s, stat());
+            }
+            r => panic!("unexpected response {:?}", r),
+        }
+    }
+
+    #[test]
+    fn parse_get_ephemerals() {
+        let paths = ["/a", "/a/b", "/locks/lock-0000000001"];
+        let mut frame = Vec::new();
+        frame.write_i32::<BigEndian>(paths.len() as i32).unwrap();
+        for p in &paths {
+            frame.write_i32::<BigEndian>(p.len() as i32).unwrap();
+            frame.extend(p.as_bytes());
+        }
+
+        match Response::parse(OpCode::GetEphemerals, &mut &frame[..]).unwrap() {
+            Response::Strings(ephemerals) => assert_eq!(ephemerals, paths),
+            r => panic!("unexpected response {:?}", r),
+        }
+    }
+
+    #[test]
+    fn parse_get_all_children_number() {
+        for &n in &[0, 42] {
+            let mut frame = Vec::new();
+            frame.write_i32::<BigEndian>(n).unwrap();
+            match Response::parse(OpCode::GetAllChildrenNumber, &mut &frame[..]).unwrap() {
+                Response::Count(count) => assert_eq!(count, n),
+                r => panic!("unexpected response {:?}", r),
+            }
+        }
+
+        // a truncated frame is an error, not a zero count
+        assert!(Response::parse(OpCode::GetAllChildrenNumber, &mut &[0u8, 0][..]).is_err());
+    }
+
+    #[test]
+    fn parse_who_am_i() {
+        let mut frame = Vec::new();
+        frame.write_i32::<BigEndian>(2).unwrap();
+        for s in &["ip", "127.0.0.1", "digest", "user"] {
+            frame.write_i32::<BigEndian>(s.len() as i32).unwrap();
+            frame.extend(s.as_bytes());
+        }
+        match Response::parse(OpCode::WhoAmI, &mut &frame[..]).unwrap() {
+            Response::AuthInfo(ids) => assert_eq!(
+                ids,
+                vec![
+                    ("ip".to_string(), "127.0.0.1".to_string()),
+                    ("digest".to_string(), "user".to_string()),
+                ]
+            ),
+            r => panic!("unexpected response {:?}", r),
+        }
+
+        let empty = [0u8; 4];
+        match Response::parse(OpCode::WhoAmI, &mut &empty[..]).unwrap() {
+            Response::AuthInfo(ids) => assert!(ids.is_empty()),
+            r => panic!("unexpected response {:?}", r),
+        }
+    }
+    #[test]
+    fn read_unknown_event() {
+        let mut frame = Vec::new();
+        frame.write_i32::<BigEndian>(42).unwrap();
+        frame.write_i32::<BigEndian>(43).unwrap();
+        frame.write_i32::<BigEndian>(2).unwrap();
+        frame.extend(b"/a");
+        assert_eq!(
+            WatchedEvent::read_from(&mut &frame[..]).unwrap(),
+            WatchedEvent {
+                event_type: WatchedEventType::Unknown(42),
+                keeper_state: KeeperState::Unknown(43),
+                path: "/a".to_string(),
+                zxid: None,
+            }
+        );
+    }
+
+    #[test]
+    fn read_event_with_and_without_zxid() {
+        let mut frame = Vec::new();
+        frame.write_i32::<BigEndian>(3).unwrap(); // NodeDataChanged
+        frame.write_i32::<BigEndian>(3).unwrap(); // SyncConnected
+        frame.write_i32::<BigEndian>(2).unwrap();
+        frame.extend(b"/a");
+        let mut old = &frame[..];
+        let e = read_watched_event(&mut old, Utf8Policy::Strict).unwrap();
+        assert_eq!(e.event_type, WatchedEventType::NodeDataChanged);
+        assert_eq!(e.path, "/a");
+        assert_eq!(e.zxid, None);
+        assert!(old.is_empty());
+
+        frame.write_i64::<BigEndian>(0x1_0000_0042).unwrap();
+        let mut new = &frame[..];
+        let e = read_watched_event(&mut new, Utf8Policy::Strict).unwrap();
+        assert_eq!(e.path, "/a");
+        assert_eq!(e.zxid, Some(0x1_0000_0042));
+        assert!(new.is_empty());
+    }
 }
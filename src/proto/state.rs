@@ -0,0 +1,263 @@
+use super::request::OpCode;
+use futures::sync::{mpsc, oneshot};
+use std::cmp;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use KeeperState;
+
+/// Tracks the state of a session, and tells everyone who is interested about its transitions.
+///
+/// Clones share the same state.
+#[derive(Clone, Debug)]
+pub(crate) struct SessionState(Arc<Mutex<Inner>>);
+
+#[derive(Debug)]
+struct Inner {
+    current: KeeperState,
+    /// The highest zxid the server has told us about.
+    last_zxid: i64,
+    /// Index of the server we are connected (or are connecting) to.
+    server: usize,
+    /// Which kinds of requests that server has told us it implements.
+    support: HashMap<OpCode, bool>,
+    /// How many requests have been sent to the server, and are waiting for its response.
+    outstanding: usize,
+    /// Who to tell once no more requests are waiting for a response.
+    drained: Vec<oneshot::Sender<()>>,
+    /// `None` once the session has ended, and no more transitions will happen.
+    listeners: Option<Vec<mpsc::UnboundedSender<KeeperState>>>,
+}
+
+impl Default for SessionState {
+    fn default() -> Self {
+        SessionState(Arc::new(Mutex::new(Inner {
+            current: KeeperState::Disconnected,
+            last_zxid: 0,
+            server: 0,
+            support: HashMap::new(),
+            outstanding: 0,
+            drained: Vec::new(),
+            listeners: Some(Vec::new()),
+        })))
+    }
+}
+
+impl SessionState {
+    /// The state the session is currently in.
+    pub(crate) fn current(&self) -> KeeperState {
+        self.0.lock().unwrap().current
+    }
+
+    /// The highest zxid seen in any reply from the server.
+    pub(crate) fn last_zxid(&self) -> i64 {
+        self.0.lock().unwrap().last_zxid
+    }
+
+    /// Record a zxid seen in a reply from the server.
+    pub(crate) fn saw_zxid(&self, zxid: i64) {
+        let mut inner = self.0.lock().unwrap();
+        inner.last_zxid = cmp::max(inner.last_zxid, zxid);
+    }
+
+    /// Index of the server the session is currently connected to, if any.
+    pub(crate) fn connected_server(&self) -> Option<usize> {
+        let inner = self.0.lock().unwrap();
+        match inner.current {
+            KeeperState::SyncConnected | KeeperState::ConnectedReadOnly => Some(inner.server),
+            _ => None,
+        }
+    }
+
+    /// Record that the session is about to be established on the server at index `server`.
+    pub(crate) fn set_server(&self, server: usize) {
+        let mut inner = self.0.lock().unwrap();
+        inner.server = server;
+        // the server may run another version of ZooKeeper (even if it is the same one again)
+        inner.support.clear();
+    }
+
+    /// Whether the current server implements requests with the given `opcode`, or `None` if it
+    /// has not told us yet.
+    pub(super) fn supports(&self, opcode: OpCode) -> Option<bool> {
+        self.0.lock().unwrap().support.get(&opcode).cloned()
+    }
+
+    /// Record whether the current server implements requests with the given `opcode`.
+    pub(super) fn learn(&self, opcode: OpCode, supported: bool) {
+        self.0.lock().unwrap().support.insert(opcode, supported);
+    }
+
+    /// How many requests are waiting for a response from the server.
+    pub(crate) fn outstanding(&self) -> usize {
+        self.0.lock().unwrap().outstanding
+    }
+
+    /// Record how many requests are waiting for a response from the server.
+    pub(super) fn set_outstanding(&self, outstanding: usize) {
+        let mut inner = self.0.lock().unwrap();
+        inner.outstanding = outstanding;
+        if outstanding == 0 {
+            for tx in inner.drained.drain(..) {
+                let _ = tx.send(());
+            }
+        }
+    }
+
+    /// Get a future that resolves once no requests are waiting for a response from the server,
+    /// or once the client has shut down.
+    pub(crate) fn drained(&self) -> oneshot::Receiver<()> {
+        let mut inner = self.0.lock().unwrap();
+        let (tx, rx) = oneshot::channel();
+        if inner.outstanding == 0 || inner.listeners.is_none() {
+            let _ = tx.send(());
+        } else {
+            inner.drained.push(tx);
+        }
+        rx
+    }
+
+    /// Get a stream of all future transitions, starting with the current state.
+    ///
+    /// The stream ends after the session has expired, or once the client has shut down.
+    pub(crate) fn subscribe(&self) -> mpsc::UnboundedReceiver<KeeperState> {
+        let mut inner = self.0.lock().unwrap();
+        let (tx, rx) = mpsc::unbounded();
+        let current = inner.current;
+        if let Some(ref mut listeners) = inner.listeners {
+            tx.unbounded_send(current)
+                .expect("receiver is still around");
+            listeners.push(tx);
+        } else if is_final(current) {
+            // the stream should still tell why it ended
+            tx.unbounded_send(current)
+                .expect("receiver is still around");
+        }
+        rx
+    }
+
+    /// Move the session to the given state.
+    ///
+    /// Moving to the current state does nothing. Once the session has expired, or the client has
+    /// given up on it, it does not change state again.
+    pub(crate) fn transition(&self, state: KeeperState) {
+        let mut inner = self.0.lock().unwrap();
+        if inner.current == state || inner.listeners.is_none() {
+            return;
+        }
+
+        inner.current = state;
+        if let Some(ref mut listeners) = inner.listeners {
+            listeners.retain(|tx| tx.unbounded_send(state).is_ok());
+        }
+        if is_final(state) {
+            inner.listeners = None;
+        }
+    }
+
+    /// End all streams of transitions, as the session will not change state again.
+    ///
+    /// This also releases everyone who waits for outstanding requests, as no more responses will
+    /// arrive.
+    pub(crate) fn close(&self) {
+        let mut inner = self.0.lock().unwrap();
+        inner.listeners = None;
+        inner.drained.clear();
+    }
+}
+
+/// Whether the session never leaves `state` again.
+fn is_final(state: KeeperState) -> bool {
+    state == KeeperState::Expired || state == KeeperState::Closed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::Stream;
+
+    #[test]
+    fn transitions() {
+        let state = SessionState::default();
+        let early = state.subscribe();
+        state.transition(KeeperState::SyncConnected);
+        let late = state.subscribe();
+        state.transition(KeeperState::SyncConnected);
+        state.transition(KeeperState::Disconnected);
+        state.transition(KeeperState::Expired);
+        state.transition(KeeperState::SyncConnected);
+        state.transition(KeeperState::Expired);
+        let after = state.subscribe();
+
+        assert_eq!(
+            early.wait().collect::<Result<Vec<_>, _>>().unwrap(),
+            vec![
+                KeeperState::Disconnected,
+                KeeperState::SyncConnected,
+                KeeperState::Disconnected,
+                KeeperState::Expired,
+            ]
+        );
+        assert_eq!(
+            late.wait().collect::<Result<Vec<_>, _>>().unwrap(),
+            vec![
+                KeeperState::SyncConnected,
+                KeeperState::Disconnected,
+                KeeperState::Expired,
+            ]
+        );
+        assert_eq!(
+            after.wait().collect::<Result<Vec<_>, _>>().unwrap(),
+            vec![KeeperState::Expired]
+        );
+    }
+
+    #[test]
+    fn last_zxid() {
+        let state = SessionState::default();
+        assert_eq!(state.last_zxid(), 0);
+        state.saw_zxid(5);
+        state.clone().saw_zxid(7);
+        state.saw_zxid(6);
+        assert_eq!(state.last_zxid(), 7);
+    }
+
+    #[test]
+    fn connected_server() {
+        let state = SessionState::default();
+        state.set_server(2);
+        assert_eq!(state.connected_server(), None);
+        state.transition(KeeperState::SyncConnected);
+        assert_eq!(state.connected_server(), Some(2));
+        state.transition(KeeperState::Disconnected);
+        state.set_server(1);
+        assert_eq!(state.connected_server(), None);
+        state.transition(KeeperState::ConnectedReadOnly);
+        assert_eq!(state.connected_server(), Some(1));
+    }
+
+    #[test]
+    fn support() {
+        let state = SessionState::default();
+        assert_eq!(state.supports(OpCode::CreateTtl), None);
+        state.learn(OpCode::CreateTtl, false);
+        state.learn(OpCode::Create2, true);
+        assert_eq!(state.supports(OpCode::CreateTtl), Some(false));
+        assert_eq!(state.supports(OpCode::Create2), Some(true));
+        // another server may know better
+        state.set_server(1);
+        assert_eq!(state.supports(OpCode::CreateTtl), None);
+    }
+
+    #[test]
+    fn close() {
+        let state = SessionState::default();
+        state.transition(KeeperState::SyncConnected);
+        let rx = state.subscribe();
+        state.close();
+        state.transition(KeeperState::Disconnected);
+        assert_eq!(
+            rx.wait().collect::<Result<Vec<_>, _>>().unwrap(),
+            vec![KeeperState::SyncConnected]
+        );
+    }
+}
@@ -1,5 +1,6 @@
 use futures::sync::oneshot;
-use WatchedEvent;
+use std::collections::HashMap;
+use {AddWatchMode, KeeperState, WatchedEvent, WatchedEventType, WatcherType};
 
 #[derive(Debug)]
 pub(crate) enum Watch {
@@ -28,3 +29,334 @@ pub(crate) enum WatchType {
     /// Watching for the creation of a node at the given path.
     Exist,
 }
+
+impl WatchType {
+    /// Whether a watch of this type is selected by the given `WatcherType`.
+    pub(crate) fn selected_by(self, selector: WatcherType) -> bool {
+        match selector {
+            WatcherType::Any => true,
+            WatcherType::Children => self == WatchType::Child,
+            WatcherType::Data => self != WatchType::Child,
+        }
+    }
+
+    /// Whether a watch of this type is triggered by an event of the given type.
+    pub(crate) fn triggered_by(self, event_type: WatchedEventType) -> bool {
+        match (self, event_type) {
+            (WatchType::Child, WatchedEventType::NodeDeleted)
+            | (WatchType::Child, WatchedEventType::NodeChildrenChanged) => true,
+            (WatchType::Child, _) => false,
+            (WatchType::Data, WatchedEventType::NodeDeleted)
+            | (WatchType::Data, WatchedEventType::NodeDataChanged) => true,
+            (WatchType::Data, _) => false,
+            (WatchType::Exist, WatchedEventType::NodeChildrenChanged) => false,
+            (WatchType::Exist, _) => true,
+        }
+    }
+}
+
+/// The watches that are currently registered with the server, keyed by path.
+///
+/// The server only ever tracks a single watch of each type per path and session, so any number of
+/// local waiters can share one server-side watch. The registry upholds the following invariant:
+/// when an event fires for a path, every waiter registered for that path *before* the event whose
+/// watch type is triggered by the event is notified exactly once, and is then removed. Waiters
+/// that are not triggered remain registered, and waiters registered after the event are only
+/// notified by later events.
+///
+/// Watches whose events only go to the global watcher stream are tracked as waiters without a
+/// sender, so that all watches can be re-registered with the server after a reconnect.
+#[derive(Debug, Default)]
+pub(crate) struct WatchRegistry {
+    watchers: HashMap<String, Vec<Waiter>>,
+    persistent: HashMap<String, AddWatchMode>,
+}
+
+/// Someone waiting for a watch of the given type, who is told about its event if there is a
+/// sender.
+type Waiter = (Option<oneshot::Sender<WatchedEvent>>, WatchType);
+
+/// The paths of all watches in a [`WatchRegistry`], by the kind of watch.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct WatchPaths {
+    pub(crate) data: Vec<String>,
+    pub(crate) exist: Vec<String>,
+    pub(crate) child: Vec<String>,
+    pub(crate) persistent: Vec<String>,
+    pub(crate) persistent_recursive: Vec<String>,
+}
+
+impl WatchPaths {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.data.is_empty()
+            && self.exist.is_empty()
+            && self.child.is_empty()
+            && self.persistent.is_empty()
+            && self.persistent_recursive.is_empty()
+    }
+}
+
+impl WatchRegistry {
+    /// Register a waiter for the next event of type `wtype` on `path`.
+    ///
+    /// If `tx` is `None`, the watch is only tracked so that it can be re-registered.
+    pub(crate) fn add(
+        &mut self,
+        path: String,
+        tx: Option<oneshot::Sender<WatchedEvent>>,
+        wtype: WatchType,
+    ) {
+        self.watchers
+            .entry(path)
+            .or_insert_with(Vec::new)
+            .push((tx, wtype));
+    }
+
+    /// Register a persistent watch on `path`.
+    pub(crate) fn add_persistent(&mut self, path: String, mode: AddWatchMode) {
+        self.persistent.insert(path, mode);
+    }
+
+    /// Notify all waiters triggered by `e`, and return how many were notified.
+    pub(crate) fn fire(&mut self, e: &WatchedEvent) -> usize {
+        let mut notified = 0;
+        let remove = if let Some(watchers) = self.watchers.get_mut(&e.path) {
+            let mut i = 0;
+            while i < watchers.len() {
+                if watchers[i].1.triggered_by(e.event_type) {
+                    // this watcher is no longer active
+                    let w = watchers.swap_remove(i);
+                    if let Some(tx) = w.0 {
+                        // NOTE: ignore the case where the receiver has been dropped
+                        let _ = tx.send(e.clone());
+                        notified += 1;
+                    }
+                } else {
+                    i += 1;
+                }
+            }
+            watchers.is_empty()
+        } else {
+            false
+        };
+
+        if remove {
+            self.watchers.remove(&e.path);
+        }
+        notified
+    }
+
+    /// Remove all watches on `path` selected by `selector`, and return how many were removed.
+    ///
+    /// Removed waiters are notified with a `DataWatchRemoved` or `ChildWatchRemoved` event.
+    pub(crate) fn remove(&mut self, path: &str, selector: WatcherType) -> usize {
+        let mut removed = 0;
+        let empty = if let Some(watchers) = self.watchers.get_mut(path) {
+            let mut i = 0;
+            while i < watchers.len() {
+                if watchers[i].1.selected_by(selector) {
+                    let w = watchers.swap_remove(i);
+                    let event_type = match w.1 {
+                        WatchType::Child => WatchedEventType::ChildWatchRemoved,
+                        WatchType::Data | WatchType::Exist => WatchedEventType::DataWatchRemoved,
+                    };
+                    if let Some(tx) = w.0 {
+                        // NOTE: ignore the case where the receiver has been dropped
+                        let _ = tx.send(WatchedEvent {
+                            event_type,
+                            keeper_state: KeeperState::SyncConnected,
+                            path: path.to_string(),
+                            zxid: None,
+                        });
+                    }
+                    removed += 1;
+                } else {
+                    i += 1;
+                }
+            }
+            watchers.is_empty()
+        } else {
+            false
+        };
+
+        if empty {
+            self.watchers.remove(path);
+        }
+        if selector == WatcherType::Any && self.persistent.remove(path).is_some() {
+            removed += 1;
+        }
+        removed
+    }
+
+    /// The paths of all registered watches, in a stable order.
+    pub(crate) fn paths(&self) -> WatchPaths {
+        let mut paths = WatchPaths::default();
+        for (path, watchers) in &self.watchers {
+            for &wtype in &[WatchType::Data, WatchType::Exist, WatchType::Child] {
+                if watchers.iter().any(|w| w.1 == wtype) {
+                    match wtype {
+                        WatchType::Data => &mut paths.data,
+                        WatchType::Exist => &mut paths.exist,
+                        WatchType::Child => &mut paths.child,
+                    }
+                    .push(path.clone());
+                }
+            }
+        }
+        for (path, &mode) in &self.persistent {
+            match mode {
+                AddWatchMode::Persistent => &mut paths.persistent,
+                AddWatchMode::PersistentRecursive => &mut paths.persistent_recursive,
+            }
+            .push(path.clone());
+        }
+
+        paths.data.sort();
+        paths.exist.sort();
+        paths.child.sort();
+        paths.persistent.sort();
+        paths.persistent_recursive.sort();
+        paths
+    }
+
+    /// The number of waiters currently registered for `path`.
+    #[cfg(test)]
+    pub(crate) fn waiting(&self, path: &str) -> usize {
+        self.watchers.get(path).map(Vec::len).unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::Future;
+
+    fn event(event_type: WatchedEventType, path: &str) -> WatchedEvent {
+        WatchedEvent {
+            event_type,
+            keeper_state: KeeperState::SyncConnected,
+            path: path.to_string(),
+            zxid: None,
+        }
+    }
+
+    fn register(
+        registry: &mut WatchRegistry,
+        path: &str,
+        wtype: WatchType,
+        n: usize,
+    ) -> Vec<oneshot::Receiver<WatchedEvent>> {
+        (0..n)
+            .map(|_| {
+                let (tx, rx) = oneshot::channel();
+                registry.add(path.to_string(), Some(tx), wtype);
+                rx
+            })
+            .collect()
+    }
+
+    #[test]
+    fn fire_only_triggers_matching_types() {
+        let mut registry = WatchRegistry::default();
+        let data = register(&mut registry, "/a", WatchType::Data, 1);
+        let child = register(&mut registry, "/a", WatchType::Child, 1);
+        let exist = register(&mut registry, "/a", WatchType::Exist, 1);
+
+        let e = event(WatchedEventType::NodeDataChanged, "/a");
+        assert_eq!(registry.fire(&e), 2);
+        assert_eq!(registry.waiting("/a"), 1);
+        for rx in data.into_iter().chain(exist) {
+            assert_eq!(rx.wait().unwrap(), e);
+        }
+
+        // events for other paths do not affect the remaining waiter
+        assert_eq!(registry.fire(&event(WatchedEventType::NodeDeleted, "/b")), 0);
+        let e = event(WatchedEventType::NodeDeleted, "/a");
+        assert_eq!(registry.fire(&e), 1);
+        assert_eq!(child.into_iter().next().unwrap().wait().unwrap(), e);
+        assert_eq!(registry.waiting("/a"), 0);
+    }
+
+    #[test]
+    fn fan_out_with_reregistration() {
+        let mut registry = WatchRegistry::default();
+        let first = register(&mut registry, "/fan", WatchType::Data, 1000);
+        // a couple of waiters go away before the event fires
+        let mut first: Vec<_> = first.into_iter().enumerate().collect();
+        first.retain(|&(i, _)| i % 100 != 0);
+
+        let e1 = event(WatchedEventType::NodeDataChanged, "/fan");
+        assert_eq!(registry.fire(&e1), 1000);
+        assert_eq!(registry.waiting("/fan"), 0);
+
+        // re-register a new round of waiters after the first fire
+        let second = register(&mut registry, "/fan", WatchType::Data, 500);
+        assert_eq!(registry.waiting("/fan"), 500);
+
+        // the first round saw exactly the first event, and nothing else
+        for (_, mut rx) in first {
+            assert_eq!(rx.poll().unwrap(), futures::Async::Ready(e1.clone()));
+        }
+
+        let e2 = event(WatchedEventType::NodeDeleted, "/fan");
+        assert_eq!(registry.fire(&e2), 500);
+        for rx in second {
+            assert_eq!(rx.wait().unwrap(), e2);
+        }
+
+        // nothing is left to notify
+        assert_eq!(registry.fire(&e2), 0);
+    }
+
+    #[test]
+    fn remove_selects_by_type() {
+        let mut registry = WatchRegistry::default();
+        let data = register(&mut registry, "/r", WatchType::Data, 2);
+        let exist = register(&mut registry, "/r", WatchType::Exist, 1);
+        let child = register(&mut registry, "/r", WatchType::Child, 1);
+
+        assert_eq!(registry.remove("/other", WatcherType::Any), 0);
+        assert_eq!(registry.remove("/r", WatcherType::Data), 3);
+        assert_eq!(registry.waiting("/r"), 1);
+        for rx in data.into_iter().chain(exist) {
+            assert_eq!(
+                rx.wait().unwrap().event_type,
+                WatchedEventType::DataWatchRemoved
+            );
+        }
+
+        assert_eq!(registry.remove("/r", WatcherType::Data), 0);
+        assert_eq!(registry.remove("/r", WatcherType::Any), 1);
+        assert_eq!(
+            child.into_iter().next().unwrap().wait().unwrap().event_type,
+            WatchedEventType::ChildWatchRemoved
+        );
+        assert_eq!(registry.waiting("/r"), 0);
+    }
+
+    #[test]
+    fn paths_for_replay() {
+        let mut registry = WatchRegistry::default();
+        let _data = register(&mut registry, "/b", WatchType::Data, 2);
+        let _child = register(&mut registry, "/b", WatchType::Child, 1);
+        registry.add("/a".to_string(), None, WatchType::Data);
+        registry.add("/c".to_string(), None, WatchType::Exist);
+        registry.add_persistent("/p".to_string(), AddWatchMode::PersistentRecursive);
+
+        let paths = registry.paths();
+        assert_eq!(paths.data, vec!["/a", "/b"]);
+        assert_eq!(paths.exist, vec!["/c"]);
+        assert_eq!(paths.child, vec!["/b"]);
+        assert!(paths.persistent.is_empty());
+        assert_eq!(paths.persistent_recursive, vec!["/p"]);
+
+        // watches without a waiter are triggered like any other, but notify no one
+        assert_eq!(
+            registry.fire(&event(WatchedEventType::NodeDeleted, "/a")),
+            0
+        );
+        assert_eq!(registry.waiting("/a"), 0);
+        assert_eq!(registry.remove("/p", WatcherType::Any), 1);
+        assert!(registry.paths().persistent_recursive.is_empty());
+    }
+}
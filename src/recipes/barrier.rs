@@ -0,0 +1,334 @@
+use failure;
+use futures::future::{self, Either, Loop};
+use tokio::prelude::*;
+use {child_path, error, Acl, CreateMode, WatchedEventType, ZooKeeper};
+
+/// The prefix of the name of every participant's node.
+const PREFIX: &str = "n_";
+
+/// The name of the node that marks that enough participants have entered.
+const READY: &str = "ready";
+
+/// A double barrier, which makes a fixed number of participants start and finish a computation
+/// together.
+///
+/// Participants [`enter`](#method.enter) the barrier by creating an ephemeral-sequential node
+/// below a well-known `path`, and wait until `count` of them have done so. Once done with their
+/// computation, they [`leave`](Participant::leave) by deleting their node, and wait until all
+/// other participants have done so as well.
+///
+/// Since the nodes are ephemeral, a participant whose session ends is no longer counted. While
+/// entering, this means that the barrier waits for another participant to take its place. While
+/// leaving, it means that the others stop waiting for it.
+///
+/// A barrier can be used again once all participants have left it.
+#[derive(Debug, Clone)]
+pub struct DoubleBarrier {
+    zk: ZooKeeper,
+    path: String,
+    count: usize,
+}
+
+impl DoubleBarrier {
+    /// Create a handle to the barrier for `count` participants that live below `path`.
+    ///
+    /// The node at `path` must already exist.
+    pub fn new(zk: ZooKeeper, path: &str, count: usize) -> Self {
+        DoubleBarrier {
+            zk,
+            path: path.to_string(),
+            count,
+        }
+    }
+
+    /// Enter the barrier as a new participant.
+    ///
+    /// The returned future resolves once `count` participants have entered.
+    pub fn enter(&self) -> impl Future<Item = Participant, Error = failure::Error> {
+        let path = self.path.clone();
+        let count = self.count;
+        self.zk
+            .clone()
+            .create(
+                &child_path(&self.path, PREFIX),
+                &[][..],
+                Acl::open_unsafe(),
+                CreateMode::EphemeralSequential,
+            )
+            .and_then(move |(zk, res)| {
+                let node = res?;
+                Ok((zk, node))
+            })
+            .and_then(move |(zk, node)| {
+                let ready = child_path(&path, READY);
+                let barrier = path.clone();
+                future::loop_fn(zk, move |zk| {
+                    let path = path.clone();
+                    let ready = ready.clone();
+                    // watch for the ready node before counting, so that we cannot miss it being
+                    // created by whoever completes the count
+                    zk.with_watcher()
+                        .exists(&ready)
+                        .and_then(move |(zk, watch, stat)| {
+                            if stat.is_some() {
+                                return Either::A(future::ok(Loop::Break(zk)));
+                            }
+                            Either::B(zk.get_children(&path).and_then(move |(zk, children)| {
+                                let entered = match children {
+                                    Some(children) => {
+                                        children.iter().filter(|c| c.starts_with(PREFIX)).count()
+                                    }
+                                    None => {
+                                        return Either::A(future::err(format_err!(
+                                            "barrier node {} was deleted",
+                                            path
+                                        )))
+                                    }
+                                };
+                                if entered >= count {
+                                    return Either::B(Either::A(mark_ready(zk, &ready)));
+                                }
+                                // a participant whose session ends takes its node with it, so
+                                // the count is only ever taken from the nodes that are there
+                                Either::B(Either::B(
+                                    watch
+                                        .map(move |e| match e.event_type {
+                                            WatchedEventType::NodeCreated => Loop::Break(zk),
+                                            _ => Loop::Continue(zk),
+                                        })
+                                        .map_err(|e| format_err!("barrier watch dropped: {:?}", e)),
+                                ))
+                            }))
+                        })
+                })
+                .map(move |zk| Participant {
+                    zk,
+                    path: barrier,
+                    node,
+                })
+            })
+    }
+}
+
+/// Create the node that tells all participants that enough of them have entered.
+fn mark_ready(
+    zk: ZooKeeper,
+    ready: &str,
+) -> impl Future<Item = Loop<ZooKeeper, ZooKeeper>, Error = failure::Error> {
+    zk.create(ready, &[][..], Acl::open_unsafe(), CreateMode::Persistent)
+        .and_then(|(zk, res)| match res {
+            Ok(_) | Err(error::Create::NodeExists) => Ok(Loop::Break(zk)),
+            Err(e) => Err(e.into()),
+        })
+}
+
+/// A participant that has entered a [`DoubleBarrier`].
+#[derive(Debug)]
+pub struct Participant {
+    zk: ZooKeeper,
+    path: String,
+    node: String,
+}
+
+impl Participant {
+    /// The path of the node of this participant.
+    pub fn node(&self) -> &str {
+        &self.node
+    }
+
+    /// Leave the barrier.
+    ///
+    /// The returned future resolves once all participants have left.
+    pub fn leave(self) -> impl Future<Item = (), Error = failure::Error> {
+        let Participant { zk, path, node } = self;
+        let name = node[node.rfind('/').map_or(0, |i| i + 1)..].to_string();
+        future::loop_fn(zk, move |zk| {
+            let (path, node, name) = (path.clone(), node.clone(), name.clone());
+            zk.get_children(&path).and_then(move |(zk, children)| {
+                match next_step(children.unwrap_or_default(), &name) {
+                    Step::Done => Either::A(Either::A(future::ok(Loop::Break(())))),
+                    Step::Last => Either::A(Either::B(leave_last(zk, &node, &path))),
+                    Step::Wait { on, delete } => {
+                        let on = child_path(&path, &on);
+                        let deleted = if delete {
+                            Either::A(delete_node(zk, &node))
+                        } else {
+                            Either::B(future::ok(zk))
+                        };
+                        Either::B(deleted.and_then(move |zk| wait_for_deletion(zk, &on)))
+                    }
+                }
+            })
+        })
+    }
+}
+
+/// Delete the node of the last participant to leave, along with the ready node, so that the
+/// barrier can be used again.
+fn leave_last(
+    zk: ZooKeeper,
+    node: &str,
+    path: &str,
+) -> impl Future<Item = Loop<(), ZooKeeper>, Error = failure::Error> {
+    let ready = child_path(path, READY);
+    delete_node(zk, node)
+        .and_then(move |zk| delete_node(zk, &ready))
+        .map(|_| Loop::Break(()))
+}
+
+/// Delete the node at `path`, if it still exists.
+fn delete_node(zk: ZooKeeper, path: &str) -> impl Future<Item = ZooKeeper, Error = failure::Error> {
+    zk.delete(path, None).and_then(|(zk, res)| match res {
+        Ok(()) | Err(error::Delete::NoNode) => Ok(zk),
+        Err(e) => Err(e.into()),
+    })
+}
+
+/// Wait for the node at `path` to be deleted (which it may already be).
+fn wait_for_deletion(
+    zk: ZooKeeper,
+    path: &str,
+) -> impl Future<Item = Loop<(), ZooKeeper>, Error = failure::Error> {
+    zk.with_watcher()
+        .exists(path)
+        .and_then(|(zk, watch, stat)| match stat {
+            // the participant we were waiting for left already (or its session ended)
+            None => Either::A(future::ok(Loop::Continue(zk))),
+            Some(_) => Either::B(
+                watch
+                    .map(move |_| Loop::Continue(zk))
+                    .map_err(|e| format_err!("barrier watch dropped: {:?}", e)),
+            ),
+        })
+}
+
+/// What a leaving participant should do next.
+#[derive(Debug, PartialEq, Eq)]
+enum Step {
+    /// All participants have left.
+    Done,
+    /// We are the last participant, and can leave right away.
+    Last,
+    /// Wait for the participant with the node called `on` to leave, after leaving ourselves if
+    /// `delete` is set.
+    Wait { on: String, delete: bool },
+}
+
+/// Determine what the participant with the node called `name` should do to leave a barrier with
+/// the given `children`.
+///
+/// The participant with the lowest sequence number leaves last, and waits for the others by
+/// watching the one with the highest sequence number. All others leave right away, and then wait
+/// for the lowest one to leave. This way, each change wakes up only a few participants.
+fn next_step(mut children: Vec<String>, name: &str) -> Step {
+    children.retain(|c| c.starts_with(PREFIX));
+    // the sequence number is zero-padded, so the names sort in the order they were created in
+    children.sort();
+    match (children.len(), children.iter().position(|c| c == name)) {
+        (0, _) => Step::Done,
+        (1, Some(_)) => Step::Last,
+        (_, Some(0)) => Step::Wait {
+            on: children.pop().expect("there is more than one child"),
+            delete: false,
+        },
+        (_, me) => Step::Wait {
+            on: children.swap_remove(0),
+            delete: me.is_some(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::time;
+    use ZooKeeperBuilder;
+
+    #[test]
+    fn leave_order() {
+        let children = |names: &[&str]| names.iter().map(|n| n.to_string()).collect();
+        let all = || children(&["n_0000000002", "ready", "n_0000000001", "n_0000000003"]);
+        assert_eq!(
+            next_step(all(), "n_0000000001"),
+            Step::Wait {
+                on: "n_0000000003".to_string(),
+                delete: false
+            }
+        );
+        assert_eq!(
+            next_step(all(), "n_0000000003"),
+            Step::Wait {
+                on: "n_0000000001".to_string(),
+                delete: true
+            }
+        );
+        // once we have left, we still wait for the lowest participant
+        assert_eq!(
+            next_step(children(&["n_0000000001", "n_0000000002"]), "n_0000000003"),
+            Step::Wait {
+                on: "n_0000000001".to_string(),
+                delete: false
+            }
+        );
+        assert_eq!(
+            next_step(children(&["ready", "n_0000000001"]), "n_0000000001"),
+            Step::Last
+        );
+        assert_eq!(next_step(children(&["ready"]), "n_0000000001"), Step::Done);
+    }
+
+    #[test]
+    fn three_participants() {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let addr = "127.0.0.1:2181".parse().unwrap();
+        let mut clients = Vec::new();
+        for _ in 0..3 {
+            let (zk, _) = rt
+                .block_on(ZooKeeperBuilder::default().connect(&addr))
+                .unwrap();
+            clients.push(zk);
+        }
+        let (zk, res) = rt
+            .block_on(clients[0].clone().ensure_path("/barrier_test"))
+            .unwrap();
+        assert_eq!(res, Ok(()));
+
+        let (tx, rx) = mpsc::channel();
+        for zk in &clients[..2] {
+            let tx = tx.clone();
+            rt.spawn(
+                DoubleBarrier::new(zk.clone(), "/barrier_test", 3)
+                    .enter()
+                    .then(move |r| tx.send(r.unwrap()).map_err(|_| ())),
+            );
+        }
+        // nobody proceeds before everyone has entered
+        assert!(rx.recv_timeout(time::Duration::from_millis(500)).is_err());
+        let third = rt
+            .block_on(DoubleBarrier::new(clients[2].clone(), "/barrier_test", 3).enter())
+            .unwrap();
+        let mut participants = vec![rx.recv().unwrap(), rx.recv().unwrap(), third];
+        participants.sort_by(|a, b| a.node().cmp(b.node()));
+
+        // nobody finishes leaving before everyone has left
+        let (tx, rx) = mpsc::channel();
+        let last = participants.pop().unwrap();
+        for p in participants {
+            let tx = tx.clone();
+            rt.spawn(p.leave().then(move |r| {
+                r.unwrap();
+                tx.send(()).map_err(|_| ())
+            }));
+        }
+        assert!(rx.recv_timeout(time::Duration::from_millis(500)).is_err());
+        rt.block_on(last.leave()).unwrap();
+        rx.recv().unwrap();
+        rx.recv().unwrap();
+
+        let (zk, res) = rt.block_on(zk.delete("/barrier_test", None)).unwrap();
+        assert_eq!(res, Ok(()));
+        drop((zk, clients));
+        rt.shutdown_on_idle().wait().unwrap();
+    }
+}
@@ -0,0 +1,314 @@
+use failure;
+use futures::future::{self, Either};
+use futures::sync::oneshot;
+use std::fmt;
+use tokio::prelude::*;
+use {child_path, error, Acl, CreateMode, WatchedEvent, ZooKeeper};
+
+/// The prefix of the name of every candidate's node.
+const PREFIX: &str = "n_";
+
+/// A leader election among any number of clients.
+///
+/// Every client that wants to lead [`volunteer`](#method.volunteer)s by creating an
+/// ephemeral-sequential node below a well-known `path`, and the client with the lowest sequence
+/// number leads. When the leader resigns or its session ends, its node goes away, and the next
+/// candidate in line takes over.
+///
+/// Each candidate only watches the candidate right before it, so that a change of leadership
+/// wakes up a single client rather than all of them.
+#[derive(Debug, Clone)]
+pub struct Election {
+    zk: ZooKeeper,
+    path: String,
+}
+
+impl Election {
+    /// Create a handle to the election whose candidates live below `path`.
+    ///
+    /// The node at `path` must already exist.
+    pub fn new(zk: ZooKeeper, path: &str) -> Self {
+        Election {
+            zk,
+            path: path.to_string(),
+        }
+    }
+
+    /// Enter the election as a new candidate.
+    pub fn volunteer(&self) -> impl Future<Item = Candidate, Error = failure::Error> {
+        let path = self.path.clone();
+        self.zk
+            .clone()
+            .create(
+                &child_path(&self.path, PREFIX),
+                &[][..],
+                Acl::open_unsafe(),
+                CreateMode::EphemeralSequential,
+            )
+            .and_then(move |(zk, res)| {
+                let node = res?;
+                Ok(Candidate::new(zk, path, node))
+            })
+    }
+}
+
+/// A change in the leadership of a [`Candidate`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Leadership {
+    /// The candidate is now the leader.
+    Gained,
+    /// The candidate is no longer the leader.
+    Lost,
+}
+
+/// A participant in an [`Election`].
+///
+/// A candidate is a `Stream` of the changes to its leadership, which must be polled for the
+/// candidate to find out when it has become the leader. The stream ends once the candidate can no
+/// longer lead, which happens when its node is deleted or its session ends. If the state of the
+/// election cannot be determined, the stream yields the error and then ends as well. A leader
+/// that leaves the election this way yields [`Leadership::Lost`] before the stream ends.
+pub struct Candidate {
+    zk: ZooKeeper,
+    path: String,
+    node: String,
+    leader: bool,
+    state: State,
+}
+
+enum State {
+    /// Looking for our place in line.
+    Checking(Box<dyn Future<Item = Position, Error = failure::Error> + Send>),
+    /// Waiting for the watched candidate (which may be ourselves) to change.
+    Waiting(oneshot::Receiver<WatchedEvent>),
+    /// We are no longer a candidate.
+    Done,
+}
+
+enum Position {
+    /// We are first in line, and the watch is on our own node.
+    First(oneshot::Receiver<WatchedEvent>),
+    /// Another candidate is ahead of us, and the watch is on the one right before us.
+    Behind(oneshot::Receiver<WatchedEvent>),
+    /// The candidate before us went away before we could watch it.
+    Moved,
+    /// Our node no longer exists.
+    Gone,
+}
+
+impl fmt::Debug for Candidate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Candidate")
+            .field("node", &self.node)
+            .field("leader", &self.leader)
+            .finish()
+    }
+}
+
+impl Candidate {
+    fn new(zk: ZooKeeper, path: String, node: String) -> Self {
+        let state = State::Checking(check(zk.clone(), path.clone(), node.clone()));
+        Candidate {
+            zk,
+            path,
+            node,
+            leader: false,
+            state,
+        }
+    }
+
+    /// The path of the node of this candidate.
+    pub fn node(&self) -> &str {
+        &self.node
+    }
+
+    /// Whether this candidate is currently the leader, as of the last event from the stream.
+    pub fn is_leader(&self) -> bool {
+        self.leader
+    }
+
+    /// Leave the election, handing over leadership to the next candidate if this one leads.
+    pub fn resign(self) -> impl Future<Item = (), Error = failure::Error> {
+        self.zk
+            .delete(&self.node, None)
+            .and_then(|(_, res)| match res {
+                Ok(()) | Err(error::Delete::NoNode) => Ok(()),
+                Err(e) => Err(e.into()),
+            })
+    }
+
+    /// Check whether the node of this candidate still exists on behalf of its session.
+    pub(super) fn owned(&self) -> impl Future<Item = bool, Error = failure::Error> {
+        self.zk.clone().owns(&self.node).map(|(_, owned)| owned)
+    }
+
+    fn check(&self) -> Box<dyn Future<Item = Position, Error = failure::Error> + Send> {
+        check(self.zk.clone(), self.path.clone(), self.node.clone())
+    }
+}
+
+impl Stream for Candidate {
+    type Item = Leadership;
+    type Error = failure::Error;
+
+    fn poll(&mut self) -> Result<Async<Option<Self::Item>>, Self::Error> {
+        loop {
+            let next = match self.state {
+                State::Checking(ref mut f) => match f.poll() {
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Ok(Async::Ready(Position::First(watch))) => {
+                        self.state = State::Waiting(watch);
+                        if !self.leader {
+                            self.leader = true;
+                            return Ok(Async::Ready(Some(Leadership::Gained)));
+                        }
+                        continue;
+                    }
+                    Ok(Async::Ready(Position::Behind(watch))) => State::Waiting(watch),
+                    Ok(Async::Ready(Position::Moved)) => State::Checking(self.check()),
+                    Ok(Async::Ready(Position::Gone)) => State::Done,
+                    Err(e) => {
+                        self.state = State::Done;
+                        return Err(e);
+                    }
+                },
+                State::Waiting(ref mut watch) => match watch.poll() {
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    // something changed, so find out where we stand now
+                    Ok(Async::Ready(_)) => State::Checking(self.check()),
+                    // the client has shut down, taking our node with it
+                    Err(oneshot::Canceled) => State::Done,
+                },
+                State::Done if self.leader => {
+                    self.leader = false;
+                    return Ok(Async::Ready(Some(Leadership::Lost)));
+                }
+                State::Done => return Ok(Async::Ready(None)),
+            };
+            self.state = next;
+        }
+    }
+}
+
+/// Find the place of the candidate with the given `node` in the election at `path`, and watch the
+/// candidate it is waiting for.
+fn check(
+    zk: ZooKeeper,
+    path: String,
+    node: String,
+) -> Box<dyn Future<Item = Position, Error = failure::Error> + Send> {
+    Box::new(zk.get_children(&path).and_then(move |(zk, children)| {
+        let name = &node[node.rfind('/').map_or(0, |i| i + 1)..];
+        let (watched, first) = match children.and_then(|c| to_watch(c, name)) {
+            Some(w) => w,
+            None => return Either::A(future::ok(Position::Gone)),
+        };
+        Either::B(zk.with_watcher().exists(&child_path(&path, &watched)).map(
+            move |(_, watch, stat)| match (stat, first) {
+                (Some(_), true) => Position::First(watch),
+                (Some(_), false) => Position::Behind(watch),
+                (None, true) => Position::Gone,
+                (None, false) => Position::Moved,
+            },
+        ))
+    }))
+}
+
+/// Determine which of the `children` of an election the candidate with the node called `name`
+/// should watch, and whether that candidate is first in line (and thus watches itself).
+///
+/// Returns `None` if `name` is not among the candidates.
+fn to_watch(mut children: Vec<String>, name: &str) -> Option<(String, bool)> {
+    children.retain(|c| c.starts_with(PREFIX));
+    // the sequence number is zero-padded, so the names sort in the order they were created in
+    children.sort();
+    match children.iter().position(|c| c == name)? {
+        0 => Some((name.to_string(), true)),
+        i => Some((children.swap_remove(i - 1), false)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::time;
+    use ZooKeeperBuilder;
+
+    #[test]
+    fn watches_predecessor() {
+        let children = |names: &[&str]| names.iter().map(|n| n.to_string()).collect();
+        assert_eq!(
+            to_watch(children(&["n_0000000002", "n_0000000001"]), "n_0000000001"),
+            Some(("n_0000000001".to_string(), true))
+        );
+        assert_eq!(
+            to_watch(
+                children(&["n_0000000010", "n_0000000003", "other", "n_0000000007"]),
+                "n_0000000010"
+            ),
+            Some(("n_0000000007".to_string(), false))
+        );
+        assert_eq!(to_watch(children(&["n_0000000002"]), "n_0000000001"), None);
+    }
+
+    #[test]
+    fn second_candidate_takes_over() {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let addr = "127.0.0.1:2181".parse().unwrap();
+        let (zk1, _) = rt
+            .block_on(ZooKeeperBuilder::default().connect(&addr))
+            .unwrap();
+        let (zk2, _) = rt
+            .block_on(ZooKeeperBuilder::default().connect(&addr))
+            .unwrap();
+        let (zk1, res) = rt.block_on(zk1.ensure_path("/election_test")).unwrap();
+        assert_eq!(res, Ok(()));
+
+        let first = rt
+            .block_on(Election::new(zk1.clone(), "/election_test").volunteer())
+            .unwrap();
+        let second = rt
+            .block_on(Election::new(zk2.clone(), "/election_test").volunteer())
+            .unwrap();
+        assert!(first.node() < second.node());
+
+        let (event, first) = rt
+            .block_on(first.into_future())
+            .map_err(|(e, _)| e)
+            .unwrap();
+        assert_eq!(event, Some(Leadership::Gained));
+        assert!(first.is_leader());
+
+        let (tx, rx) = mpsc::channel();
+        rt.spawn(
+            second
+                .for_each(move |event| {
+                    tx.send(event).unwrap();
+                    Ok(())
+                })
+                .map_err(|e| panic!("{:?}", e)),
+        );
+        // only one candidate can lead at a time
+        assert!(rx.recv_timeout(time::Duration::from_millis(500)).is_err());
+
+        // the first candidate's node goes away with its session
+        rt.block_on(zk1.close()).unwrap();
+        assert_eq!(
+            rt.block_on(first.collect()).unwrap(),
+            vec![Leadership::Lost]
+        );
+        assert_eq!(rx.recv().unwrap(), Leadership::Gained);
+
+        rt.block_on(zk2.close()).unwrap();
+        assert_eq!(rx.recv().unwrap(), Leadership::Lost);
+
+        let (zk, _) = rt
+            .block_on(ZooKeeperBuilder::default().connect(&addr))
+            .unwrap();
+        let (zk, res) = rt.block_on(zk.delete("/election_test", None)).unwrap();
+        assert_eq!(res, Ok(()));
+        drop(zk);
+        rt.shutdown_on_idle().wait().unwrap();
+    }
+}
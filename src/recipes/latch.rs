@@ -0,0 +1,106 @@
+use failure;
+use tokio::prelude::*;
+use {error, Acl, CreateMode, ZooKeeper};
+
+/// A one-way distributed latch.
+///
+/// A latch starts out unset, and is set by creating a marker node at a well-known `path`. Any
+/// number of parties can [`wait`](#method.wait) for the latch to be set; once it has been set, all
+/// current and future waiters resolve. A latch cannot be reset other than by deleting the marker
+/// node.
+///
+/// Waiting uses [`ZooKeeper::wait_for_creation`], so a `set` that races with a `wait` cannot be
+/// missed.
+#[derive(Debug, Clone)]
+pub struct Latch {
+    zk: ZooKeeper,
+    path: String,
+}
+
+impl Latch {
+    /// Create a handle to the latch whose marker node lives at `path`.
+    ///
+    /// The parent of `path` must already exist.
+    pub fn new(zk: ZooKeeper, path: &str) -> Self {
+        Latch {
+            zk,
+            path: path.to_string(),
+        }
+    }
+
+    /// Set the latch, releasing all waiters.
+    ///
+    /// Setting an already set latch is not an error.
+    pub fn set(&self) -> impl Future<Item = (), Error = failure::Error> {
+        self.zk
+            .clone()
+            .create(
+                &self.path,
+                &[][..],
+                Acl::open_unsafe(),
+                CreateMode::Persistent,
+            )
+            .and_then(|(_, res)| match res {
+                Ok(_) | Err(error::Create::NodeExists) => Ok(()),
+                Err(e) => Err(e.into()),
+            })
+    }
+
+    /// Check whether the latch has been set, without waiting for it.
+    pub fn is_set(&self) -> impl Future<Item = bool, Error = failure::Error> {
+        self.zk
+            .clone()
+            .exists(&self.path)
+            .map(|(_, stat)| stat.is_some())
+    }
+
+    /// Wait for the latch to be set.
+    ///
+    /// The returned future resolves immediately if the latch has already been set.
+    pub fn wait(&self) -> impl Future<Item = (), Error = failure::Error> {
+        self.zk.clone().wait_for_creation(&self.path).map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ZooKeeperBuilder;
+
+    #[test]
+    fn latch_releases_all_waiters() {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let (zk, _) = rt
+            .block_on(ZooKeeperBuilder::default().connect(&"127.0.0.1:2181".parse().unwrap()))
+            .unwrap();
+
+        let latch = Latch::new(zk.clone(), "/latch_test");
+        assert!(!rt.block_on(latch.is_set()).unwrap());
+
+        // the initial watched exists is issued as soon as wait() is called, so these waiters are
+        // registered before the latch is set below.
+        let early: Vec<_> = (0..3)
+            .map(|_| {
+                let (tx, rx) = futures::sync::oneshot::channel();
+                rt.spawn(latch.wait().then(move |r| tx.send(r.is_ok()).map_err(|_| ())));
+                rx
+            })
+            .collect();
+
+        rt.block_on(latch.set()).unwrap();
+        // setting twice is fine
+        rt.block_on(latch.set()).unwrap();
+
+        for rx in early {
+            assert!(rt.block_on(rx).unwrap());
+        }
+        // waiters that show up after the latch was set resolve right away
+        rt.block_on(latch.wait()).unwrap();
+        assert!(rt.block_on(latch.is_set()).unwrap());
+
+        let (zk, res) = rt.block_on(zk.delete("/latch_test", None)).unwrap();
+        assert_eq!(res, Ok(()));
+        drop((zk, latch));
+        rt.shutdown_on_idle().wait().unwrap();
+    }
+}
@@ -0,0 +1,209 @@
+use super::{Candidate, Election, Leadership};
+use failure;
+use std::fmt;
+use tokio::executor::{DefaultExecutor, Executor};
+use tokio::prelude::*;
+use ZooKeeper;
+
+/// A distributed mutual exclusion lock.
+///
+/// Any number of parties can contend for the lock at a well-known `path`, and at most one of them
+/// holds it at any time. Contenders queue up by creating ephemeral-sequential nodes below `path`,
+/// and the lock goes to them in the order they asked for it. Like in an [`Election`], each
+/// contender only watches the one right before it in line.
+///
+/// Since the nodes are ephemeral, a lock whose holder's session ends is released, and the next
+/// contender in line acquires it. Use [`LockGuard::is_held`] to check that the lock has not been
+/// lost this way before acting on behalf of it.
+#[derive(Debug, Clone)]
+pub struct Lock {
+    election: Election,
+}
+
+impl Lock {
+    /// Create a handle to the lock whose contenders live below `path`.
+    ///
+    /// The node at `path` must already exist.
+    pub fn new(zk: ZooKeeper, path: &str) -> Self {
+        Lock {
+            election: Election::new(zk, path),
+        }
+    }
+
+    /// Acquire the lock.
+    ///
+    /// The returned future resolves once the lock is held, which may take until the current holder
+    /// and all contenders ahead of this one have released it. Dropping the future before then
+    /// gives up on the lock.
+    pub fn lock(&self) -> impl Future<Item = LockGuard, Error = failure::Error> {
+        self.election.volunteer().and_then(|candidate| Acquire {
+            candidate: Some(candidate),
+        })
+    }
+}
+
+/// A future that resolves once the lock is held.
+struct Acquire {
+    candidate: Option<Candidate>,
+}
+
+impl Future for Acquire {
+    type Item = LockGuard;
+    type Error = failure::Error;
+
+    fn poll(&mut self) -> Result<Async<Self::Item>, Self::Error> {
+        let event = {
+            let candidate = self
+                .candidate
+                .as_mut()
+                .expect("Acquire polled after completion");
+            try_ready!(candidate.poll())
+        };
+        match event {
+            Some(Leadership::Gained) => Ok(Async::Ready(LockGuard {
+                candidate: self.candidate.take(),
+            })),
+            _ => {
+                self.candidate = None;
+                bail!("lost our place in line before acquiring the lock")
+            }
+        }
+    }
+}
+
+impl Drop for Acquire {
+    fn drop(&mut self) {
+        if let Some(candidate) = self.candidate.take() {
+            // let the contenders after us have the lock
+            release(candidate);
+        }
+    }
+}
+
+/// Proof that a [`Lock`] is held.
+///
+/// The lock is released when the guard is dropped, or explicitly with
+/// [`unlock`](#method.unlock).
+pub struct LockGuard {
+    candidate: Option<Candidate>,
+}
+
+impl fmt::Debug for LockGuard {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("LockGuard")
+            .field("node", &self.node())
+            .finish()
+    }
+}
+
+impl LockGuard {
+    fn candidate(&self) -> &Candidate {
+        self.candidate
+            .as_ref()
+            .expect("the candidate is only taken when the guard goes away")
+    }
+
+    /// The path of the node that represents this holder of the lock.
+    pub fn node(&self) -> &str {
+        self.candidate().node()
+    }
+
+    /// Check whether the lock is still held.
+    ///
+    /// This is only not the case if the session of the holder has ended, or its node was deleted
+    /// by someone else.
+    pub fn is_held(&self) -> impl Future<Item = bool, Error = failure::Error> {
+        self.candidate().owned()
+    }
+
+    /// Release the lock.
+    ///
+    /// The returned future resolves once the server has released the lock.
+    pub fn unlock(mut self) -> impl Future<Item = (), Error = failure::Error> {
+        self.candidate
+            .take()
+            .expect("the candidate is only taken when the guard goes away")
+            .resign()
+    }
+}
+
+impl Drop for LockGuard {
+    fn drop(&mut self) {
+        if let Some(candidate) = self.candidate.take() {
+            release(candidate);
+        }
+    }
+}
+
+/// Give up the place in line of `candidate` without waiting for the server to confirm.
+fn release(candidate: Candidate) {
+    // the delete may have to wait for a free slot in the request queue, and is only sent if
+    // someone still waits for its response by then. Outside of a runtime, it is only sent if it
+    // did not have to wait.
+    let resign = candidate.resign().map_err(|_| ());
+    let _ = DefaultExecutor::current().spawn(Box::new(resign));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::time;
+    use ZooKeeperBuilder;
+
+    #[test]
+    fn mutual_exclusion() {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let addr = "127.0.0.1:2181".parse().unwrap();
+        let (zk1, _) = rt
+            .block_on(ZooKeeperBuilder::default().connect(&addr))
+            .unwrap();
+        let (zk2, _) = rt
+            .block_on(ZooKeeperBuilder::default().connect(&addr))
+            .unwrap();
+        let (zk1, res) = rt.block_on(zk1.ensure_path("/lock_test")).unwrap();
+        assert_eq!(res, Ok(()));
+        let lock1 = Lock::new(zk1.clone(), "/lock_test");
+        let lock2 = Lock::new(zk2.clone(), "/lock_test");
+
+        let guard1 = rt.block_on(lock1.lock()).unwrap();
+        assert!(rt.block_on(guard1.is_held()).unwrap());
+
+        let (tx, rx) = mpsc::channel();
+        rt.spawn(
+            lock2
+                .lock()
+                .then(move |r| tx.send(r.unwrap()).map_err(|_| ())),
+        );
+        // the second client has to wait for the first one
+        assert!(rx.recv_timeout(time::Duration::from_millis(500)).is_err());
+        rt.block_on(guard1.unlock()).unwrap();
+        let guard2 = rx.recv().unwrap();
+        assert!(rt.block_on(guard2.is_held()).unwrap());
+
+        let (tx, rx) = mpsc::channel();
+        rt.spawn(
+            lock1
+                .lock()
+                .then(move |r| tx.send(r.unwrap()).map_err(|_| ())),
+        );
+        assert!(rx.recv_timeout(time::Duration::from_millis(500)).is_err());
+        // the lock is released along with the session of its holder
+        rt.block_on(zk2.close()).unwrap();
+        let guard1 = rx.recv().unwrap();
+        assert!(rt.block_on(guard1.is_held()).unwrap());
+        drop(guard2);
+
+        // dropping the guard releases the lock too
+        let node = guard1.node().to_string();
+        drop(guard1);
+        let guard1 = rt.block_on(lock1.lock()).unwrap();
+        assert_ne!(guard1.node(), node);
+        rt.block_on(guard1.unlock()).unwrap();
+
+        let (zk1, res) = rt.block_on(zk1.delete("/lock_test", None)).unwrap();
+        assert_eq!(res, Ok(()));
+        drop((zk1, lock1, lock2));
+        rt.shutdown_on_idle().wait().unwrap();
+    }
+}
@@ -0,0 +1,18 @@
+//! Implementations of common [ZooKeeper
+//! recipes](https://zookeeper.apache.org/doc/current/recipes.html) built on top of the
+//! [`ZooKeeper`](../struct.ZooKeeper.html) client API.
+
+mod barrier;
+pub use self::barrier::{DoubleBarrier, Participant};
+
+mod election;
+pub use self::election::{Candidate, Election, Leadership};
+
+mod latch;
+pub use self::latch::Latch;
+
+mod lock;
+pub use self::lock::{Lock, LockGuard};
+
+mod queue;
+pub use self::queue::Queue;
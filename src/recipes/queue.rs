@@ -0,0 +1,195 @@
+use failure;
+use futures::future::{self, Either, Loop};
+use std::borrow::Cow;
+use std::vec;
+use tokio::prelude::*;
+use {child_path, error, sequence_number, Acl, CreateMode, ZooKeeper};
+
+/// The prefix of the name of every item's node.
+const PREFIX: &str = "qn-";
+
+/// A distributed first-in, first-out queue.
+///
+/// Producers [`enqueue`](#method.enqueue) items by creating persistent-sequential nodes below a
+/// well-known `path`, and consumers [`dequeue`](#method.dequeue) the item with the lowest sequence
+/// number by deleting its node. The server hands out sequence numbers in the order the creates
+/// arrive, so items from any number of producers come out in the order they went in.
+///
+/// Every item is dequeued by exactly one consumer: if several consumers go for the same item, the
+/// one that deletes it gets it, and the others move on to the next item.
+#[derive(Debug, Clone)]
+pub struct Queue {
+    zk: ZooKeeper,
+    path: String,
+}
+
+impl Queue {
+    /// Create a handle to the queue whose items live below `path`.
+    ///
+    /// The node at `path` must already exist.
+    pub fn new(zk: ZooKeeper, path: &str) -> Self {
+        Queue {
+            zk,
+            path: path.to_string(),
+        }
+    }
+
+    /// Add an item with the given `data` to the back of the queue, and return the path of its
+    /// node.
+    pub fn enqueue<D>(&self, data: D) -> impl Future<Item = String, Error = failure::Error>
+    where
+        D: Into<Cow<'static, [u8]>>,
+    {
+        self.zk
+            .clone()
+            .create(
+                &child_path(&self.path, PREFIX),
+                data,
+                Acl::open_unsafe(),
+                CreateMode::PersistentSequential,
+            )
+            .and_then(|(_, res)| Ok(res?))
+    }
+
+    /// Take the item at the front of the queue, and return its data.
+    ///
+    /// If the queue is empty, the returned future resolves once an item has been added and taken.
+    pub fn dequeue(&self) -> impl Future<Item = Vec<u8>, Error = failure::Error> {
+        let path = self.path.clone();
+        future::loop_fn(self.zk.clone(), move |zk| {
+            let path = path.clone();
+            zk.with_watcher()
+                .get_children(&path)
+                .and_then(move |(zk, children)| {
+                    let (watch, items) = match children {
+                        Some((watch, children)) => (watch, in_order(children)),
+                        None => bail!("queue node {} does not exist", path),
+                    };
+                    if items.is_empty() {
+                        // wait for an item to be added, and then look again (the watch also fires
+                        // if the session is disconnected).
+                        return Ok(Either::A(
+                            watch
+                                .map(move |_| Loop::Continue(zk))
+                                .map_err(|e| format_err!("queue watch dropped: {:?}", e)),
+                        ));
+                    }
+                    Ok(Either::B(take_first(zk, path, items.into_iter()).map(
+                        |(zk, data)| match data {
+                            Some(data) => Loop::Break(data),
+                            // other consumers took all of them, so look for more
+                            None => Loop::Continue(zk),
+                        },
+                    )))
+                })
+                .flatten()
+        })
+    }
+}
+
+/// Take the first of the `items` of the queue at `path` that no other consumer takes first, and
+/// return its data, or `None` if all of them were taken.
+fn take_first(
+    zk: ZooKeeper,
+    path: String,
+    items: vec::IntoIter<String>,
+) -> impl Future<Item = (ZooKeeper, Option<Vec<u8>>), Error = failure::Error> {
+    future::loop_fn((zk, items), move |(zk, mut items)| {
+        let item = match items.next() {
+            Some(item) => child_path(&path, &item),
+            None => return Either::A(future::ok(Loop::Break((zk, None)))),
+        };
+        Either::B(zk.get_data(&item).and_then(move |(zk, data)| {
+            let data = match data {
+                Some((data, _)) => data,
+                None => return Either::A(future::ok(Loop::Continue((zk, items)))),
+            };
+            // whoever deletes the item has taken it
+            Either::B(zk.delete(&item, None).and_then(|(zk, res)| match res {
+                Ok(()) => Ok(Loop::Break((zk, Some(data)))),
+                Err(error::Delete::NoNode) => Ok(Loop::Continue((zk, items))),
+                Err(e) => Err(e.into()),
+            }))
+        }))
+    })
+}
+
+/// The names of the items among the `children` of a queue, in the order they were added.
+fn in_order(children: Vec<String>) -> Vec<String> {
+    let mut items: Vec<_> = children
+        .into_iter()
+        .filter(|c| c.starts_with(PREFIX) && sequence_number(c).is_some())
+        .collect();
+    items.sort_by_key(|c| sequence_number(c));
+    items
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ZooKeeperBuilder;
+
+    #[test]
+    fn items_in_order() {
+        let children = |names: &[&str]| names.iter().map(|n| n.to_string()).collect();
+        assert_eq!(
+            in_order(children(&[
+                "qn-0000000010",
+                "qn-0000000002",
+                "other",
+                "qn-",
+                "qn-0000000007",
+            ])),
+            vec!["qn-0000000002", "qn-0000000007", "qn-0000000010"]
+        );
+        assert!(in_order(children(&[])).is_empty());
+    }
+
+    #[test]
+    fn two_producers_one_consumer() {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let addr = "127.0.0.1:2181".parse().unwrap();
+        let (zk1, _) = rt
+            .block_on(ZooKeeperBuilder::default().connect(&addr))
+            .unwrap();
+        let (zk2, _) = rt
+            .block_on(ZooKeeperBuilder::default().connect(&addr))
+            .unwrap();
+        let (zk1, res) = rt.block_on(zk1.ensure_path("/queue_test")).unwrap();
+        assert_eq!(res, Ok(()));
+
+        let first = Queue::new(zk1.clone(), "/queue_test");
+        let second = Queue::new(zk2.clone(), "/queue_test");
+        // the consumer is waiting before anything is produced
+        let consumer = Queue::new(zk1.clone(), "/queue_test");
+        let (tx, rx) = futures::sync::oneshot::channel();
+        rt.spawn(
+            consumer
+                .dequeue()
+                .then(move |r| tx.send(r.unwrap()).map_err(|_| ())),
+        );
+
+        // the producers race each other, so only the sequence numbers tell the order
+        let mut nodes = Vec::new();
+        for i in 0..3u8 {
+            let (a, b) = rt
+                .block_on(first.enqueue(vec![1, i]).join(second.enqueue(vec![2, i])))
+                .unwrap();
+            nodes.push((a, vec![1, i]));
+            nodes.push((b, vec![2, i]));
+        }
+        nodes.sort_by_key(|item| sequence_number(&item.0));
+
+        let mut dequeued = vec![rt.block_on(rx).unwrap()];
+        for _ in 1..nodes.len() {
+            dequeued.push(rt.block_on(consumer.dequeue()).unwrap());
+        }
+        let expected: Vec<_> = nodes.into_iter().map(|(_, data)| data).collect();
+        assert_eq!(dequeued, expected);
+
+        let (zk1, res) = rt.block_on(zk1.delete("/queue_test", None)).unwrap();
+        assert_eq!(res, Ok(()));
+        drop((zk1, zk2, first, second, consumer));
+        rt.shutdown_on_idle().wait().unwrap();
+    }
+}
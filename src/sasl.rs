@@ -0,0 +1,86 @@
+use failure;
+use futures::future::{self, Either, Loop};
+use proto;
+use std::fmt;
+use std::sync::Arc;
+use tokio::prelude::*;
+use transform;
+
+/// The client side of a SASL mechanism, such as a GSSAPI (Kerberos) security context.
+///
+/// The client starts by sending its initial response to the server, and then evaluates every
+/// token the server sends back, until the mechanism is complete. This mirrors the
+/// `javax.security.sasl.SaslClient` interface that the reference client is built on, so that any
+/// SASL implementation can be plugged in. See
+/// [`ZooKeeperBuilder::set_sasl`](::ZooKeeperBuilder::set_sasl).
+pub trait SaslClient: Send {
+    /// The token that starts the exchange.
+    fn initial_response(&mut self) -> Result<Vec<u8>, failure::Error>;
+
+    /// Evaluate a `challenge` from the server, and return the token to respond with, if any.
+    fn evaluate_challenge(&mut self, challenge: &[u8]) -> Result<Option<Vec<u8>>, failure::Error>;
+
+    /// Whether the exchange is complete, which means that the connection is authenticated.
+    fn is_complete(&self) -> bool;
+}
+
+/// What happens to writes that are made before SASL authentication has completed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SaslWritePolicy {
+    /// Hold back writes, and send them in order once the connection is authenticated. Reads are
+    /// sent right away, and so may overtake writes that are held back.
+    Queue,
+    /// Fail writes with [`error::AuthPending`](::error::AuthPending).
+    Reject,
+}
+
+/// How to authenticate a new client, as set with
+/// [`ZooKeeperBuilder::set_sasl`](::ZooKeeperBuilder::set_sasl).
+#[derive(Clone)]
+pub(crate) struct SaslConfig {
+    pub(crate) new_client: Arc<dyn Fn() -> Box<dyn SaslClient> + Send + Sync>,
+    pub(crate) policy: SaslWritePolicy,
+}
+
+impl fmt::Debug for SaslConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SaslConfig")
+            .field("policy", &self.policy)
+            .finish()
+    }
+}
+
+/// Authenticate the connection behind `connection` by exchanging tokens between `client` and the
+/// server until the mechanism is complete.
+///
+/// If the server rejects a token, this fails with [`error::Auth::AuthFailed`](::error::Auth).
+pub(crate) fn authenticate(
+    connection: proto::Enqueuer,
+    mut client: Box<dyn SaslClient>,
+) -> impl Future<Item = (), Error = failure::Error> {
+    let token = match client.initial_response() {
+        Ok(token) => token,
+        Err(e) => return Either::B(future::err(e)),
+    };
+    Either::A(future::loop_fn(
+        (client, token),
+        move |(mut client, token)| {
+            connection
+                .enqueue(proto::Request::Sasl { token })
+                .and_then(transform::sasl)
+                .and_then(move |res| {
+                    let challenge = res?;
+                    if !client.is_complete() {
+                        match client.evaluate_challenge(&challenge)? {
+                            Some(token) => return Ok(Loop::Continue((client, token))),
+                            None if !client.is_complete() => {
+                                bail!("SASL mechanism has nothing to send, but is not complete")
+                            }
+                            None => {}
+                        }
+                    }
+                    Ok(Loop::Break(()))
+                })
+        },
+    ))
+}
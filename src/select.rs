@@ -0,0 +1,171 @@
+use rand::{self, Rng};
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time;
+
+/// Decides which server the client re-connects to once its connection is lost.
+///
+/// Whenever the client needs to connect to another server, it asks for the `next` one among the
+/// `servers` of the ensemble (in the order they were given in, or shuffled, see
+/// [`ZooKeeperBuilder::set_shuffle`](::ZooKeeperBuilder::set_shuffle)), and tells which server it
+/// could not use last, if any. Once it has tried as many times as there are servers without
+/// success, it backs off before it asks again. See
+/// [`ZooKeeperBuilder::set_server_selector`](::ZooKeeperBuilder::set_server_selector).
+pub trait ServerSelector: Send {
+    /// The server to connect to next, which must be one of `servers`.
+    ///
+    /// `last_failed` is the server the client lost its connection to, or could not connect to on
+    /// its last attempt.
+    fn next(&mut self, servers: &[SocketAddr], last_failed: Option<SocketAddr>) -> SocketAddr;
+}
+
+/// Tries the servers in order, starting with the one after the server that failed last.
+///
+/// The client thus only comes back to a server once all the others have failed. This is the
+/// default.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Ordered;
+
+impl ServerSelector for Ordered {
+    fn next(&mut self, servers: &[SocketAddr], last_failed: Option<SocketAddr>) -> SocketAddr {
+        let next = last_failed
+            .and_then(|failed| servers.iter().position(|&s| s == failed))
+            .map_or(0, |i| (i + 1) % servers.len());
+        servers[next]
+    }
+}
+
+/// Picks a server at random, other than the one that failed last if there is another.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Shuffle;
+
+impl ServerSelector for Shuffle {
+    fn next(&mut self, servers: &[SocketAddr], last_failed: Option<SocketAddr>) -> SocketAddr {
+        let candidates: Vec<_> = servers
+            .iter()
+            .filter(|&&s| Some(s) != last_failed)
+            .collect();
+        if candidates.is_empty() {
+            return servers[0];
+        }
+        *candidates[rand::thread_rng().gen_range(0, candidates.len())]
+    }
+}
+
+/// Avoids a server for a `period` after it failed, and otherwise tries the servers in order.
+///
+/// If all servers failed within the `period`, the one that failed the longest ago is tried.
+#[derive(Clone, Debug)]
+pub struct Cooldown {
+    period: time::Duration,
+    failed_at: HashMap<SocketAddr, time::Instant>,
+}
+
+impl Cooldown {
+    /// Avoid each server for `period` after it failed.
+    pub fn new(period: time::Duration) -> Self {
+        Cooldown {
+            period,
+            failed_at: HashMap::new(),
+        }
+    }
+}
+
+impl ServerSelector for Cooldown {
+    fn next(&mut self, servers: &[SocketAddr], last_failed: Option<SocketAddr>) -> SocketAddr {
+        let now = time::Instant::now();
+        if let Some(failed) = last_failed {
+            self.failed_at.insert(failed, now);
+        }
+        let period = self.period;
+        self.failed_at.retain(|_, &mut at| now - at < period);
+
+        // go on in order from the server that failed last, as `Ordered` does
+        let start = Ordered.next(servers, last_failed);
+        let start = servers.iter().position(|&s| s == start).unwrap_or(0);
+        let in_order = servers[start..].iter().chain(&servers[..start]);
+        let failed_at = &self.failed_at;
+        in_order
+            .clone()
+            .find(|s| !failed_at.contains_key(s))
+            .or_else(|| in_order.min_by_key(|s| failed_at[s]))
+            .cloned()
+            .unwrap_or(servers[0])
+    }
+}
+
+/// The selector set with
+/// [`ZooKeeperBuilder::set_server_selector`](::ZooKeeperBuilder::set_server_selector), shared by
+/// all clones of the builder.
+#[derive(Clone)]
+pub(crate) struct Selector(pub(crate) Arc<Mutex<dyn ServerSelector>>);
+
+impl Selector {
+    /// The index of the server in `servers` to connect to next.
+    pub(crate) fn next(&self, servers: &[SocketAddr], last_failed: usize) -> usize {
+        let next = self
+            .0
+            .lock()
+            .unwrap()
+            .next(servers, Some(servers[last_failed]));
+        // fall back to the first server if the selector chose one that is not in the ensemble
+        servers.iter().position(|&s| s == next).unwrap_or(0)
+    }
+}
+
+impl Default for Selector {
+    fn default() -> Self {
+        Selector(Arc::new(Mutex::new(Ordered)))
+    }
+}
+
+impl fmt::Debug for Selector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Selector").finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn servers() -> Vec<SocketAddr> {
+        (1..4)
+            .map(|i| format!("127.0.0.{}:2181", i).parse().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn ordered() {
+        let servers = servers();
+        assert_eq!(Ordered.next(&servers, None), servers[0]);
+        assert_eq!(Ordered.next(&servers, Some(servers[0])), servers[1]);
+        assert_eq!(Ordered.next(&servers, Some(servers[2])), servers[0]);
+    }
+
+    #[test]
+    fn shuffle_avoids_last_failed() {
+        let servers = servers();
+        for _ in 0..20 {
+            assert_ne!(Shuffle.next(&servers, Some(servers[1])), servers[1]);
+        }
+        assert_eq!(Shuffle.next(&servers[..1], Some(servers[0])), servers[0]);
+    }
+
+    #[test]
+    fn cooldown() {
+        let servers = servers();
+        let mut cooldown = Cooldown::new(time::Duration::from_secs(60));
+        assert_eq!(cooldown.next(&servers, Some(servers[0])), servers[1]);
+        assert_eq!(cooldown.next(&servers, Some(servers[1])), servers[2]);
+        // the first two servers are still cooling down
+        assert_eq!(cooldown.next(&servers, Some(servers[2])), servers[0]);
+        assert_eq!(cooldown.failed_at.len(), 3);
+
+        let mut cooldown = Cooldown::new(time::Duration::from_secs(0));
+        assert_eq!(cooldown.next(&servers, Some(servers[0])), servers[1]);
+        assert_eq!(cooldown.next(&servers, Some(servers[2])), servers[0]);
+    }
+}
@@ -0,0 +1,778 @@
+//! Helpers for testing code built on this crate without a real ZooKeeper.
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+use ZkError;
+
+// The codes of the events the server notifies watchers of.
+const NODE_CREATED: i32 = 1;
+const NODE_DELETED: i32 = 2;
+const NODE_DATA_CHANGED: i32 = 3;
+const NODE_CHILDREN_CHANGED: i32 = 4;
+
+/// A ZooKeeper server that keeps its tree in memory.
+///
+/// The server speaks enough of the ZooKeeper protocol for the common operations: `create` (of
+/// persistent, ephemeral, and sequential nodes), `delete`, `exists`, `get_data`, `set_data`,
+/// `get_children`, and `sync`, along with the data and child watches that reads can leave. It
+/// answers any other operation with [`ZkError::Unimplemented`]. ACLs are accepted, but not
+/// enforced or stored.
+///
+/// A session ends as soon as its connection is closed, at which point its ephemeral nodes are
+/// deleted. Clients that try to resume it on another connection are told that it has expired.
+///
+/// The server runs on threads of its own, and shuts down when it is dropped.
+///
+/// ```no_run
+/// # extern crate tokio;
+/// # extern crate tokio_zookeeper;
+/// # use tokio_zookeeper::*;
+/// # use tokio_zookeeper::test_util::MockServer;
+/// # fn main() {
+/// let server = MockServer::start().unwrap();
+/// let mut rt = tokio::runtime::Runtime::new().unwrap();
+/// let (zk, _) = rt.block_on(ZooKeeper::connect(&server.addr())).unwrap();
+/// let (zk, res) = rt
+///     .block_on(zk.create("/a", &b"data"[..], Acl::open_unsafe(), CreateMode::Persistent))
+///     .unwrap();
+/// assert_eq!(res, Ok("/a".to_string()));
+/// # drop(zk);
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct MockServer {
+    addr: SocketAddr,
+    tree: Arc<Mutex<Tree>>,
+}
+
+impl MockServer {
+    /// Start a server with an empty tree on a free local port.
+    pub fn start() -> io::Result<MockServer> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let tree = Arc::new(Mutex::new(Tree::default()));
+        let accepting = tree.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                if accepting.lock().unwrap().shut_down {
+                    break;
+                }
+                if let Ok(stream) = stream {
+                    let tree = accepting.clone();
+                    thread::spawn(move || serve(&tree, stream));
+                }
+            }
+        });
+        Ok(MockServer { addr, tree })
+    }
+
+    /// The address the server listens on.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        {
+            let mut tree = self.tree.lock().unwrap();
+            tree.shut_down = true;
+            for session in tree.sessions.values() {
+                let _ = session.stream.shutdown(Shutdown::Both);
+            }
+        }
+        // wake up the thread that accepts connections, so that it notices
+        let _ = TcpStream::connect(self.addr);
+    }
+}
+
+#[derive(Debug)]
+struct Node {
+    data: Vec<u8>,
+    czxid: i64,
+    mzxid: i64,
+    ctime: i64,
+    mtime: i64,
+    version: i32,
+    cversion: i32,
+    ephemeral_owner: i64,
+    pzxid: i64,
+    children: BTreeSet<String>,
+}
+
+impl Node {
+    fn new(data: Vec<u8>, zxid: i64, ephemeral_owner: i64) -> Self {
+        let now = now();
+        Node {
+            data,
+            czxid: zxid,
+            mzxid: zxid,
+            ctime: now,
+            mtime: now,
+            version: 0,
+            cversion: 0,
+            ephemeral_owner,
+            pzxid: zxid,
+            children: BTreeSet::new(),
+        }
+    }
+
+    fn write_stat(&self, buf: &mut Vec<u8>) {
+        buf.write_i64::<BigEndian>(self.czxid).unwrap();
+        buf.write_i64::<BigEndian>(self.mzxid).unwrap();
+        buf.write_i64::<BigEndian>(self.ctime).unwrap();
+        buf.write_i64::<BigEndian>(self.mtime).unwrap();
+        buf.write_i32::<BigEndian>(self.version).unwrap();
+        buf.write_i32::<BigEndian>(self.cversion).unwrap();
+        buf.write_i32::<BigEndian>(0).unwrap(); // aversion
+        buf.write_i64::<BigEndian>(self.ephemeral_owner).unwrap();
+        buf.write_i32::<BigEndian>(self.data.len() as i32).unwrap();
+        buf.write_i32::<BigEndian>(self.children.len() as i32)
+            .unwrap();
+        buf.write_i64::<BigEndian>(self.pzxid).unwrap();
+    }
+}
+
+#[derive(Debug)]
+struct Session {
+    /// Tells apart the connections a session has been on.
+    connection: u64,
+    stream: TcpStream,
+}
+
+#[derive(Debug)]
+struct Tree {
+    nodes: HashMap<String, Node>,
+    zxid: i64,
+    sessions: HashMap<i64, Session>,
+    last_session_id: i64,
+    connections: u64,
+    /// The sessions that watch the data of each node.
+    data_watches: HashMap<String, HashSet<i64>>,
+    /// The sessions that watch the children of each node.
+    child_watches: HashMap<String, HashSet<i64>>,
+    shut_down: bool,
+}
+
+impl Default for Tree {
+    fn default() -> Self {
+        let mut nodes = HashMap::new();
+        nodes.insert("/".to_string(), Node::new(Vec::new(), 0, 0));
+        Tree {
+            nodes,
+            zxid: 0,
+            sessions: HashMap::new(),
+            last_session_id: 0,
+            connections: 0,
+            data_watches: HashMap::new(),
+            child_watches: HashMap::new(),
+            shut_down: false,
+        }
+    }
+}
+
+/// A request, as far as the server understands it.
+enum Op {
+    Create {
+        path: String,
+        data: Vec<u8>,
+        flags: i32,
+        stat: bool,
+    },
+    Delete {
+        path: String,
+        version: i32,
+    },
+    Exists {
+        path: String,
+        watch: bool,
+    },
+    GetData {
+        path: String,
+        watch: bool,
+    },
+    SetData {
+        path: String,
+        data: Vec<u8>,
+        version: i32,
+    },
+    GetChildren {
+        path: String,
+        watch: bool,
+        stat: bool,
+    },
+    Sync {
+        path: String,
+    },
+    Ping,
+    Close,
+    Unknown,
+}
+
+impl Op {
+    fn parse(opcode: i32, body: &mut &[u8]) -> io::Result<Op> {
+        Ok(match opcode {
+            // create, create2, and createContainer
+            1 | 15 | 19 => {
+                let path = read_string(body)?;
+                let data = read_buffer(body)?;
+                for _ in 0..body.read_i32::<BigEndian>()? {
+                    let _perms = body.read_i32::<BigEndian>()?;
+                    let _scheme = read_string(body)?;
+                    let _id = read_string(body)?;
+                }
+                Op::Create {
+                    path,
+                    data,
+                    flags: body.read_i32::<BigEndian>()?,
+                    stat: opcode != 1,
+                }
+            }
+            2 => Op::Delete {
+                path: read_string(body)?,
+                version: body.read_i32::<BigEndian>()?,
+            },
+            3 => Op::Exists {
+                path: read_string(body)?,
+                watch: body.read_u8()? != 0,
+            },
+            4 => Op::GetData {
+                path: read_string(body)?,
+                watch: body.read_u8()? != 0,
+            },
+            5 => Op::SetData {
+                path: read_string(body)?,
+                data: read_buffer(body)?,
+                version: body.read_i32::<BigEndian>()?,
+            },
+            8 | 12 => Op::GetChildren {
+                path: read_string(body)?,
+                watch: body.read_u8()? != 0,
+                stat: opcode == 12,
+            },
+            9 => Op::Sync {
+                path: read_string(body)?,
+            },
+            11 => Op::Ping,
+            -11 => Op::Close,
+            _ => Op::Unknown,
+        })
+    }
+}
+
+impl Tree {
+    /// Carry out one request of `session`, and write the reply to it.
+    ///
+    /// Returns whether the session was closed.
+    fn handle(&mut self, session: i64, frame: &[u8]) -> io::Result<bool> {
+        let mut body = frame;
+        let xid = body.read_i32::<BigEndian>()?;
+        let opcode = body.read_i32::<BigEndian>()?;
+        let op = Op::parse(opcode, &mut body);
+        let closed = opcode == -11;
+        let reply = match op {
+            Ok(op) => self.apply(session, op),
+            Err(_) => Err(ZkError::MarshallingError),
+        };
+        let (err, body) = match reply {
+            Ok(body) => (0, body),
+            Err(e) => (e.code(), Vec::new()),
+        };
+        let zxid = self.zxid;
+        self.write(session, xid, zxid, err, &body);
+        if closed {
+            self.end_session(session);
+        }
+        Ok(closed)
+    }
+
+    fn apply(&mut self, session: i64, op: Op) -> Result<Vec<u8>, ZkError> {
+        let mut reply = Vec::new();
+        match op {
+            Op::Create {
+                path,
+                data,
+                flags,
+                stat,
+            } => {
+                let path = self.create(session, &path, data, flags)?;
+                write_string(&mut reply, &path);
+                if stat {
+                    self.nodes[&path].write_stat(&mut reply);
+                }
+            }
+            Op::Delete { path, version } => self.delete(&path, version)?,
+            Op::Exists { path, watch } => {
+                if watch {
+                    watch_node(&mut self.data_watches, &path, session);
+                }
+                let node = self.nodes.get(&path).ok_or(ZkError::NoNode)?;
+                node.write_stat(&mut reply);
+            }
+            Op::GetData { path, watch } => {
+                let node = self.nodes.get(&path).ok_or(ZkError::NoNode)?;
+                write_buffer(&mut reply, &node.data);
+                node.write_stat(&mut reply);
+                if watch {
+                    watch_node(&mut self.data_watches, &path, session);
+                }
+            }
+            Op::SetData {
+                path,
+                data,
+                version,
+            } => {
+                self.zxid += 1;
+                {
+                    let node = self.nodes.get_mut(&path).ok_or(ZkError::NoNode)?;
+                    if version != -1 && version != node.version {
+                        return Err(ZkError::BadVersion);
+                    }
+                    node.data = data;
+                    node.version += 1;
+                    node.mzxid = self.zxid;
+                    node.mtime = now();
+                    node.write_stat(&mut reply);
+                }
+                self.fire(&path, NODE_DATA_CHANGED, true, false);
+            }
+            Op::GetChildren { path, watch, stat } => {
+                let node = self.nodes.get(&path).ok_or(ZkError::NoNode)?;
+                reply
+                    .write_i32::<BigEndian>(node.children.len() as i32)
+                    .unwrap();
+                for child in &node.children {
+                    write_string(&mut reply, child);
+                }
+                if stat {
+                    node.write_stat(&mut reply);
+                }
+                if watch {
+                    watch_node(&mut self.child_watches, &path, session);
+                }
+            }
+            Op::Sync { path } => write_string(&mut reply, &path),
+            Op::Ping | Op::Close => {}
+            Op::Unknown => return Err(ZkError::Unimplemented),
+        }
+        Ok(reply)
+    }
+
+    fn create(
+        &mut self,
+        session: i64,
+        path: &str,
+        data: Vec<u8>,
+        flags: i32,
+    ) -> Result<String, ZkError> {
+        let (ephemeral, sequential) = match flags {
+            // containers are left alone as if they were persistent nodes
+            0 | 4 => (false, false),
+            1 => (true, false),
+            2 => (false, true),
+            3 => (true, true),
+            _ => return Err(ZkError::Unimplemented),
+        };
+        if !path.starts_with('/') || path.len() == 1 || path[1..].contains("//") {
+            return Err(ZkError::BadArguments);
+        }
+        if path.ends_with('/') && !sequential {
+            return Err(ZkError::BadArguments);
+        }
+        let parent = parent(path).to_string();
+        let path = {
+            let parent = self.nodes.get(&parent).ok_or(ZkError::NoNode)?;
+            if parent.ephemeral_owner != 0 {
+                return Err(ZkError::NoChildrenForEphemerals);
+            }
+            if sequential {
+                format!("{}{:010}", path, parent.cversion)
+            } else {
+                path.to_string()
+            }
+        };
+        if self.nodes.contains_key(&path) {
+            return Err(ZkError::NodeExists);
+        }
+
+        self.zxid += 1;
+        let owner = if ephemeral { session } else { 0 };
+        self.nodes
+            .insert(path.clone(), Node::new(data, self.zxid, owner));
+        {
+            let parent = self.nodes.get_mut(&parent).unwrap();
+            parent
+                .children
+                .insert(path[path.rfind('/').unwrap() + 1..].to_string());
+            parent.cversion += 1;
+            parent.pzxid = self.zxid;
+        }
+        self.fire(&path, NODE_CREATED, true, false);
+        self.fire(&parent, NODE_CHILDREN_CHANGED, false, true);
+        Ok(path)
+    }
+
+    fn delete(&mut self, path: &str, version: i32) -> Result<(), ZkError> {
+        if path == "/" {
+            return Err(ZkError::BadArguments);
+        }
+        {
+            let node = self.nodes.get(path).ok_or(ZkError::NoNode)?;
+            if version != -1 && version != node.version {
+                return Err(ZkError::BadVersion);
+            }
+            if !node.children.is_empty() {
+                return Err(ZkError::NotEmpty);
+            }
+        }
+
+        self.zxid += 1;
+        self.nodes.remove(path);
+        let parent = parent(path).to_string();
+        {
+            let parent = self.nodes.get_mut(&parent).unwrap();
+            parent
+                .children
+                .remove(&path[path.rfind('/').unwrap() + 1..]);
+            parent.cversion += 1;
+            parent.pzxid = self.zxid;
+        }
+        self.fire(path, NODE_DELETED, true, true);
+        self.fire(&parent, NODE_CHILDREN_CHANGED, false, true);
+        Ok(())
+    }
+
+    /// Notify the sessions that watch the data and/or the children of the node at `path`, and
+    /// forget about their watches.
+    fn fire(&mut self, path: &str, event_type: i32, data: bool, children: bool) {
+        let mut sessions = HashSet::new();
+        if data {
+            sessions.extend(self.data_watches.remove(path).unwrap_or_default());
+        }
+        if children {
+            sessions.extend(self.child_watches.remove(path).unwrap_or_default());
+        }
+        let mut event = Vec::new();
+        event.write_i32::<BigEndian>(event_type).unwrap();
+        event.write_i32::<BigEndian>(3).unwrap(); // SyncConnected
+        write_string(&mut event, path);
+        for session in sessions {
+            self.write(session, -1, -1, 0, &event);
+        }
+    }
+
+    /// Write a reply with the given header fields and `body` to `session`, if it is still there.
+    fn write(&mut self, session: i64, xid: i32, zxid: i64, err: i32, body: &[u8]) {
+        if let Some(session) = self.sessions.get_mut(&session) {
+            let mut frame = Vec::with_capacity(20 + body.len());
+            frame
+                .write_i32::<BigEndian>(16 + body.len() as i32)
+                .unwrap();
+            frame.write_i32::<BigEndian>(xid).unwrap();
+            frame.write_i64::<BigEndian>(zxid).unwrap();
+            frame.write_i32::<BigEndian>(err).unwrap();
+            frame.extend(body);
+            // a session whose connection is gone is ended by the thread that reads from it
+            let _ = session.stream.write_all(&frame);
+        }
+    }
+
+    /// Forget about `session`, its watches, and its ephemeral nodes.
+    fn end_session(&mut self, session: i64) {
+        if self.sessions.remove(&session).is_none() {
+            return;
+        }
+        for watches in self
+            .data_watches
+            .values_mut()
+            .chain(self.child_watches.values_mut())
+        {
+            watches.remove(&session);
+        }
+        let ephemerals: Vec<_> = self
+            .nodes
+            .iter()
+            .filter(|&(_, node)| node.ephemeral_owner == session)
+            .map(|(path, _)| path.clone())
+            .collect();
+        for path in ephemerals {
+            // ephemeral nodes have no children, so this cannot fail
+            let _ = self.delete(&path, -1);
+        }
+    }
+}
+
+/// Serve the client on the other end of `stream` until it goes away.
+fn serve(tree: &Mutex<Tree>, mut stream: TcpStream) {
+    let (session, connection) = match accept(tree, &mut stream) {
+        Ok(Some(session)) => session,
+        Ok(None) | Err(_) => return,
+    };
+    while let Ok(frame) = read_frame(&mut stream) {
+        match tree.lock().unwrap().handle(session, &frame) {
+            Ok(false) => {}
+            Ok(true) | Err(_) => break,
+        }
+    }
+
+    let mut tree = tree.lock().unwrap();
+    let current = tree.sessions.get(&session).map(|s| s.connection);
+    if current == Some(connection) {
+        tree.end_session(session);
+    }
+}
+
+/// Read the connect request from `stream`, and establish the session it asks for.
+///
+/// Returns the id of the session and of the connection, or `None` if the session has expired.
+fn accept(tree: &Mutex<Tree>, stream: &mut TcpStream) -> io::Result<Option<(i64, u64)>> {
+    let frame = read_frame(stream)?;
+    let mut body = &frame[..];
+    let _protocol_version = body.read_i32::<BigEndian>()?;
+    let _last_zxid_seen = body.read_i64::<BigEndian>()?;
+    let timeout = body.read_i32::<BigEndian>()?;
+    let session_id = body.read_i64::<BigEndian>()?;
+
+    let mut tree = tree.lock().unwrap();
+    if tree.shut_down {
+        return Ok(None);
+    }
+    let mut reply = Vec::new();
+    reply.write_i32::<BigEndian>(0).unwrap(); // protocol version
+    if session_id != 0 && !tree.sessions.contains_key(&session_id) {
+        // the session ended along with its last connection
+        reply.write_i32::<BigEndian>(0).unwrap();
+        reply.write_i64::<BigEndian>(0).unwrap();
+        write_buffer(&mut reply, &[0; 16]);
+        write_frame(stream, &reply)?;
+        return Ok(None);
+    }
+    let session = if session_id == 0 {
+        tree.last_session_id += 1;
+        tree.last_session_id
+    } else {
+        session_id
+    };
+    tree.connections += 1;
+    let connection = tree.connections;
+    let old = tree.sessions.insert(
+        session,
+        Session {
+            connection,
+            stream: stream.try_clone()?,
+        },
+    );
+    if let Some(old) = old {
+        let _ = old.stream.shutdown(Shutdown::Both);
+    }
+    // like a server with the default tick time of 2s
+    reply
+        .write_i32::<BigEndian>(timeout.clamp(4_000, 40_000))
+        .unwrap();
+    reply.write_i64::<BigEndian>(session).unwrap();
+    write_buffer(&mut reply, &[0; 16]);
+    reply.push(0); // not read-only
+    write_frame(stream, &reply)?;
+    Ok(Some((session, connection)))
+}
+
+/// Watch the node at `path` (which need not exist) for `session` in `watches`.
+fn watch_node(watches: &mut HashMap<String, HashSet<i64>>, path: &str, session: i64) {
+    watches.entry(path.to_string()).or_default().insert(session);
+}
+
+fn parent(path: &str) -> &str {
+    match path.rfind('/') {
+        Some(0) | None => "/",
+        Some(i) => &path[..i],
+    }
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64 * 1000 + i64::from(d.subsec_millis()))
+        .unwrap_or(0)
+}
+
+fn read_frame(stream: &mut TcpStream) -> io::Result<Vec<u8>> {
+    let len = stream.read_i32::<BigEndian>()?;
+    if len < 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "negative length",
+        ));
+    }
+    let mut frame = vec![0; len as usize];
+    stream.read_exact(&mut frame)?;
+    Ok(frame)
+}
+
+fn write_frame(stream: &mut TcpStream, body: &[u8]) -> io::Result<()> {
+    stream.write_i32::<BigEndian>(body.len() as i32)?;
+    stream.write_all(body)
+}
+
+fn read_buffer(read: &mut &[u8]) -> io::Result<Vec<u8>> {
+    let len = read.read_i32::<BigEndian>()?;
+    if len < 0 {
+        return Ok(Vec::new());
+    }
+    let mut buf = vec![0; len as usize];
+    read.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn read_string(read: &mut &[u8]) -> io::Result<String> {
+    String::from_utf8(read_buffer(read)?).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn write_buffer(buf: &mut Vec<u8>, bytes: &[u8]) {
+    buf.write_i32::<BigEndian>(bytes.len() as i32).unwrap();
+    buf.extend(bytes);
+}
+
+fn write_string(buf: &mut Vec<u8>, s: &str) {
+    write_buffer(buf, s.as_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio;
+    use tokio::prelude::*;
+    use {Acl, CreateMode, KeeperState, WatchedEvent, WatchedEventType, ZooKeeper};
+
+    #[test]
+    fn create_and_get_data() {
+        let server = MockServer::start().unwrap();
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let (zk, _) = rt.block_on(ZooKeeper::connect(&server.addr())).unwrap();
+
+        let (zk, res) = rt
+            .block_on(zk.create("/a", &b"1"[..], Acl::open_unsafe(), CreateMode::Persistent))
+            .unwrap();
+        assert_eq!(res, Ok("/a".to_string()));
+        let (zk, res) = rt
+            .block_on(zk.create("/a", &b"2"[..], Acl::open_unsafe(), CreateMode::Persistent))
+            .unwrap();
+        assert_eq!(res, Err(::error::Create::NodeExists));
+        let (zk, res) = rt
+            .block_on(zk.create(
+                "/a/s-",
+                &b""[..],
+                Acl::open_unsafe(),
+                CreateMode::PersistentSequential,
+            ))
+            .unwrap();
+        assert_eq!(res, Ok("/a/s-0000000000".to_string()));
+
+        let (zk, res) = rt.block_on(zk.get_data("/a")).unwrap();
+        let (data, stat) = res.unwrap();
+        assert_eq!(data, b"1");
+        assert_eq!(stat.num_children, 1);
+        let (zk, res) = rt.block_on(zk.set_data("/a", Some(0), &b"3"[..])).unwrap();
+        assert_eq!(res.unwrap().version, 1);
+        let (zk, res) = rt.block_on(zk.set_data("/a", Some(0), &b"4"[..])).unwrap();
+        assert_eq!(
+            res,
+            Err(::error::SetData::BadVersion {
+                expected: 0,
+                actual: None,
+            })
+        );
+        let (zk, res) = rt.block_on(zk.get_data("/a")).unwrap();
+        assert_eq!(res.unwrap().0, b"3");
+
+        let (zk, res) = rt.block_on(zk.delete("/a", None)).unwrap();
+        assert_eq!(res, Err(::error::Delete::NotEmpty));
+        let (zk, res) = rt.block_on(zk.delete("/a/s-0000000000", None)).unwrap();
+        assert_eq!(res, Ok(()));
+        let (zk, res) = rt.block_on(zk.delete("/a", None)).unwrap();
+        assert_eq!(res, Ok(()));
+        let (zk, res) = rt.block_on(zk.get_data("/a")).unwrap();
+        assert_eq!(res, None);
+        drop(zk);
+    }
+
+    #[test]
+    fn watches_fire_once() {
+        let server = MockServer::start().unwrap();
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let (zk, events) = rt.block_on(ZooKeeper::connect(&server.addr())).unwrap();
+        let (other, _) = rt.block_on(ZooKeeper::connect(&server.addr())).unwrap();
+
+        let (zk, stat) = rt.block_on(zk.watch().exists("/w")).unwrap();
+        assert_eq!(stat, None);
+        let (zk, children) = rt.block_on(zk.watch().get_children("/")).unwrap();
+        assert_eq!(children, Some(vec![]));
+        // another session makes the changes
+        let (other, res) = rt
+            .block_on(other.create("/w", &b""[..], Acl::open_unsafe(), CreateMode::Persistent))
+            .unwrap();
+        assert!(res.is_ok());
+        let (other, res) = rt.block_on(other.set_data("/w", None, &b"x"[..])).unwrap();
+        assert!(res.is_ok());
+
+        let events = rt.block_on(events.take(2).collect()).unwrap();
+        let mut events: Vec<_> = events.into_iter().map(|e| (e.event_type, e.path)).collect();
+        events.sort_by_key(|e| e.1.clone());
+        assert_eq!(
+            events,
+            vec![
+                (WatchedEventType::NodeChildrenChanged, "/".to_string()),
+                (WatchedEventType::NodeCreated, "/w".to_string()),
+            ]
+        );
+
+        // a custom watch on the data is only told about the first change
+        let (zk, res) = rt.block_on(zk.with_watcher().get_data("/w")).unwrap();
+        let (watch, data, _) = res.unwrap();
+        assert_eq!(data, b"x");
+        let (other, _) = rt.block_on(other.set_data("/w", None, &b"y"[..])).unwrap();
+        let (other, _) = rt.block_on(other.delete("/w", None)).unwrap();
+        assert_eq!(
+            rt.block_on(watch).unwrap(),
+            WatchedEvent {
+                event_type: WatchedEventType::NodeDataChanged,
+                keeper_state: KeeperState::SyncConnected,
+                path: "/w".to_string(),
+                zxid: None,
+            }
+        );
+        drop((zk, other));
+    }
+
+    #[test]
+    fn ephemeral_nodes_go_with_their_session() {
+        let server = MockServer::start().unwrap();
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let (zk, _) = rt.block_on(ZooKeeper::connect(&server.addr())).unwrap();
+        let (observer, _) = rt.block_on(ZooKeeper::connect(&server.addr())).unwrap();
+
+        let (zk, res) = rt
+            .block_on(zk.create("/e", &b""[..], Acl::open_unsafe(), CreateMode::Ephemeral))
+            .unwrap();
+        assert!(res.is_ok());
+        let (zk, res) = rt
+            .block_on(zk.create("/e/c", &b""[..], Acl::open_unsafe(), CreateMode::Persistent))
+            .unwrap();
+        assert_eq!(res, Err(::error::Create::NoChildrenForEphemerals));
+        let (observer, watch, stat) = rt.block_on(observer.with_watcher().exists("/e")).unwrap();
+        assert_eq!(stat.unwrap().ephemeral_owner, zk.session_id());
+
+        rt.block_on(zk.close()).unwrap();
+        assert_eq!(
+            rt.block_on(watch).unwrap().event_type,
+            WatchedEventType::NodeDeleted
+        );
+        let (observer, stat) = rt.block_on(observer.exists("/e")).unwrap();
+        assert_eq!(stat, None);
+        drop(observer);
+    }
+}
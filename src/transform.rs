@@ -1,31 +1,54 @@
+use bytes::Bytes;
+use failure::Fail;
 use proto::{Request, Response, ZkError};
-use {error, Acl, MultiResponse, Stat};
+use {error, Acl, EnsembleConfig, MultiResponse, Stat};
+
+/// Turn an error code into the typed error of an operation, or into a failure if the operation
+/// does not expect the code (such as `ConnectionLoss`).
+fn typed<T, E>(
+    res: Result<E, ZkError>,
+    failed: &'static str,
+) -> Result<Result<T, E>, failure::Error> {
+    match res {
+        Ok(e) => Ok(Err(e)),
+        Err(e) => Err(e.context(failed).into()),
+    }
+}
 
 pub(crate) fn create(
     res: Result<Response, ZkError>,
 ) -> Result<Result<String, error::Create>, failure::Error> {
     match res {
         Ok(Response::String(s)) => Ok(Ok(s)),
+        // container and TTL nodes are created with their own opcodes, which also return a stat
+        Ok(Response::CreateStat { path, .. }) => Ok(Ok(path)),
         Ok(r) => bail!("got non-string response to create: {:?}", r),
-        Err(ZkError::NoNode) => Ok(Err(error::Create::NoNode)),
-        Err(ZkError::NodeExists) => Ok(Err(error::Create::NodeExists)),
-        Err(ZkError::InvalidACL) => Ok(Err(error::Create::InvalidAcl)),
-        Err(ZkError::NoChildrenForEphemerals) => Ok(Err(error::Create::NoChildrenForEphemerals)),
-        Err(e) => Err(format_err!("create call failed: {:?}", e)),
+        Err(e) => typed(error::Create::from_zk(e), "create call failed"),
+    }
+}
+
+pub(crate) fn create2(
+    res: Result<Response, ZkError>,
+) -> Result<Result<(String, Stat), error::Create>, failure::Error> {
+    match res {
+        Ok(Response::CreateStat { path, stat }) => Ok(Ok((path, stat))),
+        Ok(r) => bail!("got non-create-stat response to create2: {:?}", r),
+        Err(e) => typed(error::Create::from_zk(e), "create2 call failed"),
     }
 }
 
 pub(crate) fn set_data(
     version: i32,
+    path: &str,
     res: Result<Response, ZkError>,
 ) -> Result<Result<Stat, error::SetData>, failure::Error> {
     match res {
         Ok(Response::Stat(stat)) => Ok(Ok(stat)),
         Ok(r) => bail!("got a non-stat response to a set_data request: {:?}", r),
-        Err(ZkError::NoNode) => Ok(Err(error::SetData::NoNode)),
-        Err(ZkError::BadVersion) => Ok(Err(error::SetData::BadVersion { expected: version })),
-        Err(ZkError::NoAuth) => Ok(Err(error::SetData::NoAuth)),
-        Err(e) => bail!("set_data call failed: {:?}", e),
+        Err(e) => typed(
+            error::SetData::from_zk(e, version, path),
+            "set_data call failed",
+        ),
     }
 }
 
@@ -36,10 +59,7 @@ pub(crate) fn delete(
     match res {
         Ok(Response::Empty) => Ok(Ok(())),
         Ok(r) => bail!("got non-empty response to delete: {:?}", r),
-        Err(ZkError::NoNode) => Ok(Err(error::Delete::NoNode)),
-        Err(ZkError::NotEmpty) => Ok(Err(error::Delete::NotEmpty)),
-        Err(ZkError::BadVersion) => Ok(Err(error::Delete::BadVersion { expected: version })),
-        Err(e) => Err(format_err!("delete call failed: {:?}", e)),
+        Err(e) => typed(error::Delete::from_zk(e, version), "delete call failed"),
     }
 }
 
@@ -49,32 +69,34 @@ pub(crate) fn get_acl(
     match res {
         Ok(Response::GetAcl { acl, stat }) => Ok(Ok((acl, stat))),
         Ok(r) => bail!("got non-acl response to a get_acl request: {:?}", r),
-        Err(ZkError::NoNode) => Ok(Err(error::GetAcl::NoNode)),
-        Err(e) => Err(format_err!("get_acl call failed: {:?}", e)),
+        Err(e) => typed(error::GetAcl::from_zk(e), "get_acl call failed"),
     }
 }
 
 pub(crate) fn set_acl(
     version: i32,
+    path: &str,
     res: Result<Response, ZkError>,
 ) -> Result<Result<Stat, error::SetAcl>, failure::Error> {
     match res {
         Ok(Response::Stat(stat)) => Ok(Ok(stat)),
         Ok(r) => bail!("got non-stat response to a set_acl request: {:?}", r),
-        Err(ZkError::NoNode) => Ok(Err(error::SetAcl::NoNode)),
-        Err(ZkError::BadVersion) => Ok(Err(error::SetAcl::BadVersion { expected: version })),
-        Err(ZkError::InvalidACL) => Ok(Err(error::SetAcl::InvalidAcl)),
-        Err(ZkError::NoAuth) => Ok(Err(error::SetAcl::NoAuth)),
-        Err(e) => Err(format_err!("set_acl call failed: {:?}", e)),
+        Err(e) => typed(
+            error::SetAcl::from_zk(e, version, path),
+            "set_acl call failed",
+        ),
     }
 }
 
 pub(crate) fn exists(res: Result<Response, ZkError>) -> Result<Option<Stat>, failure::Error> {
     match res {
         Ok(Response::Stat(stat)) => Ok(Some(stat)),
-        Ok(r) => bail!("got a non-create response to a create request: {:?}", r),
+        Ok(r) => bail!("got a non-stat response to exists: {:?}", r),
         Err(ZkError::NoNode) => Ok(None),
-        Err(e) => bail!("exists call failed: {:?}", e),
+        Err(e) => match error::Exists::from_zk(e) {
+            Ok(e) => Err(e.context("exists call failed").into()),
+            Err(e) => Err(e.context("exists call failed").into()),
+        },
     }
 }
 
@@ -83,16 +105,159 @@ pub(crate) fn get_children(res: Result<Response, ZkError>) -> Result<Option<Vec<
         Ok(Response::Strings(children)) => Ok(Some(children)),
         Ok(r) => bail!("got non-strings response to get-children: {:?}", r),
         Err(ZkError::NoNode) => Ok(None),
-        Err(e) => Err(format_err!("get-children call failed: {:?}", e)),
+        Err(e) => Err(e.context("get-children call failed").into()),
     }
 }
 
-pub(crate) fn get_data(res: Result<Response, ZkError>) -> Result<Option<(Vec<u8>, Stat)>, failure::Error> {
+pub(crate) fn get_children2(
+    res: Result<Response, ZkError>,
+) -> Result<Option<(Vec<String>, Stat)>, failure::Error> {
+    match res {
+        Ok(Response::StringsStat { children, stat }) => Ok(Some((children, stat))),
+        Ok(r) => bail!("got non-strings-stat response to get-children2: {:?}", r),
+        Err(ZkError::NoNode) => Ok(None),
+        Err(e) => Err(e.context("get-children2 call failed").into()),
+    }
+}
+
+pub(crate) fn get_data(
+    path: &str,
+    res: Result<Response, ZkError>,
+) -> Result<Option<(Vec<u8>, Stat)>, failure::Error> {
     match res {
         Ok(Response::GetData { bytes, stat }) => Ok(Some((bytes, stat))),
         Ok(r) => bail!("got non-data response to get-data: {:?}", r),
+        Err(e) => match error::GetData::from_zk(e, path) {
+            Ok(error::GetData::NoNode) => Ok(None),
+            Ok(e) => Err(e.context("get-data call failed").into()),
+            Err(e) => Err(e.context("get-data call failed").into()),
+        },
+    }
+}
+
+pub(crate) fn get_data_shared(
+    path: &str,
+    res: Result<Response, ZkError>,
+) -> Result<Option<(Bytes, Stat)>, failure::Error> {
+    match res {
+        Ok(Response::SharedData { bytes, stat }) => Ok(Some((bytes, stat))),
+        Ok(r) => bail!("got non-data response to get-data: {:?}", r),
+        Err(e) => match error::GetData::from_zk(e, path) {
+            Ok(error::GetData::NoNode) => Ok(None),
+            Ok(e) => Err(e.context("get-data call failed").into()),
+            Err(e) => Err(e.context("get-data call failed").into()),
+        },
+    }
+}
+
+pub(crate) fn get_config(
+    res: Result<Response, ZkError>,
+) -> Result<Option<(EnsembleConfig, Stat)>, failure::Error> {
+    match res {
+        Ok(Response::GetData { bytes, stat }) => Ok(Some((EnsembleConfig::parse(&bytes)?, stat))),
+        Ok(r) => bail!("got non-data response to get-config: {:?}", r),
+        // servers without dynamic reconfiguration do not have the config node
         Err(ZkError::NoNode) => Ok(None),
-        Err(e) => Err(format_err!("get-data call failed: {:?}", e)),
+        Err(e) => Err(e.context("get-config call failed").into()),
+    }
+}
+
+pub(crate) fn reconfig(
+    from_config: i64,
+    res: Result<Response, ZkError>,
+) -> Result<Result<(EnsembleConfig, Stat), error::Reconfig>, failure::Error> {
+    match res {
+        Ok(Response::GetData { bytes, stat }) => Ok(Ok((EnsembleConfig::parse(&bytes)?, stat))),
+        Ok(r) => bail!("got non-data response to reconfig: {:?}", r),
+        Err(e) => typed(error::Reconfig::from_zk(e, from_config), "reconfig call failed"),
+    }
+}
+
+pub(crate) fn sync(res: Result<Response, ZkError>) -> Result<String, failure::Error> {
+    match res {
+        Ok(Response::String(path)) => Ok(path),
+        Ok(r) => bail!("got non-string response to sync: {:?}", r),
+        Err(e) => Err(e.context("sync call failed").into()),
+    }
+}
+
+pub(crate) fn get_ephemerals(res: Result<Response, ZkError>) -> Result<Vec<String>, failure::Error> {
+    match res {
+        Ok(Response::Strings(paths)) => Ok(paths),
+        Ok(r) => bail!("got non-strings response to get-ephemerals: {:?}", r),
+        Err(e) => Err(e.context("get-ephemerals call failed").into()),
+    }
+}
+
+pub(crate) fn get_all_children_number(
+    res: Result<Response, ZkError>,
+) -> Result<Option<i32>, failure::Error> {
+    match res {
+        Ok(Response::Count(n)) => Ok(Some(n)),
+        Ok(r) => bail!("got non-count response to get-all-children-number: {:?}", r),
+        Err(ZkError::NoNode) => Ok(None),
+        Err(e) => Err(e.context("get-all-children-number call failed").into()),
+    }
+}
+
+pub(crate) fn who_am_i(
+    res: Result<Response, ZkError>,
+) -> Result<Vec<(String, String)>, failure::Error> {
+    match res {
+        Ok(Response::AuthInfo(ids)) => Ok(ids),
+        Ok(r) => bail!("got non-auth-info response to who-am-i: {:?}", r),
+        Err(e) => Err(e.context("who-am-i call failed").into()),
+    }
+}
+
+pub(crate) fn add_watch(res: Result<Response, ZkError>) -> Result<(), failure::Error> {
+    match res {
+        Ok(Response::Empty) => Ok(()),
+        Ok(r) => bail!("got non-empty response to add-watch: {:?}", r),
+        Err(e) => Err(e.context("add-watch call failed").into()),
+    }
+}
+
+pub(crate) fn remove_watches(
+    res: Result<Response, ZkError>,
+) -> Result<Result<(), error::RemoveWatches>, failure::Error> {
+    match res {
+        Ok(Response::Empty) => Ok(Ok(())),
+        Ok(r) => bail!("got non-empty response to remove-watches: {:?}", r),
+        Err(e) => typed(
+            error::RemoveWatches::from_zk(e),
+            "remove-watches call failed",
+        ),
+    }
+}
+
+pub(crate) fn add_auth(
+    res: Result<Response, ZkError>,
+) -> Result<Result<(), error::Auth>, failure::Error> {
+    match res {
+        Ok(Response::Empty) => Ok(Ok(())),
+        Ok(r) => bail!("got non-empty response to auth: {:?}", r),
+        Err(e) => typed(error::Auth::from_zk(e), "auth call failed"),
+    }
+}
+
+pub(crate) fn sasl(
+    res: Result<Response, ZkError>,
+) -> Result<Result<Vec<u8>, error::Auth>, failure::Error> {
+    match res {
+        Ok(Response::Sasl(token)) => Ok(Ok(token)),
+        Ok(r) => bail!("got non-sasl response to sasl: {:?}", r),
+        Err(e) => typed(error::Auth::from_zk(e), "sasl call failed"),
+    }
+}
+
+pub(crate) fn raw(
+    res: Result<Response, ZkError>,
+) -> Result<Result<Vec<u8>, ZkError>, failure::Error> {
+    match res {
+        Ok(Response::Raw(body)) => Ok(Ok(body)),
+        Ok(r) => bail!("got non-raw response to raw request: {:?}", r),
+        Err(e) => Ok(Err(e)),
     }
 }
 
@@ -103,9 +268,7 @@ pub(crate) fn check(
     match res {
         Ok(Response::Empty) => Ok(Ok(())),
         Ok(r) => bail!("got a non-check response to a check request: {:?}", r),
-        Err(ZkError::NoNode) => Ok(Err(error::Check::NoNode)),
-        Err(ZkError::BadVersion) => Ok(Err(error::Check::BadVersion { expected: version })),
-        Err(e) => bail!("check call failed: {:?}", e),
+        Err(e) => typed(error::Check::from_zk(e, version), "check call failed"),
     }
 }
 
@@ -120,18 +283,37 @@ pub(crate) fn check(
 #[derive(Debug)]
 pub(crate) enum RequestMarker {
     Create,
-    SetData { version: i32 },
+    SetData { version: i32, path: String },
     Delete { version: i32 },
     Check { version: i32 },
+    GetData { path: String },
+    GetChildren,
+}
+
+impl RequestMarker {
+    pub(crate) fn is_read(&self) -> bool {
+        match *self {
+            RequestMarker::GetData { .. } | RequestMarker::GetChildren => true,
+            RequestMarker::Create
+            | RequestMarker::SetData { .. }
+            | RequestMarker::Delete { .. }
+            | RequestMarker::Check { .. } => false,
+        }
+    }
 }
 
 impl From<&Request> for RequestMarker {
     fn from(r: &Request) -> RequestMarker {
         match r {
             Request::Create { .. } => RequestMarker::Create,
-            Request::SetData { version, .. } => RequestMarker::SetData { version: *version },
+            Request::SetData { version, path, .. } => RequestMarker::SetData {
+                version: *version,
+                path: path.clone(),
+            },
             Request::Delete { version, .. } => RequestMarker::Delete { version: *version },
             Request::Check { version, .. } => RequestMarker::Check { version: *version },
+            Request::GetData { path, .. } => RequestMarker::GetData { path: path.clone() },
+            Request::GetChildren { .. } => RequestMarker::GetChildren,
             _ => unimplemented!(),
         }
     }
@@ -156,7 +338,7 @@ pub(crate) fn multi(
         RequestMarker::Create => create(res)?
             .map(|name| MultiResponse::Create(name))
             .map_err(|err| err.into()),
-        RequestMarker::SetData { version } => set_data(*version, res)?
+        RequestMarker::SetData { version, ref path } => set_data(*version, path, res)?
             .map(|stat| MultiResponse::SetData(stat))
             .map_err(|err| err.into()),
         RequestMarker::Delete { version } => delete(*version, res)?
@@ -165,5 +347,156 @@ pub(crate) fn multi(
         RequestMarker::Check { version } => check(*version, res)?
             .map(|_| MultiResponse::Check)
             .map_err(|err| err.into()),
+        RequestMarker::GetData { ref path } => Ok(MultiResponse::GetData(get_data(path, res)?)),
+        RequestMarker::GetChildren => Ok(MultiResponse::GetChildren(get_children(res)?)),
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Permission;
+
+    #[test]
+    fn zk_error_is_attached_as_cause() {
+        let err = set_data(1, "/a", Err(ZkError::NoNode))
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(err, error::SetData::NoNode);
+        assert_eq!(
+            err.cause().and_then(|c| c.downcast_ref::<ZkError>()),
+            Some(&ZkError::NoNode)
+        );
+
+        // codes without a typed variant still carry the originating ZkError
+        let err = set_data(1, "/a", Err(ZkError::ConnectionLoss)).unwrap_err();
+        assert_eq!(err.to_string(), "set_data call failed");
+        assert_eq!(
+            err.find_root_cause().downcast_ref::<ZkError>(),
+            Some(&ZkError::ConnectionLoss)
+        );
+    }
+
+    #[test]
+    fn exists_no_node_is_none() {
+        let stat = Stat {
+            czxid: 1,
+            mzxid: 1,
+            ctime: 0,
+            mtime: 0,
+            version: 0,
+            cversion: 0,
+            aversion: 0,
+            ephemeral_owner: 0,
+            data_length: 0,
+            num_children: 0,
+            pzxid: 1,
+        };
+        assert_eq!(exists(Ok(Response::Stat(stat))).unwrap(), Some(stat));
+        assert_eq!(exists(Err(ZkError::NoNode)).unwrap(), None);
+
+        // anything else is still an error
+        let err = exists(Err(ZkError::NoAuth)).unwrap_err();
+        assert_eq!(
+            err.iter_causes()
+                .filter_map(|c| c.downcast_ref::<error::Exists>())
+                .next(),
+            Some(&error::Exists::NoAuth)
+        );
+        assert_eq!(
+            err.find_root_cause().downcast_ref::<ZkError>(),
+            Some(&ZkError::NoAuth)
+        );
+        let err = exists(Err(ZkError::ConnectionLoss)).unwrap_err();
+        assert_eq!(err.to_string(), "exists call failed");
+        assert!(err
+            .iter_causes()
+            .all(|c| c.downcast_ref::<error::Exists>().is_none()));
+    }
+
+    #[test]
+    fn get_data_no_auth_is_typed() {
+        assert!(get_data("/a", Err(ZkError::NoNode)).unwrap().is_none());
+
+        let err = get_data("/a", Err(ZkError::NoAuth)).unwrap_err();
+        assert_eq!(err.to_string(), "get-data call failed");
+        let no_auth = err
+            .iter_causes()
+            .filter_map(|c| c.downcast_ref::<error::GetData>())
+            .next();
+        assert_eq!(
+            no_auth,
+            Some(&error::GetData::NoAuth {
+                path: "/a".to_string(),
+                required: Permission::READ,
+            })
+        );
+        assert_eq!(
+            no_auth.unwrap().to_string(),
+            "insufficient authentication: READ permission on /a is required"
+        );
+        assert_eq!(
+            err.find_root_cause().downcast_ref::<ZkError>(),
+            Some(&ZkError::NoAuth)
+        );
+    }
+
+    #[test]
+    fn no_auth_names_path() {
+        let err = set_acl(-1, "/a/b", Err(ZkError::NoAuth))
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "insufficient authentication: ADMIN permission on /a/b is required"
+        );
+
+        let set_data = RequestMarker::SetData {
+            version: -1,
+            path: "/a/b".to_string(),
+        };
+        assert_eq!(
+            multi(&set_data, Err(ZkError::NoAuth)).unwrap(),
+            Err(error::Multi::SetData(error::SetData::NoAuth {
+                path: "/a/b".to_string(),
+                required: Permission::WRITE,
+            }))
+        );
+    }
+
+    #[test]
+    fn multi_read() {
+        let res = multi(
+            &RequestMarker::GetChildren,
+            Ok(Response::Strings(vec!["a".to_string()])),
+        );
+        assert_eq!(
+            res.unwrap(),
+            Ok(MultiResponse::GetChildren(Some(vec!["a".to_string()])))
+        );
+        let get_data = RequestMarker::GetData {
+            path: "/a".to_string(),
+        };
+        let res = multi(&get_data, Err(ZkError::NoNode));
+        assert_eq!(res.unwrap(), Ok(MultiResponse::GetData(None)));
+
+        // reads go through the same rollback handling as writes
+        let res = multi(&get_data, Err(ZkError::Ok));
+        assert_eq!(res.unwrap(), Err(error::Multi::RolledBack));
+        let res = multi(
+            &RequestMarker::GetChildren,
+            Err(ZkError::RuntimeInconsistency),
+        );
+        assert_eq!(res.unwrap(), Err(error::Multi::Skipped));
+    }
+
+    #[test]
+    fn get_all_children_number_zero_is_not_missing() {
+        assert_eq!(
+            get_all_children_number(Ok(Response::Count(0))).unwrap(),
+            Some(0)
+        );
+        assert_eq!(get_all_children_number(Err(ZkError::NoNode)).unwrap(), None);
+        assert!(get_all_children_number(Err(ZkError::NoAuth)).is_err());
+    }
+}
@@ -1,4 +1,8 @@
+use base64;
+use error;
+use sha1::Sha1;
 use std::fmt;
+use std::net::IpAddr;
 use std::ops;
 
 use std::string::ToString;
@@ -6,7 +10,8 @@ use std::string::ToString;
 /// Describes the ability of a user to perform a certain action.
 ///
 /// Permissions can be mixed together like integers with `|` and `&`.
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Permission(u32);
 
 impl Permission {
@@ -31,12 +36,13 @@ impl Permission {
     /// You can do anything.
     pub const ALL: Permission = Permission(0b11111);
 
-    /// Extract a permission value from raw `bits`.
-    pub(crate) fn from_raw(bits: u32) -> Permission {
+    /// Extract a permission value from raw `bits`, as they are represented on the wire.
+    pub fn from_raw(bits: u32) -> Permission {
         Permission(bits)
     }
 
-    pub(crate) fn code(&self) -> u32 {
+    /// The raw bits of this permission value, as they are represented on the wire.
+    pub fn to_raw(self) -> u32 {
         self.0
     }
 
@@ -113,6 +119,19 @@ mod tests {
         assert_eq!(Permission::ALL, all);
     }
 
+    #[test]
+    fn permission_raw() {
+        for &p in &[
+            Permission::NONE,
+            Permission::READ,
+            Permission::READ | Permission::ADMIN,
+            Permission::ALL,
+        ] {
+            assert_eq!(Permission::from_raw(p.to_raw()), p);
+        }
+        assert_eq!(Permission::ALL.to_raw(), 0b11111);
+    }
+
     #[test]
     fn permission_can() {
         assert!(Permission::ALL.can(Permission::WRITE));
@@ -133,6 +152,105 @@ mod tests {
         );
         assert_eq!("ADMIN", Permission::ADMIN.to_string());
     }
+
+    #[test]
+    fn digest_id() {
+        assert_eq!(
+            Acl::digest_id("super", "test"),
+            "super:D/InIHSb7yEEbrWz8b9l71RjZJU="
+        );
+        assert_eq!(
+            Acl::digest_id("user", "password"),
+            "user:tpUq/4Pn5A64fVZyQ0gOJ8ZWqkY="
+        );
+    }
+
+    #[test]
+    fn digest() {
+        let acl = Acl::digest("super", "test", Permission::READ | Permission::WRITE);
+        assert_eq!(acl.scheme, "digest");
+        assert_eq!(acl.id, "super:D/InIHSb7yEEbrWz8b9l71RjZJU=");
+        assert_eq!(acl.perms, Permission::READ | Permission::WRITE);
+    }
+
+    #[test]
+    fn valid_acls() {
+        assert_eq!(validate_acl(Acl::open_unsafe()), Ok(()));
+        assert_eq!(validate_acl(Acl::creator_all()), Ok(()));
+        assert_eq!(
+            validate_acl(&[
+                Acl::digest("user", "password", Permission::ALL),
+                Acl::new(Permission::READ, "ip", "10.0.0.0/8"),
+                Acl::new(Permission::READ, "ip", "::1"),
+                Acl::new(Permission::READ, "sasl", "zk@EXAMPLE.COM"),
+                Acl::new(Permission::READ, "x509", "CN=client"),
+            ]),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn invalid_acls() {
+        let reason = |acl: Acl| validate_acl(&[acl]).unwrap_err().reason;
+        assert_eq!(
+            validate_acl(&[]),
+            Err(error::InvalidAcl {
+                entry: None,
+                reason: "ACL must not be empty",
+            })
+        );
+        assert_eq!(
+            reason(Acl::new(Permission::ALL, "world", "everyone")),
+            "the world scheme only has the id anyone"
+        );
+        assert_eq!(
+            reason(Acl::new(Permission::ALL, "kerberos", "zk")),
+            "unknown scheme"
+        );
+        // the password rather than its hash
+        let plain = Acl::new(Permission::ALL, "digest", "user:password");
+        assert!(reason(plain.clone()).starts_with("digest ids must be"));
+        let hash = &Acl::digest_id("user", "password")[5..];
+        for id in &[
+            format!(":{}", hash),
+            format!("user:{}:{}", hash, hash),
+            "user".to_string(),
+        ] {
+            let acl = Acl::new(Permission::ALL, "digest", id);
+            assert!(reason(acl).starts_with("digest ids must be"), "{}", id);
+        }
+        for id in &[
+            "localhost",
+            "10.0.0.0/33",
+            "::1/129",
+            "10.0.0.0/x",
+            "10.0.0.0/",
+        ] {
+            let acl = Acl::new(Permission::ALL, "ip", id);
+            assert!(reason(acl).starts_with("ip ids must be"), "{}", id);
+        }
+
+        let err = validate_acl(&[Acl::open_unsafe()[0].clone(), plain]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid ACL entry (digest:user:password, ALL): digest ids must be \
+             user:base64(sha1(user:password)) (see Acl::digest_id)"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn serde_round_trip() {
+        let perms = Permission::READ | Permission::ADMIN;
+        let json = serde_json::to_string(&perms).unwrap();
+        assert_eq!(json, "17");
+        assert_eq!(serde_json::from_str::<Permission>(&json).unwrap(), perms);
+
+        let acl = Acl::new(Permission::ALL, "world", "anyone");
+        let json = serde_json::to_string(&acl).unwrap();
+        assert_eq!(json, r#"{"perms":31,"scheme":"world","id":"anyone"}"#);
+        assert_eq!(serde_json::from_str::<Acl>(&json).unwrap(), acl);
+    }
 }
 
 /// An access control list.
@@ -146,6 +264,7 @@ mod tests {
 /// See the [ZooKeeper Programmer's Guide](https://zookeeper.apache.org/doc/current/zookeeperProgrammers.html#sc_ZooKeeperAccessControl)
 /// for more information.
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Acl {
     /// The permissions associated with this ACL.
     pub perms: Permission,
@@ -171,6 +290,25 @@ impl Acl {
         }
     }
 
+    /// Compute the id that identifies `user` with the given `password` under the `digest` scheme.
+    ///
+    /// The id is `user:base64(sha1(user:password))`. A client authenticates as this id by calling
+    /// `add_auth("digest", "user:password")`.
+    pub fn digest_id(user: &str, password: &str) -> String {
+        let hash = Sha1::from(format!("{}:{}", user, password))
+            .digest()
+            .bytes();
+        format!("{}:{}", user, base64::encode(&hash))
+    }
+
+    /// Create an ACL that grants `permissions` to `user` with the given `password` under the
+    /// `digest` scheme.
+    ///
+    /// See [`Acl::digest_id`] for how the id is computed.
+    pub fn digest(user: &str, password: &str, permissions: Permission) -> Acl {
+        Acl::new(permissions, "digest", Acl::digest_id(user, password))
+    }
+
     /// This ACL gives the creators authentication id's all permissions.
     pub fn creator_all() -> &'static [Acl] {
         &ACL_CREATOR_ALL[..]
@@ -193,6 +331,64 @@ lazy_static! {
     static ref ACL_READ_UNSAFE: [Acl; 1] = [Acl::new(Permission::READ, "world", "anyone")];
 }
 
+/// Check that `acl` is an ACL the server accepts, before sending it to the server.
+///
+/// The server may still refuse an entry with the `auth` scheme if the client has not
+/// authenticated.
+pub(crate) fn validate_acl(acl: &[Acl]) -> Result<(), error::InvalidAcl> {
+    if acl.is_empty() {
+        return Err(error::InvalidAcl {
+            entry: None,
+            reason: "ACL must not be empty",
+        });
+    }
+    for entry in acl {
+        let reason = match &*entry.scheme {
+            "world" if entry.id != "anyone" => "the world scheme only has the id anyone",
+            "digest" if !is_digest_id(&entry.id) => {
+                "digest ids must be user:base64(sha1(user:password)) (see Acl::digest_id)"
+            }
+            "ip" if !is_ip_id(&entry.id) => "ip ids must be an address with an optional /bits mask",
+            "world" | "auth" | "digest" | "ip" | "sasl" | "x509" => continue,
+            _ => "unknown scheme",
+        };
+        return Err(error::InvalidAcl {
+            entry: Some(entry.clone()),
+            reason,
+        });
+    }
+    Ok(())
+}
+
+/// Whether `id` is a `digest` id as computed by [`Acl::digest_id`].
+fn is_digest_id(id: &str) -> bool {
+    let mut parts = id.split(':');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(user), Some(hash), None) => {
+            // a SHA-1 hash is 20 bytes long
+            !user.is_empty() && base64::decode(hash).ok().map(|h| h.len()) == Some(20)
+        }
+        _ => false,
+    }
+}
+
+/// Whether `id` is an `ip` id, which is an address with an optional mask (as in `10.0.0.0/8`).
+fn is_ip_id(id: &str) -> bool {
+    let mut parts = id.splitn(2, '/');
+    let addr = match parts.next().and_then(|addr| addr.parse::<IpAddr>().ok()) {
+        Some(addr) => addr,
+        None => return false,
+    };
+    match parts.next() {
+        None => true,
+        Some(bits) => match bits.parse::<u8>() {
+            Ok(bits) if addr.is_ipv4() => bits <= 32,
+            Ok(bits) => bits <= 128,
+            Err(_) => false,
+        },
+    }
+}
+
 impl fmt::Display for Acl {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "({}:{}, {})", self.scheme, self.id, self.perms)
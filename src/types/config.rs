@@ -0,0 +1,124 @@
+use failure;
+use std::fmt;
+use std::str;
+
+/// The path of the node that holds the configuration of the ensemble.
+pub(crate) const CONFIG_NODE: &str = "/zookeeper/config";
+
+/// A server that is part of a ZooKeeper ensemble.
+///
+/// This corresponds to a `server.<id>=<address>` line of the ensemble configuration, where the
+/// address has the form `host:quorum_port:election_port[:role][;[client_host:]client_port]`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Member {
+    /// The ID of the server, which is unique within the ensemble.
+    pub id: u64,
+    /// The addresses of the server, and its role in the ensemble.
+    pub address: String,
+}
+
+impl Member {
+    /// Create a member with the given `id` and `address`.
+    pub fn new<A: Into<String>>(id: u64, address: A) -> Self {
+        Member {
+            id,
+            address: address.into(),
+        }
+    }
+}
+
+impl fmt::Display for Member {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "server.{}={}", self.id, self.address)
+    }
+}
+
+/// The dynamic configuration of a ZooKeeper ensemble, as returned by
+/// [`ZooKeeper::get_config`](::ZooKeeper::get_config).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EnsembleConfig {
+    /// The servers of the ensemble, in the order they are listed in.
+    pub members: Vec<Member>,
+    /// The version of the configuration, which is the zxid of the transaction that last changed
+    /// it. This is what [`ZooKeeper::reconfig`](::ZooKeeper::reconfig) expects for
+    /// `from_config`.
+    pub version: i64,
+}
+
+impl EnsembleConfig {
+    /// Parse the configuration as it is stored on the server.
+    pub(crate) fn parse(data: &[u8]) -> Result<Self, failure::Error> {
+        let data = str::from_utf8(data)?;
+        let mut members = Vec::new();
+        let mut version = None;
+        for line in data.lines().map(str::trim).filter(|l| !l.is_empty()) {
+            let eq = match line.find('=') {
+                Some(eq) => eq,
+                None => bail!("malformed ensemble configuration line {:?}", line),
+            };
+            let (key, value) = (&line[..eq], &line[eq + 1..]);
+            if let Some(id) = key.strip_prefix("server.") {
+                let id = id
+                    .parse()
+                    .map_err(|e| format_err!("bad server id in {:?}: {}", line, e))?;
+                members.push(Member::new(id, value));
+            } else if key == "version" {
+                let v = u64::from_str_radix(value, 16)
+                    .map_err(|e| format_err!("bad configuration version {:?}: {}", value, e))?;
+                version = Some(v as i64);
+            }
+            // other keys (such as `group.` and `weight.` settings) do not describe members
+        }
+        match version {
+            Some(version) => Ok(EnsembleConfig { members, version }),
+            None => bail!("ensemble configuration has no version"),
+        }
+    }
+}
+
+/// A change to the membership of an ensemble, for
+/// [`ZooKeeper::reconfig`](::ZooKeeper::reconfig).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Reconfig {
+    /// Add servers to and remove servers from the current ensemble.
+    Incremental {
+        /// The servers to add, or to change the address or role of.
+        joining: Vec<Member>,
+        /// The IDs of the servers to remove.
+        leaving: Vec<u64>,
+    },
+    /// Replace the current ensemble with the given servers.
+    NonIncremental {
+        /// The servers of the new ensemble.
+        members: Vec<Member>,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let data = b"server.1=10.0.0.1:2888:3888:participant;0.0.0.0:2181\n\
+                     server.2=10.0.0.2:2888:3888:observer;2181\n\
+                     version=1000000a5\n";
+        assert_eq!(
+            EnsembleConfig::parse(&data[..]).unwrap(),
+            EnsembleConfig {
+                members: vec![
+                    Member::new(1, "10.0.0.1:2888:3888:participant;0.0.0.0:2181"),
+                    Member::new(2, "10.0.0.2:2888:3888:observer;2181"),
+                ],
+                version: 0x1_0000_00a5,
+            }
+        );
+        assert_eq!(
+            Member::new(3, "h:1:2").to_string(),
+            "server.3=h:1:2".to_string()
+        );
+
+        assert!(EnsembleConfig::parse(b"server.1=h:1:2\n").is_err());
+        assert!(EnsembleConfig::parse(b"server.x=h:1:2\nversion=1\n").is_err());
+    }
+}
@@ -1,3 +1,6 @@
+use bytes::Bytes;
+use std::time;
+
 mod acl;
 pub use self::acl::*;
 
@@ -7,6 +10,10 @@ pub use self::watch::*;
 mod multi;
 pub use self::multi::*;
 
+mod config;
+pub(crate) use self::config::CONFIG_NODE;
+pub use self::config::{EnsembleConfig, Member, Reconfig};
+
 /// Statistics about a znode, similar to the UNIX `stat` structure.
 ///
 /// # Time in ZooKeeper
@@ -22,6 +29,7 @@ pub use self::multi::*;
 /// - **Clock Time**: ZooKeeper does not use clock time to make decisions, but it uses it to put
 ///   timestamps into the `Stat` structure.
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Stat {
     /// The transaction ID that created the znode.
     pub czxid: i64,
@@ -47,32 +55,285 @@ pub struct Stat {
     pub pzxid: i64,
 }
 
+/// The `ephemeral_owner` of container nodes.
+const CONTAINER_OWNER: i64 = i64::MIN;
+
+/// The top byte of the `ephemeral_owner` of TTL nodes.
+const TTL_OWNER_PREFIX: u64 = 0xFF;
+
+impl Stat {
+    /// The time at which the znode was created.
+    pub fn created_at(&self) -> time::SystemTime {
+        from_epoch_millis(self.ctime)
+    }
+
+    /// The time at which the znode was last modified.
+    pub fn modified_at(&self) -> time::SystemTime {
+        from_epoch_millis(self.mtime)
+    }
+
+    /// Whether the children of the znode changed since it had the given `cversion`.
+    ///
+    /// This is much cheaper than comparing lists of children for large directories, and it also
+    /// notices changes that leave the list as it was, such as a child that was deleted and then
+    /// created again. The `cversion` starts over if the znode itself is deleted and created again,
+    /// so any difference counts as a change.
+    pub fn children_changed_since(&self, cversion: i32) -> bool {
+        self.cversion != cversion
+    }
+
+    /// Whether the znode is an ephemeral node.
+    ///
+    /// The server also uses `ephemeral_owner` to mark container and TTL nodes, which are not
+    /// ephemeral.
+    pub fn is_ephemeral(&self) -> bool {
+        self.owner_session_id().is_some()
+    }
+
+    /// The id of the session that owns the znode, if it is an ephemeral node.
+    pub fn owner_session_id(&self) -> Option<i64> {
+        match self.ephemeral_owner {
+            0 | CONTAINER_OWNER => None,
+            owner if owner as u64 >> 56 == TTL_OWNER_PREFIX => None,
+            owner => Some(owner),
+        }
+    }
+}
+
+/// The size of the chunks [`ZooKeeper::get_data_streaming`](::ZooKeeper::get_data_streaming)
+/// splits data into.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// A piece of the data of a node, as yielded by
+/// [`ZooKeeper::get_data_streaming`](::ZooKeeper::get_data_streaming).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DataChunk {
+    /// The next chunk of the data of the node.
+    Data(Bytes),
+    /// The [`Stat`] of the node, which comes after all of its data.
+    Stat(Stat),
+}
+
+impl DataChunk {
+    /// Split `data` into chunks without copying it, and follow them with `stat`.
+    pub(crate) fn split(mut data: Bytes, stat: Stat) -> Vec<DataChunk> {
+        let mut chunks = Vec::new();
+        while !data.is_empty() {
+            let len = ::std::cmp::min(CHUNK_SIZE, data.len());
+            chunks.push(DataChunk::Data(data.split_to(len)));
+        }
+        chunks.push(DataChunk::Stat(stat));
+        chunks
+    }
+}
+
+fn from_epoch_millis(ms: i64) -> time::SystemTime {
+    let offset = time::Duration::from_millis(ms.unsigned_abs());
+    if ms >= 0 {
+        time::UNIX_EPOCH + offset
+    } else {
+        time::UNIX_EPOCH - offset
+    }
+}
+
 /// CreateMode value determines how the znode is created on ZooKeeper.
-#[repr(i32)]
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum CreateMode {
     /// The znode will not be automatically deleted upon client's disconnect.
-    Persistent = 0,
+    Persistent,
     /// The znode will be deleted upon the client's disconnect.
-    Ephemeral = 1,
+    Ephemeral,
     /// The name of the znode will be appended with a monotonically increasing number. The actual
     /// path name of a sequential node will be the given path plus a suffix `"i"` where *i* is the
     /// current sequential number of the node. The sequence number is always fixed length of 10
     /// digits, 0 padded. Once such a node is created, the sequential number will be incremented by
     /// one.
-    PersistentSequential = 2,
+    PersistentSequential,
     /// The znode will be deleted upon the client's disconnect, and its name will be appended with a
     /// monotonically increasing number.
-    EphemeralSequential = 3,
+    EphemeralSequential,
     /// Container nodes are special purpose nodes useful for recipes such as leader, lock, etc. When
     /// the last child of a container is deleted, the container becomes a candidate to be deleted by
     /// the server at some point in the future. Given this property, you should be prepared to get
     /// `ZkError::NoNode` when creating children inside of this container node.
-    Container = 4,
-    //
-    // 421
-    // 000
-    // ^----- is it a container?
-    //  ^---- is it sequential?
-    //   ^--- is it ephemeral?
+    ///
+    /// Container nodes require ZooKeeper 3.5 or newer.
+    Container,
+    /// The znode will not be automatically deleted upon client's disconnect. However, if it has not
+    /// been modified within the given TTL and has no children, it becomes a candidate to be
+    /// deleted by the server at some point in the future.
+    ///
+    /// The TTL must be between 1 millisecond and `0xFFFFFFFFFF` milliseconds (about 34 years).
+    /// TTL nodes require ZooKeeper 3.6 or newer, and must be enabled on the server with
+    /// `zookeeper.extendedTypesEnabled`.
+    PersistentWithTtl(time::Duration),
+    /// Like [`CreateMode::PersistentWithTtl`], but the name of the znode will be appended with a
+    /// monotonically increasing number.
+    PersistentSequentialWithTtl(time::Duration),
+}
+
+/// The largest TTL the server accepts, in milliseconds.
+const MAX_TTL_MILLIS: u128 = 0xFF_FFFF_FFFF;
+
+impl CreateMode {
+    /// The create flags the server knows this mode by.
+    pub(crate) fn flag(&self) -> i32 {
+        // 421
+        // 000
+        // ^----- is it a container?
+        //  ^---- is it sequential?
+        //   ^--- is it ephemeral?
+        //
+        // TTL modes do not follow this scheme, and just use the next two free values.
+        match *self {
+            CreateMode::Persistent => 0,
+            CreateMode::Ephemeral => 1,
+            CreateMode::PersistentSequential => 2,
+            CreateMode::EphemeralSequential => 3,
+            CreateMode::Container => 4,
+            CreateMode::PersistentWithTtl(_) => 5,
+            CreateMode::PersistentSequentialWithTtl(_) => 6,
+        }
+    }
+
+    /// Whether the server appends a sequence number to the names of nodes created in this mode.
+    pub(crate) fn is_sequential(&self) -> bool {
+        match *self {
+            CreateMode::PersistentSequential
+            | CreateMode::EphemeralSequential
+            | CreateMode::PersistentSequentialWithTtl(_) => true,
+            CreateMode::Persistent
+            | CreateMode::Ephemeral
+            | CreateMode::Container
+            | CreateMode::PersistentWithTtl(_) => false,
+        }
+    }
+
+    /// The TTL of this mode, if it has one.
+    pub(crate) fn ttl(&self) -> Option<time::Duration> {
+        match *self {
+            CreateMode::PersistentWithTtl(ttl) | CreateMode::PersistentSequentialWithTtl(ttl) => {
+                Some(ttl)
+            }
+            _ => None,
+        }
+    }
+
+    /// The TTL of this mode in milliseconds, or `None` if it is outside the range the server
+    /// accepts.
+    pub(crate) fn ttl_millis(&self) -> Option<i64> {
+        self.ttl().and_then(|ttl| match ttl.as_millis() {
+            ms @ 1..=MAX_TTL_MILLIS => Some(ms as i64),
+            _ => None,
+        })
+    }
+}
+
+/// An optional capability of a server, for [`ZooKeeper::supports`](::ZooKeeper::supports).
+///
+/// Older servers do not implement some requests, and newer ones can have some of them disabled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Feature {
+    /// Returning the `Stat` of a newly created node, as done by
+    /// [`ZooKeeper::create_with_stat`](::ZooKeeper::create_with_stat). This requires ZooKeeper 3.5
+    /// or newer.
+    CreateWithStat,
+    /// Nodes created with [`CreateMode::Container`]. This requires ZooKeeper 3.5 or newer.
+    Container,
+    /// Nodes created with [`CreateMode::PersistentWithTtl`] or
+    /// [`CreateMode::PersistentSequentialWithTtl`]. This requires ZooKeeper 3.6 or newer, with
+    /// `zookeeper.extendedTypesEnabled` set.
+    Ttl,
+    /// [`ZooKeeper::get_ephemerals`](::ZooKeeper::get_ephemerals). This requires ZooKeeper 3.6 or
+    /// newer.
+    GetEphemerals,
+    /// Persistent watches, as added by [`ZooKeeper::add_watch`](::ZooKeeper::add_watch). This
+    /// requires ZooKeeper 3.6 or newer.
+    PersistentWatches,
+    /// [`ZooKeeper::who_am_i`](::ZooKeeper::who_am_i). This requires ZooKeeper 3.7 or newer.
+    WhoAmI,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stat(ctime: i64, mtime: i64, ephemeral_owner: i64) -> Stat {
+        Stat {
+            czxid: 0,
+            mzxid: 0,
+            ctime,
+            mtime,
+            version: 0,
+            cversion: 0,
+            aversion: 0,
+            ephemeral_owner,
+            data_length: 0,
+            num_children: 0,
+            pzxid: 0,
+        }
+    }
+
+    #[test]
+    fn timestamps() {
+        let s = stat(1_536_000_000_123, -1_500, 0);
+        assert_eq!(
+            s.created_at().duration_since(time::UNIX_EPOCH).unwrap(),
+            time::Duration::from_millis(1_536_000_000_123)
+        );
+        assert_eq!(
+            time::UNIX_EPOCH.duration_since(s.modified_at()).unwrap(),
+            time::Duration::from_millis(1_500)
+        );
+    }
+
+    #[test]
+    fn children_changed() {
+        let mut s = stat(0, 0, 0);
+        s.cversion = 2;
+        assert!(!s.children_changed_since(2));
+        assert!(s.children_changed_since(0));
+        // the node was deleted and created again since
+        assert!(s.children_changed_since(5));
+    }
+
+    #[test]
+    fn ephemeral_owner() {
+        let s = stat(0, 0, 0x0100_0000_0000_002a);
+        assert!(s.is_ephemeral());
+        assert_eq!(s.owner_session_id(), Some(0x0100_0000_0000_002a));
+
+        // persistent, container, and TTL nodes have no owner
+        for &owner in &[0, i64::MIN, 0xFF00_0000_0000_2710_u64 as i64] {
+            let s = stat(0, 0, owner);
+            assert!(!s.is_ephemeral());
+            assert_eq!(s.owner_session_id(), None);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn serde_round_trip() {
+        let s = Stat {
+            czxid: 1,
+            mzxid: 2,
+            ctime: 3,
+            mtime: 4,
+            version: 5,
+            cversion: 6,
+            aversion: 7,
+            ephemeral_owner: 8,
+            data_length: 9,
+            num_children: 10,
+            pzxid: 11,
+        };
+        let json = serde_json::to_string(&s).unwrap();
+        assert_eq!(
+            json,
+            "{\"czxid\":1,\"mzxid\":2,\"ctime\":3,\"mtime\":4,\"version\":5,\"cversion\":6,\
+             \"aversion\":7,\"ephemeral_owner\":8,\"data_length\":9,\"num_children\":10,\
+             \"pzxid\":11}"
+        );
+        assert_eq!(serde_json::from_str::<Stat>(&json).unwrap(), s);
+    }
 }
@@ -1,4 +1,5 @@
 use super::Stat;
+use error;
 
 /// An individual response in a `multi` request.
 #[derive(Debug, PartialEq)]
@@ -11,4 +12,87 @@ pub enum MultiResponse {
     Delete,
     /// The response to a `check` request within a `multi` batch.
     Check,
+    /// The response to a `get_data` request within a `multi` batch, or `None` if the node did not
+    /// exist.
+    GetData(Option<(Vec<u8>, Stat)>),
+    /// The response to a `get_children` request within a `multi` batch, or `None` if the node did
+    /// not exist.
+    GetChildren(Option<Vec<String>>),
+}
+
+impl MultiResponse {
+    /// Collapse the per-operation `results` of a `multi` request into the responses of all of
+    /// them, or the error that made the whole request fail.
+    ///
+    /// The operations of a `multi` request succeed or fail together, so the other operations of a
+    /// failed request report [`error::Multi::RolledBack`] or [`error::Multi::Skipped`]. The error
+    /// returned here is the real cause of the failure instead, along with the position of the
+    /// operation that failed.
+    pub fn all(
+        results: Vec<Result<MultiResponse, error::Multi>>,
+    ) -> Result<Vec<MultiResponse>, error::MultiError> {
+        let mut responses = Vec::with_capacity(results.len());
+        let mut failure = None;
+        for (i, result) in results.into_iter().enumerate() {
+            let error = match result {
+                Ok(response) => {
+                    responses.push(response);
+                    continue;
+                }
+                Err(e) => error::MultiError {
+                    failed_index: i,
+                    cause: e,
+                },
+            };
+            match error.cause {
+                error::Multi::RolledBack | error::Multi::Skipped => {
+                    failure = failure.or(Some(error));
+                }
+                _ => return Err(error),
+            }
+        }
+        match failure {
+            // the server did not say which operation failed
+            Some(e) => Err(e),
+            None => Ok(responses),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn all_reports_the_real_failure() {
+        assert_eq!(
+            MultiResponse::all(vec![Ok(MultiResponse::Delete), Ok(MultiResponse::Check)]),
+            Ok(vec![MultiResponse::Delete, MultiResponse::Check])
+        );
+        let err = MultiResponse::all(vec![
+            Err(error::Multi::RolledBack),
+            Err(error::Multi::Create(error::Create::NodeExists)),
+            Err(error::Multi::Skipped),
+        ])
+        .unwrap_err();
+        assert_eq!(
+            err,
+            error::MultiError {
+                failed_index: 1,
+                cause: error::Multi::Create(error::Create::NodeExists),
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "operation 1 of multi request failed: create failed: target node already exists"
+        );
+        assert_eq!(
+            MultiResponse::all(vec![Ok(MultiResponse::Check), Err(error::Multi::Skipped)]),
+            Err(error::MultiError {
+                failed_index: 1,
+                cause: error::Multi::Skipped,
+            })
+        );
+        assert_eq!(MultiResponse::all(vec![]), Ok(vec![]));
+    }
 }
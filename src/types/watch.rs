@@ -11,32 +11,46 @@ pub struct WatchedEvent {
     /// The path of the znode that was involved.
     // This will be `None` for session-related triggers.
     pub path: String,
+    /// The zxid of the change that triggered the watch.
+    ///
+    /// Only newer servers include this in their notifications, so it is `None` for events from
+    /// older servers, as well as for events that the client generates itself.
+    pub zxid: Option<i64>,
 }
 
 /// Enumeration of states the client may be at a Watcher Event. It represents the state of the
 /// server at the time the event was generated.
-#[repr(i32)]
+///
+/// States that this client does not know about, such as those added by newer servers, are
+/// represented by [`KeeperState::Unknown`].
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum KeeperState {
     /// The client is in the disconnected state - it is not connected to any server in the ensemble.
-    Disconnected = 0,
+    Disconnected,
     /// The client is in the connected state - it is connected to a server in the ensemble (one of
     /// the servers specified in the host connection parameter during ZooKeeper client creation).
-    SyncConnected = 3,
+    SyncConnected,
     /// Authentication has failed -- connection requires a new `ZooKeeper` instance.
-    AuthFailed = 4,
+    AuthFailed,
     /// The client is connected to a read-only server, that is the server which is not currently
     /// connected to the majority. The only operations allowed after receiving this state is read
     /// operations. This state is generated for read-only clients only since read/write clients
     /// aren't allowed to connect to read-only servers.
-    ConnectedReadOnly = 5,
+    ConnectedReadOnly,
     /// Used to notify clients that they are SASL-authenticated, so that they can perform ZooKeeper
     /// actions with their SASL-authorized permissions.
-    SaslAuthenticated = 6,
+    SaslAuthenticated,
     /// The serving cluster has expired this session. The ZooKeeper client connection (the session)
     /// is no longer valid. You must create a new client connection (instantiate a new `ZooKeeper`
     /// instance) if you with to access the ensemble.
-    Expired = -112,
+    Expired,
+    /// The client gave up on connecting to the ensemble (see
+    /// [`ZooKeeperBuilder::set_max_reconnect_attempts`](::ZooKeeperBuilder::set_max_reconnect_attempts)),
+    /// and will not try again. The session may still be alive on the server until it times out,
+    /// but this client can no longer use it. The server never sends this state.
+    Closed,
+    /// A state with the given code that this client does not know about.
+    Unknown(i32),
 }
 
 impl From<i32> for KeeperState {
@@ -48,33 +62,37 @@ impl From<i32> for KeeperState {
             5 => KeeperState::ConnectedReadOnly,
             6 => KeeperState::SaslAuthenticated,
             -112 => KeeperState::Expired,
-            _ => unreachable!("unknown keeper state {:x}", code),
+            code => KeeperState::Unknown(code),
         }
     }
 }
 
 /// Enumeration of types of events that may occur on the znode.
-#[repr(i32)]
+///
+/// Types of events that this client does not know about, such as those added by newer servers,
+/// are represented by [`WatchedEventType::Unknown`].
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum WatchedEventType {
     /// Nothing known has occurred on the znode. This value is issued as part of a `WatchedEvent`
     /// when the `KeeperState` changes.
-    None = -1,
+    None,
     /// Issued when a znode at a given path is created.
-    NodeCreated = 1,
+    NodeCreated,
     /// Issued when a znode at a given path is deleted.
-    NodeDeleted = 2,
+    NodeDeleted,
     /// Issued when the data of a watched znode are altered. This event value is issued whenever a
     /// *set* operation occurs without an actual contents check, so there is no guarantee the data
     /// actually changed.
-    NodeDataChanged = 3,
+    NodeDataChanged,
     /// Issued when the children of a watched znode are created or deleted. This event is not issued
     /// when the data within children is altered.
-    NodeChildrenChanged = 4,
+    NodeChildrenChanged,
     /// Issued when the client removes a data watcher.
-    DataWatchRemoved = 5,
+    DataWatchRemoved,
     /// Issued when the client removes a child watcher.
-    ChildWatchRemoved = 6,
+    ChildWatchRemoved,
+    /// An event with the given code that this client does not know about.
+    Unknown(i32),
 }
 
 impl From<i32> for WatchedEventType {
@@ -87,7 +105,60 @@ impl From<i32> for WatchedEventType {
             4 => WatchedEventType::NodeChildrenChanged,
             5 => WatchedEventType::DataWatchRemoved,
             6 => WatchedEventType::ChildWatchRemoved,
-            _ => unreachable!("unknown event type {:x}", code),
+            code => WatchedEventType::Unknown(code),
+        }
+    }
+}
+
+/// The kind of watch set with [`ZooKeeper::add_watch`](::ZooKeeper::add_watch).
+#[repr(i32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AddWatchMode {
+    /// A watch on the given path that, unlike the watches set by read operations, is not removed
+    /// when it is triggered. It fires for changes to the node's data, its creation and deletion,
+    /// and changes to its children.
+    Persistent = 0,
+    /// Like [`AddWatchMode::Persistent`], but also fires for the data, creation, and deletion of
+    /// every node below the given path. Children change events are not sent, as they are implied
+    /// by the creation and deletion events of the children themselves.
+    PersistentRecursive = 1,
+}
+
+/// The kind of watches to remove with
+/// [`ZooKeeper::remove_watches`](::ZooKeeper::remove_watches).
+#[repr(i32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WatcherType {
+    /// Watches for changes to a node's children, as set by `get_children`.
+    Children = 1,
+    /// Watches for changes to a node's data or existence, as set by `get_data` and `exists`.
+    Data = 2,
+    /// Watches of any kind.
+    Any = 3,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_codes() {
+        assert_eq!(KeeperState::from(3), KeeperState::SyncConnected);
+        assert_eq!(KeeperState::from(-112), KeeperState::Expired);
+        for &code in &[1, 2, 7, -1, -113, i32::MAX] {
+            assert_eq!(KeeperState::from(code), KeeperState::Unknown(code));
+        }
+
+        assert_eq!(WatchedEventType::from(-1), WatchedEventType::None);
+        assert_eq!(
+            WatchedEventType::from(6),
+            WatchedEventType::ChildWatchRemoved
+        );
+        for &code in &[0, 7, -2, i32::MIN] {
+            assert_eq!(
+                WatchedEventType::from(code),
+                WatchedEventType::Unknown(code)
+            );
         }
     }
 }